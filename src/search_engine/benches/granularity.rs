@@ -0,0 +1,93 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Indexing and searching a document per line against one per file.
+//!
+//! `indexing` indexes the same tree at both granularities. `search` runs a
+//! query requiring two terms on different lines of every file, which only
+//! whole-file documents match, and a single-term query matching a line of
+//! every file. Whole-file matches are located with a `SnippetGenerator`.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use search_engine::{CodeSearchEngine, EngineConfig, Granularity, SearchOptions};
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+const FILES: usize = 200;
+const LINES_PER_FILE: usize = 100;
+
+fn write_tree(root: &Path) {
+    for file in 0..FILES {
+        let content = (0..LINES_PER_FILE)
+            .map(|line| match line {
+                10 => "    let reader = open(path);".to_string(),
+                90 => "    reader.close();".to_string(),
+                _ => format!("    let value_{line} = compute({file}, {line});"),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        fs::write(root.join(format!("file_{file}.rs")), content).unwrap();
+    }
+}
+
+fn config(granularity: Granularity) -> EngineConfig {
+    EngineConfig {
+        granularity,
+        ..EngineConfig::default()
+    }
+}
+
+fn index(
+    runtime: &tokio::runtime::Runtime,
+    dir: &TempDir,
+    granularity: Granularity,
+) -> CodeSearchEngine {
+    let directory = dir.path().to_str().unwrap();
+    runtime
+        .block_on(CodeSearchEngine::new(directory, config(granularity)))
+        .unwrap()
+}
+
+fn granularity(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    write_tree(dir.path());
+    let modes = [("line", Granularity::Line), ("file", Granularity::File)];
+
+    let mut group = c.benchmark_group("indexing");
+    group.sample_size(10);
+    for (name, mode) in modes {
+        group.bench_function(name, |b| b.iter(|| index(&runtime, &dir, mode)));
+    }
+    group.finish();
+
+    let options = SearchOptions {
+        limit: Some(FILES),
+        ..SearchOptions::default()
+    };
+    let mut group = c.benchmark_group("search");
+    for (name, mode) in modes {
+        let engine = index(&runtime, &dir, mode);
+        for query in ["+open +close", "reader"] {
+            group.bench_function(format!("{name}/{query}"), |b| {
+                b.iter(|| runtime.block_on(engine.search(query, &options)).unwrap())
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, granularity);
+criterion_main!(benches);
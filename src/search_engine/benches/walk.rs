@@ -0,0 +1,107 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cost of an excluded `node_modules` when indexing a tree.
+//!
+//! `indexing` indexes the same sources with and without 20 000 files in an
+//! excluded `node_modules`. Excluded directories are pruned from the walk,
+//! so the two should take about as long. `unpruned_walk` walks the tree with
+//! `node_modules` and filters the files afterwards, as the walk did before
+//! directories were pruned, which is the cost pruning saves.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use search_engine::{CodeSearchEngine, EngineConfig};
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+use walkdir::WalkDir;
+
+const SOURCE_FILES: usize = 200;
+const DEPENDENCY_DIRS: usize = 1_000;
+const FILES_PER_DEPENDENCY: usize = 20;
+
+fn write_sources(root: &Path) {
+    let src = root.join("src");
+    fs::create_dir_all(&src).unwrap();
+    for file in 0..SOURCE_FILES {
+        let content = format!("fn function_{file}() {{\n    println!(\"{file}\");\n}}\n");
+        fs::write(src.join(format!("file_{file}.rs")), content).unwrap();
+    }
+}
+
+fn write_dependencies(root: &Path) {
+    for package in 0..DEPENDENCY_DIRS {
+        let dir = root.join(format!("node_modules/package_{package}/lib"));
+        fs::create_dir_all(&dir).unwrap();
+        for file in 0..FILES_PER_DEPENDENCY {
+            let content = format!("module.exports = {package} + {file};\n");
+            fs::write(dir.join(format!("module_{file}.js")), content).unwrap();
+        }
+    }
+}
+
+/// Only `node_modules` is excluded, by pattern, so the built-in default
+/// excludes don't hide the difference.
+fn config() -> EngineConfig {
+    EngineConfig {
+        exclude_patterns: vec![".git".to_string(), "node_modules".to_string()],
+        use_default_excludes: false,
+        ..EngineConfig::default()
+    }
+}
+
+fn index(runtime: &tokio::runtime::Runtime, dir: &TempDir) -> CodeSearchEngine {
+    let directory = dir.path().to_str().unwrap();
+    runtime
+        .block_on(CodeSearchEngine::new(directory, config()))
+        .unwrap()
+}
+
+fn excluded_node_modules(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let sources_only = tempfile::tempdir().unwrap();
+    write_sources(sources_only.path());
+    let with_dependencies = tempfile::tempdir().unwrap();
+    write_sources(with_dependencies.path());
+    write_dependencies(with_dependencies.path());
+
+    let mut group = c.benchmark_group("indexing");
+    group.sample_size(20);
+    group.bench_function("sources_only", |b| {
+        b.iter(|| index(&runtime, &sources_only))
+    });
+    group.bench_function("excluded_node_modules", |b| {
+        b.iter(|| index(&runtime, &with_dependencies))
+    });
+    group.finish();
+
+    c.bench_function("unpruned_walk", |b| {
+        b.iter(|| {
+            WalkDir::new(with_dependencies.path())
+                .into_iter()
+                .filter_map(Result::ok)
+                .filter(|entry| entry.file_type().is_file())
+                .filter(|entry| {
+                    !entry
+                        .path()
+                        .to_str()
+                        .is_some_and(|path| path.contains("node_modules"))
+                })
+                .count()
+        })
+    });
+}
+
+criterion_group!(benches, excluded_node_modules);
+criterion_main!(benches);
@@ -0,0 +1,78 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Peak memory of the initial index, with and without commit batching.
+//!
+//! Peak memory is only known per process, so each configuration is a run:
+//!
+//! ```text
+//! cargo run --release --example peak_memory
+//! cargo run --release --example peak_memory -- 100000
+//! ```
+//!
+//! indexes a generated tree of 2 000 files of 500 lines, committing once at
+//! the end, then every 100 000 documents, and prints the peak resident set
+//! size (`VmHWM`, Linux only).
+
+use search_engine::{CodeSearchEngine, EngineConfig};
+use std::fs;
+
+const FILES: usize = 2_000;
+const LINES_PER_FILE: usize = 500;
+
+fn peak_resident_kb() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+#[tokio::main]
+async fn main() {
+    let commit_every_docs = std::env::args().nth(1).map(|docs| {
+        docs.parse::<usize>()
+            .expect("the argument is a number of documents")
+    });
+
+    let dir = tempfile::tempdir().unwrap();
+    for file in 0..FILES {
+        let lines: Vec<String> = (0..LINES_PER_FILE)
+            .map(|line| format!("let value_{file}_{line} = compute(item_{line}, {file});"))
+            .collect();
+        fs::write(dir.path().join(format!("file_{file}.rs")), lines.join("\n")).unwrap();
+    }
+    let before = peak_resident_kb();
+
+    let config = EngineConfig {
+        commit_every_docs,
+        ..EngineConfig::default()
+    };
+    let engine = CodeSearchEngine::new(dir.path().to_str().unwrap(), config)
+        .await
+        .unwrap();
+    drop(engine);
+
+    let batching = match commit_every_docs {
+        Some(docs) => format!("every {docs} documents"),
+        None => "once".to_string(),
+    };
+    match (before, peak_resident_kb()) {
+        (Some(before), Some(peak)) => println!(
+            "{} documents, committed {batching}: peak RSS {} MiB ({} MiB before indexing)",
+            FILES * LINES_PER_FILE,
+            peak / 1024,
+            before / 1024
+        ),
+        _ => println!("Peak RSS isn't available on this platform"),
+    }
+}
@@ -0,0 +1,111 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Search results as grep style lines for terminals, optionally colored
+//! with ANSI escapes.
+
+use std::ops::Range;
+
+use crate::{read_lock, CodeSearchEngine, RgMatcher, SearchResult};
+
+const RESET: &str = "\x1b[0m";
+const PATH: &str = "\x1b[35m";
+const LINE_NUMBER: &str = "\x1b[32m";
+const MATCH: &str = "\x1b[1;31m";
+const CONTEXT: &str = "\x1b[2m";
+
+impl CodeSearchEngine {
+    /// The lines of `result` as `path:line:text`, its context lines as
+    /// `path-line-text`, or nothing when the file isn't cached. With `color`,
+    /// the query terms located by `matcher` are highlighted and the context
+    /// is dimmed.
+    pub fn terminal_lines(
+        &self,
+        matcher: &RgMatcher,
+        result: &SearchResult,
+        color: bool,
+    ) -> Vec<String> {
+        let content_path = self
+            .indexed_copy(&result.path)
+            .unwrap_or_else(|| result.path.clone());
+        self.use_lines(&content_path);
+        let lines_map = read_lock(&self.lines_map);
+        let Some(lines) = lines_map.get(&content_path) else {
+            return Vec::new();
+        };
+        let (start, end) = match &result.line_range {
+            Some(range) => (range.start, range.end),
+            None => (result.line, result.line),
+        };
+        (start.max(1)..=end.min(lines.len()))
+            .map(|number| {
+                let text = &lines[number - 1];
+                let matched = number == result.line;
+                let separator = if matched { ':' } else { '-' };
+                if !color {
+                    return format!("{}{separator}{number}{separator}{text}", result.path);
+                }
+                let text = if matched {
+                    highlighted(text, &matcher.ranges(text))
+                } else if text.is_empty() {
+                    String::new()
+                } else {
+                    format!("{CONTEXT}{text}{RESET}")
+                };
+                format!(
+                    "{PATH}{}{RESET}{separator}{LINE_NUMBER}{number}{RESET}{separator}{text}",
+                    result.path
+                )
+            })
+            .collect()
+    }
+}
+
+/// `text` with the byte `ranges` in the match color.
+fn highlighted(text: &str, ranges: &[Range<usize>]) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut written = 0;
+    for range in ranges {
+        // Overlapping ranges are highlighted once.
+        let Some(term) = text.get(range.clone()).filter(|_| range.start >= written) else {
+            continue;
+        };
+        out.push_str(&text[written..range.start]);
+        out.push_str(MATCH);
+        out.push_str(term);
+        out.push_str(RESET);
+        written = range.end;
+    }
+    out.push_str(&text[written..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges_are_highlighted_once_in_order() {
+        assert_eq!(
+            highlighted("let needle = needle;", &[4..10, 13..19]),
+            format!("let {MATCH}needle{RESET} = {MATCH}needle{RESET};")
+        );
+        // Overlapping and out of bounds ranges are skipped.
+        assert_eq!(
+            highlighted("needle", &[0..6, 2..4, 4..20]),
+            format!("{MATCH}needle{RESET}")
+        );
+        assert_eq!(highlighted("plain", &[]), "plain");
+    }
+}
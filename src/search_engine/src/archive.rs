@@ -0,0 +1,92 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reading text files stored inside `.zip` and `.tar.gz` archives.
+
+use flate2::read::GzDecoder;
+use std::fs;
+use std::io::{self, Read};
+
+use crate::skipped::{decode_lines, Decoding};
+use crate::{is_excluded, Documents};
+
+/// Separates the archive path from the path of a file inside it.
+const SEPARATOR: &str = "!/";
+
+pub(crate) fn is_archive(path: &str) -> bool {
+    path.ends_with(".zip") || path.ends_with(".tar.gz") || path.ends_with(".tgz")
+}
+
+/// Build the virtual path of a file inside an archive, e.g. `archive.zip!/src/lib.rs`.
+pub(crate) fn virtual_path(archive_path: &str, entry: &str) -> String {
+    format!("{archive_path}{SEPARATOR}{entry}")
+}
+
+/// The path of the archive holding a virtual path, or `path` itself when
+/// it isn't one.
+pub(crate) fn container_path(path: &str) -> &str {
+    path.split_once(SEPARATOR)
+        .map_or(path, |(archive, _)| archive)
+}
+
+/// Read every file in the archive as a `(virtual path, lines)` pair, with
+/// the reason in place of the lines for files that aren't text.
+pub(crate) fn read_entries(
+    path: &str,
+    exclude_patterns: &[String],
+    decoding: Decoding,
+) -> io::Result<Documents> {
+    let entries = if path.ends_with(".zip") {
+        read_zip(path)?
+    } else {
+        read_tar_gz(path)?
+    };
+
+    Ok(entries
+        .into_iter()
+        .filter(|(name, _)| !is_excluded(name, exclude_patterns))
+        .map(|(name, bytes)| (virtual_path(path, &name), decode_lines(bytes, decoding)))
+        .collect())
+}
+
+fn read_zip(path: &str) -> io::Result<Vec<(String, Vec<u8>)>> {
+    let mut archive = zip::ZipArchive::new(fs::File::open(path)?)?;
+    let mut entries = Vec::new();
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        if !entry.is_file() {
+            continue;
+        }
+        let mut bytes = Vec::new();
+        entry.read_to_end(&mut bytes)?;
+        entries.push((entry.name().to_string(), bytes));
+    }
+    Ok(entries)
+}
+
+fn read_tar_gz(path: &str) -> io::Result<Vec<(String, Vec<u8>)>> {
+    let mut archive = tar::Archive::new(GzDecoder::new(fs::File::open(path)?));
+    let mut entries = Vec::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path()?.to_string_lossy().to_string();
+        let mut bytes = Vec::new();
+        entry.read_to_end(&mut bytes)?;
+        entries.push((name, bytes));
+    }
+    Ok(entries)
+}
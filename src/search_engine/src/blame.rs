@@ -0,0 +1,145 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Who last changed each line of a file, from `git blame`.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::git::git;
+
+/// The commit that last changed a line.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Blame {
+    pub author: String,
+    pub commit: String,
+    /// Author date in UTC, e.g. `2025-03-14T09:26:53Z`.
+    pub date: String,
+}
+
+/// The blame of every line of the file at `path`, `None` for lines not
+/// committed yet. `None` altogether when the file isn't in a git work tree.
+pub(crate) fn blame_file(path: &str) -> Option<Vec<Option<Blame>>> {
+    let path = Path::new(path);
+    let directory = path.parent()?.to_str()?;
+    let name = path.file_name()?.to_str()?;
+    let output = git(directory, &["blame", "--porcelain", "--", name]).ok()?;
+    Some(parse_porcelain(&String::from_utf8_lossy(&output)))
+}
+
+/// Read the output of `git blame --porcelain`. Each line starts with a
+/// `<commit> <original line> <final line>` header, followed by the details
+/// of the commit the first time it appears, then the line itself after a tab.
+fn parse_porcelain(output: &str) -> Vec<Option<Blame>> {
+    // Author name and time by commit.
+    let mut commits: HashMap<&str, (&str, i64)> = HashMap::new();
+    let mut lines = Vec::new();
+    let mut current: Option<(&str, usize)> = None;
+    for line in output.lines() {
+        if line.starts_with('\t') {
+            let Some((commit, number)) = current.take() else {
+                continue;
+            };
+            let blame = commits
+                .get(commit)
+                .filter(|_| commit.bytes().any(|byte| byte != b'0'))
+                .map(|&(author, time)| Blame {
+                    author: author.to_string(),
+                    commit: commit.to_string(),
+                    date: utc_date(time),
+                });
+            if lines.len() < number {
+                lines.resize(number, None);
+            }
+            lines[number - 1] = blame;
+        } else if let Some(author) = line.strip_prefix("author ") {
+            if let Some((commit, _)) = current {
+                commits.entry(commit).or_default().0 = author;
+            }
+        } else if let Some(time) = line.strip_prefix("author-time ") {
+            if let Some((commit, _)) = current {
+                commits.entry(commit).or_default().1 = time.parse().unwrap_or(0);
+            }
+        } else {
+            let mut parts = line.split(' ');
+            let commit = parts.next().unwrap_or_default();
+            let number = parts.nth(1).and_then(|number| number.parse().ok());
+            if let (40, Some(number)) = (commit.len(), number) {
+                if number > 0 && commit.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+                    current = Some((commit, number));
+                }
+            }
+        }
+    }
+    lines
+}
+
+/// Format seconds since the Unix epoch as an RFC 3339 UTC timestamp.
+fn utc_date(seconds: i64) -> String {
+    let (days, time) = (seconds.div_euclid(86_400), seconds.rem_euclid(86_400));
+    // Civil date from a day count, shifted to start years in March so the
+    // leap day comes last.
+    let shifted = days + 719_468;
+    let era = shifted.div_euclid(146_097);
+    let day_of_era = shifted.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates_are_utc_civil_dates() {
+        assert_eq!(utc_date(0), "1970-01-01T00:00:00Z");
+        assert_eq!(utc_date(1_709_210_096), "2024-02-29T12:34:56Z");
+        assert_eq!(utc_date(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(utc_date(-1), "1969-12-31T23:59:59Z");
+    }
+
+    #[test]
+    fn uncommitted_lines_have_no_blame() {
+        let commit = "a".repeat(40);
+        let zeros = "0".repeat(40);
+        let output = format!(
+            "{commit} 1 1 1\nauthor Alice\nauthor-time 0\n\tfirst\n\
+             {zeros} 2 2 1\nauthor Not Committed Yet\nauthor-time 0\n\tsecond\n\
+             {commit} 2 3\n\tthird\n"
+        );
+        let blames = parse_porcelain(&output);
+        let alice = Some(Blame {
+            author: "Alice".to_string(),
+            commit,
+            date: "1970-01-01T00:00:00Z".to_string(),
+        });
+        assert_eq!(blames, [alice.clone(), None, alice]);
+    }
+}
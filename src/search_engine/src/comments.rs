@@ -0,0 +1,173 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Extracting the comments and docstrings of a file, line by line, for
+//! searches limited to them.
+
+/// How comments are written in a language.
+struct Syntax {
+    /// Markers starting a comment that runs to the end of the line.
+    line: &'static [&'static str],
+    /// Opening and closing markers of comments that may span lines.
+    block: &'static [(&'static str, &'static str)],
+    /// Characters quoting strings, in which markers don't count.
+    quotes: &'static [char],
+}
+
+const C_LIKE: Syntax = Syntax {
+    line: &["//"],
+    block: &[("/*", "*/")],
+    quotes: &['"'],
+};
+
+const HASH: Syntax = Syntax {
+    line: &["#"],
+    block: &[],
+    quotes: &['"', '\''],
+};
+
+const MARKUP: Syntax = Syntax {
+    line: &[],
+    block: &[("<!--", "-->")],
+    quotes: &[],
+};
+
+/// The comment syntax of a language named by [`crate::language::detect`].
+fn syntax(language: &str) -> Option<Syntax> {
+    Some(match language {
+        "c" | "cpp" | "csharp" | "go" | "java" | "javascript" | "kotlin" | "rust" | "scala"
+        | "swift" | "typescript" => C_LIKE,
+        "php" => Syntax {
+            line: &["//", "#"],
+            ..C_LIKE
+        },
+        "css" => Syntax {
+            line: &[],
+            ..C_LIKE
+        },
+        "bash" | "perl" | "ruby" | "toml" | "yaml" => HASH,
+        // Docstrings are string literals, but they document like comments.
+        "python" => Syntax {
+            block: &[("\"\"\"", "\"\"\""), ("'''", "'''")],
+            ..HASH
+        },
+        "lua" => Syntax {
+            line: &["--"],
+            block: &[("--[[", "]]")],
+            quotes: &['"', '\''],
+        },
+        "sql" => Syntax {
+            line: &["--"],
+            block: &[("/*", "*/")],
+            quotes: &['\''],
+        },
+        "html" | "markdown" | "xml" => MARKUP,
+        _ => return None,
+    })
+}
+
+/// The comment text on each of `lines`, empty for lines without any, or
+/// `None` when comments of `language` aren't recognized. Markers inside
+/// string literals are skipped; a block comment still open at the last
+/// line is cut off there.
+pub(crate) fn comment_lines<'a>(
+    language: Option<&str>,
+    lines: impl Iterator<Item = &'a str>,
+) -> Option<Vec<String>> {
+    let syntax = syntax(language?)?;
+    // The closing marker of the block comment the current line starts in.
+    let mut open: Option<&str> = None;
+    let comments = lines
+        .map(|line| {
+            let mut comment = String::new();
+            let mut rest = line;
+            loop {
+                if let Some(close) = open {
+                    match rest.find(close) {
+                        Some(end) => {
+                            push_text(&mut comment, &rest[..end]);
+                            rest = &rest[end + close.len()..];
+                            open = None;
+                        }
+                        None => {
+                            push_text(&mut comment, rest);
+                            break;
+                        }
+                    }
+                }
+                match next_marker(&syntax, rest) {
+                    Some((start, Marker::Line(marker))) => {
+                        push_text(&mut comment, &rest[start + marker.len()..]);
+                        break;
+                    }
+                    Some((start, Marker::Block(opening, close))) => {
+                        rest = &rest[start + opening.len()..];
+                        open = Some(close);
+                    }
+                    None => break,
+                }
+            }
+            comment
+        })
+        .collect();
+    Some(comments)
+}
+
+enum Marker {
+    Line(&'static str),
+    Block(&'static str, &'static str),
+}
+
+/// The first comment marker in `text` outside a string literal, and where
+/// it starts.
+fn next_marker(syntax: &Syntax, text: &str) -> Option<(usize, Marker)> {
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    for (index, c) in text.char_indices() {
+        if let Some(q) = quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == q {
+                quote = None;
+            }
+            continue;
+        }
+        let rest = &text[index..];
+        // Block markers first, as `--[[` also starts with the line marker `--`.
+        if let Some((opening, close)) = syntax.block.iter().find(|(o, _)| rest.starts_with(o)) {
+            return Some((index, Marker::Block(opening, close)));
+        }
+        if let Some(marker) = syntax.line.iter().find(|m| rest.starts_with(**m)) {
+            return Some((index, Marker::Line(marker)));
+        }
+        if syntax.quotes.contains(&c) {
+            quote = Some(c);
+        }
+    }
+    None
+}
+
+/// Append a piece of comment text, separated from any earlier one.
+fn push_text(comment: &mut String, text: &str) {
+    let text = text.trim();
+    if text.is_empty() {
+        return;
+    }
+    if !comment.is_empty() {
+        comment.push(' ');
+    }
+    comment.push_str(text);
+}
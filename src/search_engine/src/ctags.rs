@@ -0,0 +1,91 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Symbol definitions found by ctags, to look up where a name is defined.
+//!
+//! The configured command is run with `-R --fields=+n -f -` over the
+//! scanned directories, which both Universal and Exuberant Ctags answer
+//! with tags lines on stdout.
+
+use serde::Serialize;
+use std::process::Command;
+use tantivy::{Result as TantivyResult, TantivyError};
+
+/// Where a symbol is defined.
+#[derive(Debug, Clone, Serialize)]
+pub struct Symbol {
+    pub path: String,
+    pub line: usize,
+    /// The ctags kind, e.g. `function` or `f` depending on the ctags flavor.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+}
+
+/// Run `command`, a ctags executable and its own arguments, over `paths`
+/// and return the symbols by name.
+pub(crate) fn generate(command: &[String], paths: &[&str]) -> TantivyResult<Vec<(String, Symbol)>> {
+    let Some((program, args)) = command.split_first() else {
+        return Ok(Vec::new());
+    };
+    let run_error =
+        |e: String| TantivyError::InvalidArgument(format!("Failed to run '{program}': {e}"));
+    let output = Command::new(program)
+        .args(args)
+        .args(["-R", "--fields=+n", "-f", "-"])
+        .args(paths)
+        .output()
+        .map_err(|e| run_error(e.to_string()))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(run_error(stderr.trim().to_string()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(parse_tag)
+        .collect())
+}
+
+/// Parse a tags line, `name<TAB>path<TAB>address;"<TAB>fields...`. The
+/// line comes from the `line:` field, or from the address when it's a
+/// line number. Pseudo-tags such as `!_TAG_FILE_FORMAT` are skipped.
+fn parse_tag(tag: &str) -> Option<(String, Symbol)> {
+    if tag.starts_with("!_") {
+        return None;
+    }
+    let mut parts = tag.splitn(3, '\t');
+    let name = parts.next()?;
+    let path = parts.next()?;
+    // The address may be a search pattern holding tabs itself.
+    let rest = parts.next()?;
+    let (address, fields) = rest
+        .rsplit_once(";\"\t")
+        .unwrap_or((rest.trim_end_matches(";\""), ""));
+    let mut line = address.parse().ok();
+    let mut kind = None;
+    for field in fields.split('\t') {
+        match field.split_once(':') {
+            Some(("line", number)) => line = number.parse().ok(),
+            Some(("kind", value)) => kind = Some(value.to_string()),
+            // A field without a name is the kind.
+            None if !field.is_empty() => kind = Some(field.to_string()),
+            _ => {}
+        }
+    }
+    let symbol = Symbol {
+        path: path.to_string(),
+        line: line?,
+        kind,
+    };
+    Some((name.to_string(), symbol))
+}
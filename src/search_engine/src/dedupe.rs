@@ -0,0 +1,84 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tracking files with identical content, so it is indexed only once.
+
+use std::collections::HashMap;
+
+/// Paths grouped by content checksum. Only the first path of a group is
+/// indexed; search results for it are repeated for the others.
+#[derive(Debug, Default)]
+pub(crate) struct SharedContent {
+    paths: HashMap<String, Vec<String>>,
+}
+
+impl SharedContent {
+    /// Group the checksummed files, putting the ones for which `is_indexed`
+    /// holds first.
+    pub(crate) fn from_hashes<'a>(
+        hashes: impl IntoIterator<Item = (&'a String, &'a String)>,
+        is_indexed: impl Fn(&str) -> bool,
+    ) -> Self {
+        let mut shared = Self::default();
+        for (path, hash) in hashes {
+            let paths = shared.paths.entry(hash.clone()).or_default();
+            if is_indexed(path) {
+                paths.insert(0, path.clone());
+            } else {
+                paths.push(path.clone());
+            }
+        }
+        shared
+    }
+
+    /// Record `path` as holding the content `hash`. Returns true when it is
+    /// the first path with that content, so it must be indexed.
+    pub(crate) fn add(&mut self, path: &str, hash: &str) -> bool {
+        let paths = self.paths.entry(hash.to_string()).or_default();
+        paths.push(path.to_string());
+        paths.len() == 1
+    }
+
+    /// Forget `path` as holding the content `hash`. Returns true when it was
+    /// the indexed path and others remain, so [`Self::first`] needs indexing.
+    pub(crate) fn remove(&mut self, path: &str, hash: &str) -> bool {
+        let Some(paths) = self.paths.get_mut(hash) else {
+            return false;
+        };
+        let Some(position) = paths.iter().position(|p| p == path) else {
+            return false;
+        };
+        paths.remove(position);
+        if paths.is_empty() {
+            self.paths.remove(hash);
+            return false;
+        }
+        position == 0
+    }
+
+    /// The path indexed for the content `hash`.
+    pub(crate) fn first(&self, hash: &str) -> Option<&String> {
+        self.paths.get(hash)?.first()
+    }
+
+    /// The paths sharing the content `hash` besides the indexed one.
+    pub(crate) fn copies(&self, hash: &str) -> &[String] {
+        self.paths.get(hash).map_or(&[], |paths| &paths[1..])
+    }
+
+    /// Every path that isn't indexed itself.
+    pub(crate) fn all_copies(&self) -> impl Iterator<Item = &String> {
+        self.paths.values().flat_map(|paths| &paths[1..])
+    }
+}
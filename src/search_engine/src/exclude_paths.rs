@@ -0,0 +1,121 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Leaving out results by path at query time, without reindexing.
+
+use regex::Regex;
+use serde::{de, Deserialize, Deserializer};
+use std::str::FromStr;
+use tantivy::TantivyError;
+
+use crate::ignore_file::glob_to_regex;
+
+/// Paths whose results are dropped, parsed from a comma-separated list
+/// such as `tests,*_test.rs,vendor/**`. Empty by default.
+#[derive(Debug, Clone, Default)]
+pub struct ExcludePaths {
+    patterns: Vec<ExcludePattern>,
+}
+
+#[derive(Debug, Clone)]
+enum ExcludePattern {
+    /// A pattern without `*` or `?`, found anywhere in the path.
+    Substring(String),
+    /// A glob like in ignore files, matched against the file name, or
+    /// against the end of the path when it holds a `/`.
+    Glob(Regex),
+}
+
+impl ExcludePaths {
+    /// Whether `path` matches any of the patterns.
+    pub fn matches(&self, path: &str) -> bool {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        self.patterns.iter().any(|pattern| match pattern {
+            ExcludePattern::Substring(text) => path.contains(text.as_str()),
+            ExcludePattern::Glob(regex) => regex.is_match(path) || regex.is_match(name),
+        })
+    }
+}
+
+impl FromStr for ExcludePaths {
+    type Err = TantivyError;
+
+    fn from_str(list: &str) -> Result<Self, Self::Err> {
+        let patterns = list
+            .split(',')
+            .map(str::trim)
+            .filter(|pattern| !pattern.is_empty())
+            .map(|pattern| {
+                if !pattern.contains(['*', '?']) {
+                    return Ok(ExcludePattern::Substring(pattern.to_string()));
+                }
+                // Globs with an inner `/` may start in any directory.
+                let glob = if pattern.contains('/') && !pattern.starts_with('/') {
+                    format!("**/{pattern}")
+                } else {
+                    pattern.to_string()
+                };
+                Regex::new(&glob_to_regex(&glob))
+                    .map(ExcludePattern::Glob)
+                    .map_err(|e| {
+                        TantivyError::InvalidArgument(format!(
+                            "Invalid exclude pattern {pattern:?}: {e}"
+                        ))
+                    })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(ExcludePaths { patterns })
+    }
+}
+
+impl<'de> Deserialize<'de> for ExcludePaths {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(|e| match e {
+                TantivyError::InvalidArgument(message) => de::Error::custom(message),
+                e => de::Error::custom(e),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn substrings_match_anywhere_in_the_path() {
+        let exclude: ExcludePaths = "tests, vendor/".parse().unwrap();
+        assert!(exclude.matches("/repo/tests/search.rs"));
+        assert!(exclude.matches("/repo/src/tests.rs"));
+        assert!(exclude.matches("/repo/vendor/lib.rs"));
+        assert!(!exclude.matches("/repo/src/vendored.rs"));
+    }
+
+    #[test]
+    fn globs_match_the_name_or_the_end_of_the_path() {
+        let exclude: ExcludePaths = "*_test.rs,src/**/gen?.rs".parse().unwrap();
+        assert!(exclude.matches("/repo/src/parse_test.rs"));
+        assert!(!exclude.matches("/repo/src/test.rs"));
+        assert!(exclude.matches("/repo/src/a/b/gen1.rs"));
+        assert!(!exclude.matches("/repo/lib/gen1.rs"));
+    }
+
+    #[test]
+    fn empty_lists_match_nothing() {
+        let exclude: ExcludePaths = " , ".parse().unwrap();
+        assert!(!exclude.matches("/repo/main.rs"));
+        assert!(!ExcludePaths::default().matches("/repo/main.rs"));
+    }
+}
@@ -0,0 +1,116 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Counting the matches of a query per file extension, size bucket or
+//! top-level directory, from the metadata indexed with `index_metadata`.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use tantivy::collector::Count;
+use tantivy::query::{BooleanQuery, TermQuery};
+use tantivy::schema::{Field, IndexRecordOption};
+use tantivy::{IndexReader, Result as TantivyResult, TantivyError, Term};
+
+use crate::{read_lock, CodeSearchEngine, SearchOptions};
+
+/// The file metadata to group matches by.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FacetField {
+    #[default]
+    Extension,
+    Size,
+    TopDir,
+}
+
+/// Match counts per value of a [`FacetField`], most matches first.
+#[derive(Debug, Serialize)]
+pub struct Facets {
+    /// Matching documents: lines, or files with file granularity.
+    total: usize,
+    counts: Vec<FacetCount>,
+    /// Matches in files without a value, e.g. without an extension.
+    other: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FacetCount {
+    value: String,
+    count: usize,
+}
+
+impl CodeSearchEngine {
+    /// Count the documents matching `query_text` for each value of `by`.
+    /// Counts are taken from the index, before the filters applied to
+    /// results such as `min_score`, `exclude` and the per-file caps, and
+    /// deduplicated copies aren't counted again.
+    pub fn facets(
+        &self,
+        query_text: &str,
+        options: &SearchOptions,
+        by: FacetField,
+    ) -> TantivyResult<Facets> {
+        if !self.config.index_metadata {
+            return Err(TantivyError::InvalidArgument(
+                "Counting matches by extension, size or top_dir needs index_metadata".to_string(),
+            ));
+        }
+        let index = read_lock(&self.index);
+        let reader: IndexReader = index.reader_builder().try_into()?;
+        let searcher = reader.searcher();
+        let query_text = self.config.normalized(query_text);
+        let query = self.search_query(&index, &query_text, options)?.query;
+        let field = self.facet_field(by);
+
+        // Every value indexed in any segment, including those only left in
+        // deleted documents, which count nothing.
+        let mut values = BTreeSet::new();
+        for segment in searcher.segment_readers() {
+            let inverted_index = segment.inverted_index(field)?;
+            let mut terms = inverted_index.terms().stream()?;
+            while terms.advance() {
+                values.insert(String::from_utf8_lossy(terms.key()).into_owned());
+            }
+        }
+
+        let total = searcher.search(&query, &Count)?;
+        let mut counts = Vec::new();
+        for value in values {
+            let term = TermQuery::new(
+                Term::from_field_text(field, &value),
+                IndexRecordOption::Basic,
+            );
+            let in_bucket = BooleanQuery::intersection(vec![query.box_clone(), Box::new(term)]);
+            let count = searcher.search(&in_bucket, &Count)?;
+            if count > 0 {
+                counts.push(FacetCount { value, count });
+            }
+        }
+        counts.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+        let other = total - counts.iter().map(|facet| facet.count).sum::<usize>();
+        Ok(Facets {
+            total,
+            counts,
+            other,
+        })
+    }
+
+    fn facet_field(&self, by: FacetField) -> Field {
+        match by {
+            FacetField::Extension => self.fields.extension,
+            FacetField::Size => self.fields.size,
+            FacetField::TopDir => self.fields.top_dir,
+        }
+    }
+}
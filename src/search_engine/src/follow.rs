@@ -0,0 +1,60 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Indexing only the lines appended to followed files, such as logs.
+
+use sha2::{Digest, Sha256};
+use std::fs;
+
+use crate::skipped::{self, Decoding};
+
+/// Size and checksum of a file's contents as of a scan.
+pub(crate) struct Version<'a> {
+    pub(crate) len: u64,
+    pub(crate) hash: &'a str,
+}
+
+/// The lines added to the file at `path` going from `old` to `new`, with
+/// the index of the first of them. `None` unless `new` only appended whole
+/// lines to `old`: the old contents must be kept as they were and end with
+/// a line break, otherwise the file has to be indexed anew.
+pub(crate) fn appended_lines(
+    path: &str,
+    old: Version,
+    new: Version,
+    decoding: Decoding,
+) -> Option<(usize, Vec<String>)> {
+    let old_len = usize::try_from(old.len).ok()?;
+    let new_len = usize::try_from(new.len).ok()?;
+    if new_len <= old_len {
+        return None;
+    }
+    let mut bytes = fs::read(path).ok()?;
+    // Bytes written since the scan are left to the next one.
+    bytes.truncate(new_len);
+    if bytes.len() < new_len || !matches_hash(&bytes, new.hash) {
+        return None;
+    }
+    let (prefix, appended) = bytes.split_at(old_len);
+    if prefix.last().is_some_and(|&byte| byte != b'\n') || !matches_hash(prefix, old.hash) {
+        return None;
+    }
+    let first = prefix.iter().filter(|&&byte| byte == b'\n').count();
+    let lines = skipped::decode_lines(appended.to_vec(), decoding).ok()?;
+    Some((first, lines))
+}
+
+fn matches_hash(bytes: &[u8], hash: &str) -> bool {
+    format!("{:x}", Sha256::digest(bytes)) == hash
+}
@@ -0,0 +1,68 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Comparing matched lines with the files on disk, to flag results served
+//! after a file changed but before the next reload picked the change up.
+
+use std::collections::HashMap;
+
+use crate::{read_lock, skipped, CodeSearchEngine};
+
+/// How a matched line compares with its file on disk.
+#[derive(Debug, Clone)]
+pub(crate) struct Freshness {
+    /// The line on disk differs from the indexed one, or is gone.
+    pub(crate) stale: bool,
+    /// The line on disk when stale, `None` when the file or line is gone.
+    pub(crate) current_line: Option<String>,
+}
+
+/// The lines of the files on disk, each read once per search.
+#[derive(Default)]
+pub(crate) struct DiskLines {
+    files: HashMap<String, Option<Vec<String>>>,
+}
+
+impl CodeSearchEngine {
+    /// Compare `line` (1-based) of `path`, whose content is cached under
+    /// `content_path`, with the file on disk. `None` for paths without a
+    /// file of their own, such as archive entries, pushed documents and
+    /// blobs at `git_ref`, and for lines past `max_lines_per_file`.
+    pub(crate) fn freshness_of(
+        &self,
+        disk: &mut DiskLines,
+        path: &str,
+        content_path: &str,
+        line: usize,
+    ) -> Option<Freshness> {
+        if !read_lock(&self.file_stamps).contains_key(path) {
+            return None;
+        }
+        let index = line.checked_sub(1)?;
+        let indexed = read_lock(&self.lines_map)
+            .get(content_path)?
+            .get(index)?
+            .clone();
+        let lines = disk.files.entry(path.to_string()).or_insert_with(|| {
+            let lines = skipped::read_lines(path, self.config.decoding()).ok()?;
+            Some(self.config.normalized_lines(lines))
+        });
+        let current = lines.as_ref().and_then(|lines| lines.get(index));
+        let stale = current != Some(&indexed);
+        Some(Freshness {
+            stale,
+            current_line: current.filter(|_| stale).cloned(),
+        })
+    }
+}
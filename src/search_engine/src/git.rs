@@ -0,0 +1,78 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reading files from a git ref instead of the working tree.
+
+use std::collections::HashMap;
+use std::io;
+use std::process::Command;
+use tantivy::{Result as TantivyResult, TantivyError};
+
+use crate::is_excluded;
+use crate::skipped::{decode_lines, Decoding, SkipReason};
+
+/// Run a git command in `directory` and return its stdout.
+pub(crate) fn git(directory: &str, args: &[&str]) -> io::Result<Vec<u8>> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(directory)
+        .output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(stderr.trim().to_string()));
+    }
+    Ok(output.stdout)
+}
+
+/// Map every file at `git_ref` to its blob id. The blob id changes whenever
+/// the content does, so it doubles as the checksum used by `reload()`.
+pub(crate) fn blob_hashes(
+    directory: &str,
+    git_ref: &str,
+    exclude_patterns: &[String],
+) -> TantivyResult<HashMap<String, String>> {
+    let output = git(directory, &["ls-tree", "-r", "-z", git_ref]).map_err(|e| {
+        TantivyError::InvalidArgument(format!("Failed to list files at '{}': {}", git_ref, e))
+    })?;
+
+    let mut hashes = HashMap::new();
+    for entry in output.split(|byte| *byte == 0) {
+        // Each entry looks like `<mode> <type> <object>\t<path>`.
+        let entry = String::from_utf8_lossy(entry);
+        let Some((meta, path)) = entry.split_once('\t') else {
+            continue;
+        };
+        let mut parts = meta.split(' ').skip(1);
+        if parts.next() != Some("blob") || is_excluded(path, exclude_patterns) {
+            continue;
+        }
+        if let Some(object) = parts.next() {
+            hashes.insert(path.to_string(), object.to_string());
+        }
+    }
+    Ok(hashes)
+}
+
+/// Read the lines of `path` as stored at `git_ref`.
+pub(crate) fn read_blob(
+    directory: &str,
+    git_ref: &str,
+    path: &str,
+    decoding: Decoding,
+) -> Result<Vec<String>, SkipReason> {
+    // `./` makes the path relative to `directory` rather than the repository root.
+    let bytes = git(directory, &["show", &format!("{git_ref}:./{path}")])
+        .map_err(|_| SkipReason::Unreadable)?;
+    decode_lines(bytes, decoding)
+}
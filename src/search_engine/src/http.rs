@@ -0,0 +1,175 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reading the files listed in a manifest served over HTTP instead of the
+//! working tree.
+//!
+//! The manifest is plain text with one file URL per line. URLs may be
+//! absolute or relative to the manifest; blank lines and lines starting with
+//! `#` are skipped. Both `http://` and `https://` are supported.
+
+use reqwest::{redirect, Client, Url};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::time::Duration;
+use tantivy::{Result as TantivyResult, TantivyError};
+
+use crate::is_excluded;
+use crate::skipped::SkipReason;
+use crate::source::{listed, FileSource, Scan};
+use crate::{Documents, EngineConfig, KnownFiles};
+
+/// Longest a fetch may take, from connecting to the end of the body.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Redirects followed before a fetch fails.
+const MAX_REDIRECTS: usize = 5;
+
+/// The files listed in the manifest at a URL.
+pub(crate) struct Manifest<'a>(pub(crate) &'a str);
+
+impl FileSource for Manifest<'_> {
+    /// Every listed file is fetched to take its checksum, and its content is
+    /// kept so that it's indexed without being fetched again. Files that
+    /// can't be fetched are left out, like files deleted from the working
+    /// tree.
+    fn scan<'a>(
+        &'a self,
+        _directory: &'a str,
+        config: &'a EngineConfig,
+        _known: &'a KnownFiles,
+    ) -> Scan<'a> {
+        Box::pin(async move {
+            let fetched = fetch_listed(self.0, &config.exclude_patterns).await?;
+            let hashes = fetched
+                .iter()
+                .map(|(url, bytes)| (url.clone(), format!("{:x}", Sha256::digest(bytes))))
+                .collect();
+            let mut scanned = listed(hashes, config);
+            scanned.contents = fetched
+                .into_iter()
+                .filter(|(url, _)| scanned.hashes.contains_key(url))
+                .collect();
+            Ok(scanned)
+        })
+    }
+
+    /// Listed files are only read from the content their scan fetched.
+    fn read(&self, _directory: &str, path: &str, _config: &EngineConfig) -> Documents {
+        vec![(path.to_string(), Err(SkipReason::Unreadable))]
+    }
+
+    /// `url` below the directory of the manifest, or below its host when it
+    /// is elsewhere, e.g. `src/main.rs`.
+    fn relative_path<'a>(&self, _base: &str, url: &'a str) -> &'a str {
+        relative_path(url, self.0)
+    }
+}
+
+/// The content of every file listed in the manifest at `manifest_url` that
+/// could be fetched, by URL.
+async fn fetch_listed(
+    manifest_url: &str,
+    exclude_patterns: &[String],
+) -> TantivyResult<HashMap<String, Vec<u8>>> {
+    let client = Client::builder()
+        .timeout(TIMEOUT)
+        .redirect(redirect::Policy::limited(MAX_REDIRECTS))
+        .user_agent("spidermonkey")
+        .build()
+        .map_err(|e| TantivyError::InternalError(format!("Failed to create HTTP client: {e}")))?;
+    let manifest = get(&client, manifest_url).await.map_err(|e| {
+        TantivyError::InvalidArgument(format!("Failed to fetch manifest '{manifest_url}': {e}"))
+    })?;
+    let mut fetched = HashMap::new();
+    for entry in String::from_utf8_lossy(&manifest).lines() {
+        let entry = entry.trim();
+        if entry.is_empty() || entry.starts_with('#') {
+            continue;
+        }
+        let url = resolve(manifest_url, entry);
+        if is_excluded(&url, exclude_patterns) {
+            continue;
+        }
+        if let Ok(bytes) = get(&client, &url).await {
+            fetched.insert(url, bytes);
+        }
+    }
+    Ok(fetched)
+}
+
+/// The body of a `GET` of `url`, following redirects. Any status other than
+/// 2xx is an error.
+async fn get(client: &Client, url: &str) -> reqwest::Result<Vec<u8>> {
+    let response = client.get(url).send().await?.error_for_status()?;
+    Ok(response.bytes().await?.to_vec())
+}
+
+/// `url` below the directory of `manifest_url`, or below its host when it
+/// is elsewhere, e.g. `src/main.rs`.
+pub(crate) fn relative_path<'a>(url: &'a str, manifest_url: &str) -> &'a str {
+    let base = &manifest_url[..manifest_url.rfind('/').map_or(0, |slash| slash + 1)];
+    url.strip_prefix(base)
+        .or_else(|| {
+            let (_, rest) = url.split_once("://")?;
+            Some(rest.split_once('/').map_or("", |(_, path)| path))
+        })
+        .unwrap_or(url)
+}
+
+/// Why `url` can't be fetched, checked when the config is loaded.
+pub(crate) fn unsupported(url: &str) -> Option<String> {
+    match Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => None,
+        Ok(_) => Some(format!("'{url}' isn't an http:// or https:// URL")),
+        Err(e) => Some(format!("'{url}' isn't a URL: {e}")),
+    }
+}
+
+/// `entry` of the manifest at `manifest_url` as an absolute URL.
+fn resolve(manifest_url: &str, entry: &str) -> String {
+    Url::parse(manifest_url)
+        .and_then(|manifest| manifest.join(entry))
+        .map_or_else(|_| entry.to_string(), String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_http_and_https_urls_are_supported() {
+        assert_eq!(unsupported("http://host:8080/dir/manifest.txt"), None);
+        assert_eq!(unsupported("https://host/manifest.txt"), None);
+        assert!(unsupported("ftp://host/manifest.txt").is_some());
+        assert!(unsupported("http://:80/").is_some());
+        assert!(unsupported("manifest.txt").is_some());
+    }
+
+    #[test]
+    fn manifest_entries_resolve_against_the_manifest() {
+        let manifest = "http://host/repo/manifest.txt";
+        assert_eq!(resolve(manifest, "src/a.rs"), "http://host/repo/src/a.rs");
+        assert_eq!(resolve(manifest, "/other/b.rs"), "http://host/other/b.rs");
+        assert_eq!(
+            resolve(manifest, "http://elsewhere/c.rs"),
+            "http://elsewhere/c.rs"
+        );
+        assert_eq!(
+            relative_path("http://host/repo/src/a.rs", manifest),
+            "src/a.rs"
+        );
+        assert_eq!(relative_path("http://elsewhere/c/d.rs", manifest), "c/d.rs");
+    }
+}
@@ -0,0 +1,124 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Grouping the matches of each file into hunks of context that don't
+//! overlap, like `grep -C` does.
+
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::snippet::{self, Snippet, SnippetFormat};
+use crate::{read_lock, CodeSearchEngine, LineRange, SearchOptions, SearchResult};
+
+/// Contiguous lines of a file around one or more matches.
+#[derive(Debug, Serialize)]
+pub struct Hunk {
+    path: String,
+    line_range: LineRange,
+    /// The lines of `line_range`, laid out like a joined snippet.
+    body: String,
+    /// The matched lines within `line_range`, in order.
+    matches: Vec<usize>,
+    /// The best score among the matches.
+    score: Option<f32>,
+}
+
+impl CodeSearchEngine {
+    /// Merge the context of the `results` in each file wherever it overlaps
+    /// or touches. Files come in the order of their best result, and their
+    /// hunks in line order.
+    pub fn hunks(&self, results: &[SearchResult], options: &SearchOptions) -> Vec<Hunk> {
+        let mut files: Vec<(&str, Vec<&SearchResult>)> = Vec::new();
+        let mut positions: HashMap<&str, usize> = HashMap::new();
+        for result in results {
+            let position = *positions.entry(&result.path).or_insert_with(|| {
+                files.push((&result.path, Vec::new()));
+                files.len() - 1
+            });
+            files[position].1.push(result);
+        }
+        files
+            .into_iter()
+            .flat_map(|(path, matches)| self.file_hunks(path, matches, options))
+            .collect()
+    }
+
+    fn file_hunks(
+        &self,
+        path: &str,
+        mut matches: Vec<&SearchResult>,
+        options: &SearchOptions,
+    ) -> Vec<Hunk> {
+        // Results without a line range, e.g. as `fields` left it out, cover their line.
+        let range = |result: &SearchResult| {
+            result
+                .line_range
+                .as_ref()
+                .map_or((result.line, result.line), |range| (range.start, range.end))
+        };
+        matches.sort_by_key(|result| (range(result), result.line));
+        let mut groups: Vec<((usize, usize), Vec<&SearchResult>)> = Vec::new();
+        for result in matches {
+            let (start, end) = range(result);
+            match groups.last_mut() {
+                Some(((_, last_end), members)) if start <= *last_end + 1 => {
+                    *last_end = end.max(*last_end);
+                    members.push(result);
+                }
+                _ => groups.push(((start, end), vec![result])),
+            }
+        }
+
+        let content_path = self.indexed_copy(path).unwrap_or_else(|| path.to_string());
+        self.use_lines(&content_path);
+        let joined = SearchOptions {
+            snippet_format: SnippetFormat::Joined,
+            ..options.clone()
+        };
+        let lines_map = read_lock(&self.lines_map);
+        let cached = lines_map.get(&content_path);
+        groups
+            .into_iter()
+            .map(|((start, end), members)| {
+                let lines = cached
+                    .and_then(|lines| lines.get(start.saturating_sub(1)..end.min(lines.len())));
+                let body = lines.map_or_else(String::new, |lines| {
+                    let lines = if options.dedent {
+                        snippet::dedent(lines)
+                    } else {
+                        lines.iter().map(String::as_str).collect()
+                    };
+                    match self.snippet(&lines, 0, &joined, None) {
+                        Snippet::Joined { body } => body,
+                        // Laid out joined above.
+                        Snippet::Split { .. } => String::new(),
+                    }
+                });
+                let mut matched: Vec<usize> = members.iter().map(|result| result.line).collect();
+                matched.sort_unstable();
+                matched.dedup();
+                Hunk {
+                    path: path.to_string(),
+                    line_range: LineRange { start, end },
+                    body,
+                    matches: matched,
+                    score: members
+                        .iter()
+                        .filter_map(|result| result.score)
+                        .reduce(f32::max),
+                }
+            })
+            .collect()
+    }
+}
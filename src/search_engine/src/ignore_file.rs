@@ -0,0 +1,158 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `.spidermonkeyignore` files: gitignore-like glob patterns excluding paths
+//! below the directory holding the file.
+//!
+//! Each line is a pattern; blank lines and lines starting with `#` are
+//! skipped. `*` matches within a path component, `?` one character and `**`
+//! any number of components. A pattern ending in `/` only matches
+//! directories. A pattern containing another `/` is matched against the path
+//! relative to the ignore file, otherwise against the file or directory name
+//! at any depth. Negation with `!` isn't supported.
+
+use regex::Regex;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub(crate) const IGNORE_FILE: &str = ".spidermonkeyignore";
+
+struct Pattern {
+    regex: Regex,
+    /// Match against the relative path instead of the name.
+    anchored: bool,
+    dir_only: bool,
+}
+
+/// The patterns of one ignore file.
+pub(crate) struct IgnoreRules {
+    directory: PathBuf,
+    patterns: Vec<Pattern>,
+}
+
+impl IgnoreRules {
+    /// Read the ignore file in `directory`, if there is one.
+    pub(crate) fn load(directory: &Path) -> Option<Self> {
+        let contents = fs::read_to_string(directory.join(IGNORE_FILE)).ok()?;
+        let patterns = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(parse_pattern)
+            .collect();
+        Some(Self {
+            directory: directory.to_path_buf(),
+            patterns,
+        })
+    }
+
+    /// Whether `path` is below the ignore file's directory and matches one
+    /// of its patterns.
+    pub(crate) fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let Some(relative) = path
+            .strip_prefix(&self.directory)
+            .ok()
+            .and_then(Path::to_str)
+        else {
+            return false;
+        };
+        let name = relative.rsplit('/').next().unwrap_or(relative);
+        self.patterns.iter().any(|pattern| {
+            (is_dir || !pattern.dir_only)
+                && pattern
+                    .regex
+                    .is_match(if pattern.anchored { relative } else { name })
+        })
+    }
+}
+
+fn parse_pattern(line: &str) -> Option<Pattern> {
+    let dir_only = line.ends_with('/');
+    let glob = line.trim_end_matches('/');
+    let anchored = glob.contains('/');
+    let glob = glob.trim_start_matches('/');
+    let regex = Regex::new(&glob_to_regex(glob)).ok()?;
+    Some(Pattern {
+        regex,
+        anchored,
+        dir_only,
+    })
+}
+
+/// Translate a glob into a regex matching the whole path.
+pub(crate) fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    let mut rest = glob;
+    while let Some(c) = rest.chars().next() {
+        if let Some(after) = rest.strip_prefix("**/") {
+            regex.push_str("(?:.*/)?");
+            rest = after;
+            continue;
+        }
+        if let Some(after) = rest.strip_prefix("**") {
+            regex.push_str(".*");
+            rest = after;
+            continue;
+        }
+        match c {
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+    regex.push('$');
+    regex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(patterns: &str) -> IgnoreRules {
+        IgnoreRules {
+            directory: PathBuf::from("/repo"),
+            patterns: patterns.lines().filter_map(parse_pattern).collect(),
+        }
+    }
+
+    #[test]
+    fn names_match_at_any_depth_and_paths_from_the_ignore_file() {
+        let rules = rules("*.log\nbuild/out");
+        assert!(rules.is_ignored(Path::new("/repo/a.log"), false));
+        assert!(rules.is_ignored(Path::new("/repo/deep/down/b.log"), false));
+        assert!(rules.is_ignored(Path::new("/repo/build/out"), true));
+        assert!(!rules.is_ignored(Path::new("/repo/src/build/out"), true));
+        assert!(!rules.is_ignored(Path::new("/repo/a.logs"), false));
+        // Paths outside the ignore file's directory are left alone.
+        assert!(!rules.is_ignored(Path::new("/other/a.log"), false));
+    }
+
+    #[test]
+    fn trailing_slashes_only_match_directories() {
+        let rules = rules("cache/");
+        assert!(rules.is_ignored(Path::new("/repo/cache"), true));
+        assert!(rules.is_ignored(Path::new("/repo/sub/cache"), true));
+        assert!(!rules.is_ignored(Path::new("/repo/cache"), false));
+    }
+
+    #[test]
+    fn double_stars_cross_directories() {
+        let rules = rules("docs/**/*.png");
+        assert!(rules.is_ignored(Path::new("/repo/docs/a.png"), false));
+        assert!(rules.is_ignored(Path::new("/repo/docs/x/y/a.png"), false));
+        assert!(!rules.is_ignored(Path::new("/repo/img/a.png"), false));
+        assert_eq!(glob_to_regex("a?c*"), r"^a[^/]c[^/]*$");
+    }
+}
@@ -0,0 +1,145 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Guessing the language of a file for syntax highlighting in clients.
+
+/// File extensions (lowercase, without the dot) and their language names.
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("bash", "bash"),
+    ("c", "c"),
+    ("cc", "cpp"),
+    ("cpp", "cpp"),
+    ("cs", "csharp"),
+    ("css", "css"),
+    ("cxx", "cpp"),
+    ("go", "go"),
+    ("h", "c"),
+    ("hpp", "cpp"),
+    ("htm", "html"),
+    ("html", "html"),
+    ("java", "java"),
+    ("js", "javascript"),
+    ("json", "json"),
+    ("jsx", "javascript"),
+    ("kt", "kotlin"),
+    ("lua", "lua"),
+    ("md", "markdown"),
+    ("mjs", "javascript"),
+    ("php", "php"),
+    ("pl", "perl"),
+    ("py", "python"),
+    ("rb", "ruby"),
+    ("rs", "rust"),
+    ("scala", "scala"),
+    ("sh", "bash"),
+    ("sql", "sql"),
+    ("swift", "swift"),
+    ("toml", "toml"),
+    ("ts", "typescript"),
+    ("tsx", "typescript"),
+    ("xml", "xml"),
+    ("yaml", "yaml"),
+    ("yml", "yaml"),
+    ("zsh", "bash"),
+];
+
+/// Interpreters named on a shebang line and their language names.
+const INTERPRETERS: &[(&str, &str)] = &[
+    ("bash", "bash"),
+    ("node", "javascript"),
+    ("perl", "perl"),
+    ("python", "python"),
+    ("ruby", "ruby"),
+    ("sh", "bash"),
+    ("zsh", "bash"),
+];
+
+/// Detect the language from the extension of `path`, falling back to the
+/// interpreter named on a shebang `first_line`.
+pub(crate) fn detect(path: &str, first_line: Option<&str>) -> Option<&'static str> {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let by_extension = name.rsplit_once('.').and_then(|(_, extension)| {
+        let extension = extension.to_lowercase();
+        EXTENSIONS
+            .iter()
+            .find(|(known, _)| *known == extension)
+            .map(|(_, language)| *language)
+    });
+    by_extension.or_else(|| from_shebang(first_line?))
+}
+
+/// The language called `name`, should it be one that is detected.
+pub(crate) fn named(name: &str) -> Option<&'static str> {
+    EXTENSIONS
+        .iter()
+        .chain(INTERPRETERS)
+        .map(|(_, language)| *language)
+        .find(|language| *language == name)
+}
+
+fn from_shebang(line: &str) -> Option<&'static str> {
+    let command = line.strip_prefix("#!")?;
+    let mut words = command.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        program = words.find(|word| !word.starts_with('-'))?;
+    }
+    // `python3.11` is still python.
+    let program = program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    INTERPRETERS
+        .iter()
+        .find(|(known, _)| *known == program)
+        .map(|(_, language)| *language)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::detect;
+
+    #[test]
+    fn languages_come_from_the_extension() {
+        assert_eq!(detect("src/main.rs", None), Some("rust"));
+        assert_eq!(detect("app/models.py", None), Some("python"));
+        assert_eq!(detect("web/index.tsx", None), Some("typescript"));
+        assert_eq!(detect("include/vec.hpp", None), Some("cpp"));
+        assert_eq!(detect("README.MD", None), Some("markdown"));
+    }
+
+    #[test]
+    fn shebangs_name_the_language_of_files_without_a_known_extension() {
+        assert_eq!(detect("bin/deploy", Some("#!/bin/bash")), Some("bash"));
+        assert_eq!(
+            detect("bin/tool", Some("#!/usr/bin/env -S python3.11 -u")),
+            Some("python")
+        );
+        assert_eq!(
+            detect("bin/serve", Some("#!/usr/bin/env node")),
+            Some("javascript")
+        );
+        // The extension wins over the shebang.
+        assert_eq!(
+            detect("build.rs", Some("#!/usr/bin/env python")),
+            Some("rust")
+        );
+    }
+
+    #[test]
+    fn unknown_files_have_no_language() {
+        assert_eq!(detect("data.bin", None), None);
+        assert_eq!(detect("Makefile", Some("all: build")), None);
+        assert_eq!(detect("bin/run", Some("#!/usr/bin/awk -f")), None);
+        // A dot in a directory name isn't an extension.
+        assert_eq!(detect("v1.rs/LICENSE", None), None);
+    }
+}
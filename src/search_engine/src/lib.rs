@@ -12,302 +12,3366 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use serde::Serialize;
-use std::sync::RwLock;
+mod ansi;
+mod archive;
+mod blame;
+mod comments;
+mod ctags;
+mod dedupe;
+mod exclude_paths;
+mod facets;
+mod follow;
+mod fresh;
+mod git;
+mod http;
+mod hunk;
+mod ignore_file;
+mod language;
+mod line_cache;
+mod magic;
+mod metadata;
+mod offset;
+mod query_fields;
+mod result_fields;
+mod rg;
+mod scope;
+mod skipped;
+mod snapshot;
+mod snippet;
+mod source;
+mod tokenizer;
+mod wildcard;
+
+pub use blame::Blame;
+pub use ctags::Symbol;
+pub use exclude_paths::ExcludePaths;
+pub use facets::{FacetCount, FacetField, Facets};
+pub use hunk::Hunk;
+pub use line_cache::LineCacheStats;
+pub use metadata::SizeBucket;
+pub use query_fields::QueryFields;
+pub use result_fields::ResultFields;
+pub use rg::RgMatcher;
+pub use skipped::{SkipReason, SkippedFile};
+pub use snippet::SnippetFormat;
+pub use tokenizer::Analyzer;
+
+use dedupe::SharedContent;
+use fresh::{DiskLines, Freshness};
+use ignore_file::IgnoreRules;
+use line_cache::LineCache;
+use metadata::FileMetadata;
+use serde::ser::{SerializeMap, Serializer};
+use serde::{Deserialize, Deserializer, Serialize};
+use skipped::Decoding;
+use snippet::Snippet;
+use source::{FileSource, GitRef, WorkingTree};
+use std::sync::{Arc, Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 use sha2::{Digest, Sha256};
-use std::time::Instant;
+use std::ops::Bound;
+use std::time::{Duration, Instant, SystemTime};
 use std::{
+    borrow::Cow,
     collections::{HashMap, HashSet},
-    fs,
-    io::{self, BufRead},
+    fs, io, mem,
 };
 use tantivy::schema::Value;
 use tantivy::{
+    collector::{Count, DocSetCollector, TopDocs},
     doc,
-    schema::{Field, Schema, STORED, TEXT},
-    Index, Result as TantivyResult, TantivyDocument, Term,
+    query::{
+        AllQuery, BooleanQuery, ConstScoreQuery, EmptyQuery, PhrasePrefixQuery, Query, RangeQuery,
+        RegexQuery, TermQuery, TermSetQuery,
+    },
+    schema::{Field, IndexRecordOption, Schema, INDEXED, STORED, STRING},
+    snippet::SnippetGenerator,
+    Index, IndexReader, IndexWriter, Result as TantivyResult, Searcher, TantivyDocument,
+    TantivyError, Term,
 };
 use walkdir::WalkDir;
 
-use tokio::task;
-#[derive(Debug, Serialize)]
+use tokio::sync::broadcast;
+use tokio::sync::Semaphore;
+use tokio::task::{self, JoinSet};
+use unicode_normalization::UnicodeNormalization;
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LineRange {
     start: usize,
     end: usize,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug)]
 pub struct SearchResult {
-    body: String,
+    /// Not read when neither `body` nor `line_range` is requested.
+    snippet: Option<Snippet>,
     path: String,
     line: usize,
-    line_range: LineRange,
+    line_range: Option<LineRange>,
+    /// `None` for snippets that aren't a search match.
+    score: Option<f32>,
+    /// Language guessed from the file extension or shebang, e.g. `rust`.
+    language: Option<&'static str>,
+    /// Function or class the matched line is in, with `SearchOptions::scope`.
+    scope: Option<String>,
+    /// Where the matched line starts in the file, with `SearchOptions::byte_offset`.
+    byte_offset: Option<usize>,
+    /// The commit that last changed the matched line, with `SearchOptions::blame`.
+    blame: Option<Blame>,
+    /// How the matched line compares with the file on disk, with
+    /// `SearchOptions::verify_fresh`.
+    freshness: Option<Freshness>,
+    /// The matched line cut around the match, with `SearchOptions::preview_chars`.
+    preview: Option<String>,
+    /// The fields serialized.
+    fields: ResultFields,
 }
 
-#[derive(Debug, Serialize)]
+impl Serialize for SearchResult {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let fields = self.fields;
+        let mut map = serializer.serialize_map(None)?;
+        if let Some(snippet) = self.snippet.as_ref().filter(|_| fields.body) {
+            snippet.serialize_fields(&mut map)?;
+        }
+        if fields.path {
+            map.serialize_entry("path", &self.path)?;
+        }
+        if fields.line {
+            map.serialize_entry("line", &self.line)?;
+        }
+        if let Some(line_range) = self.line_range.as_ref().filter(|_| fields.line_range) {
+            map.serialize_entry("line_range", line_range)?;
+        }
+        if let Some(score) = self.score.filter(|_| fields.score) {
+            map.serialize_entry("score", &score)?;
+        }
+        if fields.language {
+            map.serialize_entry("language", &self.language)?;
+        }
+        if let Some(scope) = &self.scope {
+            map.serialize_entry("scope", scope)?;
+        }
+        if let Some(byte_offset) = self.byte_offset {
+            map.serialize_entry("byte_offset", &byte_offset)?;
+        }
+        if let Some(blame) = &self.blame {
+            map.serialize_entry("blame", blame)?;
+        }
+        if let Some(preview) = &self.preview {
+            map.serialize_entry("preview", preview)?;
+        }
+        if let Some(freshness) = &self.freshness {
+            map.serialize_entry("stale", &freshness.stale)?;
+            if freshness.stale {
+                map.serialize_entry("current_line", &freshness.current_line)?;
+            }
+        }
+        map.end()
+    }
+}
+
+/// A [`SearchResult`] as serialized, each field only there when it was.
+#[derive(Deserialize)]
+struct SerializedResult {
+    body: Option<String>,
+    before: Option<Vec<String>>,
+    matched_line: Option<String>,
+    after: Option<Vec<String>>,
+    path: Option<String>,
+    line: Option<usize>,
+    line_range: Option<LineRange>,
+    score: Option<f32>,
+    /// `Some(None)` for a file of no known language.
+    #[serde(default, deserialize_with = "present")]
+    language: Option<Option<String>>,
+    scope: Option<String>,
+    byte_offset: Option<usize>,
+    blame: Option<Blame>,
+    preview: Option<String>,
+    stale: Option<bool>,
+    current_line: Option<String>,
+}
+
+/// A field that is there, if maybe `null`.
+fn present<'de, D: Deserializer<'de>, T: Deserialize<'de>>(
+    deserializer: D,
+) -> Result<Option<T>, D::Error> {
+    T::deserialize(deserializer).map(Some)
+}
+
+/// Reads back what `Serialize` writes, e.g. for clients of MessagePack
+/// responses. The fields left out are unset.
+impl<'de> Deserialize<'de> for SearchResult {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let result = SerializedResult::deserialize(deserializer)?;
+        let snippet = match (result.body, result.matched_line) {
+            (Some(body), _) => Some(Snippet::Joined { body }),
+            (None, Some(matched_line)) => Some(Snippet::Split {
+                before: result.before.unwrap_or_default(),
+                matched_line,
+                after: result.after.unwrap_or_default(),
+            }),
+            (None, None) => None,
+        };
+        let fields = ResultFields {
+            body: snippet.is_some(),
+            path: result.path.is_some(),
+            line: result.line.is_some(),
+            line_range: result.line_range.is_some(),
+            score: result.score.is_some(),
+            language: result.language.is_some(),
+        };
+        Ok(Self {
+            snippet,
+            path: result.path.unwrap_or_default(),
+            line: result.line.unwrap_or_default(),
+            line_range: result.line_range,
+            score: result.score,
+            language: result
+                .language
+                .flatten()
+                .and_then(|name| language::named(&name)),
+            scope: result.scope,
+            byte_offset: result.byte_offset,
+            blame: result.blame,
+            freshness: result.stale.map(|stale| Freshness {
+                stale,
+                current_line: result.current_line,
+            }),
+            preview: result.preview,
+            fields,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct SearchResults {
     results: Vec<SearchResult>,
     time: f64,
+    /// Matches left out because their file is no longer cached, e.g. it was
+    /// deleted since it was indexed. Non-zero means a reload is due.
+    dropped: usize,
+    /// More documents matched than `offset` plus `limit`, so a later page
+    /// has results.
+    has_more: bool,
+    timings: Timings,
+}
+
+impl SearchResults {
+    pub fn results(&self) -> &[SearchResult] {
+        &self.results
+    }
+}
+
+/// Seconds spent in each phase of a search.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct Timings {
+    /// Building the query from the query text.
+    pub parse_time: f64,
+    /// Running the query and collecting the top documents.
+    pub search_time: f64,
+    /// Fetching the matched documents and extracting their snippets.
+    pub snippet_time: f64,
+}
+
+/// What [`CodeSearchEngine::search_with`] reports besides the results.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SearchSummary {
+    /// Matches dropped because their file isn't cached.
+    pub dropped: usize,
+    /// Documents matched past `SearchOptions::offset` plus `limit`.
+    pub has_more: bool,
+    pub timings: Timings,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IndexStats {
+    files: usize,
+    lines: usize,
+    index: SegmentStats,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    memory: Option<MemoryUsage>,
+    /// Only with `EngineConfig::max_cached_bytes`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line_cache: Option<LineCacheStats>,
+}
+
+/// Document and segment counts of the tantivy index. Many segments or many
+/// deleted documents mean a merge would shrink and speed up the index.
+#[derive(Debug, Serialize)]
+pub struct SegmentStats {
+    /// Live documents, one per indexed line, or per file with
+    /// [`Granularity::File`].
+    documents: u64,
+    segments: usize,
+    /// Documents deleted, e.g. by a reload, but not yet merged away.
+    deleted_documents: u64,
+}
+
+impl SegmentStats {
+    fn of(searcher: &Searcher) -> Self {
+        let segment_readers = searcher.segment_readers();
+        Self {
+            documents: searcher.num_docs(),
+            segments: segment_readers.len(),
+            deleted_documents: segment_readers
+                .iter()
+                .map(|segment| u64::from(segment.num_deleted_docs()))
+                .sum(),
+        }
+    }
+}
+
+/// Outcome of [`CodeSearchEngine::compact`].
+#[derive(Debug, Serialize)]
+pub struct Compaction {
+    before: SegmentStats,
+    after: SegmentStats,
+    /// Segment bytes released by dropping deleted documents.
+    freed_bytes: u64,
+}
+
+/// Outcome of [`CodeSearchEngine::reindex`].
+#[derive(Debug, Serialize)]
+pub struct Reindex {
+    /// Time taken to build the new index, in seconds.
+    seconds: f64,
+    /// Files indexed by the rebuild.
+    files: usize,
+}
+
+/// Estimated memory usage in bytes.
+#[derive(Debug, Serialize)]
+pub struct MemoryUsage {
+    /// Sum of the byte lengths of every line cached for snippets.
+    lines_bytes: usize,
+    /// Size of the tantivy index segments.
+    index_bytes: u64,
+    /// Resident set size of the whole process, where the platform reports it.
+    rss_bytes: Option<u64>,
+}
+
+/// How the query text of a search is parsed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryMode {
+    /// Query syntax without field scopes: `foo:bar` is searched as text, as
+    /// colons are common in code.
+    #[default]
+    Simple,
+    /// Full query syntax, including scopes such as `path:src` or `line:10`.
+    Advanced,
+}
+
+/// What each indexed document holds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Granularity {
+    /// One document per line, so every matching line is a result.
+    #[default]
+    Line,
+    /// One document per file, so the terms of a query may be on different
+    /// lines. Each matching file is one result, at its line matching the
+    /// most query terms. Indexing takes about half as long, but locating
+    /// that line makes searches several times slower.
+    File,
+}
+
+/// Order of the results of [`CodeSearchEngine::search`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    /// Best scoring match first.
+    #[default]
+    Score,
+    /// Files with the most matches first, each file's matches together in
+    /// score order. Files with as many matches keep the order of their best
+    /// match.
+    Density,
+}
+
+/// Per-query options for [`CodeSearchEngine::search`].
+#[derive(Debug, Clone, Default)]
+pub struct SearchOptions {
+    /// Drop results whose score is below this value. Tantivy scores are not
+    /// normalized, so a threshold is only meaningful relative to the scores of
+    /// similar queries against the same index.
+    pub min_score: Option<f32>,
+    /// Keep only results scoring within this percentage of the best match,
+    /// e.g. `20.0` keeps results with at least 80% of the top score.
+    pub top_percent: Option<f32>,
+    /// Only match lines within this inclusive `(first, last)` range of each file.
+    pub line_range: Option<(usize, usize)>,
+    /// Which analyzed field to query. [`Analyzer::Code`] only matches files
+    /// whose extension is mapped to it in `EngineConfig::extension_analyzers`.
+    pub analyzer: Analyzer,
+    /// Widen each snippet to the indentation block enclosing the match
+    /// instead of a fixed number of lines around it.
+    pub smart_context: bool,
+    /// Treat the query text as a single `*`/`?` wildcard pattern matched
+    /// against each term, e.g. `*config*`, rather than query syntax.
+    pub wildcard: bool,
+    /// Treat the query text as a regex matched against each indexed term,
+    /// e.g. `colou?r` or `pars[a-z]+`. Terms are lowercase words, so a regex can't span
+    /// words and only matches uppercase letters with `case_insensitive`.
+    pub regex: bool,
+    /// Compile the `regex` with `(?i)`, so `HashMap` matches the indexed
+    /// `hashmap`. Rejected when the regex turns the flag off itself.
+    pub case_insensitive: bool,
+    /// Remove the leading whitespace shared by every line of each snippet,
+    /// keeping their relative indentation.
+    pub dedent: bool,
+    /// Whether `field:` in the query text scopes a term to a field.
+    pub mode: QueryMode,
+    /// Whether each snippet is one `body` string or split around the match.
+    pub snippet_format: SnippetFormat,
+    /// Joins the lines of a joined `body` in place of
+    /// `EngineConfig::snippet_line_separator`. `line_range` counts lines,
+    /// so it is the same whatever the separator.
+    pub line_separator: Option<String>,
+    /// Match the last word of the query as a prefix, for search as you type:
+    /// `async f` matches `async fn`.
+    pub prefix_last: bool,
+    /// Lines shown on each side of a match without smart context, 3 by default.
+    pub context_lines: Option<usize>,
+    /// Match at most this many documents, the best scoring ones. Results
+    /// are reranked, filtered and capped within them.
+    pub limit: Option<usize>,
+    /// Skip this many of the best scoring documents, for the next page.
+    pub offset: usize,
+    /// Only used by [`CodeSearchEngine::search`], `search_with` hands out
+    /// results in score order as they are found.
+    pub sort: SortOrder,
+    /// Fields included in each result. Leaving out both `body` and
+    /// `line_range` skips reading the lines around each match.
+    pub fields: ResultFields,
+    /// Only match files changed by the last reload that changed any, e.g. to
+    /// find the TODOs a pull just brought in. Nothing matches before then.
+    pub changed_only: bool,
+    /// Match the query text literally, like `grep -F`: `foo(bar)` or `a && b`
+    /// only match lines holding exactly that text, case included. Query
+    /// syntax, `mode` and `prefix_last` don't apply. Text without any term,
+    /// such as `&&`, is looked for in every line.
+    pub fixed: bool,
+    /// Keep at most this many results per file, the best scoring ones, so
+    /// a single noisy file doesn't fill the page.
+    pub max_per_file: Option<usize>,
+    /// Keep at most this many results per directory, not counting the
+    /// files of its subdirectories.
+    pub max_per_dir: Option<usize>,
+    /// Only match files with this extension, without the dot, any case.
+    /// This and the other metadata filters need `index_metadata`.
+    pub extension: Option<String>,
+    /// Only match files of this size.
+    pub size: Option<SizeBucket>,
+    /// Only match files below this directory of the scanned directory or
+    /// root, e.g. `src`.
+    pub top_dir: Option<String>,
+    /// Drop results whose path matches, applied after the search so
+    /// nothing needs reindexing. See [`ExcludePaths`].
+    pub exclude: ExcludePaths,
+    /// Name each result's enclosing function or class, found by walking up
+    /// to the nearest less indented `fn`, `def`, `class`, `function` or
+    /// similar declaration.
+    pub scope: bool,
+    /// Match terms with their case as written, so `HTTP` doesn't find
+    /// `http`. Needs `index_exact_case` and the default analyzer; wildcard
+    /// and regex patterns keep their case too.
+    pub case_sensitive: bool,
+    /// Search even if every term is shorter than `min_term_length`.
+    pub allow_short_terms: bool,
+    /// The fields the query terms are matched in, any one sufficing:
+    /// `body` alone by default. Plain, wildcard, regex and prefix searches
+    /// can span several.
+    pub search_fields: QueryFields,
+    /// Match only the text of comments and docstrings, to find `TODO`
+    /// notes or doc references. Needs `index_comments` and the default
+    /// analyzer.
+    pub comments_only: bool,
+    /// Add the byte offset at which each matched line starts in its file,
+    /// counting `\r\n` line endings as two bytes.
+    pub byte_offset: bool,
+    /// Add the author, commit and date that last changed each matched line,
+    /// from `git blame`. Runs git once per file and content, so it is
+    /// opt-in. Files outside a git work tree, read from `git_ref`, pushed
+    /// or inside archives get none.
+    pub blame: bool,
+    /// Compare each matched line with its file on disk, adding `stale` and,
+    /// when it differs, the `current_line`. Reads every matched file.
+    pub verify_fresh: bool,
+    /// Add `preview`, the matched line cut to at most this many characters
+    /// around the match, independently of the snippet. At least 3, to fit a
+    /// character between two `…`.
+    pub preview_chars: Option<usize>,
+}
+
+impl SearchOptions {
+    /// Lowest score a result may have to be kept, given the best score.
+    fn score_cutoff(&self, best_score: f32) -> Option<f32> {
+        let relative = self
+            .top_percent
+            .map(|percent| best_score * (1.0 - percent.clamp(0.0, 100.0) / 100.0));
+        match (self.min_score, relative) {
+            (Some(min), Some(rel)) => Some(min.max(rel)),
+            (min, rel) => min.or(rel),
+        }
+    }
 }
 
 #[derive(Clone)]
 struct SearchFields {
     path: Field,
+    /// The untokenized path, so every document of a file can be deleted by term.
+    path_key: Field,
     line: Field,
     body: Field,
+    body_code: Field,
+    /// Case-preserving terms, only indexed with `EngineConfig::index_exact_case`.
+    body_exact: Field,
+    /// Comment and docstring text, only indexed with `EngineConfig::index_comments`.
+    comments: Field,
+    /// Metadata terms, only indexed with `EngineConfig::index_metadata`.
+    extension: Field,
+    top_dir: Field,
+    size: Field,
 }
 
-const DEFAULT_SEARCH_LIMIT: usize = 100_000_000;
-const DEFAULT_MEMORY_SIZE: usize = 50_000_000;
-
-fn calculate_checksum(file_path: &str) -> TantivyResult<String> {
-    let file = fs::File::open(file_path)?;
-    let mut reader = io::BufReader::new(file);
-    let mut hasher = Sha256::new();
-    io::copy(&mut reader, &mut hasher)?;
-    Ok(format!("{:x}", hasher.finalize()))
-}
+impl SearchFields {
+    /// Index every line of a file as its own document, also into the code
+    /// field when the file is analyzed as code.
+    fn add_lines(
+        &self,
+        writer: &IndexWriter,
+        directory: &str,
+        path: &str,
+        lines: &[String],
+        config: &EngineConfig,
+    ) -> TantivyResult<()> {
+        self.add_lines_from(writer, directory, path, 0, lines, config)
+    }
 
-fn find_file_paths(directory: &str, exclude_patterns: &Vec<String>) -> TantivyResult<Vec<String>> {
-    let mut file_paths: Vec<String> = Vec::new();
-    for entry in WalkDir::new(directory).into_iter().filter_map(|e| e.ok()) {
-        let path = entry.path();
-        if path.is_file() {
-            if let Some(name) = path.to_str() {
-                // Skip file if it matches any exclude pattern
-                if exclude_patterns
-                    .iter()
-                    .any(|pattern| name.contains(pattern))
-                {
-                    continue;
+    /// Like [`Self::add_lines`], numbering the lines from index `first`.
+    fn add_lines_from(
+        &self,
+        writer: &IndexWriter,
+        directory: &str,
+        path: &str,
+        first: usize,
+        lines: &[String],
+        config: &EngineConfig,
+    ) -> TantivyResult<()> {
+        let analyzer = config.analyzer_for(path);
+        let comments = if config.index_comments {
+            // Appended lines are read without the ones before them, so a
+            // block comment spanning the two is only seen from its start.
+            let first_line = lines.first().filter(|_| first == 0);
+            let language = language::detect(path, first_line.map(String::as_str));
+            comments::comment_lines(language, lines.iter().map(String::as_str))
+        } else {
+            None
+        };
+        let metadata = config
+            .index_metadata
+            .then(|| FileMetadata::of(directory, path, lines, config));
+        let lines = lines.iter().map(|text| {
+            if config.trim_trailing_whitespace {
+                text.trim_end()
+            } else {
+                text.as_str()
+            }
+        });
+        let add = |num: usize, text: &str, comment: Option<&str>| {
+            let mut document = doc!(
+                self.path => path,
+                self.path_key => path,
+                self.line => (num as i64 + 1),
+                self.body => text,
+            );
+            if analyzer == Analyzer::Code {
+                document.add_text(self.body_code, text);
+            }
+            if config.index_exact_case {
+                document.add_text(self.body_exact, text);
+            }
+            if let Some(comment) = comment.filter(|comment| !comment.is_empty()) {
+                document.add_text(self.comments, comment);
+            }
+            if let Some(metadata) = &metadata {
+                document.add_text(self.size, metadata.size.as_str());
+                if let Some(extension) = &metadata.extension {
+                    document.add_text(self.extension, extension);
+                }
+                if let Some(top_dir) = &metadata.top_dir {
+                    document.add_text(self.top_dir, top_dir);
                 }
-                file_paths.push(name.to_string());
+            }
+            writer.add_document(document).map(|_| ())
+        };
+        match config.granularity {
+            Granularity::Line => lines.enumerate().try_for_each(|(num, text)| {
+                let comment = comments.as_ref().map(|comments| comments[num].as_str());
+                add(first + num, text, comment)
+            }),
+            Granularity::File => {
+                let comment = comments.map(|comments| comments.join("\n"));
+                add(0, &lines.collect::<Vec<_>>().join("\n"), comment.as_deref())
             }
         }
     }
-    Ok(file_paths)
+
+    /// Term matching every document of the file at `path`.
+    fn path_term(&self, path: &str) -> Term {
+        Term::from_field_text(self.path_key, path)
+    }
+
+    /// The field searched by default for the given analyzer.
+    fn body_for(&self, analyzer: Analyzer) -> Field {
+        match analyzer {
+            Analyzer::Default => self.body,
+            Analyzer::Code => self.body_code,
+        }
+    }
 }
 
-async fn get_file_hashes(
-    directory: &str,
-    exclude_patterns: &Vec<String>,
-) -> TantivyResult<HashMap<String, String>> {
-    let paths = find_file_paths(directory, exclude_patterns)?;
-    let mut handles = Vec::with_capacity(paths.len());
+/// A parsed search, see [`CodeSearchEngine::search_query`].
+struct SearchQuery {
+    query: Box<dyn Query>,
+    /// The field the query text is matched in.
+    body: Field,
+    /// The files changed by the last reload, with `SearchOptions::changed_only`.
+    changed: Option<HashSet<String>>,
+}
+
+/// Counts the results kept per file and per directory, to cap them at
+/// `SearchOptions::max_per_file` and `max_per_dir`.
+#[derive(Default)]
+struct ResultCaps {
+    max_per_file: Option<usize>,
+    max_per_dir: Option<usize>,
+    files: HashMap<String, usize>,
+    dirs: HashMap<String, usize>,
+}
 
-    // Spawn tasks for each file
-    for path in paths {
-        let handle =
-            task::spawn_blocking(move || calculate_checksum(&path).map(|hash| (path, hash)));
-        handles.push(handle);
+impl ResultCaps {
+    fn new(options: &SearchOptions) -> Self {
+        Self {
+            max_per_file: options.max_per_file,
+            max_per_dir: options.max_per_dir,
+            ..Self::default()
+        }
     }
 
-    // Collect results
-    let mut hashes = HashMap::new();
-    for handle in handles {
-        if let Ok(Ok((path, hash))) = handle.await {
-            hashes.insert(path, hash);
+    /// Count a result in `path`, or return false when its file or
+    /// directory already has as many as allowed.
+    fn admit(&mut self, path: &str) -> bool {
+        let dir = path.rsplit_once('/').map_or("", |(dir, _)| dir);
+        let under = |counts: &HashMap<String, usize>, key: &str, max: Option<usize>| {
+            max.is_none_or(|max| counts.get(key).copied().unwrap_or(0) < max)
+        };
+        if !under(&self.files, path, self.max_per_file) || !under(&self.dirs, dir, self.max_per_dir)
+        {
+            return false;
+        }
+        if self.max_per_file.is_some() {
+            *self.files.entry(path.to_string()).or_default() += 1;
+        }
+        if self.max_per_dir.is_some() {
+            *self.dirs.entry(dir.to_string()).or_default() += 1;
         }
+        true
     }
+}
 
-    Ok(hashes)
+/// Stable sort of score-ordered results by descending match count per file.
+fn sort_by_density(results: &mut [SearchResult]) {
+    // Per file, the match count and the position of its best match.
+    let mut files: HashMap<String, (usize, usize)> = HashMap::new();
+    for (position, result) in results.iter().enumerate() {
+        files.entry(result.path.clone()).or_insert((0, position)).0 += 1;
+    }
+    results.sort_by_key(|result| {
+        let (count, first) = files[&result.path];
+        (std::cmp::Reverse(count), first)
+    });
 }
 
-pub struct CodeSearchEngine {
-    index: RwLock<Index>,
-    fields: SearchFields,
-    /// In-memory storage of all file lines by path
-    lines_map: RwLock<HashMap<String, Vec<String>>>,
-    file_hashes: RwLock<HashMap<String, String>>,
-    exclude_patterns: Vec<String>,
+/// Multiply the score of results whose file name contains a word of the
+/// query by `boost`, then restore score order. Ties keep their order.
+fn boost_file_name_matches(results: &mut [SearchResult], query_text: &str, boost: f32) {
+    let words: Vec<String> = query_text
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|word| !word.is_empty() && !matches!(*word, "AND" | "OR" | "NOT"))
+        .map(str::to_lowercase)
+        .collect();
+    if words.is_empty() {
+        return;
+    }
+    for result in results.iter_mut() {
+        let name = result.path.rsplit('/').next().unwrap_or(&result.path);
+        let name = name.to_lowercase();
+        if words.iter().any(|word| name.contains(word.as_str())) {
+            result.score = result.score.map(|score| score * boost);
+        }
+    }
+    results.sort_by(|a, b| b.score.unwrap_or(0.0).total_cmp(&a.score.unwrap_or(0.0)));
 }
 
-impl CodeSearchEngine {
-    /// Create a new search engine, build schema and index all files in directory
-    pub async fn new(dir: &str, exclude_patterns: Vec<String>) -> TantivyResult<Self> {
-        let mut schema_builder = Schema::builder();
-        let path_field = schema_builder.add_text_field("path", TEXT | STORED);
-        let line_field = schema_builder.add_i64_field("line", STORED);
-        let body_field = schema_builder.add_text_field("body", TEXT | STORED);
-        let schema = schema_builder.build();
+/// Split off the last whitespace separated word of the query.
+fn split_last_word(query_text: &str) -> (&str, Option<&str>) {
+    let query_text = query_text.trim_end();
+    match query_text.rsplit_once(char::is_whitespace) {
+        Some((head, last)) => (head, Some(last)),
+        None => ("", Some(query_text)),
+    }
+}
 
-        let index = Index::create_in_ram(schema.clone());
-        let mut writer = index.writer(DEFAULT_MEMORY_SIZE)?;
-        let mut lines_map: HashMap<String, Vec<String>> = HashMap::new();
+/// Match the documents holding every term of `literal`, the candidates of a
+/// fixed-string search. Text without terms matches every document.
+fn fixed_query(index: &Index, field: Field, literal: &str) -> TantivyResult<Box<dyn Query>> {
+    let mut tokenizer = index.tokenizer_for_field(field)?;
+    let mut clauses: Vec<Box<dyn Query>> = Vec::new();
+    tokenizer.token_stream(literal).process(&mut |token| {
+        let term = Term::from_field_text(field, &token.text);
+        clauses.push(Box::new(TermQuery::new(term, IndexRecordOption::WithFreqs)));
+    });
+    if clauses.is_empty() {
+        return Ok(Box::new(AllQuery));
+    }
+    Ok(Box::new(BooleanQuery::intersection(clauses)))
+}
 
-        let start = Instant::now();
-        let hashes = get_file_hashes(dir, &exclude_patterns).await?;
-
-        for path in hashes.keys() {
-            if let Ok(file) = fs::File::open(path) {
-                let mut vec_lines: Vec<String> = Vec::new();
-                for (num, line) in io::BufReader::new(file).lines().enumerate() {
-                    if let Ok(text) = line {
-                        // Index each line
-                        writer.add_document(doc!(
-                            path_field => path.clone(),
-                            line_field => (num as i64 + 1),
-                            body_field => text.clone(),
-                        ))?;
-                        vec_lines.push(text);
-                    }
-                }
-                lines_map.insert(path.to_string(), vec_lines);
-            }
+/// The terms `field`'s analyzer makes of `query_text`, leaving out the
+/// `AND`, `OR` and `NOT` operators unless the text is matched literally.
+fn query_terms(
+    index: &Index,
+    field: Field,
+    query_text: &str,
+    options: &SearchOptions,
+) -> TantivyResult<Vec<String>> {
+    let mut tokenizer = index.tokenizer_for_field(field)?;
+    let mut terms = Vec::new();
+    let words = query_text
+        .split_whitespace()
+        .filter(|word| options.fixed || !matches!(*word, "AND" | "OR" | "NOT"));
+    for word in words {
+        tokenizer
+            .token_stream(word)
+            .process(&mut |token| terms.push(token.text.clone()));
+    }
+    Ok(terms)
+}
+
+/// Match `word` as the start of the text, its last term being a prefix, e.g.
+/// `f` matches `fn` and `foo.ba` matches `foo.bar`. Returns `None` when the
+/// word has no terms, e.g. it is only punctuation or a stop word.
+fn prefix_query(index: &Index, field: Field, word: &str) -> TantivyResult<Option<Box<dyn Query>>> {
+    let mut tokenizer = index.tokenizer_for_field(field)?;
+    let mut terms = Vec::new();
+    tokenizer
+        .token_stream(word)
+        .process(&mut |token| terms.push(Term::from_field_text(field, &token.text)));
+    if terms.is_empty() {
+        return Ok(None);
+    }
+    let mut query = PhrasePrefixQuery::new(terms);
+    query.set_max_expansions(PREFIX_MAX_EXPANSIONS);
+    Ok(Some(Box::new(query)))
+}
+
+/// Match `query` in any of `fields`, `None` when it makes no query in any.
+fn any_field(
+    fields: &[Field],
+    query: impl Fn(Field) -> TantivyResult<Option<Box<dyn Query>>>,
+) -> TantivyResult<Option<Box<dyn Query>>> {
+    let mut clauses = Vec::new();
+    for &field in fields {
+        clauses.extend(query(field)?);
+    }
+    Ok(match clauses.len() {
+        0 => None,
+        1 => clauses.pop(),
+        _ => Some(Box::new(BooleanQuery::union(clauses))),
+    })
+}
+
+/// Escape every unescaped `:` so the query parser reads `field:value` as
+/// plain text instead of a field scope.
+fn escape_field_scopes(query_text: &str) -> String {
+    let mut escaped = String::with_capacity(query_text.len());
+    let mut after_backslash = false;
+    for c in query_text.chars() {
+        if c == ':' && !after_backslash {
+            escaped.push('\\');
         }
-        let duration = start.elapsed();
-        writer.commit()?;
-        println!("Seconds to index all files: {}", duration.as_secs_f64());
+        after_backslash = c == '\\' && !after_backslash;
+        escaped.push(c);
+    }
+    escaped
+}
 
-        Ok(Self {
-            index: RwLock::new(index),
-            fields: SearchFields {
-                path: path_field,
-                line: line_field,
-                body: body_field,
-            },
-            lines_map: RwLock::new(lines_map),
-            file_hashes: RwLock::new(hashes),
-            exclude_patterns: exclude_patterns,
+/// Build the index schema. Snapshots are only compatible with an index built
+/// from the same schema.
+fn build_schema(options: &SchemaOptions) -> (Schema, SearchFields) {
+    let mut schema_builder = Schema::builder();
+    let path_field = schema_builder.add_text_field(
+        "path",
+        tokenizer::path_options(options.path_analyzer, options.match_case_styles),
+    );
+    let path_key_field = schema_builder.add_text_field("path_key", STRING);
+    let line_field = schema_builder.add_i64_field("line", INDEXED | STORED);
+    let body_field =
+        schema_builder.add_text_field("body", tokenizer::body_options(options.store_body));
+    let body_code_field = schema_builder.add_text_field(
+        "body_code",
+        tokenizer::code_options(options.match_case_styles),
+    );
+    let body_exact_field = schema_builder.add_text_field("body_exact", tokenizer::exact_options());
+    let comments_field = schema_builder.add_text_field("comments", tokenizer::body_options(true));
+    let extension_field = schema_builder.add_text_field("extension", STRING);
+    let top_dir_field = schema_builder.add_text_field("top_dir", STRING);
+    let size_field = schema_builder.add_text_field("size", STRING);
+    let schema = schema_builder.build();
+
+    let fields = SearchFields {
+        path: path_field,
+        path_key: path_key_field,
+        line: line_field,
+        body: body_field,
+        body_code: body_code_field,
+        body_exact: body_exact_field,
+        comments: comments_field,
+        extension: extension_field,
+        top_dir: top_dir_field,
+        size: size_field,
+    };
+    (schema, fields)
+}
+
+/// Settings controlling which files are indexed and how.
+#[derive(Debug, Clone)]
+pub struct EngineConfig {
+    /// Skip every path containing one of these substrings.
+    pub exclude_patterns: Vec<String>,
+    /// Index the text files inside `.zip` and `.tar.gz` archives as virtual
+    /// paths like `archive.zip!/src/lib.rs`.
+    pub index_archives: bool,
+    /// Skip files holding a NUL byte or that aren't valid UTF-8, rather
+    /// than indexing what lines they have that are.
+    pub skip_non_text: bool,
+    /// Skip files as `skip_non_text` does, telling binary files by their
+    /// magic bytes and share of control characters instead of by any NUL
+    /// byte, so text with a stray NUL is indexed and binaries without early
+    /// NULs are skipped. UTF-16 files with a byte order mark are decoded.
+    pub detect_content_type: bool,
+    /// Normalize indexed lines and query text alike to Unicode NFC, so
+    /// composed and decomposed forms of the same text match. Files with
+    /// decomposed text are then cached and served composed.
+    pub normalize_unicode: bool,
+    /// Keep at most this many lines per file in memory for snippets. Longer
+    /// files are still fully searchable, but matches past the cap are
+    /// returned without surrounding context.
+    pub max_lines_per_file: Option<usize>,
+    /// Index the files at this git ref (branch, tag or commit) instead of the
+    /// working tree. Paths are then relative to the scanned directory.
+    pub git_ref: Option<String>,
+    /// Where the files of the scanned directory come from. Additional roots
+    /// are always read from the working tree.
+    pub source: Source,
+    /// Words dropped from both indexed lines and queries, so searching for
+    /// one never matches. Changing the list requires a reindex.
+    pub stop_words: Vec<String>,
+    /// During the initial index, commit after this many documents (lines)
+    /// instead of once at the end. The writer already flushes whenever its
+    /// memory budget fills, and with the index in memory the extra segments
+    /// and their merges raise peak memory rather than lower it
+    /// (`examples/peak_memory.rs`).
+    pub commit_every_docs: Option<usize>,
+    /// During the initial index, commit after this many bytes of line text.
+    pub commit_every_bytes: Option<usize>,
+    /// Analyzer used for files by extension (without the dot). Unlisted
+    /// extensions only get the default analyzer.
+    pub extension_analyzers: HashMap<String, Analyzer>,
+    /// Directories indexed alongside the scanned one, each with its own
+    /// rules. They are always read from the working tree, even with `git_ref`.
+    pub roots: Vec<RootConfig>,
+    /// Index the content of identical files once. Matches in it are
+    /// returned for every path holding it. Archives are never deduplicated.
+    pub dedupe_content: bool,
+    /// Skip files with these extensions (without the dot, any case), in
+    /// every root and inside archives. Applied before, and regardless of,
+    /// include and exclude patterns.
+    pub exclude_extensions: Vec<String>,
+    /// Files checksummed at the same time while scanning, so large trees
+    /// stay below the process's open file limit.
+    pub max_open_files: usize,
+    /// Multiply the score of matches in files whose name contains a word of
+    /// the query by this factor, ranking `parser.rs` first when searching
+    /// `parser`. `1.0` turns it off.
+    pub file_name_boost: f32,
+    /// Index each line without its trailing whitespace, shrinking the stored
+    /// text. Snippets still show the lines as they are on disk, except for
+    /// matches past `max_lines_per_file`, which come from the stored text.
+    pub trim_trailing_whitespace: bool,
+    /// Index a document per line or per file. Changing it requires a reindex.
+    pub granularity: Granularity,
+    /// Skip the [`DEFAULT_EXCLUDED_DIRS`] on top of the exclude patterns.
+    pub use_default_excludes: bool,
+    /// Reject query text longer than this many characters.
+    pub max_query_length: Option<usize>,
+    /// Reject queries analyzed into more than this many terms, which
+    /// would make for expensive boolean queries.
+    pub max_query_terms: Option<usize>,
+    /// Reject queries whose terms are all shorter than this many
+    /// characters, as they match a large part of the index. Searches can
+    /// opt out with `SearchOptions::allow_short_terms`.
+    pub min_term_length: Option<usize>,
+    /// Strip control characters other than tab from returned snippets, for
+    /// consumers that choke on them. The index and cache keep them.
+    pub sanitize_output: bool,
+    /// Joins the lines of a joined snippet `body`, `\n` by default. Set to
+    /// `\r\n` for consumers that expect Windows line endings.
+    pub snippet_line_separator: String,
+    /// Cut returned snippet lines down to this many characters, marking
+    /// each cut with `…`, for layouts that break on wide lines. The matched
+    /// line is centered on its first highlighted term. At least 3, room for
+    /// a character between two markers. The index and cache keep whole lines.
+    pub max_snippet_width: Option<usize>,
+    /// Soft cap on the bytes of lines cached for snippets. Past it, the
+    /// lines of the least recently searched files are dropped from memory
+    /// and read back from the index when a search needs them again, without
+    /// trailing whitespace if `trim_trailing_whitespace` is set. Checked
+    /// after every scan, push and read back, so a scan can exceed it.
+    pub max_cached_bytes: Option<usize>,
+    /// Rank matches in recently modified files higher. A file modified just
+    /// now scores twice as high, the extra halving with every half-life of
+    /// age. Only files read from the working tree have a modification time.
+    pub recency_half_life: Option<Duration>,
+    /// Also index the extension, size bucket and top-level directory of each
+    /// file, for `SearchOptions::extension`, `size` and `top_dir`. Changing
+    /// it requires a reindex.
+    pub index_metadata: bool,
+    /// Also index each line keeping its case, for
+    /// `SearchOptions::case_sensitive`. Changing it requires a reindex.
+    pub index_exact_case: bool,
+    /// Also index the text of comments and docstrings on its own, for
+    /// searches with `SearchOptions::comments_only`. Comments are
+    /// recognized in the languages detected from file extensions and
+    /// shebangs, except JSON.
+    pub index_comments: bool,
+    /// Files whose path contains one of these substrings, e.g. `.log`,
+    /// are expected to only grow. When one did, only its new lines are
+    /// indexed. Any other change still reindexes it entirely.
+    pub follow_patterns: Vec<String>,
+    /// A ctags executable and its arguments, run over the scanned
+    /// directories after every change to look up symbol definitions.
+    /// Empty turns symbol lookup off. Not used with `git_ref`, as ctags
+    /// reads the working tree.
+    pub ctags_command: Vec<String>,
+    /// How fields are indexed and stored. Changing it requires a reindex.
+    pub schema: SchemaOptions,
+}
+
+/// An additional directory to index and the rules for the files below it.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RootConfig {
+    pub path: String,
+    /// Only index paths containing one of these substrings. Empty includes
+    /// every path.
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Skip every path containing one of these substrings. Used instead of
+    /// `EngineConfig::exclude_patterns`, not in addition to it.
+    #[serde(default = "default_exclude_patterns")]
+    pub exclude: Vec<String>,
+}
+
+impl RootConfig {
+    fn contains(&self, path: &str) -> bool {
+        path.strip_prefix(self.path.as_str()).is_some_and(|rest| {
+            rest.is_empty() || rest.starts_with('/') || self.path.ends_with('/')
         })
     }
+}
 
-    /// Execute a query and return matching results as JSON
-    pub async fn search(&self, query_text: &str) -> TantivyResult<SearchResults> {
-        let start = Instant::now();
-        let index_read = self.index.read().unwrap(); // acquire the lock once
-        let reader = index_read.reader_builder().try_into()?;
-        let searcher = reader.searcher();
-
-        let query_parser =
-            tantivy::query::QueryParser::for_index(&index_read, vec![self.fields.body]);
+/// Where the files to index are listed and read from.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Source {
+    /// The scanned directory, or its `git_ref`.
+    #[default]
+    Filesystem,
+    /// The files listed in a plain text manifest, one `http://` or
+    /// `https://` URL per line, absolute or relative to the manifest. Every file is fetched
+    /// on each reload to tell whether it changed. Paths are the URLs.
+    Http { manifest_url: String },
+}
 
-        let query = query_parser.parse_query(query_text)?;
-        let top_docs = searcher.search(
-            &query,
-            &tantivy::collector::TopDocs::with_limit(DEFAULT_SEARCH_LIMIT),
-        )?;
+impl Source {
+    /// Why the source can't be read, checked when the config is loaded.
+    pub fn validate(&self, config: &EngineConfig) -> TantivyResult<()> {
+        let Source::Http { manifest_url } = self else {
+            return Ok(());
+        };
+        if config.git_ref.is_some() {
+            return Err(TantivyError::InvalidArgument(
+                "git_ref reads the scanned directory, it can't be used with an http source"
+                    .to_string(),
+            ));
+        }
+        match http::unsupported(manifest_url) {
+            Some(reason) => Err(TantivyError::InvalidArgument(format!(
+                "Invalid manifest_url: {reason}"
+            ))),
+            None => Ok(()),
+        }
+    }
+}
 
-        let mut found_results: Vec<SearchResult> = Vec::new();
-        for (_score, doc_address) in top_docs {
-            let retrieved: TantivyDocument = searcher.doc(doc_address)?;
-            let file_path = retrieved
-                .get_first(self.fields.path)
-                .unwrap()
-                .as_str()
-                .unwrap();
-            let line_num = retrieved
-                .get_first(self.fields.line)
-                .unwrap()
-                .as_i64()
-                .unwrap() as usize;
+/// The tunable part of the index schema.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SchemaOptions {
+    /// Store the text of each line in the index. Without it the index is
+    /// smaller, but `fixed` searches, `max_lines_per_file` and
+    /// `max_cached_bytes` can't be used, as they read the stored text.
+    pub store_body: bool,
+    /// How paths are split into words for `search_fields=path`. `code` also
+    /// finds `fooBar.rs` by `bar`.
+    pub path_analyzer: Analyzer,
+    /// Index identifiers analyzed as `code` so that `get_foo_bar`,
+    /// `getFooBar` and `GetFooBar` match each other.
+    pub match_case_styles: bool,
+}
 
-            if let Some((lines, (start, end))) = self.read_lines(file_path, line_num, 3) {
-                found_results.push(SearchResult {
-                    body: lines,
-                    path: file_path.to_string(),
-                    line: line_num,
-                    line_range: LineRange { start, end },
-                });
-            }
+impl Default for SchemaOptions {
+    fn default() -> Self {
+        Self {
+            store_body: true,
+            path_analyzer: Analyzer::Default,
+            match_case_styles: false,
         }
+    }
+}
 
-        let duration = start.elapsed();
-        Ok(SearchResults {
-            results: found_results,
-            time: duration.as_secs_f64(),
-        })
+impl SchemaOptions {
+    /// Check that the rest of `config` doesn't need what these options leave out.
+    pub fn validate(&self, config: &EngineConfig) -> TantivyResult<()> {
+        if self.store_body {
+            return Ok(());
+        }
+        let needs_body = [
+            ("max_lines_per_file", config.max_lines_per_file.is_some()),
+            ("max_cached_bytes", config.max_cached_bytes.is_some()),
+        ];
+        match needs_body.iter().find(|(_, set)| *set) {
+            Some((setting, _)) => Err(TantivyError::InvalidArgument(format!(
+                "{setting} reads the stored line text, which schema.store_body: false leaves out"
+            ))),
+            None => Ok(()),
+        }
     }
+}
 
-    /// Helper method to read N lines around a target line from in-memory cache
-    fn read_lines(
-        &self,
-        file_path: &str,
-        line: usize,
-        n: usize,
-    ) -> Option<(String, (usize, usize))> {
-        let binding = self.lines_map.read().unwrap();
-        let file_lines = binding.get(file_path)?;
-        let total = file_lines.len();
-        if line > total {
-            return None;
+fn default_exclude_patterns() -> Vec<String> {
+    vec![".git".to_string()]
+}
+
+/// Build output, dependency and cache directories skipped by default. They
+/// are matched by name at any depth below a scanned directory.
+pub const DEFAULT_EXCLUDED_DIRS: [&str; 12] = [
+    "target",
+    "node_modules",
+    "bower_components",
+    ".venv",
+    "venv",
+    "__pycache__",
+    ".tox",
+    ".mypy_cache",
+    ".pytest_cache",
+    "dist",
+    ".gradle",
+    ".next",
+];
+
+/// Whether a directory of `path`, relative to the scanned directory or
+/// archive, is one of `dir_names`.
+fn in_excluded_dir(path: &str, dir_names: &[&str]) -> bool {
+    let mut components = path.split('/');
+    components.next_back();
+    components.any(|component| dir_names.contains(&component))
+}
+
+impl EngineConfig {
+    /// Names of the directories never walked into.
+    fn excluded_dir_names(&self) -> &'static [&'static str] {
+        if self.use_default_excludes {
+            &DEFAULT_EXCLUDED_DIRS
+        } else {
+            &[]
         }
+    }
 
-        let start = line.saturating_sub(1).saturating_sub(n);
-        let end = (line - 1 + n).min(total - 1);
-        let snippet = file_lines[start..=end].join("\n");
-        Some((snippet, (start + 1, end + 1)))
+    /// Whether only the lines appended to `path` can be indexed when it
+    /// grew. Per-file documents and metadata describe the whole file, so
+    /// they always need a reindex, as do files without a stamp.
+    fn follows(&self, path: &str) -> bool {
+        self.follow_patterns
+            .iter()
+            .any(|pattern| path.contains(pattern.as_str()))
+            && self.granularity == Granularity::Line
+            && !self.index_metadata
+            && !self.dedupes(path)
+            && !archive::is_archive(path)
     }
 
-    pub async fn reload(&self, directory: &str) -> TantivyResult<()> {
-        let hashes = get_file_hashes(directory, &self.exclude_patterns).await?;
-        let current_paths: HashSet<String> = hashes.keys().cloned().collect();
+    /// Whether `path` is indexed once together with identical files.
+    fn dedupes(&self, path: &str) -> bool {
+        self.dedupe_content && !archive::is_archive(path)
+    }
 
-        let old_hashes_read = self.file_hashes.read().unwrap();
-        let old_paths: HashSet<String> = old_hashes_read.keys().cloned().collect();
-        drop(old_hashes_read); // Done reading
+    /// The additional root `path` was found under, if any.
+    fn root_for(&self, path: &str) -> Option<&RootConfig> {
+        self.roots.iter().find(|root| root.contains(path))
+    }
 
-        // Determine missing files.
-        let missing_files: Vec<String> = old_paths.difference(&current_paths).cloned().collect();
+    /// Where the files of the scanned directory are listed and read from.
+    fn source(&self) -> Box<dyn FileSource + '_> {
+        match (&self.source, &self.git_ref) {
+            (Source::Http { manifest_url }, _) => Box::new(http::Manifest(manifest_url)),
+            (Source::Filesystem, Some(git_ref)) => Box::new(GitRef(git_ref)),
+            (Source::Filesystem, None) => Box::new(WorkingTree {
+                exclude: &self.exclude_patterns,
+                include: &[],
+            }),
+        }
+    }
 
-        let mut writer = self.index.write().unwrap().writer(DEFAULT_MEMORY_SIZE)?;
+    /// Where `path` is read from: the working tree for the files of an
+    /// additional root, else the scanned directory's source.
+    fn source_for(&self, path: &str) -> Box<dyn FileSource + '_> {
+        match self.root_for(path) {
+            Some(root) => Box::new(WorkingTree {
+                exclude: &root.exclude,
+                include: &root.include,
+            }),
+            None => self.source(),
+        }
+    }
 
-        // Add/update files
-        for (path, hash) in &hashes {
-            let should_update = {
-                let file_hashes_read = self.file_hashes.read().unwrap();
-                match file_hashes_read.get(path) {
-                    Some(last_checksum) if last_checksum == hash => false,
-                    _ => true,
-                }
-            };
+    /// Whether the scanned directory is read from the working tree, rather
+    /// than from `git_ref` or a remote `source`.
+    fn scans_tree(&self) -> bool {
+        self.git_ref.is_none() && self.source == Source::Filesystem
+    }
 
-            if !should_update {
-                continue;
-            }
+    /// Whether `path` is a file of the working tree.
+    fn reads_tree(&self, path: &str) -> bool {
+        self.scans_tree() || self.root_for(path).is_some()
+    }
 
-            // Update hash
-            {
-                let mut file_hashes_write = self.file_hashes.write().unwrap();
-                file_hashes_write.insert(path.clone(), hash.clone());
-            }
+    /// Whether `path`, read from the working tree, is no longer a file,
+    /// e.g. it was deleted after the scan found it.
+    fn vanished(&self, path: &str) -> bool {
+        self.reads_tree(path) && !fs::metadata(path).is_ok_and(|metadata| metadata.is_file())
+    }
 
-            // Open file and index lines
-            if let Ok(file) = fs::File::open(path) {
-                let mut vec_lines = Vec::new();
-                for (num, line) in io::BufReader::new(file).lines().enumerate() {
-                    if let Ok(text) = line {
-                        writer.add_document(doc!(
-                            self.fields.path => path.clone(),
-                            self.fields.line => (num as i64 + 1),
-                            self.fields.body => text.clone(),
-                        ))?;
-                        vec_lines.push(text);
-                    }
-                }
+    /// The analyzer configured for the extension of `path`.
+    fn analyzer_for(&self, path: &str) -> Analyzer {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        name.rsplit_once('.')
+            .and_then(|(_, extension)| self.extension_analyzers.get(extension))
+            .copied()
+            .unwrap_or_default()
+    }
 
-                let mut lines_map_write = self.lines_map.write().unwrap();
-                lines_map_write.insert(path.clone(), vec_lines);
-            }
+    /// Checksums of the files in `directory`, found in place of a file that
+    /// was walked earlier, under the rules of the root it belongs to.
+    fn walk_new_directory(&self, directory: &str) -> Vec<(String, String)> {
+        let (exclude, include) = match self.root_for(directory) {
+            Some(root) => (&root.exclude, root.include.as_slice()),
+            None => (&self.exclude_patterns, [].as_slice()),
+        };
+        find_file_paths(directory, exclude, self)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|path| is_included(path, include))
+            .filter_map(|path| calculate_checksum(&path).ok().map(|hash| (path, hash)))
+            .collect()
+    }
+
+    /// The lines of a file as indexed, with `normalize_unicode`.
+    fn normalized_lines(&self, lines: Vec<String>) -> Vec<String> {
+        if self.normalize_unicode {
+            lines.into_iter().map(|line| line.nfc().collect()).collect()
+        } else {
+            lines
         }
+    }
 
-        // Remove missing files
-        if !missing_files.is_empty() {
-            for path in &missing_files {
-                let term = Term::from_field_text(self.fields.path, path);
-                writer.delete_term(term);
-            }
+    /// Query text as matched against lines, with `normalize_unicode`.
+    fn normalized<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if self.normalize_unicode {
+            Cow::Owned(text.nfc().collect())
+        } else {
+            Cow::Borrowed(text)
+        }
+    }
 
-            let mut file_hashes_write = self.file_hashes.write().unwrap();
-            let mut lines_map_write = self.lines_map.write().unwrap();
-            for path in &missing_files {
-                file_hashes_write.remove(path);
-                lines_map_write.remove(path);
-            }
+    /// How file contents are split into lines.
+    fn decoding(&self) -> Decoding {
+        if self.detect_content_type {
+            Decoding::Detect
+        } else if self.skip_non_text {
+            Decoding::Strict
+        } else {
+            Decoding::Lenient
         }
+    }
 
-        writer.commit()?;
-        Ok(())
+    /// Trim the lines of a file down to what is kept for snippet extraction.
+    fn cached_lines(&self, mut lines: Vec<String>) -> Vec<String> {
+        if let Some(max) = self.max_lines_per_file {
+            lines.truncate(max);
+        }
+        lines
+    }
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            exclude_patterns: default_exclude_patterns(),
+            index_archives: false,
+            skip_non_text: false,
+            detect_content_type: false,
+            normalize_unicode: false,
+            max_lines_per_file: None,
+            git_ref: None,
+            source: Source::Filesystem,
+            stop_words: Vec::new(),
+            commit_every_docs: None,
+            commit_every_bytes: None,
+            extension_analyzers: HashMap::new(),
+            roots: Vec::new(),
+            dedupe_content: false,
+            exclude_extensions: Vec::new(),
+            max_open_files: DEFAULT_MAX_OPEN_FILES,
+            file_name_boost: 1.0,
+            trim_trailing_whitespace: false,
+            granularity: Granularity::Line,
+            use_default_excludes: true,
+            max_query_length: None,
+            max_query_terms: None,
+            min_term_length: None,
+            sanitize_output: false,
+            snippet_line_separator: "\n".to_string(),
+            max_snippet_width: None,
+            max_cached_bytes: None,
+            recency_half_life: None,
+            index_metadata: false,
+            index_exact_case: false,
+            index_comments: false,
+            follow_patterns: Vec::new(),
+            ctags_command: Vec::new(),
+            schema: SchemaOptions::default(),
+        }
     }
 }
+
+/// Documents added since the last commit during the initial index.
+#[derive(Default)]
+struct CommitBatch {
+    docs: usize,
+    bytes: usize,
+}
+
+impl CommitBatch {
+    /// Record the lines of a file, returning true once a commit is due.
+    fn add(&mut self, lines: &[String], config: &EngineConfig) -> bool {
+        self.docs += lines.len();
+        self.bytes += lines.iter().map(String::len).sum::<usize>();
+        let due = config.commit_every_docs.is_some_and(|max| self.docs >= max)
+            || config
+                .commit_every_bytes
+                .is_some_and(|max| self.bytes >= max);
+        if due {
+            *self = Self::default();
+        }
+        due
+    }
+}
+
+/// Acquire a read lock, recovering the data if a panicking thread poisoned it.
+/// None of the guarded state is left half-updated by a panic, so one failed
+/// request shouldn't take down every later one.
+fn read_lock<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Acquire a write lock, recovering the data if a panicking thread poisoned it.
+fn write_lock<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Take the data out of a lock, recovering it if a panicking thread poisoned it.
+fn into_inner<T>(lock: RwLock<T>) -> T {
+    lock.into_inner().unwrap_or_else(PoisonError::into_inner)
+}
+
+const DEFAULT_SEARCH_LIMIT: usize = 100_000_000;
+/// Lines of context shown on each side of a match by default.
+const CONTEXT_LINES: usize = 3;
+/// Most lines a smart-context snippet may span.
+const SMART_CONTEXT_MAX_LINES: usize = 40;
+/// Most terms a `prefix_last` prefix expands to, in term order. Very short
+/// prefixes of common letters may miss rarer completions.
+const PREFIX_MAX_EXPANSIONS: u32 = 256;
+/// Reload notifications kept for subscribers that fall behind.
+const CHANGE_CHANNEL_CAPACITY: usize = 16;
+/// Files checksummed at once unless `max_open_files` is set, well below the
+/// common soft limit of 256 descriptors on macOS and 1024 on Linux.
+const DEFAULT_MAX_OPEN_FILES: usize = 64;
+const DEFAULT_MEMORY_SIZE: usize = 50_000_000;
+
+/// Modification time and size of a file. A file whose stamp didn't change
+/// since it was hashed is assumed to keep its checksum, like `git status`
+/// does, so it isn't read again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileStamp {
+    modified: SystemTime,
+    len: u64,
+}
+
+impl FileStamp {
+    fn of(path: &str) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        Some(Self {
+            modified: metadata.modified().ok()?,
+            len: metadata.len(),
+        })
+    }
+}
+
+/// Stamp and checksum of each file from the previous scan.
+type KnownFiles = HashMap<String, (FileStamp, String)>;
+
+/// Blame of each line by path, with the checksum of the content blamed.
+type BlameCache = HashMap<String, (String, Vec<Option<Blame>>)>;
+
+/// Checksums of the files found by a scan, and the stamps of those read
+/// from the working tree.
+#[derive(Default)]
+struct ScannedFiles {
+    hashes: HashMap<String, String>,
+    stamps: HashMap<String, FileStamp>,
+    /// Content the scan fetched to take its checksum, indexed from here
+    /// instead of being fetched again.
+    contents: HashMap<String, Vec<u8>>,
+}
+
+fn calculate_checksum(file_path: &str) -> TantivyResult<String> {
+    let file = fs::File::open(file_path)?;
+    let mut reader = io::BufReader::new(file);
+    let mut hasher = Sha256::new();
+    io::copy(&mut reader, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Whether `extension` (without the dot) is one of `exclude_extensions`,
+/// ignoring case. The configured extensions may be given with a leading dot.
+fn is_excluded_extension(extension: &str, exclude_extensions: &[String]) -> bool {
+    exclude_extensions.iter().any(|excluded| {
+        excluded
+            .trim_start_matches('.')
+            .eq_ignore_ascii_case(extension)
+    })
+}
+
+/// Whether the file name of `path` has one of `exclude_extensions`.
+fn has_excluded_extension(path: &str, exclude_extensions: &[String]) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    name.rsplit_once('.')
+        .is_some_and(|(_, extension)| is_excluded_extension(extension, exclude_extensions))
+}
+
+/// Whether `path` contains one of `include_patterns`, or the list is empty.
+fn is_included(path: &str, include_patterns: &[String]) -> bool {
+    include_patterns.is_empty()
+        || include_patterns
+            .iter()
+            .any(|pattern| path.contains(pattern))
+}
+
+fn is_excluded(path: &str, exclude_patterns: &[String]) -> bool {
+    exclude_patterns
+        .iter()
+        .any(|pattern| path.contains(pattern))
+}
+
+/// Files below `directory`, skipping excluded and ignored paths as well as
+/// the default excluded directories and extensions of `config`.
+fn find_file_paths(
+    directory: &str,
+    exclude_patterns: &[String],
+    config: &EngineConfig,
+) -> TantivyResult<Vec<String>> {
+    let excluded_dirs = config.excluded_dir_names();
+    let mut file_paths: Vec<String> = Vec::new();
+    // Ignore files of the directories walked so far, read as they are entered.
+    let mut ignores: Vec<IgnoreRules> = Vec::new();
+    let walker = WalkDir::new(directory).into_iter().filter_entry(|entry| {
+        let is_dir = entry.file_type().is_dir();
+        if entry.depth() > 0
+            && ignores
+                .iter()
+                .any(|rules| rules.is_ignored(entry.path(), is_dir))
+        {
+            return false;
+        }
+        if !is_dir {
+            return true;
+        }
+        if entry.depth() > 0
+            && entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| excluded_dirs.contains(&name))
+        {
+            return false;
+        }
+        // Prune excluded directories before descending into them. Every file
+        // below `dir/` contains that prefix, so they would all be excluded anyway.
+        let keep = entry.depth() == 0
+            || !entry
+                .path()
+                .to_str()
+                .is_some_and(|dir| is_excluded(&format!("{dir}/"), exclude_patterns));
+        if keep {
+            ignores.extend(IgnoreRules::load(entry.path()));
+        }
+        keep
+    });
+    for entry in walker.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        // Checked first as it needs neither a syscall nor a substring search.
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        if extension
+            .is_some_and(|extension| is_excluded_extension(extension, &config.exclude_extensions))
+        {
+            continue;
+        }
+        if path.is_file() {
+            if let Some(name) = path.to_str() {
+                // Skip file if it matches any exclude pattern
+                if is_excluded(name, exclude_patterns) {
+                    continue;
+                }
+                file_paths.push(name.to_string());
+            }
+        }
+    }
+    Ok(file_paths)
+}
+
+/// Checksums of the files below `directory` kept by the include and exclude
+/// patterns, reading at most `max_open_files` files at once. Files whose
+/// stamp matches `known` keep their checksum without being read.
+async fn get_file_hashes(
+    directory: &str,
+    exclude_patterns: &[String],
+    include_patterns: &[String],
+    config: &EngineConfig,
+    known: &KnownFiles,
+) -> TantivyResult<ScannedFiles> {
+    let mut paths = find_file_paths(directory, exclude_patterns, config)?;
+    paths.retain(|path| is_included(path, include_patterns));
+
+    let mut scanned = ScannedFiles::default();
+    let files = paths.into_iter().map(|path| {
+        let previous = known.get(&path).cloned();
+        (path, previous)
+    });
+    let hash = |(path, previous): (String, Option<(FileStamp, String)>)| {
+        // Stamped before reading, so a write during hashing shows next time.
+        let stamp = FileStamp::of(&path);
+        let hash = match previous {
+            Some((known_stamp, hash)) if stamp == Some(known_stamp) => hash,
+            _ => calculate_checksum(&path)?,
+        };
+        TantivyResult::Ok((path, hash, stamp))
+    };
+    run_bounded(files, config.max_open_files, hash, |hashed| {
+        if let Ok((path, hash, stamp)) = hashed {
+            if let Some(stamp) = stamp {
+                scanned.stamps.insert(path.clone(), stamp);
+            }
+            scanned.hashes.insert(path, hash);
+        }
+    })
+    .await;
+
+    Ok(scanned)
+}
+
+/// Run `work` on a blocking thread for each of `items`, at most `max` at
+/// once, handing each result to `done`. A task is only spawned once a
+/// permit is free, so neither running nor pending tasks grow with the
+/// number of items. Results of tasks that panicked are dropped.
+async fn run_bounded<T, R>(
+    items: impl IntoIterator<Item = T>,
+    max: usize,
+    work: impl Fn(T) -> R + Clone + Send + 'static,
+    mut done: impl FnMut(R),
+) where
+    T: Send + 'static,
+    R: Send + 'static,
+{
+    let permits = Arc::new(Semaphore::new(max.max(1)));
+    let mut tasks = JoinSet::new();
+    for item in items {
+        let permit = Arc::clone(&permits)
+            .acquire_owned()
+            .await
+            .expect("the semaphore is never closed");
+        let work = work.clone();
+        tasks.spawn_blocking(move || {
+            let _permit = permit;
+            work(item)
+        });
+        while let Some(result) = tasks.try_join_next() {
+            result.into_iter().for_each(&mut done);
+        }
+    }
+    while let Some(result) = tasks.join_next().await {
+        result.into_iter().for_each(&mut done);
+    }
+}
+
+/// How closely a file name matches `query`, lower is better: exact name,
+/// prefix, substring and finally the query's characters appearing in order.
+/// Both arguments are expected in lowercase.
+fn name_match_rank(name: &str, query: &str) -> Option<u8> {
+    if name == query {
+        Some(0)
+    } else if name.starts_with(query) {
+        Some(1)
+    } else if name.contains(query) {
+        Some(2)
+    } else {
+        let mut chars = name.chars();
+        query
+            .chars()
+            .all(|wanted| chars.any(|c| c == wanted))
+            .then_some(3)
+    }
+}
+
+/// Resident set size of the current process, read from `/proc` on Linux.
+fn process_rss() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+/// Checksums of every file to index, taken from the working tree, the
+/// configured git ref or the remote source, followed by the files of each
+/// additional root.
+async fn collect_hashes(
+    directory: &str,
+    config: &EngineConfig,
+    known: &KnownFiles,
+) -> TantivyResult<ScannedFiles> {
+    let mut scanned = config.source().scan(directory, config, known).await?;
+    for root in &config.roots {
+        let root_scan = config
+            .source_for(&root.path)
+            .scan(&root.path, config, known)
+            .await?;
+        scanned.hashes.extend(root_scan.hashes);
+        scanned.stamps.extend(root_scan.stamps);
+    }
+    Ok(scanned)
+}
+
+/// Documents as `(path, lines)` pairs, with the reason in place of the lines
+/// for those that can't be indexed.
+pub(crate) type Documents = Vec<(String, Result<Vec<String>, SkipReason>)>;
+
+/// Read the documents stored at `path`, decoding `fetched` instead if the
+/// scan already fetched its content.
+fn read_documents(
+    directory: &str,
+    path: &str,
+    config: &EngineConfig,
+    fetched: Option<Vec<u8>>,
+) -> Documents {
+    let documents = match fetched {
+        Some(bytes) => vec![(
+            path.to_string(),
+            skipped::decode_lines(bytes, config.decoding()),
+        )],
+        None => config.source_for(path).read(directory, path, config),
+    };
+    documents
+        .into_iter()
+        .map(|(path, lines)| (path, lines.map(|lines| config.normalized_lines(lines))))
+        .collect()
+}
+
+pub struct CodeSearchEngine {
+    index: RwLock<Index>,
+    fields: SearchFields,
+    /// In-memory storage of all file lines by path
+    lines_map: RwLock<HashMap<String, Vec<String>>>,
+    file_hashes: RwLock<HashMap<String, String>>,
+    /// Stamps of the files in `file_hashes` read from the working tree, to
+    /// skip hashing unchanged files on reload.
+    file_stamps: RwLock<HashMap<String, FileStamp>>,
+    /// Files by checksum when `dedupe_content` is set. Only the first file
+    /// of each group is indexed and cached in `lines_map`.
+    shared_content: RwLock<SharedContent>,
+    config: EngineConfig,
+    /// `EngineConfig::exclude_patterns`, replaced by `set_exclude_patterns`
+    /// while running.
+    exclude_patterns: RwLock<Vec<String>>,
+    /// Notified with the changed paths every time `reload()` commits changes.
+    changes: broadcast::Sender<Arc<Vec<String>>>,
+    /// Paths changed by the last reload that changed anything, including the
+    /// entries of changed archives. Searched by `SearchOptions::changed_only`.
+    last_changed: RwLock<HashSet<String>>,
+    /// Files found by the last scans that couldn't be indexed, by path. They
+    /// aren't in `file_hashes`, so each reload reads them again.
+    skipped: RwLock<HashMap<String, SkipReason>>,
+    /// Definitions in indexed files by symbol name, with `ctags_command`.
+    symbols: RwLock<HashMap<String, Vec<Symbol>>>,
+    blames: RwLock<BlameCache>,
+    /// Which files' lines were used last, to evict past `max_cached_bytes`.
+    line_cache: Mutex<LineCache>,
+    /// Held while an index writer is open, tantivy allows only one at a time.
+    writing: Mutex<()>,
+}
+
+impl CodeSearchEngine {
+    /// Create a new search engine, build schema and index all files in directory
+    pub async fn new(dir: &str, config: EngineConfig) -> TantivyResult<Self> {
+        let start = Instant::now();
+        let scanned = collect_hashes(dir, &config, &HashMap::new()).await?;
+        let directory = dir.to_string();
+        let engine =
+            task::spawn_blocking(move || Self::index_scanned(&directory, config, scanned, start))
+                .await
+                .map_err(|e| TantivyError::InternalError(e.to_string()))??;
+        engine.refresh_symbols(dir).await?;
+        Ok(engine)
+    }
+
+    /// Index the files `scanned` in `dir`, counting the time taken from
+    /// `start`. Files deleted since they were scanned are left out.
+    fn index_scanned(
+        dir: &str,
+        config: EngineConfig,
+        scanned: ScannedFiles,
+        start: Instant,
+    ) -> TantivyResult<Self> {
+        let (schema, fields) = build_schema(&config.schema);
+
+        let index = Index::create_in_ram(schema.clone());
+        tokenizer::register(&index, &config);
+        let mut writer = index.writer(DEFAULT_MEMORY_SIZE)?;
+        let mut lines_map: HashMap<String, Vec<String>> = HashMap::new();
+
+        let ScannedFiles {
+            mut hashes,
+            mut stamps,
+            mut contents,
+        } = scanned;
+
+        let mut batch = CommitBatch::default();
+        let mut shared = SharedContent::default();
+        let mut skipped = HashMap::new();
+        let mut untracked = Vec::new();
+        for (path, hash) in &hashes {
+            if config.dedupes(path) && !shared.add(path, hash) {
+                continue;
+            }
+            for (doc_path, lines) in read_documents(dir, path, &config, contents.remove(path)) {
+                let lines = match lines {
+                    Ok(lines) => lines,
+                    Err(reason) => {
+                        // Unless it was deleted since it was hashed.
+                        if reason != SkipReason::Unreadable || !config.vanished(path) {
+                            skipped.insert(doc_path.clone(), reason);
+                        }
+                        // Not tracking the file lets the next reload read it
+                        // again, and an identical file later in the scan be
+                        // indexed instead.
+                        if doc_path == *path {
+                            shared.remove(path, hash);
+                            untracked.push(path.clone());
+                        }
+                        continue;
+                    }
+                };
+                // Index each line
+                fields.add_lines(&writer, dir, &doc_path, &lines, &config)?;
+                if batch.add(&lines, &config) {
+                    writer.commit()?;
+                }
+                lines_map.insert(doc_path, config.cached_lines(lines));
+            }
+        }
+        let duration = start.elapsed();
+        writer.commit()?;
+        println!("Seconds to index all files: {}", duration.as_secs_f64());
+        for path in untracked {
+            hashes.remove(&path);
+            stamps.remove(&path);
+        }
+
+        let engine = Self::from_parts(index, fields, lines_map, hashes, config);
+        *write_lock(&engine.file_stamps) = stamps;
+        *write_lock(&engine.skipped) = skipped;
+        engine.limit_line_cache(None);
+        Ok(engine)
+    }
+
+    fn from_parts(
+        index: Index,
+        fields: SearchFields,
+        lines_map: HashMap<String, Vec<String>>,
+        file_hashes: HashMap<String, String>,
+        config: EngineConfig,
+    ) -> Self {
+        let shared_content = if config.dedupe_content {
+            let deduped = file_hashes.iter().filter(|(path, _)| config.dedupes(path));
+            SharedContent::from_hashes(deduped, |path| lines_map.contains_key(path))
+        } else {
+            SharedContent::default()
+        };
+        Self {
+            index: RwLock::new(index),
+            fields,
+            shared_content: RwLock::new(shared_content),
+            lines_map: RwLock::new(lines_map),
+            file_hashes: RwLock::new(file_hashes),
+            file_stamps: RwLock::new(HashMap::new()),
+            exclude_patterns: RwLock::new(config.exclude_patterns.clone()),
+            config,
+            changes: broadcast::channel(CHANGE_CHANNEL_CAPACITY).0,
+            last_changed: RwLock::new(HashSet::new()),
+            skipped: RwLock::new(HashMap::new()),
+            symbols: RwLock::new(HashMap::new()),
+            blames: RwLock::new(HashMap::new()),
+            line_cache: Mutex::new(LineCache::default()),
+            writing: Mutex::new(()),
+        }
+    }
+
+    /// Execute a query and return matching results as JSON
+    pub async fn search(
+        &self,
+        query_text: &str,
+        options: &SearchOptions,
+    ) -> TantivyResult<SearchResults> {
+        self.search_blocking(query_text, options)
+    }
+
+    /// Like `search`, for callers on a blocking thread.
+    pub fn search_blocking(
+        &self,
+        query_text: &str,
+        options: &SearchOptions,
+    ) -> TantivyResult<SearchResults> {
+        let start = Instant::now();
+        let mut found_results: Vec<SearchResult> = Vec::new();
+        let summary = self.search_with(query_text, options, |result| {
+            found_results.push(result);
+            true
+        })?;
+        self.annotate(&mut found_results, options);
+
+        let boost = self.config.file_name_boost;
+        if boost != 1.0 && !options.wildcard && !options.regex {
+            boost_file_name_matches(&mut found_results, query_text, boost);
+        }
+        if let Some(half_life) = self.config.recency_half_life {
+            self.boost_recent_files(&mut found_results, half_life);
+        }
+        if options.sort == SortOrder::Density {
+            sort_by_density(&mut found_results);
+        }
+
+        let duration = start.elapsed();
+        Ok(SearchResults {
+            results: found_results,
+            time: duration.as_secs_f64(),
+            dropped: summary.dropped,
+            has_more: summary.has_more,
+            timings: summary.timings,
+        })
+    }
+
+    /// Multiply the score of each result by `1 + 2^(-age / half_life)`, the
+    /// age being the time since its file was last modified, then restore
+    /// score order. Ties keep their order.
+    fn boost_recent_files(&self, results: &mut [SearchResult], half_life: Duration) {
+        let now = SystemTime::now();
+        let half_life = half_life.as_secs_f64().max(f64::MIN_POSITIVE);
+        {
+            let file_stamps = read_lock(&self.file_stamps);
+            for result in results.iter_mut() {
+                let Some(stamp) = file_stamps.get(archive::container_path(&result.path)) else {
+                    continue;
+                };
+                // Files modified in the future count as modified now.
+                let age = now
+                    .duration_since(stamp.modified)
+                    .unwrap_or_default()
+                    .as_secs_f64();
+                let boost = 1.0 + (-age / half_life).exp2();
+                result.score = result.score.map(|score| score * boost as f32);
+            }
+        }
+        results.sort_by(|a, b| b.score.unwrap_or(0.0).total_cmp(&a.score.unwrap_or(0.0)));
+    }
+
+    /// Add the blame and freshness `options` ask for to `results`. They run
+    /// git and read the files on disk, each once per call, so they're done
+    /// outside `search_with`, which holds the index locked.
+    pub fn annotate(&self, results: &mut [SearchResult], options: &SearchOptions) {
+        let mut disk = DiskLines::default();
+        for result in results.iter_mut() {
+            // Copies have histories of their own.
+            if options.blame {
+                result.blame = self.blame_of(&result.path, result.line);
+            }
+            if options.verify_fresh {
+                let content_path = self
+                    .indexed_copy(&result.path)
+                    .unwrap_or_else(|| result.path.clone());
+                result.freshness =
+                    self.freshness_of(&mut disk, &result.path, &content_path, result.line);
+            }
+        }
+    }
+
+    /// Execute a query and hand each result to `on_result` as soon as its
+    /// snippet is extracted, stopping early once `on_result` returns `false`.
+    /// Blame and freshness aren't looked up, see `annotate`.
+    /// Returns how many matches were dropped and how long each phase took.
+    pub fn search_with<F>(
+        &self,
+        query_text: &str,
+        options: &SearchOptions,
+        mut on_result: F,
+    ) -> TantivyResult<SearchSummary>
+    where
+        F: FnMut(SearchResult) -> bool,
+    {
+        let mut summary = SearchSummary::default();
+        let index_read = read_lock(&self.index); // acquire the lock once
+        let reader = index_read.reader_builder().try_into()?;
+        let searcher = reader.searcher();
+
+        let parse_start = Instant::now();
+
+        let query_text = self.config.normalized(query_text);
+        let query_text = query_text.as_ref();
+        let SearchQuery {
+            query,
+            body,
+            changed,
+        } = self.search_query(&index_read, query_text, options)?;
+        summary.timings.parse_time = parse_start.elapsed().as_secs_f64();
+
+        let limit = options.limit.unwrap_or(DEFAULT_SEARCH_LIMIT);
+        if limit == 0 {
+            return Err(TantivyError::InvalidArgument(
+                "limit must be at least 1".to_string(),
+            ));
+        }
+        if options.preview_chars.is_some_and(|chars| chars < 3) {
+            return Err(TantivyError::InvalidArgument(
+                "preview_chars must be at least 3, to fit a character between two `…`".to_string(),
+            ));
+        }
+        let search_start = Instant::now();
+        let collector = TopDocs::with_limit(limit).and_offset(options.offset);
+        let (top_docs, matched) = searcher.search(&query, &(collector, Count))?;
+        summary.has_more = matched > options.offset.saturating_add(top_docs.len());
+        summary.timings.search_time = search_start.elapsed().as_secs_f64();
+
+        // Results are sorted by score, so the first one is the best match.
+        let cutoff = top_docs
+            .first()
+            .and_then(|(best, _)| options.score_cutoff(*best));
+
+        let mut caps = ResultCaps::new(options);
+        let mut snippet_time = Duration::ZERO;
+        let whole_files = self.config.granularity == Granularity::File;
+        // Locates the query terms in whole-file documents, and in matched
+        // lines to center their truncation and previews on.
+        let centers = self.config.max_snippet_width.is_some() || options.preview_chars.is_some();
+        let generator = if whole_files || centers {
+            let mut generator = SnippetGenerator::create(&searcher, &*query, body)?;
+            generator.set_max_num_chars(usize::MAX);
+            Some(generator)
+        } else {
+            None
+        };
+        let line_finder = generator.as_ref().filter(|_| whole_files);
+        for (score, doc_address) in top_docs {
+            if cutoff.is_some_and(|cutoff| score < cutoff) {
+                break;
+            }
+            let snippet_start = Instant::now();
+            let retrieved: TantivyDocument = searcher.doc(doc_address)?;
+            let file_path = retrieved
+                .get_first(self.fields.path)
+                .unwrap()
+                .as_str()
+                .unwrap();
+            self.use_lines_from(Some(&searcher), file_path);
+            let line_num = match line_finder {
+                // Candidates of a fixed-string search not holding it as is aren't matches.
+                _ if options.fixed => {
+                    match self.literal_line(&retrieved, query_text, options.line_range) {
+                        Some(line) => line,
+                        None => continue,
+                    }
+                }
+                Some(generator) => {
+                    match self.matched_line(generator, file_path, options.line_range) {
+                        Some(line) => line,
+                        None => continue,
+                    }
+                }
+                None => retrieved
+                    .get_first(self.fields.line)
+                    .unwrap()
+                    .as_i64()
+                    .unwrap() as usize,
+            };
+
+            let snippet = if options.fields.needs_snippet() {
+                let generator = generator.as_ref();
+                self.read_lines(file_path, line_num, options, generator)
+                    .or_else(|| {
+                        self.uncached_line(&retrieved, file_path, line_num, options, generator)
+                    })
+                    .map(|(snippet, (start, end))| (Some(snippet), Some(LineRange { start, end })))
+            } else {
+                // Still drop matches in files that are no longer cached.
+                read_lock(&self.lines_map)
+                    .contains_key(file_path)
+                    .then_some((None, None))
+            };
+            snippet_time += snippet_start.elapsed();
+            let Some((snippet, line_range)) = snippet else {
+                summary.dropped += 1;
+                continue;
+            };
+            let preview = self.preview_of(file_path, line_num, options, generator.as_ref());
+            // Identical files share the indexed file's documents.
+            let copies: Vec<(String, Option<Snippet>)> = self
+                .copies_of(file_path)
+                .into_iter()
+                .map(|copy| (copy, snippet.clone()))
+                .collect();
+            let results = std::iter::once((file_path.to_string(), snippet))
+                .chain(copies)
+                .filter(|(path, _)| {
+                    changed
+                        .as_ref()
+                        .is_none_or(|changed| changed.contains(path))
+                })
+                .filter(|(path, _)| !options.exclude.matches(path))
+                .filter(|(path, _)| caps.admit(path));
+
+            let scope = if options.scope {
+                self.scope_of(file_path, line_num)
+            } else {
+                None
+            };
+            let byte_offset = if options.byte_offset {
+                self.byte_offset_of(file_path, line_num)
+            } else {
+                None
+            };
+
+            // Time spent by `on_result`, e.g. sending to a client, isn't counted.
+            let mut keep_going = true;
+            for (path, snippet) in results {
+                let language = if options.fields.language {
+                    self.language_of(&path, file_path)
+                } else {
+                    None
+                };
+                keep_going = on_result(SearchResult {
+                    language,
+                    snippet,
+                    line: line_num,
+                    line_range: line_range.clone(),
+                    score: Some(score),
+                    scope: scope.clone(),
+                    byte_offset,
+                    // Added by `annotate` once the index is unlocked.
+                    blame: None,
+                    freshness: None,
+                    preview: preview.clone(),
+                    path,
+                    fields: options.fields,
+                });
+                if !keep_going {
+                    break;
+                }
+            }
+            if !keep_going {
+                break;
+            }
+        }
+        summary.timings.snippet_time = snippet_time.as_secs_f64();
+        Ok(summary)
+    }
+
+    /// The query matching the documents of `query_text`, already normalized,
+    /// within the line range, changed files and metadata of `options`.
+    fn search_query(
+        &self,
+        index: &Index,
+        query_text: &str,
+        options: &SearchOptions,
+    ) -> TantivyResult<SearchQuery> {
+        let body = self.body_field(options)?;
+        self.check_query_size(index, body, query_text, options)?;
+        let mut query = self.text_query(index, body, query_text, options)?;
+        // Whole-file documents are filtered by line once the matched line is known.
+        let line_range = options
+            .line_range
+            .filter(|_| self.config.granularity == Granularity::Line);
+        if let Some((first, last)) = line_range {
+            let line_term = |line: usize| {
+                Term::from_field_i64(self.fields.line, i64::try_from(line).unwrap_or(i64::MAX))
+            };
+            let lines = RangeQuery::new(
+                Bound::Included(line_term(first)),
+                Bound::Included(line_term(last)),
+            );
+            query = Box::new(BooleanQuery::intersection(vec![query, Box::new(lines)]));
+        }
+        let changed = options
+            .changed_only
+            .then(|| read_lock(&self.last_changed).clone());
+        if let Some(changed) = &changed {
+            // Identical files are indexed under the first copy's path.
+            let terms = changed
+                .iter()
+                .flat_map(|path| std::iter::once(path.clone()).chain(self.indexed_copy(path)))
+                .map(|path| self.fields.path_term(&path));
+            let paths = TermSetQuery::new(terms);
+            query = Box::new(BooleanQuery::intersection(vec![query, Box::new(paths)]));
+        }
+        if let Some(filters) = self.metadata_filters(options)? {
+            query = Box::new(BooleanQuery::intersection(vec![query, filters]));
+        }
+        Ok(SearchQuery {
+            query,
+            body,
+            changed,
+        })
+    }
+
+    /// Check that the index answers queries by counting every document with a
+    /// match-all search. Cached lines without any document means the index
+    /// lost them. Returns the document count, or why the index isn't ready.
+    pub fn readiness(&self) -> Result<usize, String> {
+        let reader: IndexReader = read_lock(&self.index)
+            .reader_builder()
+            .try_into()
+            .map_err(|e| format!("Cannot open an index reader: {e}"))?;
+        let documents = reader
+            .searcher()
+            .search(&AllQuery, &Count)
+            .map_err(|e| format!("Cannot search the index: {e}"))?;
+        let has_lines = read_lock(&self.lines_map)
+            .values()
+            .any(|lines| !lines.is_empty());
+        if documents == 0 && has_lines {
+            return Err("No documents are indexed although files are cached".to_string());
+        }
+        Ok(documents)
+    }
+
+    /// Count the indexed files and lines. Memory usage walks every cached line,
+    /// so it is only computed when `include_memory` is set.
+    pub fn stats(&self, include_memory: bool) -> TantivyResult<IndexStats> {
+        let (files, lines, lines_bytes) = {
+            let lines_map = read_lock(&self.lines_map);
+            let lines = lines_map.values().map(Vec::len).sum();
+            let lines_bytes = if include_memory {
+                lines_map.values().flatten().map(String::len).sum()
+            } else {
+                0
+            };
+            (lines_map.len(), lines, lines_bytes)
+        };
+        // Files deduplicated into another file's documents count as files too.
+        let files = files + read_lock(&self.shared_content).all_copies().count();
+
+        let reader: IndexReader = read_lock(&self.index).reader_builder().try_into()?;
+        let searcher = reader.searcher();
+        let index = SegmentStats::of(&searcher);
+
+        let memory = if include_memory {
+            let index_bytes = searcher.space_usage()?.total().get_bytes();
+            Some(MemoryUsage {
+                lines_bytes,
+                index_bytes,
+                rss_bytes: process_rss(),
+            })
+        } else {
+            None
+        };
+
+        let line_cache = self.config.max_cached_bytes.map(|max| {
+            self.line_cache
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .stats(max)
+        });
+        Ok(IndexStats {
+            files,
+            lines,
+            index,
+            memory,
+            line_cache,
+        })
+    }
+
+    /// Block until the reload, push or compaction writing to the index, if
+    /// any, has committed. Lets a shutdown avoid exiting mid-commit.
+    pub fn wait_for_writes(&self) {
+        drop(self.writing.lock().unwrap_or_else(PoisonError::into_inner));
+    }
+
+    /// Merge every segment into one, dropping deleted documents. Searches
+    /// keep using the old segments until the merge is done; reloads and
+    /// pushes wait for it.
+    pub fn compact(&self) -> TantivyResult<Compaction> {
+        let _writing = self.writing.lock().unwrap_or_else(PoisonError::into_inner);
+        let (before, bytes_before) = self.segment_usage()?;
+
+        let mut writer: IndexWriter = write_lock(&self.index).writer(DEFAULT_MEMORY_SIZE)?;
+        let segments = read_lock(&self.index).searchable_segment_ids()?;
+        if segments.len() > 1 || before.deleted_documents > 0 {
+            writer.merge(&segments).wait()?;
+        }
+        writer.wait_merging_threads()?;
+
+        let (after, bytes_after) = self.segment_usage()?;
+        Ok(Compaction {
+            before,
+            after,
+            freed_bytes: bytes_before.saturating_sub(bytes_after),
+        })
+    }
+
+    /// Discard the index and every cache and build them again from
+    /// `directory`, as [`Self::new`] does, to recover from drift. Searches
+    /// keep using the old state until the new one is swapped in. Pushed
+    /// documents aren't on disk, so they are dropped.
+    pub async fn reindex(&self, directory: &str) -> TantivyResult<Reindex> {
+        let start = Instant::now();
+        let fresh = Self::new(directory, self.current_config()).await?;
+        let files = read_lock(&fresh.file_hashes).len();
+
+        // Reloads wait for the swap, then carry on from the new state.
+        let _writing = self.writing.lock().unwrap_or_else(PoisonError::into_inner);
+        // Cached lines first, so results from the new index always have them.
+        *write_lock(&self.lines_map) = into_inner(fresh.lines_map);
+        *self
+            .line_cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = fresh
+            .line_cache
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner);
+        *write_lock(&self.shared_content) = into_inner(fresh.shared_content);
+        *write_lock(&self.file_hashes) = into_inner(fresh.file_hashes);
+        *write_lock(&self.file_stamps) = into_inner(fresh.file_stamps);
+        *write_lock(&self.skipped) = into_inner(fresh.skipped);
+        *write_lock(&self.symbols) = into_inner(fresh.symbols);
+        *write_lock(&self.last_changed) = into_inner(fresh.last_changed);
+        write_lock(&self.blames).clear();
+        *write_lock(&self.index) = into_inner(fresh.index);
+        Ok(Reindex {
+            seconds: start.elapsed().as_secs_f64(),
+            files,
+        })
+    }
+
+    /// Number of segments searches currently go through.
+    pub fn segment_count(&self) -> TantivyResult<usize> {
+        Ok(read_lock(&self.index).searchable_segment_ids()?.len())
+    }
+
+    /// Segment counts and total segment bytes, from a fresh searcher.
+    fn segment_usage(&self) -> TantivyResult<(SegmentStats, u64)> {
+        let reader: IndexReader = read_lock(&self.index).reader_builder().try_into()?;
+        let searcher = reader.searcher();
+        let bytes = searcher.space_usage()?.total().get_bytes();
+        Ok((SegmentStats::of(&searcher), bytes))
+    }
+
+    /// Find indexed files whose name (the final path component) matches
+    /// `query`, best matches first.
+    pub fn find_by_name(&self, query: &str, limit: usize) -> Vec<String> {
+        let query = query.to_lowercase();
+        let copies: Vec<String> = read_lock(&self.shared_content)
+            .all_copies()
+            .cloned()
+            .collect();
+        let lines_map = read_lock(&self.lines_map);
+        let mut matches: Vec<(u8, usize, &String)> = lines_map
+            .keys()
+            .chain(&copies)
+            .filter_map(|path| {
+                let name = path.rsplit('/').next().unwrap_or(path).to_lowercase();
+                let rank = name_match_rank(&name, &query)?;
+                Some((rank, name.len(), path))
+            })
+            .collect();
+        matches.sort();
+        matches
+            .into_iter()
+            .take(limit)
+            .map(|(_, _, path)| path.clone())
+            .collect()
+    }
+
+    /// The snippet search would show for a match on `line` (1-based) of
+    /// `path`, or `None` when the file isn't cached or has no such line.
+    pub fn snippet_at(
+        &self,
+        path: &str,
+        line: usize,
+        options: &SearchOptions,
+    ) -> Option<SearchResult> {
+        let content_path = self.indexed_copy(path).unwrap_or_else(|| path.to_string());
+        self.use_lines(&content_path);
+        let (snippet, (start, end)) = self.read_lines(&content_path, line, options, None)?;
+        Some(SearchResult {
+            snippet: Some(snippet),
+            path: path.to_string(),
+            line,
+            line_range: Some(LineRange { start, end }),
+            score: None,
+            language: self.language_of(path, &content_path),
+            scope: if options.scope {
+                self.scope_of(&content_path, line)
+            } else {
+                None
+            },
+            byte_offset: if options.byte_offset {
+                self.byte_offset_of(&content_path, line)
+            } else {
+                None
+            },
+            blame: if options.blame {
+                self.blame_of(path, line)
+            } else {
+                None
+            },
+            freshness: if options.verify_fresh {
+                self.freshness_of(&mut DiskLines::default(), path, &content_path, line)
+            } else {
+                None
+            },
+            preview: self.preview_of(&content_path, line, options, None),
+            fields: options.fields,
+        })
+    }
+
+    /// The function or class enclosing `line` (1-based) of the cached file
+    /// at `path`.
+    fn scope_of(&self, path: &str, line: usize) -> Option<String> {
+        let lines_map = read_lock(&self.lines_map);
+        scope::enclosing_scope(lines_map.get(path)?, line.checked_sub(1)?)
+    }
+
+    /// Byte offset of the start of `line` (1-based) in the cached file at
+    /// `path`. Files not read from the working tree, such as archive
+    /// entries and git blobs, are taken to end their lines in `\n`.
+    fn byte_offset_of(&self, path: &str, line: usize) -> Option<usize> {
+        let index = line.checked_sub(1)?;
+        let stamp = read_lock(&self.file_stamps).get(path).copied();
+        {
+            let lines_map = read_lock(&self.lines_map);
+            let lines = lines_map.get(path)?;
+            let ending = match stamp {
+                Some(stamp) => offset::ends_with_newline(path, stamp.len)
+                    .and_then(|terminated| offset::line_ending_len(lines, stamp.len, terminated)),
+                None => Some(1),
+            };
+            if let (Some(ending), true) = (ending, index < lines.len()) {
+                return Some(lines[..index].iter().map(|line| line.len() + ending).sum());
+            }
+        }
+        // Mixed line endings, or a line past `max_lines_per_file`.
+        offset::line_start(&fs::read(path).ok()?, index)
+    }
+
+    /// The commit that last changed `line` (1-based) of the file at `path`.
+    /// A file's blame is cached until its content changes, unless it has
+    /// uncommitted lines, which committing blames without changing the content.
+    fn blame_of(&self, path: &str, line: usize) -> Option<Blame> {
+        // Paths at a git ref or fetched from a remote source aren't files
+        // to run git on.
+        if !self.config.reads_tree(path) {
+            return None;
+        }
+        let index = line.checked_sub(1)?;
+        let hash = read_lock(&self.file_hashes).get(path).cloned()?;
+        if let Some((blamed, lines)) = read_lock(&self.blames).get(path) {
+            if *blamed == hash {
+                return lines.get(index).cloned().flatten();
+            }
+        }
+        // Not in a git work tree, which won't change either.
+        let lines = blame::blame_file(path).unwrap_or_default();
+        let blame = lines.get(index).cloned().flatten();
+        if lines.iter().all(Option::is_some) {
+            write_lock(&self.blames).insert(path.to_string(), (hash, lines));
+        }
+        blame
+    }
+
+    /// Return the cached lines of an indexed file. Virtual archive paths are
+    /// served the same way as regular files.
+    pub fn file_lines(&self, path: &str) -> Option<Vec<String>> {
+        let indexed = self.indexed_copy(path);
+        self.use_lines(indexed.as_deref().unwrap_or(path));
+        let lines_map = read_lock(&self.lines_map);
+        lines_map
+            .get(path)
+            .or_else(|| lines_map.get(indexed.as_ref()?))
+            .cloned()
+    }
+
+    /// Whether `path` is searchable: a file or virtual archive path that was
+    /// indexed and not skipped, or a deduplicated copy of one.
+    pub fn is_indexed(&self, path: &str) -> bool {
+        // Skipped files are neither cached nor grouped by content.
+        read_lock(&self.lines_map).contains_key(path) || self.indexed_copy(path).is_some()
+    }
+
+    /// How many lines of the indexed `path` are cached, at most
+    /// `max_lines_per_file`, or `None` when it isn't indexed.
+    pub fn line_count(&self, path: &str) -> Option<usize> {
+        let content_path = self.indexed_copy(path).unwrap_or_else(|| path.to_string());
+        self.use_lines(&content_path);
+        read_lock(&self.lines_map).get(&content_path).map(Vec::len)
+    }
+
+    /// The indexed file holding the same content as `path`, when deduplicating.
+    fn indexed_copy(&self, path: &str) -> Option<String> {
+        if !self.config.dedupes(path) {
+            return None;
+        }
+        let hash = read_lock(&self.file_hashes).get(path)?.clone();
+        read_lock(&self.shared_content).first(&hash).cloned()
+    }
+
+    /// The files sharing the content of the indexed `path`, when deduplicating.
+    fn copies_of(&self, path: &str) -> Vec<String> {
+        if !self.config.dedupes(path) {
+            return Vec::new();
+        }
+        let Some(hash) = read_lock(&self.file_hashes).get(path).cloned() else {
+            return Vec::new();
+        };
+        read_lock(&self.shared_content).copies(&hash).to_vec()
+    }
+
+    /// The language of `file_path`, whose content is cached under `content_path`.
+    fn language_of(&self, file_path: &str, content_path: &str) -> Option<&'static str> {
+        let lines_map = read_lock(&self.lines_map);
+        let first_line = lines_map
+            .get(content_path)
+            .and_then(|lines| lines.first())
+            .map(String::as_str);
+        language::detect(file_path, first_line)
+    }
+
+    /// The query for the text of a search, before any line, path or change
+    /// restrictions.
+    fn text_query(
+        &self,
+        index: &Index,
+        body: Field,
+        query_text: &str,
+        options: &SearchOptions,
+    ) -> TantivyResult<Box<dyn Query>> {
+        if [options.wildcard, options.regex, options.fixed]
+            .iter()
+            .filter(|&&set| set)
+            .count()
+            > 1
+        {
+            Err(TantivyError::InvalidArgument(
+                "Only one of wildcard, regex and fixed can be used at once".to_string(),
+            ))
+        } else if options.fixed && options.search_fields.beyond_body() {
+            // Fixed-string results are the lines holding the text.
+            Err(TantivyError::InvalidArgument(
+                "fixed only searches body, drop search_fields".to_string(),
+            ))
+        } else if options.fixed && !self.config.schema.store_body {
+            // Candidates are confirmed against their stored text.
+            Err(TantivyError::InvalidArgument(
+                "fixed needs the line text stored, which schema.store_body: false leaves out"
+                    .to_string(),
+            ))
+        } else if options.wildcard {
+            let pattern = wildcard::to_regex(query_text, !options.case_sensitive)?;
+            self.regex_query(&pattern, body, options)
+        } else if options.regex {
+            let pattern = wildcard::term_regex(query_text, options.case_insensitive)?;
+            self.regex_query(&pattern, body, options)
+        } else if options.fixed {
+            fixed_query(index, body, query_text)
+        } else {
+            let fields = self.query_fields(body, options)?;
+            let (query_text, last_word) = if options.prefix_last {
+                split_last_word(query_text)
+            } else {
+                (query_text, None)
+            };
+            let query_text = match options.mode {
+                QueryMode::Simple => escape_field_scopes(query_text),
+                QueryMode::Advanced => query_text.to_string(),
+            };
+            // Terms match in any of the default fields.
+            let parser = tantivy::query::QueryParser::for_index(index, fields.clone());
+
+            let mut clauses = Vec::new();
+            if let Some(word) = last_word {
+                clauses.extend(any_field(&fields, |field| {
+                    prefix_query(index, field, word)
+                })?);
+            }
+            if !query_text.trim().is_empty() || clauses.is_empty() {
+                clauses.push(parser.parse_query(&query_text)?);
+            }
+            if clauses.len() == 1 {
+                Ok(clauses.remove(0))
+            } else {
+                Ok(Box::new(BooleanQuery::intersection(clauses)))
+            }
+        }
+    }
+
+    /// Match terms against `pattern` in the fields of `options`.
+    fn regex_query(
+        &self,
+        pattern: &str,
+        body: Field,
+        options: &SearchOptions,
+    ) -> TantivyResult<Box<dyn Query>> {
+        let query = any_field(&self.query_fields(body, options)?, |field| {
+            Ok(Some(Box::new(RegexQuery::from_pattern(pattern, field)?)))
+        })?;
+        Ok(query.unwrap_or_else(|| Box::new(EmptyQuery)))
+    }
+
+    /// The fields `options` match the query terms in, `body` standing for
+    /// the one of [`Self::body_field`].
+    fn query_fields(&self, body: Field, options: &SearchOptions) -> TantivyResult<Vec<Field>> {
+        let mut fields = Vec::new();
+        if options.search_fields.body {
+            fields.push(body);
+        }
+        if options.search_fields.path {
+            fields.push(self.fields.path);
+        }
+        if options.search_fields.comments {
+            fields.push(self.comments_field(options)?);
+        }
+        Ok(fields)
+    }
+
+    /// The field searched for `options`: the analyzer's, the comments one
+    /// for a comments-only search, or the exact-case one for a
+    /// case-sensitive search.
+    fn body_field(&self, options: &SearchOptions) -> TantivyResult<Field> {
+        if options.comments_only {
+            return self.comments_field(options);
+        }
+        if !options.case_sensitive {
+            return Ok(self.fields.body_for(options.analyzer));
+        }
+        if !self.config.index_exact_case {
+            return Err(TantivyError::InvalidArgument(
+                "Case-sensitive search needs index_exact_case".to_string(),
+            ));
+        }
+        if options.analyzer != Analyzer::Default {
+            return Err(TantivyError::InvalidArgument(
+                "case_sensitive only works with the default analyzer".to_string(),
+            ));
+        }
+        if options.case_insensitive {
+            return Err(TantivyError::InvalidArgument(
+                "case_sensitive and case_insensitive can't be used at once".to_string(),
+            ));
+        }
+        Ok(self.fields.body_exact)
+    }
+
+    fn comments_field(&self, options: &SearchOptions) -> TantivyResult<Field> {
+        if !self.config.index_comments {
+            return Err(TantivyError::InvalidArgument(
+                "Comments-only search needs index_comments".to_string(),
+            ));
+        }
+        if options.analyzer != Analyzer::Default {
+            return Err(TantivyError::InvalidArgument(
+                "comments_only only works with the default analyzer".to_string(),
+            ));
+        }
+        if options.case_sensitive {
+            return Err(TantivyError::InvalidArgument(
+                "comments_only and case_sensitive can't be used at once".to_string(),
+            ));
+        }
+        Ok(self.fields.comments)
+    }
+
+    /// The metadata filters of `options` as a single query, or `None` when
+    /// there are none. Rejected when metadata isn't indexed, as nothing
+    /// would match.
+    fn metadata_filters(&self, options: &SearchOptions) -> TantivyResult<Option<Box<dyn Query>>> {
+        let extension = options.extension.as_ref().map(|e| e.to_lowercase());
+        let filters: Vec<(Field, &str)> = [
+            (self.fields.extension, extension.as_deref()),
+            (self.fields.size, options.size.map(SizeBucket::as_str)),
+            (self.fields.top_dir, options.top_dir.as_deref()),
+        ]
+        .into_iter()
+        .filter_map(|(field, value)| Some((field, value?)))
+        .collect();
+        if filters.is_empty() {
+            return Ok(None);
+        }
+        if !self.config.index_metadata {
+            return Err(TantivyError::InvalidArgument(
+                "Filtering by extension, size or top_dir needs index_metadata".to_string(),
+            ));
+        }
+        let clauses = filters
+            .into_iter()
+            .map(|(field, value)| {
+                let term = Term::from_field_text(field, value);
+                Box::new(TermQuery::new(term, IndexRecordOption::Basic)) as Box<dyn Query>
+            })
+            .collect();
+        // Filters leave the scores of the text query as they are.
+        let filters = BooleanQuery::intersection(clauses);
+        Ok(Some(Box::new(ConstScoreQuery::new(Box::new(filters), 0.0))))
+    }
+
+    /// Reject query text over `max_query_length` characters, or analyzed into
+    /// more than `max_query_terms` terms by the field's analyzer, or into
+    /// terms all shorter than `min_term_length`. A wildcard or regex pattern
+    /// is a single term, checked by neither.
+    fn check_query_size(
+        &self,
+        index: &Index,
+        field: Field,
+        query_text: &str,
+        options: &SearchOptions,
+    ) -> TantivyResult<()> {
+        if let Some(max) = self.config.max_query_length {
+            let length = query_text.chars().count();
+            if length > max {
+                return Err(TantivyError::InvalidArgument(format!(
+                    "Query is {length} characters long, the limit is {max}"
+                )));
+            }
+        }
+        let min_length = self
+            .config
+            .min_term_length
+            .filter(|_| !options.allow_short_terms);
+        if options.wildcard || options.regex {
+            return Ok(());
+        }
+        if self.config.max_query_terms.is_none() && min_length.is_none() {
+            return Ok(());
+        }
+        let terms = query_terms(index, field, query_text, options)?;
+        if let Some(max) = self.config.max_query_terms {
+            if terms.len() > max {
+                return Err(TantivyError::InvalidArgument(format!(
+                    "Query has {} terms, the limit is {max}",
+                    terms.len()
+                )));
+            }
+        }
+        if let Some(min) = min_length {
+            let all_short = terms.iter().all(|term| term.chars().count() < min);
+            if !terms.is_empty() && all_short {
+                return Err(TantivyError::InvalidArgument(format!(
+                    "Every query term is shorter than {min} characters and would match too \
+                     many lines; add a longer term or pass allow_short_terms=true"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// The line of a matching whole-file document with the most highlighted
+    /// query terms, the first one on ties. Falls back to the first line when
+    /// no cached line has any, e.g. for wildcard searches, whose terms aren't
+    /// known up front. `None` when `line_range` rules out every candidate.
+    fn matched_line(
+        &self,
+        generator: &SnippetGenerator,
+        file_path: &str,
+        line_range: Option<(usize, usize)>,
+    ) -> Option<usize> {
+        let (first, last) = line_range.unwrap_or((1, usize::MAX));
+        let in_range = |line: &usize| (first..=last).contains(line);
+        let lines_map = read_lock(&self.lines_map);
+        let best = lines_map.get(file_path).and_then(|lines| {
+            lines
+                .iter()
+                .enumerate()
+                .map(|(index, text)| (index + 1, text))
+                .filter(|(line, _)| in_range(line))
+                .map(|(line, text)| (generator.snippet(text).highlighted().len(), line))
+                .filter(|&(terms, _)| terms > 0)
+                .max_by_key(|&(terms, line)| (terms, std::cmp::Reverse(line)))
+        });
+        best.map(|(_, line)| line).or(Some(1)).filter(in_range)
+    }
+
+    /// The line of `doc` holding `literal`, the first one within `line_range`
+    /// for a whole-file document. The stored text is checked, so trailing
+    /// whitespace only matches without `trim_trailing_whitespace`.
+    fn literal_line(
+        &self,
+        doc: &TantivyDocument,
+        literal: &str,
+        line_range: Option<(usize, usize)>,
+    ) -> Option<usize> {
+        let text = doc.get_first(self.fields.body)?.as_str()?;
+        match self.config.granularity {
+            Granularity::Line => {
+                let line = doc.get_first(self.fields.line)?.as_i64()?;
+                text.contains(literal).then_some(line as usize)
+            }
+            Granularity::File => {
+                let (first, last) = line_range.unwrap_or((1, usize::MAX));
+                text.lines()
+                    .zip(1..)
+                    .find(|(text, line)| (first..=last).contains(line) && text.contains(literal))
+                    .map(|(_, line)| line)
+            }
+        }
+    }
+
+    /// Lines past `max_lines_per_file` aren't cached, so a match there is
+    /// returned as the stored line without any surrounding context.
+    fn uncached_line(
+        &self,
+        doc: &TantivyDocument,
+        file_path: &str,
+        line: usize,
+        options: &SearchOptions,
+        generator: Option<&SnippetGenerator>,
+    ) -> Option<(Snippet, (usize, usize))> {
+        let max = self.config.max_lines_per_file?;
+        if line <= max || !read_lock(&self.lines_map).contains_key(file_path) {
+            return None;
+        }
+        let body = doc.get_first(self.fields.body)?.as_str()?;
+        Some((self.snippet(&[body], 0, options, generator), (line, line)))
+    }
+
+    /// Helper method to read the lines around a target line from in-memory
+    /// cache, either a fixed window or the enclosing block. `generator`
+    /// locates the match in the line for `max_snippet_width`.
+    fn read_lines(
+        &self,
+        file_path: &str,
+        line: usize,
+        options: &SearchOptions,
+        generator: Option<&SnippetGenerator>,
+    ) -> Option<(Snippet, (usize, usize))> {
+        let binding = read_lock(&self.lines_map);
+        let file_lines = binding.get(file_path)?;
+        let total = file_lines.len();
+        if line == 0 || line > total {
+            return None;
+        }
+
+        let (start, end) = if options.smart_context {
+            snippet::block_bounds(file_lines, line - 1, SMART_CONTEXT_MAX_LINES)
+        } else {
+            let context = options.context_lines.unwrap_or(CONTEXT_LINES);
+            (
+                (line - 1).saturating_sub(context),
+                (line - 1).saturating_add(context).min(total - 1),
+            )
+        };
+        let lines = &file_lines[start..=end];
+        let lines = if options.dedent {
+            snippet::dedent(lines)
+        } else {
+            lines.iter().map(String::as_str).collect()
+        };
+        let snippet = self.snippet(&lines, line - 1 - start, options, generator);
+        Some((snippet, (start + 1, end + 1)))
+    }
+
+    /// The cached `line` (1-based) of `file_path` cut to
+    /// `SearchOptions::preview_chars` around the match `generator` finds in
+    /// it, or from its start without one. Unlike `max_snippet_width`, lines
+    /// of the context are left out.
+    fn preview_of(
+        &self,
+        file_path: &str,
+        line: usize,
+        options: &SearchOptions,
+        generator: Option<&SnippetGenerator>,
+    ) -> Option<String> {
+        let width = options.preview_chars?;
+        let lines_map = read_lock(&self.lines_map);
+        let text = lines_map.get(file_path)?.get(line.checked_sub(1)?)?;
+        let text = if self.config.sanitize_output {
+            snippet::sanitized(text)
+        } else {
+            Cow::Borrowed(text.as_str())
+        };
+        let focus =
+            generator.and_then(|generator| generator.snippet(&text).highlighted().first().cloned());
+        Some(snippet::truncated(&text, width, focus).into_owned())
+    }
+
+    /// Lay out the snippet of `lines`, sanitized and truncated when
+    /// configured.
+    fn snippet(
+        &self,
+        lines: &[&str],
+        matched: usize,
+        options: &SearchOptions,
+        generator: Option<&SnippetGenerator>,
+    ) -> Snippet {
+        let separator = options
+            .line_separator
+            .as_deref()
+            .unwrap_or(&self.config.snippet_line_separator);
+        let mut lines: Vec<Cow<str>> = lines.iter().map(|&line| Cow::Borrowed(line)).collect();
+        if self.config.sanitize_output {
+            for line in &mut lines {
+                if let Cow::Owned(sanitized) = snippet::sanitized(line) {
+                    *line = Cow::Owned(sanitized);
+                }
+            }
+        }
+        if let Some(width) = self.config.max_snippet_width {
+            for (index, line) in lines.iter_mut().enumerate() {
+                let focus = generator
+                    .filter(|_| index == matched)
+                    .and_then(|generator| generator.snippet(line).highlighted().first().cloned());
+                if let Cow::Owned(truncated) = snippet::truncated(line, width, focus) {
+                    *line = Cow::Owned(truncated);
+                }
+            }
+        }
+        let lines: Vec<&str> = lines.iter().map(AsRef::as_ref).collect();
+        Snippet::new(&lines, matched, options.snippet_format, separator)
+    }
+
+    /// Index `content` under `path` without it existing on disk, replacing
+    /// any document previously pushed under the same path. Pushed documents
+    /// aren't tracked by checksum, so `reload()` leaves them alone.
+    pub fn push_document(&self, path: &str, content: &str) -> TantivyResult<usize> {
+        let lines = self
+            .config
+            .normalized_lines(content.lines().map(str::to_string).collect());
+
+        let _writing = self.writing.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut writer: IndexWriter = write_lock(&self.index).writer(DEFAULT_MEMORY_SIZE)?;
+        writer.delete_term(self.fields.path_term(path));
+        self.fields
+            .add_lines(&writer, "", path, &lines, &self.config)?;
+        writer.commit()?;
+
+        let line_count = lines.len();
+        write_lock(&self.lines_map).insert(path.to_string(), self.config.cached_lines(lines));
+        self.limit_line_cache(Some(path));
+        let _ = self.changes.send(Arc::new(vec![path.to_string()]));
+        Ok(line_count)
+    }
+
+    /// Read the lines of `path` back from the index if they were evicted,
+    /// and count them as used, with `max_cached_bytes`.
+    pub(crate) fn use_lines(&self, path: &str) {
+        self.use_lines_from(None, path);
+    }
+
+    /// `use_lines()` reading evicted lines with `searcher`, for callers
+    /// already holding the index lock, which mustn't be taken again while a
+    /// writer waits for it.
+    fn use_lines_from(&self, searcher: Option<&Searcher>, path: &str) {
+        if self.config.max_cached_bytes.is_none() {
+            return;
+        }
+        let is_evicted = self
+            .line_cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_evicted(path);
+        let evicted = is_evicted
+            && read_lock(&self.lines_map)
+                .get(path)
+                .is_some_and(Vec::is_empty);
+        if evicted {
+            let lines = match searcher {
+                Some(searcher) => self.indexed_lines(searcher, path),
+                None => self
+                    .searcher()
+                    .and_then(|searcher| self.indexed_lines(&searcher, path)),
+            };
+            // Left evicted should the index fail, its results go without snippet.
+            if let Ok(lines) = lines {
+                let lines = self.config.cached_lines(lines);
+                write_lock(&self.lines_map).insert(path.to_string(), lines);
+                self.line_cache
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .restored(path);
+                self.limit_line_cache(Some(path));
+                return;
+            }
+        }
+        self.line_cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .touch(path);
+    }
+
+    /// A searcher over the last commit. The index lock is released on return.
+    pub(crate) fn searcher(&self) -> TantivyResult<Searcher> {
+        let reader: IndexReader = read_lock(&self.index).reader_builder().try_into()?;
+        Ok(reader.searcher())
+    }
+
+    /// The lines of `path` as stored in the index, in order.
+    pub(crate) fn indexed_lines(
+        &self,
+        searcher: &Searcher,
+        path: &str,
+    ) -> TantivyResult<Vec<String>> {
+        let query = TermQuery::new(self.fields.path_term(path), IndexRecordOption::Basic);
+        let mut documents = Vec::new();
+        for address in searcher.search(&query, &DocSetCollector)? {
+            let doc: TantivyDocument = searcher.doc(address)?;
+            let line = doc
+                .get_first(self.fields.line)
+                .and_then(|line| line.as_i64());
+            let body = doc
+                .get_first(self.fields.body)
+                .and_then(|body| body.as_str());
+            documents.push((line.unwrap_or(0), body.unwrap_or_default().to_string()));
+        }
+        documents.sort();
+        let bodies = documents.into_iter().map(|(_, body)| body);
+        Ok(match self.config.granularity {
+            Granularity::Line => bodies.collect(),
+            Granularity::File => bodies
+                .flat_map(|body| body.lines().map(str::to_string).collect::<Vec<_>>())
+                .collect(),
+        })
+    }
+
+    /// Files whose cached lines were evicted.
+    pub(crate) fn evicted_files(&self) -> HashSet<String> {
+        self.line_cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .evicted()
+            .clone()
+    }
+
+    /// Evict the least recently used lines until the cache fits in
+    /// `max_cached_bytes`, never those of `keep`.
+    pub(crate) fn limit_line_cache(&self, keep: Option<&str>) {
+        let Some(max) = self.config.max_cached_bytes else {
+            return;
+        };
+        let mut lines_map = write_lock(&self.lines_map);
+        let sizes = lines_map
+            .iter()
+            .map(|(path, lines)| (path, lines.iter().map(String::len).sum()))
+            .collect();
+        let victims: Vec<String> = self
+            .line_cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .evict(sizes, max, keep)
+            .into_iter()
+            .cloned()
+            .collect();
+        for path in victims {
+            lines_map.insert(path, Vec::new());
+        }
+    }
+
+    /// Replace the exclude patterns of the working tree. The next `reload()`
+    /// drops the files they now exclude and indexes those they no longer do.
+    pub fn set_exclude_patterns(&self, patterns: Vec<String>) {
+        *write_lock(&self.exclude_patterns) = patterns;
+    }
+
+    /// The config to scan with: the one at startup, with the exclude
+    /// patterns in effect now.
+    fn current_config(&self) -> EngineConfig {
+        EngineConfig {
+            exclude_patterns: read_lock(&self.exclude_patterns).clone(),
+            ..self.config.clone()
+        }
+    }
+
+    /// Subscribe to the paths changed by each `reload()`.
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Vec<String>>> {
+        self.changes.subscribe()
+    }
+
+    pub async fn reload(&self, directory: &str) -> TantivyResult<()> {
+        let config = &self.current_config();
+        let known: KnownFiles = {
+            let file_hashes = read_lock(&self.file_hashes);
+            let file_stamps = read_lock(&self.file_stamps);
+            file_stamps
+                .iter()
+                .filter_map(|(path, stamp)| {
+                    Some((path.clone(), (*stamp, file_hashes.get(path)?.clone())))
+                })
+                .collect()
+        };
+        let scanned = collect_hashes(directory, config, &known).await?;
+        let read = self.read_ahead(directory, config, &scanned).await;
+        if self.apply_scan(directory, config, &known, scanned, read)? {
+            self.refresh_symbols(directory).await?;
+        }
+        Ok(())
+    }
+
+    /// Read the files of `scanned` that changed and aren't in the working
+    /// tree, e.g. those at `git_ref`, on blocking threads.
+    async fn read_ahead(
+        &self,
+        directory: &str,
+        config: &EngineConfig,
+        scanned: &ScannedFiles,
+    ) -> HashMap<String, Documents> {
+        let changed: Vec<String> = {
+            let file_hashes = read_lock(&self.file_hashes);
+            scanned
+                .hashes
+                .iter()
+                .filter(|&(path, hash)| {
+                    file_hashes.get(path) != Some(hash)
+                        && !config.reads_tree(path)
+                        && !scanned.contents.contains_key(path)
+                })
+                .map(|(path, _)| path.clone())
+                .collect()
+        };
+        let mut read = HashMap::new();
+        let config = Arc::new(config.clone());
+        let directory = directory.to_string();
+        run_bounded(
+            changed,
+            config.max_open_files,
+            move |path: String| {
+                let documents = read_documents(&directory, &path, &config, None);
+                (path, documents)
+            },
+            |(path, documents)| {
+                read.insert(path, documents);
+            },
+        )
+        .await;
+        read
+    }
+
+    /// Index what changed since the last scan, given the files `scanned`
+    /// now and the documents `read` ahead. Returns whether anything did.
+    fn apply_scan(
+        &self,
+        directory: &str,
+        config: &EngineConfig,
+        known: &KnownFiles,
+        scanned: ScannedFiles,
+        mut read: HashMap<String, Documents>,
+    ) -> TantivyResult<bool> {
+        let ScannedFiles {
+            hashes,
+            mut stamps,
+            mut contents,
+        } = scanned;
+        let current_paths: HashSet<String> = hashes.keys().cloned().collect();
+        // Skipped files aren't tracked, forget those that are gone.
+        write_lock(&self.skipped)
+            .retain(|path, _| current_paths.contains(archive::container_path(path)));
+
+        let old_hashes_read = read_lock(&self.file_hashes);
+        let old_paths: HashSet<String> = old_hashes_read.keys().cloned().collect();
+        drop(old_hashes_read); // Done reading
+
+        // Determine missing files.
+        let mut missing_files: Vec<String> =
+            old_paths.difference(&current_paths).cloned().collect();
+
+        let _writing = self.writing.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut writer = write_lock(&self.index).writer(DEFAULT_MEMORY_SIZE)?;
+        let mut changed_paths = Vec::new();
+        // Contents whose indexed file went away while identical files remain.
+        let mut orphaned = Vec::new();
+
+        // Add/update files
+        let mut pending: Vec<(String, String)> = hashes.into_iter().collect();
+        while let Some((path, hash)) = pending.pop() {
+            let should_update = {
+                let file_hashes_read = read_lock(&self.file_hashes);
+                file_hashes_read.get(&path) != Some(&hash)
+            };
+
+            if !should_update {
+                continue;
+            }
+
+            // The file may have been removed or replaced by a directory since
+            // it was walked. Drop it either way, and index what the directory
+            // holds now rather than waiting for the next reload.
+            if config.reads_tree(&path) {
+                match fs::metadata(&path) {
+                    Ok(metadata) if metadata.is_file() => {}
+                    Ok(metadata) if metadata.is_dir() => {
+                        pending.extend(config.walk_new_directory(&path));
+                        missing_files.push(path);
+                        continue;
+                    }
+                    _ => {
+                        missing_files.push(path);
+                        continue;
+                    }
+                }
+            }
+            changed_paths.push(path.clone());
+
+            if config.follows(&path) {
+                if let Some(appended) = self.follow(&path, &hash, known, &stamps) {
+                    let (first, lines) = appended;
+                    let lines = config.normalized_lines(lines);
+                    self.fields
+                        .add_lines_from(&writer, directory, &path, first, &lines, config)?;
+                    // Lines past `max_lines_per_file` aren't cached.
+                    if let Some(cached) = write_lock(&self.lines_map)
+                        .get_mut(&path)
+                        .filter(|cached| cached.len() == first)
+                    {
+                        cached.extend(lines);
+                        *cached = config.cached_lines(mem::take(cached));
+                    }
+                    write_lock(&self.file_hashes).insert(path, hash);
+                    continue;
+                }
+            }
+
+            let mut duplicate = false;
+            if config.dedupes(&path) {
+                let old_hash = read_lock(&self.file_hashes).get(&path).cloned();
+                let mut shared = write_lock(&self.shared_content);
+                if let Some(old_hash) = old_hash {
+                    if shared.remove(&path, &old_hash) {
+                        orphaned.push(old_hash);
+                    }
+                }
+                duplicate = !shared.add(&path, &hash);
+            }
+
+            // Update hash
+            {
+                let mut file_hashes_write = write_lock(&self.file_hashes);
+                file_hashes_write.insert(path.clone(), hash);
+            }
+
+            // Drop the previous documents of the file before re-reading it.
+            writer.delete_term(self.fields.path_term(&path));
+            // Likewise for the entries of an archive, so files removed from
+            // the archive don't linger.
+            if archive::is_archive(&path) {
+                self.remove_archive_entries(&writer, &path);
+            }
+            self.forget_skipped(&path);
+            if duplicate {
+                write_lock(&self.lines_map).remove(&path);
+                continue;
+            }
+
+            // Open file and index lines
+            let documents = match read.remove(&path) {
+                Some(documents) => documents,
+                None => read_documents(directory, &path, config, contents.remove(&path)),
+            };
+            for (doc_path, lines) in documents {
+                let Some(lines) = self.lines_or_skip(doc_path.clone(), lines) else {
+                    if doc_path == path {
+                        self.untrack(&path, &mut orphaned);
+                        stamps.remove(&path);
+                        // A file skipped by the previous reload too is
+                        // read again, but it didn't change.
+                        if !old_paths.contains(&path) {
+                            changed_paths.pop();
+                        }
+                    }
+                    continue;
+                };
+                self.fields
+                    .add_lines(&writer, directory, &doc_path, &lines, config)?;
+
+                let mut lines_map_write = write_lock(&self.lines_map);
+                lines_map_write.insert(doc_path, config.cached_lines(lines));
+            }
+        }
+
+        // Remove missing files
+        if !missing_files.is_empty() {
+            for path in &missing_files {
+                writer.delete_term(self.fields.path_term(path));
+                if archive::is_archive(path) {
+                    self.remove_archive_entries(&writer, path);
+                }
+                self.forget_skipped(path);
+            }
+
+            let mut old_hashes = Vec::new();
+            {
+                let mut file_hashes_write = write_lock(&self.file_hashes);
+                let mut lines_map_write = write_lock(&self.lines_map);
+                for path in &missing_files {
+                    old_hashes.extend(file_hashes_write.remove(path).map(|hash| (path, hash)));
+                    lines_map_write.remove(path);
+                }
+            }
+            if config.dedupe_content {
+                let mut shared = write_lock(&self.shared_content);
+                for (path, hash) in old_hashes {
+                    if shared.remove(path, &hash) {
+                        orphaned.push(hash);
+                    }
+                }
+            }
+        }
+        changed_paths.extend(missing_files);
+
+        // Index a remaining identical file in place of each one that went away.
+        while let Some(hash) = orphaned.pop() {
+            let Some(path) = read_lock(&self.shared_content).first(&hash).cloned() else {
+                continue;
+            };
+            if read_lock(&self.lines_map).contains_key(&path) {
+                continue;
+            }
+            for (doc_path, lines) in
+                read_documents(directory, &path, config, contents.remove(&path))
+            {
+                let Some(lines) = self.lines_or_skip(doc_path.clone(), lines) else {
+                    if doc_path == path {
+                        self.untrack(&path, &mut orphaned);
+                        stamps.remove(&path);
+                    }
+                    continue;
+                };
+                self.fields
+                    .add_lines(&writer, directory, &doc_path, &lines, config)?;
+                write_lock(&self.lines_map).insert(doc_path, config.cached_lines(lines));
+            }
+            changed_paths.push(path);
+        }
+
+        writer.commit()?;
+        *write_lock(&self.file_stamps) = stamps;
+        if changed_paths.is_empty() {
+            return Ok(false);
+        }
+        *write_lock(&self.last_changed) = self.with_archive_entries(&changed_paths);
+        // Nobody listening is not an error.
+        let _ = self.changes.send(Arc::new(changed_paths));
+        drop(_writing);
+        self.limit_line_cache(None);
+        Ok(true)
+    }
+
+    /// Rerun `ctags_command` on a blocking thread and keep the definitions
+    /// it finds in indexed files. On failure the previous definitions are
+    /// kept.
+    async fn refresh_symbols(&self, directory: &str) -> TantivyResult<()> {
+        if self.config.ctags_command.is_empty() || !self.config.scans_tree() {
+            return Ok(());
+        }
+        let command = self.config.ctags_command.clone();
+        let paths: Vec<String> = std::iter::once(directory)
+            .chain(self.config.roots.iter().map(|root| root.path.as_str()))
+            .map(String::from)
+            .collect();
+        let found = task::spawn_blocking(move || {
+            let paths: Vec<&str> = paths.iter().map(String::as_str).collect();
+            ctags::generate(&command, &paths)
+        })
+        .await
+        .map_err(|e| TantivyError::InternalError(e.to_string()))??;
+        let file_hashes = read_lock(&self.file_hashes);
+        let mut symbols: HashMap<String, Vec<Symbol>> = HashMap::new();
+        for (name, symbol) in found {
+            if file_hashes.contains_key(&symbol.path) {
+                symbols.entry(name).or_default().push(symbol);
+            }
+        }
+        drop(file_hashes);
+        *write_lock(&self.symbols) = symbols;
+        Ok(())
+    }
+
+    /// Where `name` is defined, by path and line. Rejected without
+    /// `ctags_command`, as nothing would ever be found.
+    pub fn find_symbol(&self, name: &str) -> TantivyResult<Vec<Symbol>> {
+        if self.config.ctags_command.is_empty() {
+            return Err(TantivyError::InvalidArgument(
+                "Symbol lookup needs ctags_command".to_string(),
+            ));
+        }
+        let mut definitions = read_lock(&self.symbols)
+            .get(name)
+            .cloned()
+            .unwrap_or_default();
+        definitions.sort_by(|a, b| (&a.path, a.line).cmp(&(&b.path, b.line)));
+        Ok(definitions)
+    }
+
+    /// The lines appended to the followed file at `path` since the previous
+    /// scan, if that's all that changed, and the index of the first of them.
+    /// A file that wasn't indexed, e.g. it was skipped, is read in full.
+    fn follow(
+        &self,
+        path: &str,
+        hash: &str,
+        known: &KnownFiles,
+        stamps: &HashMap<String, FileStamp>,
+    ) -> Option<(usize, Vec<String>)> {
+        if !read_lock(&self.lines_map).contains_key(path) {
+            return None;
+        }
+        let (old_stamp, old_hash) = known.get(path)?;
+        let new_stamp = stamps.get(path)?;
+        let old = follow::Version {
+            len: old_stamp.len,
+            hash: old_hash,
+        };
+        let new = follow::Version {
+            len: new_stamp.len,
+            hash,
+        };
+        follow::appended_lines(path, old, new, self.config.decoding())
+    }
+
+    /// `paths` plus the cached virtual entries of the archives among them.
+    fn with_archive_entries(&self, paths: &[String]) -> HashSet<String> {
+        let mut all: HashSet<String> = paths.iter().cloned().collect();
+        let prefixes: Vec<String> = paths
+            .iter()
+            .filter(|path| archive::is_archive(path))
+            .map(|path| archive::virtual_path(path, ""))
+            .collect();
+        if !prefixes.is_empty() {
+            let lines_map = read_lock(&self.lines_map);
+            let entries = lines_map
+                .keys()
+                .filter(|path| prefixes.iter().any(|prefix| path.starts_with(prefix)));
+            all.extend(entries.cloned());
+        }
+        all
+    }
+
+    /// Files found by the scans that couldn't be indexed, ordered by path.
+    pub fn skipped_files(&self) -> Vec<SkippedFile> {
+        let mut files: Vec<SkippedFile> = read_lock(&self.skipped)
+            .iter()
+            .map(|(path, reason)| SkippedFile {
+                path: path.clone(),
+                reason: *reason,
+            })
+            .collect();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        files
+    }
+
+    /// The lines of a document read by `reload()`, recording it as skipped
+    /// when there are none.
+    fn lines_or_skip(
+        &self,
+        path: String,
+        lines: Result<Vec<String>, SkipReason>,
+    ) -> Option<Vec<String>> {
+        match lines {
+            Ok(lines) => Some(lines),
+            Err(reason) => {
+                write_lock(&self.skipped).insert(path, reason);
+                None
+            }
+        }
+    }
+
+    /// Stop tracking the skipped file at `path`, as if it were missing, so
+    /// its previous lines aren't served and the next reload reads it again.
+    /// Pushes its content to `orphaned` when an identical file remains.
+    fn untrack(&self, path: &str, orphaned: &mut Vec<String>) {
+        write_lock(&self.lines_map).remove(path);
+        let Some(hash) = write_lock(&self.file_hashes).remove(path) else {
+            return;
+        };
+        if self.config.dedupes(path) && write_lock(&self.shared_content).remove(path, &hash) {
+            orphaned.push(hash);
+        }
+    }
+
+    /// Drop `path`, and the entries of it as an archive, from the skipped files.
+    fn forget_skipped(&self, path: &str) {
+        let prefix = archive::virtual_path(path, "");
+        write_lock(&self.skipped)
+            .retain(|skipped, _| skipped != path && !skipped.starts_with(&prefix));
+    }
+
+    /// Remove the virtual entries of an archive from the index and line cache.
+    fn remove_archive_entries(&self, writer: &IndexWriter, archive_path: &str) {
+        let prefix = archive::virtual_path(archive_path, "");
+        let mut lines_map_write = write_lock(&self.lines_map);
+        lines_map_write.retain(|path, _| {
+            if path.starts_with(&prefix) {
+                writer.delete_term(self.fields.path_term(path));
+                return false;
+            }
+            true
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,116 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Dropping the cached lines of the least recently searched files to keep
+//! the line cache under `EngineConfig::max_cached_bytes`.
+
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+/// Usage of a capped line cache, for `/stats`.
+#[derive(Debug, Serialize)]
+pub struct LineCacheStats {
+    /// `EngineConfig::max_cached_bytes`.
+    max_bytes: usize,
+    /// Bytes of the cached lines after the last eviction check.
+    cached_bytes: usize,
+    /// Files whose lines are left in the index only.
+    evicted_files: usize,
+}
+
+/// When each file's lines were last used and which files were evicted.
+#[derive(Debug, Default)]
+pub(crate) struct LineCache {
+    /// Value of `clock` when each file's lines were last read for a result.
+    last_used: HashMap<String, u64>,
+    clock: u64,
+    evicted: HashSet<String>,
+    cached_bytes: usize,
+}
+
+impl LineCache {
+    /// Count the lines of `path` as just used.
+    pub(crate) fn touch(&mut self, path: &str) {
+        self.clock += 1;
+        match self.last_used.get_mut(path) {
+            Some(used) => *used = self.clock,
+            None => {
+                self.last_used.insert(path.to_string(), self.clock);
+            }
+        }
+    }
+
+    pub(crate) fn is_evicted(&self, path: &str) -> bool {
+        self.evicted.contains(path)
+    }
+
+    pub(crate) fn evicted(&self) -> &HashSet<String> {
+        &self.evicted
+    }
+
+    /// Record that the lines of `path` are cached again.
+    pub(crate) fn restored(&mut self, path: &str) {
+        self.evicted.remove(path);
+        self.touch(path);
+    }
+
+    /// Pick the files to evict, given the cached bytes of every file, so
+    /// the rest fits in `max_bytes`. The least recently used go first,
+    /// files never used before any other, and `keep` never does.
+    pub(crate) fn evict<'a>(
+        &mut self,
+        sizes: Vec<(&'a String, usize)>,
+        max_bytes: usize,
+        keep: Option<&str>,
+    ) -> Vec<&'a String> {
+        // Files cached again since, e.g. by a reload, aren't evicted anymore.
+        let empty: HashSet<&str> = sizes
+            .iter()
+            .filter(|(_, bytes)| *bytes == 0)
+            .map(|(path, _)| path.as_str())
+            .collect();
+        self.evicted.retain(|path| empty.contains(path.as_str()));
+        self.last_used
+            .retain(|path, _| !empty.contains(path.as_str()));
+
+        let mut total: usize = sizes.iter().map(|(_, bytes)| bytes).sum();
+        let mut victims = Vec::new();
+        if total > max_bytes {
+            let mut candidates: Vec<(&String, usize)> = sizes
+                .into_iter()
+                .filter(|(path, bytes)| *bytes > 0 && Some(path.as_str()) != keep)
+                .collect();
+            candidates.sort_by_key(|(path, _)| (self.last_used.get(*path).copied(), *path));
+            for (path, bytes) in candidates {
+                if total <= max_bytes {
+                    break;
+                }
+                total -= bytes;
+                self.evicted.insert(path.clone());
+                self.last_used.remove(path);
+                victims.push(path);
+            }
+        }
+        self.cached_bytes = total;
+        victims
+    }
+
+    pub(crate) fn stats(&self, max_bytes: usize) -> LineCacheStats {
+        LineCacheStats {
+            max_bytes,
+            cached_bytes: self.cached_bytes,
+            evicted_files: self.evicted.len(),
+        }
+    }
+}
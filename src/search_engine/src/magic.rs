@@ -0,0 +1,122 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Telling binary contents from text by their leading magic bytes and the
+//! share of control characters, rather than by any NUL byte.
+
+use infer::MatcherType;
+
+use crate::skipped::SkipReason;
+
+/// How much of the contents is sampled for control characters.
+const SAMPLE_LEN: usize = 8192;
+
+/// Whether `bytes` start like a binary format known to `infer`, or more
+/// than a tenth of their first 8 KiB are control characters other than
+/// whitespace and escapes. UTF-16 with a byte order mark is text, see
+/// [`utf16_text`].
+pub(crate) fn is_binary(bytes: &[u8]) -> bool {
+    if utf16_order(bytes).is_some() {
+        return false;
+    }
+    if infer::get(bytes).is_some_and(|kind| kind.matcher_type() != MatcherType::Text) {
+        return true;
+    }
+    let sample = &bytes[..bytes.len().min(SAMPLE_LEN)];
+    let control = sample
+        .iter()
+        .filter(|&&byte| {
+            (byte < 0x20 && !matches!(byte, b'\t' | b'\n' | b'\r' | 0x0c | 0x1b)) || byte == 0x7f
+        })
+        .count();
+    control * 10 > sample.len()
+}
+
+/// The byte order given by the UTF-16 byte order mark `bytes` start with:
+/// true for big-endian.
+fn utf16_order(bytes: &[u8]) -> Option<bool> {
+    match bytes {
+        [0xff, 0xfe, ..] => Some(false),
+        [0xfe, 0xff, ..] => Some(true),
+        _ => None,
+    }
+}
+
+/// The text of `bytes` decoded as UTF-16, without its byte order mark.
+/// `None` without a mark.
+pub(crate) fn utf16_text(bytes: &[u8]) -> Option<Result<String, SkipReason>> {
+    let big_endian = utf16_order(bytes)?;
+    let pairs = bytes[2..].chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return Some(Err(SkipReason::InvalidEncoding));
+    }
+    let units: Vec<u16> = pairs
+        .map(|pair| {
+            let pair = [pair[0], pair[1]];
+            if big_endian {
+                u16::from_be_bytes(pair)
+            } else {
+                u16::from_le_bytes(pair)
+            }
+        })
+        .collect();
+    Some(String::from_utf16(&units).map_err(|_| SkipReason::InvalidEncoding))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_formats_are_binary_without_any_nul() {
+        assert!(is_binary(b"%PDF-1.7\n%\xe2\xe3\xcf\xd3\n1 0 obj"));
+        assert!(is_binary(b"\x7fELF\x02\x01\x01"));
+        assert!(is_binary(b"GIF89a\x01\x02"));
+    }
+
+    #[test]
+    fn a_few_control_characters_keep_text_text() {
+        let mut text = b"fn main() {\n\tprintln!(\"\x1b[1mbold\");\n}\n".repeat(10);
+        text.push(0);
+        assert!(!is_binary(&text));
+        // Mostly control characters, past a tenth of the sample.
+        assert!(is_binary(b"ab\0\x01\x02\x03cd\x04\x05"));
+        assert!(!is_binary(b""));
+    }
+
+    #[test]
+    fn utf16_with_a_byte_order_mark_is_text() {
+        assert!(!is_binary(b"\xff\xfeh\0i\0\n\0"));
+        assert!(!is_binary(b"\xfe\xff\0h\0i\0\n"));
+        // Without one, its NULs make it binary.
+        assert!(is_binary(b"h\0i\0\n\0"));
+    }
+
+    #[test]
+    fn utf16_is_decoded_in_the_order_of_its_byte_order_mark() {
+        let text = |bytes: &[u8]| utf16_text(bytes).map(Result::ok);
+        assert_eq!(
+            text(b"\xff\xfeh\0\xe9\0\n\0"),
+            Some(Some("h\u{e9}\n".to_string()))
+        );
+        assert_eq!(
+            text(b"\xfe\xff\0h\xd8\x3d\xde\x00"),
+            Some(Some("h\u{1f600}".to_string()))
+        );
+        // A lone surrogate, or an odd byte out.
+        assert_eq!(text(b"\xff\xfe\x3d\xd8"), Some(None));
+        assert_eq!(text(b"\xff\xfeh\0i"), Some(None));
+        assert_eq!(text(b"hi"), None);
+    }
+}
@@ -0,0 +1,97 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! File metadata indexed next to the content when `index_metadata` is set,
+//! so searches can be narrowed to e.g. large Rust files below `src`.
+
+use serde::Deserialize;
+
+use crate::{archive, EngineConfig};
+
+/// Upper bounds of the size buckets below [`SizeBucket::Huge`], in bytes.
+const SMALL_MAX: usize = 10 * 1024;
+const MEDIUM_MAX: usize = 100 * 1024;
+const LARGE_MAX: usize = 1024 * 1024;
+
+/// Rough size of a file's text, counting a newline after every line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SizeBucket {
+    /// Under 10 KiB.
+    Small,
+    /// 10 KiB up to 100 KiB.
+    Medium,
+    /// 100 KiB up to 1 MiB.
+    Large,
+    /// 1 MiB or more.
+    Huge,
+}
+
+impl SizeBucket {
+    fn of(bytes: usize) -> Self {
+        if bytes < SMALL_MAX {
+            SizeBucket::Small
+        } else if bytes < MEDIUM_MAX {
+            SizeBucket::Medium
+        } else if bytes < LARGE_MAX {
+            SizeBucket::Large
+        } else {
+            SizeBucket::Huge
+        }
+    }
+
+    /// The indexed term of the bucket.
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            SizeBucket::Small => "small",
+            SizeBucket::Medium => "medium",
+            SizeBucket::Large => "large",
+            SizeBucket::Huge => "huge",
+        }
+    }
+}
+
+/// The metadata terms of a document.
+pub(crate) struct FileMetadata {
+    pub(crate) size: SizeBucket,
+    /// Lowercase extension of the file name, without the dot.
+    pub(crate) extension: Option<String>,
+    /// First directory of the path below the scanned directory or root it
+    /// was found in. Files of an archive share the archive's.
+    pub(crate) top_dir: Option<String>,
+}
+
+impl FileMetadata {
+    pub(crate) fn of(directory: &str, path: &str, lines: &[String], config: &EngineConfig) -> Self {
+        let bytes = lines.iter().map(|line| line.len() + 1).sum();
+        let name = path.rsplit('/').next().unwrap_or(path);
+        let extension = name
+            .rsplit_once('.')
+            .map(|(_, extension)| extension.to_lowercase());
+
+        let container = archive::container_path(path);
+        let root = config.root_for(container);
+        let base = root.map_or(directory, |root| &root.path);
+        let relative = config.source_for(container).relative_path(base, container);
+        let relative = relative.trim_start_matches('/').trim_start_matches("./");
+        let top_dir = relative
+            .split_once('/')
+            .map(|(top_dir, _)| top_dir.to_string());
+        Self {
+            size: SizeBucket::of(bytes),
+            extension,
+            top_dir,
+        }
+    }
+}
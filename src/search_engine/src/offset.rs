@@ -0,0 +1,102 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Byte offsets of cached lines within the file on disk.
+//!
+//! Cached lines have their line endings removed, so whether a file uses
+//! `\n` or `\r\n` is told apart by its size on disk and whether its last
+//! line is terminated. Files mixing both, or only partly cached, are
+//! measured on disk instead.
+
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Length of the line endings of a file of `file_len` bytes holding
+/// `lines`, or `None` when neither `\n` nor `\r\n` throughout adds up.
+/// Every line but the last one ends in a line break, the last one does
+/// if `terminated`. Without knowing that, a file mixing both endings could
+/// add up as well.
+pub(crate) fn line_ending_len(lines: &[String], file_len: u64, terminated: bool) -> Option<usize> {
+    let text: u64 = lines.iter().map(|line| line.len() as u64).sum();
+    let endings = (lines.len() as u64).saturating_sub(u64::from(!terminated));
+    [1, 2]
+        .into_iter()
+        .find(|ending| text + endings * ending == file_len)
+        .map(|ending| ending as usize)
+}
+
+/// Whether the `file_len` bytes long file at `path` end in `\n`, read
+/// from its last byte.
+pub(crate) fn ends_with_newline(path: &str, file_len: u64) -> Option<bool> {
+    let Some(last) = file_len.checked_sub(1) else {
+        return Some(false);
+    };
+    let mut file = fs::File::open(path).ok()?;
+    file.seek(SeekFrom::Start(last)).ok()?;
+    let mut byte = [0];
+    file.read_exact(&mut byte).ok()?;
+    Some(byte[0] == b'\n')
+}
+
+/// Byte offset of the start of line `index` (0-based) in `bytes`.
+pub(crate) fn line_start(bytes: &[u8], index: usize) -> Option<usize> {
+    if index == 0 {
+        return Some(0);
+    }
+    bytes
+        .iter()
+        .enumerate()
+        .filter(|&(_, &byte)| byte == b'\n')
+        .nth(index - 1)
+        .map(|(newline, _)| newline + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(text: &str) -> Vec<String> {
+        text.lines().map(str::to_string).collect()
+    }
+
+    fn ending_of(text: &str) -> Option<usize> {
+        line_ending_len(&split(text), text.len() as u64, text.ends_with('\n'))
+    }
+
+    #[test]
+    fn line_endings_are_told_apart_by_size() {
+        assert_eq!(ending_of("ab\ncd\n"), Some(1));
+        assert_eq!(ending_of("ab\ncd"), Some(1));
+        assert_eq!(ending_of("ab\r\ncd\r\n"), Some(2));
+        assert_eq!(ending_of("ab\r\ncd"), Some(2));
+        assert_eq!(ending_of(""), Some(1));
+    }
+
+    #[test]
+    fn mixed_line_endings_add_up_to_neither() {
+        // Sized like `\r\n` throughout without a final line break.
+        assert_eq!(ending_of("ab\r\ncd\nef\n"), None);
+        // Sized like `\n` throughout with a final line break.
+        assert_eq!(ending_of("ab\ncd\r\nef"), None);
+    }
+
+    #[test]
+    fn line_starts_count_newlines() {
+        let bytes = b"ab\r\ncd\nef";
+        assert_eq!(line_start(bytes, 0), Some(0));
+        assert_eq!(line_start(bytes, 1), Some(4));
+        assert_eq!(line_start(bytes, 2), Some(7));
+        assert_eq!(line_start(bytes, 3), None);
+    }
+}
@@ -0,0 +1,123 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Choosing which indexed fields the query text is matched against.
+
+use serde::{de, Deserialize, Deserializer};
+use std::str::FromStr;
+use tantivy::TantivyError;
+
+/// Names accepted in a field list.
+const FIELD_NAMES: [&str; 3] = ["body", "path", "comments"];
+
+/// The fields a search matches its terms in, any one of them sufficing.
+/// Only `body` by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueryFields {
+    /// The line text, as chosen by the analyzer and case options.
+    pub body: bool,
+    /// The path of the file, matching every line of it.
+    pub path: bool,
+    /// Comment and docstring text, indexed with `index_comments`.
+    pub comments: bool,
+}
+
+impl Default for QueryFields {
+    fn default() -> Self {
+        QueryFields {
+            body: true,
+            path: false,
+            comments: false,
+        }
+    }
+}
+
+impl QueryFields {
+    /// Whether anything besides `body` is searched.
+    pub(crate) fn beyond_body(&self) -> bool {
+        self.path || self.comments
+    }
+}
+
+/// Parse a comma-separated list such as `path,body`.
+impl FromStr for QueryFields {
+    type Err = TantivyError;
+
+    fn from_str(list: &str) -> Result<Self, Self::Err> {
+        let mut fields = QueryFields {
+            body: false,
+            path: false,
+            comments: false,
+        };
+        for name in list
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            let field = match name {
+                "body" => &mut fields.body,
+                "path" => &mut fields.path,
+                "comments" => &mut fields.comments,
+                _ => {
+                    return Err(TantivyError::InvalidArgument(format!(
+                        "Unknown search field `{name}`, expected any of {}",
+                        FIELD_NAMES.join(", ")
+                    )))
+                }
+            };
+            *field = true;
+        }
+        if !(fields.body || fields.path || fields.comments) {
+            return Err(TantivyError::InvalidArgument(format!(
+                "No search field given, expected any of {}",
+                FIELD_NAMES.join(", ")
+            )));
+        }
+        Ok(fields)
+    }
+}
+
+impl<'de> Deserialize<'de> for QueryFields {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(|e| match e {
+                TantivyError::InvalidArgument(message) => de::Error::custom(message),
+                e => de::Error::custom(e),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_turn_on_only_the_named_fields() {
+        let fields: QueryFields = "path, body".parse().unwrap();
+        assert!(fields.body && fields.path && !fields.comments);
+        assert!(fields.beyond_body());
+        let fields: QueryFields = "body,".parse().unwrap();
+        assert_eq!(fields, QueryFields::default());
+        assert!(!fields.beyond_body());
+    }
+
+    #[test]
+    fn unknown_and_empty_lists_are_rejected() {
+        let error = "body,symbol".parse::<QueryFields>().unwrap_err();
+        assert!(error.to_string().contains("Unknown search field `symbol`"));
+        let error = " , ".parse::<QueryFields>().unwrap_err();
+        assert!(error.to_string().contains("No search field given"));
+    }
+}
@@ -0,0 +1,125 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Choosing which fields of each search result are returned.
+
+use serde::{de, Deserialize, Deserializer};
+use std::str::FromStr;
+use tantivy::TantivyError;
+
+/// Names accepted in a field list, in the order they are serialized.
+const FIELD_NAMES: [&str; 6] = ["body", "path", "line", "line_range", "score", "language"];
+
+/// The fields of each [`crate::SearchResult`] to include, all by default.
+/// `body` stands for the snippet, also in its split format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResultFields {
+    pub body: bool,
+    pub path: bool,
+    pub line: bool,
+    pub line_range: bool,
+    pub score: bool,
+    pub language: bool,
+}
+
+impl Default for ResultFields {
+    fn default() -> Self {
+        ResultFields {
+            body: true,
+            path: true,
+            line: true,
+            line_range: true,
+            score: true,
+            language: true,
+        }
+    }
+}
+
+impl ResultFields {
+    /// Whether the lines around each match have to be read at all.
+    pub(crate) fn needs_snippet(&self) -> bool {
+        self.body || self.line_range
+    }
+}
+
+/// Parse a comma-separated list such as `path,line`.
+impl FromStr for ResultFields {
+    type Err = TantivyError;
+
+    fn from_str(list: &str) -> Result<Self, Self::Err> {
+        let mut fields = ResultFields {
+            body: false,
+            path: false,
+            line: false,
+            line_range: false,
+            score: false,
+            language: false,
+        };
+        for name in list
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            let field = match name {
+                "body" => &mut fields.body,
+                "path" => &mut fields.path,
+                "line" => &mut fields.line,
+                "line_range" => &mut fields.line_range,
+                "score" => &mut fields.score,
+                "language" => &mut fields.language,
+                _ => {
+                    return Err(TantivyError::InvalidArgument(format!(
+                        "Unknown result field `{name}`, expected any of {}",
+                        FIELD_NAMES.join(", ")
+                    )))
+                }
+            };
+            *field = true;
+        }
+        Ok(fields)
+    }
+}
+
+impl<'de> Deserialize<'de> for ResultFields {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(|e| match e {
+                TantivyError::InvalidArgument(message) => de::Error::custom(message),
+                e => de::Error::custom(e),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_turn_on_only_the_named_fields() {
+        let fields: ResultFields = "path, line".parse().unwrap();
+        assert!(fields.path && fields.line);
+        assert!(!(fields.body || fields.line_range || fields.score || fields.language));
+        assert!(!fields.needs_snippet());
+        let fields: ResultFields = "line_range,".parse().unwrap();
+        assert!(fields.line_range && !fields.body);
+        assert!(fields.needs_snippet());
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        let error = "path,snippet".parse::<ResultFields>().unwrap_err();
+        assert!(error.to_string().contains("Unknown result field `snippet`"));
+    }
+}
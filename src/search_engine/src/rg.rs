@@ -0,0 +1,104 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Search results as the `match` records of ripgrep's `--json` output, so
+//! tools reading ripgrep can read spidermonkey too.
+
+use serde_json::{json, Value};
+use std::ops::Range;
+use tantivy::snippet::SnippetGenerator;
+use tantivy::{IndexReader, Result as TantivyResult};
+
+use crate::{read_lock, CodeSearchEngine, SearchOptions, SearchResult};
+
+/// Locates the query terms within matched lines, for the `submatches` of
+/// each record.
+pub struct RgMatcher {
+    generator: SnippetGenerator,
+    /// The query text of a fixed-string search, located as is.
+    literal: Option<String>,
+}
+
+impl RgMatcher {
+    /// The byte ranges of the query terms within `text`, in order.
+    pub(crate) fn ranges(&self, text: &str) -> Vec<Range<usize>> {
+        match &self.literal {
+            Some(literal) if !literal.is_empty() => text
+                .match_indices(literal.as_str())
+                .map(|(start, _)| start..start + literal.len())
+                .collect(),
+            Some(_) => Vec::new(),
+            None => self.generator.snippet(text).highlighted().to_vec(),
+        }
+    }
+}
+
+impl CodeSearchEngine {
+    /// Prepare to describe the results of searching `query_text`.
+    pub fn rg_matcher(
+        &self,
+        query_text: &str,
+        options: &SearchOptions,
+    ) -> TantivyResult<RgMatcher> {
+        let query_text = self.config.normalized(query_text);
+        let query_text = query_text.as_ref();
+        let index = read_lock(&self.index);
+        let reader: IndexReader = index.reader_builder().try_into()?;
+        let body = self.body_field(options)?;
+        let query = self.text_query(&index, body, query_text, options)?;
+        let mut generator = SnippetGenerator::create(&reader.searcher(), &*query, body)?;
+        // A single fragment spanning the line keeps offsets relative to its start.
+        generator.set_max_num_chars(usize::MAX);
+        Ok(RgMatcher {
+            generator,
+            literal: options.fixed.then(|| query_text.to_string()),
+        })
+    }
+
+    /// The ripgrep `match` record of `result`, or `None` when its line isn't
+    /// cached. Offsets count every line as ending in a single `\n`. Terms of
+    /// wildcard, regex and prefix searches aren't known, so they have no submatches.
+    pub fn rg_match(&self, matcher: &RgMatcher, result: &SearchResult) -> Option<Value> {
+        let content_path = self
+            .indexed_copy(&result.path)
+            .unwrap_or_else(|| result.path.clone());
+        self.use_lines(&content_path);
+        let lines_map = read_lock(&self.lines_map);
+        let lines = lines_map.get(&content_path)?;
+        let index = result.line.checked_sub(1)?;
+        let text = lines.get(index)?;
+        let absolute_offset: usize = lines[..index].iter().map(|line| line.len() + 1).sum();
+        let submatches: Vec<Value> = matcher
+            .ranges(text)
+            .iter()
+            .map(|range| {
+                json!({
+                    "match": { "text": &text[range.clone()] },
+                    "start": range.start,
+                    "end": range.end,
+                })
+            })
+            .collect();
+        Some(json!({
+            "type": "match",
+            "data": {
+                "path": { "text": result.path },
+                "lines": { "text": format!("{text}\n") },
+                "line_number": result.line,
+                "absolute_offset": absolute_offset,
+                "submatches": submatches,
+            },
+        }))
+    }
+}
@@ -0,0 +1,125 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Naming the function or class a matched line is in, by walking back to
+//! the nearest less indented declaration. A heuristic that works for code
+//! indented by nesting, whatever the language.
+
+use regex::Regex;
+use std::sync::LazyLock;
+
+use crate::snippet::{indentation, is_blank};
+
+/// Lines looked at above a match before giving up.
+const MAX_SCOPE_DISTANCE: usize = 500;
+
+/// A `fn`, `def`, `class`, `function` or similar declaration, after any
+/// modifiers, capturing the declared name.
+static DECLARATION: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(concat!(
+        r"^\s*(?:(?:pub(?:\([^)]*\))?|async|unsafe|const|static|extern\s+\S+|export|default",
+        r"|public|private|protected|internal|abstract|final|override|virtual)\s+)*",
+        r"(?:fn|def|class|function\*?|func(?:\s*\([^)]*\))?|struct|enum|trait|interface|module)",
+        r"\s+([A-Za-z_$][\w$]*)",
+    ))
+    .unwrap()
+});
+
+/// The name declared by `line`, if it is a declaration.
+fn declared_name(line: &str) -> Option<&str> {
+    DECLARATION
+        .captures(line)
+        .and_then(|captures| captures.get(1))
+        .map(|name| name.as_str())
+}
+
+/// Name of the declaration enclosing `lines[index]`: the nearest one above
+/// it that is less indented than everything in between, at most
+/// [`MAX_SCOPE_DISTANCE`] lines up.
+pub(crate) fn enclosing_scope(lines: &[String], index: usize) -> Option<String> {
+    let mut indent = indentation(lines.get(index)?);
+    let first = index.saturating_sub(MAX_SCOPE_DISTANCE);
+    for line in lines[first..index].iter().rev() {
+        if indent == 0 {
+            break;
+        }
+        if is_blank(line) || indentation(line) >= indent {
+            continue;
+        }
+        if let Some(name) = declared_name(line) {
+            return Some(name.to_string());
+        }
+        // A block such as `if` or `for`, keep looking above it.
+        indent = indentation(line);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(text: &str) -> Vec<String> {
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn rust_matches_are_in_their_function_or_impl_item() {
+        let lines = split(
+            "pub struct Parser {\n    \
+                 input: String,\n\
+             }\n\
+             \n\
+             impl Parser {\n    \
+                 pub async fn parse(&self) {\n        \
+                     if self.input.is_empty() {\n            \
+                         return;\n        \
+                     }\n    \
+                 }\n\
+             }",
+        );
+        assert_eq!(enclosing_scope(&lines, 1).as_deref(), Some("Parser"));
+        assert_eq!(enclosing_scope(&lines, 6).as_deref(), Some("parse"));
+        // Past the `if` block, up to the function.
+        assert_eq!(enclosing_scope(&lines, 7).as_deref(), Some("parse"));
+        // `impl` declares no name, and top-level lines have no scope.
+        assert_eq!(enclosing_scope(&lines, 5), None);
+        assert_eq!(enclosing_scope(&lines, 0), None);
+    }
+
+    #[test]
+    fn python_matches_are_in_their_method_or_class() {
+        let lines = split(
+            "class Reader:\n    \
+                 limit = 10\n\n    \
+                 def read(self):\n        \
+                     for line in self.lines:\n            \
+                         yield line",
+        );
+        assert_eq!(enclosing_scope(&lines, 1).as_deref(), Some("Reader"));
+        assert_eq!(enclosing_scope(&lines, 5).as_deref(), Some("read"));
+        assert_eq!(enclosing_scope(&lines, 3).as_deref(), Some("Reader"));
+    }
+
+    #[test]
+    fn declarations_too_far_up_are_not_found() {
+        let body = "    x += 1;\n".repeat(MAX_SCOPE_DISTANCE);
+        let lines = split(&format!("fn far() {{\n{body}    x += 1;\n}}"));
+        assert_eq!(
+            enclosing_scope(&lines, MAX_SCOPE_DISTANCE).as_deref(),
+            Some("far")
+        );
+        assert_eq!(enclosing_scope(&lines, MAX_SCOPE_DISTANCE + 1), None);
+    }
+}
@@ -0,0 +1,98 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Recording files that were found while scanning but not indexed, and why.
+
+use serde::Serialize;
+use std::fs;
+use std::io::{self, BufRead};
+
+use crate::magic;
+
+/// Why a file, or a file inside an archive, isn't searchable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// The file couldn't be opened or read, or the archive couldn't be unpacked.
+    Unreadable,
+    /// The contents hold NUL bytes with `EngineConfig::skip_non_text`, or
+    /// look binary with `EngineConfig::detect_content_type`.
+    Binary,
+    /// The contents aren't valid UTF-8, with `EngineConfig::skip_non_text`
+    /// or `EngineConfig::detect_content_type`.
+    InvalidEncoding,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SkippedFile {
+    pub path: String,
+    pub reason: SkipReason,
+}
+
+/// How contents are split into lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Decoding {
+    /// Line by line, leaving the lines that aren't valid UTF-8 empty so the
+    /// others keep their numbers.
+    Lenient,
+    /// Skip contents holding a NUL byte or that aren't valid UTF-8.
+    Strict,
+    /// Like `Strict`, telling binary contents apart by [`magic::is_binary`]
+    /// and decoding UTF-16 with a byte order mark.
+    Detect,
+}
+
+/// Read the lines of the file at `path`, or tell why they can't be indexed
+/// as text.
+pub(crate) fn read_lines(path: &str, decoding: Decoding) -> Result<Vec<String>, SkipReason> {
+    if decoding == Decoding::Lenient {
+        let file = fs::File::open(path).map_err(|_| SkipReason::Unreadable)?;
+        return Ok(lenient_lines(io::BufReader::new(file)));
+    }
+    let bytes = fs::read(path).map_err(|_| SkipReason::Unreadable)?;
+    decode_lines(bytes, decoding)
+}
+
+/// Split the contents into lines, or tell why they can't be indexed as text.
+pub(crate) fn decode_lines(bytes: Vec<u8>, decoding: Decoding) -> Result<Vec<String>, SkipReason> {
+    let binary = match decoding {
+        Decoding::Lenient => return Ok(lenient_lines(bytes.as_slice())),
+        Decoding::Strict => bytes.contains(&0),
+        Decoding::Detect => {
+            if let Some(text) = magic::utf16_text(&bytes) {
+                return text.map(|text| text.lines().map(str::to_string).collect());
+            }
+            magic::is_binary(&bytes)
+        }
+    };
+    if binary {
+        return Err(SkipReason::Binary);
+    }
+    let text = String::from_utf8(bytes).map_err(|_| SkipReason::InvalidEncoding)?;
+    Ok(text.lines().map(str::to_string).collect())
+}
+
+/// The lines read from `reader` up to its end or first read error.
+fn lenient_lines(reader: impl BufRead) -> Vec<String> {
+    reader
+        .split(b'\n')
+        .map_while(Result::ok)
+        .map(|mut line| {
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            String::from_utf8(line).unwrap_or_default()
+        })
+        .collect()
+}
@@ -0,0 +1,201 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Exporting the index and its caches to a tarball, and restoring it.
+//!
+//! A snapshot holds a manifest, the tantivy index files under `index/` and
+//! the snippet lines and file checksums as JSON sidecars. Restoring it skips
+//! walking, hashing and indexing the scanned directory.
+
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use tantivy::{Directory, Index, Result as TantivyResult, TantivyError};
+
+use crate::{build_schema, read_lock, tokenizer, CodeSearchEngine, EngineConfig, SchemaOptions};
+
+/// Bumped whenever the snapshot layout changes.
+const FORMAT_VERSION: u32 = 1;
+const MANIFEST_FILE: &str = "manifest.json";
+const LINES_FILE: &str = "lines.json";
+const HASHES_FILE: &str = "hashes.json";
+const INDEX_DIR: &str = "index";
+/// Index files tantivy keeps outside of its managed segment files.
+const INDEX_META_FILES: [&str; 2] = ["meta.json", ".managed.json"];
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    format_version: u32,
+    engine_version: String,
+    schema: serde_json::Value,
+}
+
+impl Manifest {
+    fn current(schema: &SchemaOptions) -> TantivyResult<Self> {
+        Ok(Self {
+            format_version: FORMAT_VERSION,
+            engine_version: env!("CARGO_PKG_VERSION").to_string(),
+            schema: to_json_value(&build_schema(schema).0)?,
+        })
+    }
+
+    /// Check that a snapshot with this manifest can be loaded by this build,
+    /// and when `schema` is given, served with those schema options.
+    fn validate(&self, schema: Option<&SchemaOptions>) -> TantivyResult<()> {
+        if self.format_version != FORMAT_VERSION {
+            return Err(TantivyError::InvalidArgument(format!(
+                "Snapshot format version {} is not supported, expected {}",
+                self.format_version, FORMAT_VERSION
+            )));
+        }
+        let Some(schema) = schema else {
+            return Ok(());
+        };
+        let current = Self::current(schema)?;
+        if self.schema != current.schema {
+            return Err(TantivyError::InvalidArgument(format!(
+                "Snapshot schema from engine {} does not match engine {} with the configured \
+                 `schema` options, export it again with them",
+                self.engine_version, current.engine_version
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn to_json_value<T: Serialize>(value: &T) -> TantivyResult<serde_json::Value> {
+    serde_json::to_value(value).map_err(|e| TantivyError::InvalidArgument(e.to_string()))
+}
+
+fn to_json<T: Serialize>(value: &T) -> TantivyResult<Vec<u8>> {
+    serde_json::to_vec(value).map_err(|e| TantivyError::InvalidArgument(e.to_string()))
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(path: &Path) -> TantivyResult<T> {
+    let contents = fs::read(path)?;
+    serde_json::from_slice(&contents).map_err(|e| {
+        TantivyError::InvalidArgument(format!("Failed to parse '{}': {}", path.display(), e))
+    })
+}
+
+fn append<W: io::Write>(
+    builder: &mut tar::Builder<W>,
+    path: &Path,
+    contents: &[u8],
+) -> io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, path, contents)
+}
+
+impl CodeSearchEngine {
+    /// Write the index, snippet lines and checksums to a tarball at `out`.
+    pub fn export(&self, out: &Path) -> TantivyResult<()> {
+        let mut builder = tar::Builder::new(fs::File::create(out)?);
+        // The manifest goes first so `import` can validate before unpacking.
+        append(
+            &mut builder,
+            Path::new(MANIFEST_FILE),
+            &to_json(&Manifest::current(&self.config.schema)?)?,
+        )?;
+
+        {
+            let index = read_lock(&self.index);
+            let directory = index.directory();
+            let mut files = directory.list_managed_files();
+            files.extend(INDEX_META_FILES.iter().map(PathBuf::from));
+            for file in files {
+                let contents = directory.atomic_read(&file).map_err(io::Error::other)?;
+                append(&mut builder, &Path::new(INDEX_DIR).join(&file), &contents)?;
+            }
+        }
+
+        {
+            // Evicted lines are read back from the index, without caching them.
+            let evicted = self.evicted_files();
+            let searcher = self.searcher()?;
+            let lines_map = read_lock(&self.lines_map);
+            let mut lines: HashMap<&String, Cow<'_, [String]>> = HashMap::new();
+            for (path, cached) in lines_map.iter() {
+                let cached = if evicted.contains(path) && cached.is_empty() {
+                    Cow::Owned(
+                        self.config
+                            .cached_lines(self.indexed_lines(&searcher, path)?),
+                    )
+                } else {
+                    Cow::Borrowed(cached.as_slice())
+                };
+                lines.insert(path, cached);
+            }
+            append(&mut builder, Path::new(LINES_FILE), &to_json(&lines)?)?;
+        }
+        append(
+            &mut builder,
+            Path::new(HASHES_FILE),
+            &to_json(&*read_lock(&self.file_hashes))?,
+        )?;
+        builder.into_inner()?;
+        Ok(())
+    }
+
+    /// Unpack a tarball written by [`CodeSearchEngine::export`] into `target`,
+    /// refusing snapshots this build can't load.
+    pub fn import(archive: &Path, target: &Path) -> TantivyResult<()> {
+        let mut archive = tar::Archive::new(fs::File::open(archive)?);
+        let mut entries = archive.entries()?;
+
+        let mut manifest_entry = entries.next().transpose()?.ok_or_else(|| {
+            TantivyError::InvalidArgument("Snapshot archive is empty".to_string())
+        })?;
+        if manifest_entry.path()?.as_ref() != Path::new(MANIFEST_FILE) {
+            return Err(TantivyError::InvalidArgument(
+                "Snapshot archive does not start with a manifest".to_string(),
+            ));
+        }
+        let mut contents = Vec::new();
+        manifest_entry.read_to_end(&mut contents)?;
+        let manifest: Manifest = serde_json::from_slice(&contents)
+            .map_err(|e| TantivyError::InvalidArgument(format!("Invalid manifest: {}", e)))?;
+        manifest.validate(None)?;
+
+        fs::create_dir_all(target)?;
+        fs::write(target.join(MANIFEST_FILE), contents)?;
+        for entry in entries {
+            // `unpack_in` refuses paths escaping `target`.
+            entry?.unpack_in(target)?;
+        }
+        Ok(())
+    }
+
+    /// Load an engine from a snapshot directory created by
+    /// [`CodeSearchEngine::import`]. Later reloads write to that directory.
+    pub fn open_snapshot(snapshot: &Path, config: EngineConfig) -> TantivyResult<Self> {
+        let manifest: Manifest = read_json(&snapshot.join(MANIFEST_FILE))?;
+        manifest.validate(Some(&config.schema))?;
+
+        let index = Index::open_in_dir(snapshot.join(INDEX_DIR))?;
+        tokenizer::register(&index, &config);
+        let (_, fields) = build_schema(&config.schema);
+        let lines_map: HashMap<String, Vec<String>> = read_json(&snapshot.join(LINES_FILE))?;
+        let file_hashes: HashMap<String, String> = read_json(&snapshot.join(HASHES_FILE))?;
+        let engine = Self::from_parts(index, fields, lines_map, file_hashes, config);
+        engine.limit_line_cache(None);
+        Ok(engine)
+    }
+}
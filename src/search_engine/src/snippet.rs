@@ -0,0 +1,352 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Choosing which lines around a match make up its snippet.
+
+use std::borrow::Cow;
+use std::ops::Range;
+
+use serde::ser::SerializeMap;
+use serde::Deserialize;
+
+/// How a snippet is laid out in a search result.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SnippetFormat {
+    /// All lines joined into `body` with the configured line separator,
+    /// `\n` unless set otherwise.
+    #[default]
+    Joined,
+    /// The lines before the match, the matched line and the lines after it,
+    /// so clients needn't split `body` themselves.
+    Split,
+}
+
+/// The text of a search result, laid out according to its [`SnippetFormat`].
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Snippet {
+    Joined {
+        body: String,
+    },
+    Split {
+        before: Vec<String>,
+        matched_line: String,
+        after: Vec<String>,
+    },
+}
+
+impl Snippet {
+    /// Lay out `lines`, of which `lines[matched]` is the matched line,
+    /// joining them with `separator` when `format` is joined.
+    pub(crate) fn new(
+        lines: &[&str],
+        matched: usize,
+        format: SnippetFormat,
+        separator: &str,
+    ) -> Self {
+        match format {
+            SnippetFormat::Joined => Snippet::Joined {
+                body: lines.join(separator),
+            },
+            SnippetFormat::Split => {
+                let to_strings =
+                    |lines: &[&str]| lines.iter().map(|line| line.to_string()).collect();
+                Snippet::Split {
+                    before: to_strings(&lines[..matched]),
+                    matched_line: lines[matched].to_string(),
+                    after: to_strings(&lines[matched + 1..]),
+                }
+            }
+        }
+    }
+
+    /// Write the snippet into the result being serialized, next to its
+    /// other fields.
+    pub(crate) fn serialize_fields<M: SerializeMap>(&self, map: &mut M) -> Result<(), M::Error> {
+        match self {
+            Snippet::Joined { body } => map.serialize_entry("body", body),
+            Snippet::Split {
+                before,
+                matched_line,
+                after,
+            } => {
+                map.serialize_entry("before", before)?;
+                map.serialize_entry("matched_line", matched_line)?;
+                map.serialize_entry("after", after)
+            }
+        }
+    }
+}
+
+/// `line` without control characters other than tab. Lines are sanitized
+/// before being laid out, so a separator such as `\r\n` survives.
+pub(crate) fn sanitized(line: &str) -> Cow<'_, str> {
+    if line.chars().any(is_stripped) {
+        Cow::Owned(line.chars().filter(|&c| !is_stripped(c)).collect())
+    } else {
+        Cow::Borrowed(line)
+    }
+}
+
+/// Marks where a truncated line was cut.
+const ELLIPSIS: char = '…';
+
+/// `line` cut down to `width` characters, counting the `…` marking each
+/// cut, keeping the window centered on the byte range `focus` where the
+/// line allows, or its start without one.
+pub(crate) fn truncated(line: &str, width: usize, focus: Option<Range<usize>>) -> Cow<'_, str> {
+    let length = line.chars().count();
+    if length <= width {
+        return Cow::Borrowed(line);
+    }
+    let center = focus.map_or(0, |focus| {
+        let chars = |end: usize| line[..end].chars().count();
+        (chars(focus.start) + chars(focus.end)) / 2
+    });
+    let start = center.saturating_sub(width / 2).min(length - width);
+    let end = start + width;
+    // The markers take the place of the characters at the edges.
+    let kept_start = start + usize::from(start > 0);
+    let kept_end = end - usize::from(end < length);
+    let mut text = String::with_capacity(width + 2 * ELLIPSIS.len_utf8());
+    if start > 0 {
+        text.push(ELLIPSIS);
+    }
+    text.extend(line.chars().skip(kept_start).take(kept_end - kept_start));
+    if end < length {
+        text.push(ELLIPSIS);
+    }
+    Cow::Owned(text)
+}
+
+fn is_stripped(c: char) -> bool {
+    c.is_control() && c != '\t'
+}
+
+/// Columns a tab counts for when comparing indentation.
+const TAB_WIDTH: usize = 4;
+
+/// Prefixes of lines that close a block at the indentation of its header.
+const BLOCK_CLOSERS: [&str; 5] = ["}", ")", "]", "end", "fi"];
+
+pub(crate) fn indentation(line: &str) -> usize {
+    line.chars()
+        .take_while(|c| c.is_whitespace())
+        .map(|c| if c == '\t' { TAB_WIDTH } else { 1 })
+        .sum()
+}
+
+pub(crate) fn is_blank(line: &str) -> bool {
+    line.trim().is_empty()
+}
+
+/// Whether `line` starts with a closer as a whole token, so `end` matches
+/// but `endpoint` doesn't.
+fn closes_block(line: &str) -> bool {
+    let trimmed = line.trim_start();
+    BLOCK_CLOSERS.iter().any(|closer| {
+        trimmed
+            .strip_prefix(closer)
+            .is_some_and(|rest| !rest.starts_with(|c: char| c.is_alphanumeric() || c == '_'))
+    })
+}
+
+/// Zero-based `(first, last)` lines of the indentation block enclosing the
+/// line at `index`, capped at `max_lines` centered on the match.
+///
+/// A line followed by more indented lines is a block header and its block is
+/// itself plus its body; any other line belongs to the block of the nearest
+/// less indented line above it. A closing `}` (or similar) at the header's
+/// indentation is included. Lines at the top level with no body fall back to
+/// just the matched line.
+pub(crate) fn block_bounds(lines: &[String], index: usize, max_lines: usize) -> (usize, usize) {
+    let next_non_blank = lines[index + 1..]
+        .iter()
+        .find(|line| !is_blank(line))
+        .map(|line| indentation(line));
+    let own_indent = indentation(&lines[index]);
+
+    let header = if next_non_blank.is_some_and(|next| next > own_indent) {
+        Some(index)
+    } else {
+        (0..index)
+            .rev()
+            .find(|&i| !is_blank(&lines[i]) && indentation(&lines[i]) < own_indent)
+    };
+    let Some(header) = header else {
+        return (index, index);
+    };
+    let header_indent = indentation(&lines[header]);
+
+    let mut last = header;
+    for (i, line) in lines.iter().enumerate().skip(header + 1) {
+        if is_blank(line) {
+            continue;
+        }
+        if indentation(line) > header_indent {
+            last = i;
+            continue;
+        }
+        if indentation(line) == header_indent && closes_block(line) {
+            last = i;
+        }
+        break;
+    }
+
+    let max_lines = max_lines.max(1);
+    if last - header < max_lines {
+        return (header, last);
+    }
+    let first = index
+        .saturating_sub(max_lines / 2)
+        .clamp(header, last + 1 - max_lines);
+    (first, first + max_lines - 1)
+}
+
+/// `lines` with the whitespace prefix common to all non-blank lines
+/// removed, like Python's `textwrap.dedent`. Tabs and spaces are compared
+/// literally, so a tab-indented and a space-indented line share no prefix.
+pub(crate) fn dedent(lines: &[String]) -> Vec<&str> {
+    let mut common: Option<&str> = None;
+    for line in lines.iter().filter(|line| !is_blank(line)) {
+        let indent = &line[..line.len() - line.trim_start().len()];
+        common = Some(match common {
+            None => indent,
+            Some(common) => {
+                let shared = common
+                    .char_indices()
+                    .zip(indent.chars())
+                    .find(|((_, a), b)| a != b)
+                    .map_or(common.len().min(indent.len()), |((i, _), _)| i);
+                &common[..shared]
+            }
+        });
+    }
+    let common = common.unwrap_or_default();
+
+    lines
+        .iter()
+        .map(|line| line.strip_prefix(common).unwrap_or(line.trim_start()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(text: &str) -> Vec<String> {
+        text.lines().map(str::to_string).collect()
+    }
+
+    const RUST: &str = "\
+use std::fs;
+
+fn read(path: &str) -> String {
+    let text = fs::read_to_string(path).unwrap();
+    if text.is_empty() {
+        return String::new();
+    }
+    text
+}
+
+fn main() {}";
+
+    #[test]
+    fn a_line_in_a_body_gets_its_enclosing_block() {
+        let lines = split(RUST);
+        // `let text` is in the body of `fn read`, closing brace included.
+        assert_eq!(block_bounds(&lines, 3, 20), (2, 8));
+        // `return` is in the body of the `if`.
+        assert_eq!(block_bounds(&lines, 5, 20), (4, 6));
+    }
+
+    #[test]
+    fn a_block_header_gets_its_own_body() {
+        let lines = split(RUST);
+        assert_eq!(block_bounds(&lines, 2, 20), (2, 8));
+        assert_eq!(block_bounds(&lines, 4, 20), (4, 6));
+    }
+
+    #[test]
+    fn a_top_level_line_without_a_body_is_alone() {
+        let lines = split(RUST);
+        assert_eq!(block_bounds(&lines, 0, 20), (0, 0));
+        assert_eq!(block_bounds(&lines, 10, 20), (10, 10));
+    }
+
+    #[test]
+    fn blocks_without_closers_end_at_the_dedent() {
+        let lines = split("def f():\n    a = 1\n\n    return a\nprint(f())");
+        assert_eq!(block_bounds(&lines, 1, 20), (0, 3));
+        // `endpoint` doesn't close a block like `end` does.
+        let lines = split("def f\n  x\nendpoint = 1\nend");
+        assert_eq!(block_bounds(&lines, 1, 20), (0, 1));
+    }
+
+    #[test]
+    fn long_blocks_are_capped_around_the_match() {
+        let mut text = "fn long() {\n".to_string();
+        for i in 0..50 {
+            text.push_str(&format!("    let x{i} = {i};\n"));
+        }
+        text.push('}');
+        let lines = split(&text);
+        assert_eq!(block_bounds(&lines, 25, 10), (20, 29));
+        // The cap stays within the block.
+        assert_eq!(block_bounds(&lines, 1, 10), (0, 9));
+        assert_eq!(block_bounds(&lines, 50, 10), (42, 51));
+    }
+
+    #[test]
+    fn dedent_removes_the_common_indentation_only() {
+        let lines = split("        if ready {\n            go();\n        }");
+        assert_eq!(dedent(&lines), ["if ready {", "    go();", "}"]);
+        let lines = split("    a\n\n      b\n  ");
+        assert_eq!(dedent(&lines), ["a", "", "  b", ""]);
+    }
+
+    #[test]
+    fn dedent_compares_tabs_and_spaces_literally() {
+        let lines = split("\t\tx\n\t\t\ty");
+        assert_eq!(dedent(&lines), ["x", "\ty"]);
+        let lines = split("\tx\n    y");
+        assert_eq!(dedent(&lines), ["\tx", "    y"]);
+        let lines = split("\t  x\n\t y");
+        assert_eq!(dedent(&lines), [" x", "y"]);
+    }
+
+    #[test]
+    fn truncation_centers_on_the_focus() {
+        let line = "0123456789needle0123456789";
+        assert_eq!(truncated(line, 10, Some(10..16)), "…9needle0…");
+        // Without a focus the start of the line is kept.
+        assert_eq!(truncated(line, 10, None), "012345678…");
+        assert_eq!(truncated(line, 26, Some(10..16)), line);
+    }
+
+    #[test]
+    fn truncation_near_an_edge_keeps_the_width() {
+        let line = "needle0123456789abcdefghij";
+        assert_eq!(truncated(line, 10, Some(0..6)), "needle012…");
+        let line = "0123456789abcdefghijneedle";
+        assert_eq!(truncated(line, 10, Some(20..26)), "…hijneedle");
+        assert_eq!(truncated(line, 10, Some(20..26)).chars().count(), 10);
+    }
+
+    #[test]
+    fn truncation_counts_characters_not_bytes() {
+        let line = "ééééééééééneedleéééééééééé";
+        assert_eq!(truncated(line, 10, Some(20..26)), "…éneedleé…");
+    }
+}
@@ -0,0 +1,142 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Where the files to index are listed and read from: the working tree, a
+//! git ref or a manifest served over HTTP.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use tantivy::{Result as TantivyResult, TantivyError};
+use tokio::task;
+
+use crate::skipped::{self, SkipReason};
+use crate::{
+    archive, get_file_hashes, git, has_excluded_extension, in_excluded_dir, Documents,
+    EngineConfig, KnownFiles, ScannedFiles,
+};
+
+/// A scan in progress, see `FileSource::scan`.
+pub(crate) type Scan<'a> = Pin<Box<dyn Future<Output = TantivyResult<ScannedFiles>> + Send + 'a>>;
+
+pub(crate) trait FileSource: Send + Sync {
+    /// Checksums of the files to index in `directory`. Files whose stamp
+    /// matches `known` may keep their checksum without being read.
+    fn scan<'a>(
+        &'a self,
+        directory: &'a str,
+        config: &'a EngineConfig,
+        known: &'a KnownFiles,
+    ) -> Scan<'a>;
+
+    /// The documents stored at `path`, before any normalization. A plain
+    /// file yields a single document, an archive yields one per contained
+    /// file.
+    fn read(&self, directory: &str, path: &str, config: &EngineConfig) -> Documents;
+
+    /// `path` below `base`, the scanned directory or the root it was found
+    /// in.
+    fn relative_path<'a>(&self, base: &str, path: &'a str) -> &'a str {
+        path.strip_prefix(base).unwrap_or(path)
+    }
+}
+
+/// Files walked in the working tree, kept by the include and exclude
+/// patterns of the scanned directory or of an additional root.
+pub(crate) struct WorkingTree<'a> {
+    pub(crate) exclude: &'a [String],
+    pub(crate) include: &'a [String],
+}
+
+impl FileSource for WorkingTree<'_> {
+    fn scan<'a>(
+        &'a self,
+        directory: &'a str,
+        config: &'a EngineConfig,
+        known: &'a KnownFiles,
+    ) -> Scan<'a> {
+        Box::pin(get_file_hashes(
+            directory,
+            self.exclude,
+            self.include,
+            config,
+            known,
+        ))
+    }
+
+    fn read(&self, _directory: &str, path: &str, config: &EngineConfig) -> Documents {
+        if config.index_archives && archive::is_archive(path) {
+            let Ok(mut entries) = archive::read_entries(path, self.exclude, config.decoding())
+            else {
+                return vec![(path.to_string(), Err(SkipReason::Unreadable))];
+            };
+            entries.retain(|(entry, _)| {
+                !has_excluded_extension(entry, &config.exclude_extensions)
+                    && !in_excluded_dir(entry, config.excluded_dir_names())
+            });
+            return entries;
+        }
+        vec![(
+            path.to_string(),
+            skipped::read_lines(path, config.decoding()),
+        )]
+    }
+}
+
+/// Files as stored at a git ref of the scanned directory.
+pub(crate) struct GitRef<'a>(pub(crate) &'a str);
+
+impl FileSource for GitRef<'_> {
+    fn scan<'a>(
+        &'a self,
+        directory: &'a str,
+        config: &'a EngineConfig,
+        _known: &'a KnownFiles,
+    ) -> Scan<'a> {
+        let owned = (
+            directory.to_string(),
+            self.0.to_string(),
+            config.exclude_patterns.clone(),
+        );
+        Box::pin(async move {
+            let (directory, git_ref, exclude_patterns) = owned;
+            let hashes = task::spawn_blocking(move || {
+                git::blob_hashes(&directory, &git_ref, &exclude_patterns)
+            })
+            .await
+            .map_err(|e| TantivyError::InternalError(e.to_string()))??;
+            Ok(listed(hashes, config))
+        })
+    }
+
+    fn read(&self, directory: &str, path: &str, config: &EngineConfig) -> Documents {
+        vec![(
+            path.to_string(),
+            git::read_blob(directory, self.0, path, config.decoding()),
+        )]
+    }
+}
+
+/// The files of `hashes` not left out by their extension or directory.
+/// Listed files have no stamps, they are compared by checksum alone.
+pub(crate) fn listed(mut hashes: HashMap<String, String>, config: &EngineConfig) -> ScannedFiles {
+    hashes.retain(|path, _| {
+        !has_excluded_extension(path, &config.exclude_extensions)
+            && !in_excluded_dir(path, config.excluded_dir_names())
+    });
+    ScannedFiles {
+        hashes,
+        ..ScannedFiles::default()
+    }
+}
@@ -0,0 +1,867 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Which files are indexed and how, as set by [`EngineConfig`].
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::*;
+use crate::{Analyzer, SchemaOptions, Source};
+
+#[tokio::test]
+async fn matches_past_the_line_cap_are_returned_without_context() {
+    let content = (1..=10)
+        .map(|line| format!("line {line}"))
+        .chain(["needle here".to_string(), "line 12".to_string()])
+        .collect::<Vec<_>>()
+        .join("\n");
+    let dir = tree(&[("long.txt", &content)]);
+    let config = EngineConfig {
+        max_lines_per_file: Some(5),
+        ..EngineConfig::default()
+    };
+    let engine = engine(&dir, config).await;
+    assert_eq!(engine.line_count(&path_of(&dir, "long.txt")), Some(5));
+
+    let results = search(&engine, "needle", &SearchOptions::default()).await;
+    assert_eq!(hits(&dir, &results), [("long.txt".to_string(), 11)]);
+    let result = &results.results()[0];
+    assert_eq!(
+        result.snippet,
+        Some(Snippet::Joined {
+            body: "needle here".to_string()
+        })
+    );
+    let range = result.line_range.as_ref().unwrap();
+    assert_eq!((range.start, range.end), (11, 11));
+
+    // Matches within the cap keep their context.
+    let results = search(&engine, "line 2", &SearchOptions::default()).await;
+    let within = results.results().iter().find(|r| r.line == 2).unwrap();
+    let range = within.line_range.as_ref().unwrap();
+    assert_eq!((range.start, range.end), (1, 5));
+}
+
+#[tokio::test]
+async fn stop_words_are_neither_indexed_nor_searched() {
+    let dir = tree(&[("stop.txt", "the quick fox\nlet it be")]);
+    let config = EngineConfig {
+        stop_words: vec!["the".to_string(), "let".to_string()],
+        ..EngineConfig::default()
+    };
+    let stopped = engine(&dir, config).await;
+    let options = SearchOptions::default();
+    assert!(search(&stopped, "the", &options).await.results().is_empty());
+    assert!(search(&stopped, "let", &options).await.results().is_empty());
+    let quick = search(&stopped, "quick", &options).await;
+    assert_eq!(hits(&dir, &quick), [("stop.txt".to_string(), 1)]);
+    // Stop words in a query leave the other terms to match.
+    let fox = search(&stopped, "the fox", &options).await;
+    assert_eq!(hits(&dir, &fox), [("stop.txt".to_string(), 1)]);
+
+    let plain = engine(&dir, EngineConfig::default()).await;
+    let the = search(&plain, "the", &options).await;
+    assert_eq!(hits(&dir, &the), [("stop.txt".to_string(), 1)]);
+}
+
+#[tokio::test]
+async fn each_root_applies_its_own_rules() {
+    let main = tree(&[("main.txt", "needle"), ("tests/main.txt", "needle")]);
+    let files = [
+        ("src/lib.txt", "needle"),
+        ("tests/lib.txt", "needle"),
+        ("docs/lib.txt", "needle"),
+    ];
+    let (first, second) = (tree(&files), tree(&files));
+    let root_config = |dir: &TempDir, include: &[&str], exclude: &[&str]| RootConfig {
+        path: root(dir).to_string(),
+        include: include.iter().map(|pattern| pattern.to_string()).collect(),
+        exclude: exclude.iter().map(|pattern| pattern.to_string()).collect(),
+    };
+    let config = EngineConfig {
+        exclude_patterns: vec!["tests".to_string()],
+        roots: vec![
+            root_config(&first, &[], &["tests"]),
+            root_config(&second, &["tests", "docs"], &["docs"]),
+        ],
+        ..EngineConfig::default()
+    };
+    let engine = engine(&main, config).await;
+    // Reloads apply the same rules.
+    write(&first, "tests/new.txt", "needle");
+    write(&second, "docs/new.txt", "needle");
+    engine.reload(root(&main)).await.unwrap();
+
+    let results = search(&engine, "needle", &SearchOptions::default()).await;
+    let mut paths: Vec<&str> = results.results().iter().map(|r| r.path.as_str()).collect();
+    paths.sort_unstable();
+    let mut expected = vec![
+        path_of(&main, "main.txt"),
+        path_of(&first, "src/lib.txt"),
+        path_of(&first, "docs/lib.txt"),
+        path_of(&second, "tests/lib.txt"),
+    ];
+    expected.sort_unstable();
+    assert_eq!(paths, expected);
+}
+
+#[tokio::test]
+async fn identical_files_are_indexed_once_and_found_under_every_path() {
+    let files = [
+        (
+            "vendor/a/util.js",
+            "export const needle = 1;\nexport default needle;",
+        ),
+        (
+            "vendor/b/util.js",
+            "export const needle = 1;\nexport default needle;",
+        ),
+        ("other.js", "const haystack = 2;"),
+    ];
+    let dir = tree(&files);
+    let plain = engine(&dir, EngineConfig::default()).await;
+    assert_eq!(plain.readiness(), Ok(5));
+
+    let config = EngineConfig {
+        dedupe_content: true,
+        ..EngineConfig::default()
+    };
+    let deduped = engine(&dir, config).await;
+    assert_eq!(deduped.readiness(), Ok(3));
+    let results = search(&deduped, "needle", &SearchOptions::default()).await;
+    let mut found = hits(&dir, &results);
+    found.sort();
+    assert_eq!(
+        found,
+        [
+            ("vendor/a/util.js".to_string(), 1),
+            ("vendor/a/util.js".to_string(), 2),
+            ("vendor/b/util.js".to_string(), 1),
+            ("vendor/b/util.js".to_string(), 2),
+        ]
+    );
+
+    // A copy that changes is a file of its own again.
+    write(&dir, "vendor/a/util.js", "export const changed = 1;");
+    deduped.reload(root(&dir)).await.unwrap();
+    let results = search(&deduped, "needle", &SearchOptions::default()).await;
+    let mut found = hits(&dir, &results);
+    found.sort();
+    assert_eq!(
+        found,
+        [
+            ("vendor/b/util.js".to_string(), 1),
+            ("vendor/b/util.js".to_string(), 2),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn files_with_excluded_extensions_never_match() {
+    let dir = tree(&[
+        ("main.rs", "needle"),
+        ("bundle.min.JS", "needle"),
+        ("notes.log", "needle"),
+    ]);
+    let config = EngineConfig {
+        exclude_extensions: vec!["js".to_string(), ".log".to_string()],
+        ..EngineConfig::default()
+    };
+    let engine = engine(&dir, config).await;
+    let results = search(&engine, "needle", &SearchOptions::default()).await;
+    assert_eq!(hits(&dir, &results), [("main.rs".to_string(), 1)]);
+
+    // Nor once added later.
+    write(&dir, "app.js", "needle");
+    engine.reload(root(&dir)).await.unwrap();
+    let results = search(&engine, "needle", &SearchOptions::default()).await;
+    assert_eq!(hits(&dir, &results), [("main.rs".to_string(), 1)]);
+}
+
+#[tokio::test]
+async fn spidermonkeyignore_files_exclude_paths_below_them() {
+    let dir = tree(&[
+        (
+            ".spidermonkeyignore",
+            "# generated code\ngenerated/\n*.snap",
+        ),
+        ("src/main.rs", "needle"),
+        ("src/main.snap", "needle"),
+        ("generated/api.rs", "needle"),
+        ("lib/.spidermonkeyignore", "vendored"),
+        ("lib/vendored/dep.rs", "needle"),
+        ("lib/own.rs", "needle"),
+        ("vendored/top.rs", "needle"),
+    ]);
+    let engine = engine(&dir, EngineConfig::default()).await;
+    let results = search(&engine, "needle", &SearchOptions::default()).await;
+    let mut found: Vec<String> = hits(&dir, &results)
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect();
+    found.sort();
+    assert_eq!(found, ["lib/own.rs", "src/main.rs", "vendored/top.rs"]);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn files_are_read_at_most_max_open_files_at_once() {
+    let open = Arc::new(AtomicUsize::new(0));
+    let peak = Arc::new(AtomicUsize::new(0));
+    let read = {
+        let (open, peak) = (open.clone(), peak.clone());
+        move |file: usize| {
+            let now = open.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(1));
+            open.fetch_sub(1, Ordering::SeqCst);
+            file
+        }
+    };
+    let done = Mutex::new(Vec::new());
+    crate::run_bounded(0..300, 3, read, |file| done.lock().unwrap().push(file)).await;
+
+    let mut done = done.into_inner().unwrap();
+    done.sort_unstable();
+    assert_eq!(done, (0..300).collect::<Vec<_>>());
+    assert!(peak.load(Ordering::SeqCst) <= 3);
+    assert_eq!(open.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn a_tree_larger_than_max_open_files_is_fully_indexed() {
+    let files: Vec<(String, String)> = (0..200)
+        .map(|file| {
+            (
+                format!("dir{}/file{file}.txt", file % 7),
+                format!("needle {file}"),
+            )
+        })
+        .collect();
+    let files: Vec<(&str, &str)> = files
+        .iter()
+        .map(|(path, content)| (path.as_str(), content.as_str()))
+        .collect();
+    let dir = tree(&files);
+    let config = EngineConfig {
+        max_open_files: 2,
+        ..EngineConfig::default()
+    };
+    let engine = engine(&dir, config).await;
+    let options = SearchOptions {
+        limit: Some(1000),
+        ..SearchOptions::default()
+    };
+    assert_eq!(
+        search(&engine, "needle", &options).await.results().len(),
+        200
+    );
+}
+
+#[tokio::test]
+async fn trimmed_lines_match_despite_trailing_whitespace() {
+    let dir = tree(&[("trim.rs", "let total = 1;   \nlet count = 2;\t\n")]);
+    let config = EngineConfig {
+        trim_trailing_whitespace: true,
+        ..EngineConfig::default()
+    };
+    let trimmed = engine(&dir, config).await;
+    let options = SearchOptions::default();
+    let total = search(&trimmed, "total", &options).await;
+    assert_eq!(hits(&dir, &total), [("trim.rs".to_string(), 1)]);
+    // Snippets show the line as it is on disk.
+    let snippet = format!("{:?}", total.results()[0].snippet);
+    assert!(snippet.contains("let total = 1;   "), "{snippet}");
+
+    let fixed = SearchOptions {
+        fixed: true,
+        ..SearchOptions::default()
+    };
+    let count = search(&trimmed, "count = 2;", &fixed).await;
+    assert_eq!(hits(&dir, &count), [("trim.rs".to_string(), 2)]);
+    // The stored text has no trailing whitespace left to match.
+    assert!(search(&trimmed, "2;\t", &fixed).await.results().is_empty());
+    let plain = engine(&dir, EngineConfig::default()).await;
+    let tab = search(&plain, "2;\t", &fixed).await;
+    assert_eq!(hits(&dir, &tab), [("trim.rs".to_string(), 2)]);
+}
+
+#[tokio::test]
+async fn an_index_that_lost_its_documents_is_not_ready() {
+    let dir = tree(&[("a.rs", "fn alpha() {}\n"), ("b.rs", "fn bravo() {}\n")]);
+    let indexed = engine(&dir, EngineConfig::default()).await;
+    assert_eq!(indexed.readiness(), Ok(2));
+
+    {
+        let index = indexed.index.read().unwrap();
+        let mut writer: tantivy::IndexWriter = index.writer(15_000_000).unwrap();
+        writer.delete_all_documents().unwrap();
+        writer.commit().unwrap();
+    }
+    assert_eq!(
+        indexed.readiness(),
+        Err("No documents are indexed although files are cached".to_string())
+    );
+
+    // Nothing to index is no reason to fail.
+    let empty = tree(&[]);
+    assert_eq!(
+        engine(&empty, EngineConfig::default()).await.readiness(),
+        Ok(0)
+    );
+}
+
+#[tokio::test]
+async fn build_directories_are_excluded_by_default() {
+    let dir = tree(&[
+        ("src/main.rs", "fn needle() {}"),
+        ("target/debug/build.rs", "fn needle() {}"),
+        ("node_modules/pkg/index.js", "needle()"),
+    ]);
+    let options = SearchOptions::default();
+    let defaults = engine(&dir, EngineConfig::default()).await;
+    let results = search(&defaults, "needle", &options).await;
+    assert_eq!(hits(&dir, &results), [("src/main.rs".to_string(), 1)]);
+
+    let config = EngineConfig {
+        use_default_excludes: false,
+        ..EngineConfig::default()
+    };
+    let everything = engine(&dir, config).await;
+    let mut results = hits(&dir, &search(&everything, "needle", &options).await);
+    results.sort();
+    assert_eq!(
+        results,
+        [
+            ("node_modules/pkg/index.js".to_string(), 1),
+            ("src/main.rs".to_string(), 1),
+            ("target/debug/build.rs".to_string(), 1),
+        ]
+    );
+}
+
+/// `(path relative to dir, reason)` of every skipped file, by path.
+fn skipped(dir: &TempDir, engine: &CodeSearchEngine) -> Vec<(String, SkipReason)> {
+    let prefix = format!("{}/", root(dir));
+    engine
+        .skipped_files()
+        .into_iter()
+        .map(|file| {
+            (
+                file.path.strip_prefix(&prefix).unwrap().to_string(),
+                file.reason,
+            )
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn skipped_files_are_recorded_with_their_reason() {
+    let dir = tree(&[("text.txt", "plain"), ("broken.zip", "not a zip archive")]);
+    write(&dir, "image.bin", b"\x89PNG\0\0\0\rIHDR");
+    write(&dir, "latin1.txt", b"caf\xe9");
+    let config = EngineConfig {
+        index_archives: true,
+        skip_non_text: true,
+        ..EngineConfig::default()
+    };
+    let engine = engine(&dir, config).await;
+    assert_eq!(
+        skipped(&dir, &engine),
+        [
+            ("broken.zip".to_string(), SkipReason::Unreadable),
+            ("image.bin".to_string(), SkipReason::Binary),
+            ("latin1.txt".to_string(), SkipReason::InvalidEncoding),
+        ]
+    );
+
+    // Fixed and removed files are no longer skipped, new ones are.
+    write(&dir, "latin1.txt", "café");
+    fs::remove_file(dir.path().join("image.bin")).unwrap();
+    write(&dir, "data.bin", b"\0\x01\x02");
+    engine.reload(root(&dir)).await.unwrap();
+    assert_eq!(
+        skipped(&dir, &engine),
+        [
+            ("broken.zip".to_string(), SkipReason::Unreadable),
+            ("data.bin".to_string(), SkipReason::Binary),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn without_skip_non_text_files_are_read_line_by_line() {
+    let dir = tree(&[]);
+    write(&dir, "latin1.txt", b"caf\xe9 needle\r\nsecond needle\n");
+    write(&dir, "nul.txt", b"\0\x01\nthird needle");
+    let engine = engine(&dir, EngineConfig::default()).await;
+    assert!(engine.skipped_files().is_empty());
+    // Lines that aren't UTF-8 are left empty, the others keep their number.
+    let results = search(&engine, "needle", &SearchOptions::default()).await;
+    let mut found = hits(&dir, &results);
+    found.sort();
+    assert_eq!(
+        found,
+        [("latin1.txt".to_string(), 2), ("nul.txt".to_string(), 2)]
+    );
+    assert_eq!(
+        engine.file_lines(&path_of(&dir, "latin1.txt")).unwrap(),
+        ["", "second needle"]
+    );
+}
+
+#[tokio::test]
+async fn files_deleted_between_hashing_and_indexing_are_not_tracked() {
+    let dir = tree(&[("kept.rs", "fn kept() {}"), ("gone.rs", "fn gone() {}")]);
+    let config = EngineConfig::default();
+    let scanned = crate::collect_hashes(root(&dir), &config, &Default::default())
+        .await
+        .unwrap();
+    fs::remove_file(dir.path().join("gone.rs")).unwrap();
+    let started = std::time::Instant::now();
+    let engine = CodeSearchEngine::index_scanned(root(&dir), config, scanned, started).unwrap();
+
+    let gone = path_of(&dir, "gone.rs");
+    assert!(!engine.is_indexed(&gone));
+    assert!(!engine.file_hashes.read().unwrap().contains_key(&gone));
+    assert!(engine.skipped_files().is_empty());
+    assert!(engine.is_indexed(&path_of(&dir, "kept.rs")));
+
+    // Should it come back, the next reload indexes it.
+    write(&dir, "gone.rs", "fn gone() {}");
+    engine.reload(root(&dir)).await.unwrap();
+    let results = search(&engine, "gone", &SearchOptions::default()).await;
+    assert_eq!(hits(&dir, &results), [("gone.rs".to_string(), 1)]);
+}
+
+/// A stand-in for ctags printing the tags of `lib.rs` in the scanned
+/// directory, its last argument.
+const FAKE_CTAGS: &str = r#"dir=$5
+printf '!_TAG_FILE_FORMAT\t2\t/extended format/\n'
+printf 'parse\t%s/lib.rs\t/^fn parse() {$/;"\tkind:function\tline:2\n' "$dir"
+printf 'Parser\t%s/lib.rs\t1;"\ts\n' "$dir"
+printf 'parse\t%s/other.rs\t1;"\tf\n' "$dir"
+printf 'ghost\t%s/gone.rs\t3;"\tf\n' "$dir"
+"#;
+
+#[tokio::test]
+async fn symbols_are_looked_up_from_ctags_output() {
+    let dir = tree(&[
+        ("lib.rs", "struct Parser;\nfn parse() {}"),
+        ("other.rs", "fn parse() {}"),
+    ]);
+    let tools = tree(&[("ctags.sh", FAKE_CTAGS)]);
+    let script = tools.path().join("ctags.sh").to_str().unwrap().to_string();
+    let config = EngineConfig {
+        ctags_command: vec!["sh".to_string(), script],
+        ..EngineConfig::default()
+    };
+    let tagged = engine(&dir, config).await;
+    let found = |name| {
+        tagged
+            .find_symbol(name)
+            .unwrap()
+            .into_iter()
+            .map(|symbol| (symbol.path, symbol.line, symbol.kind))
+            .collect::<Vec<_>>()
+    };
+    let kind = |kind: &str| Some(kind.to_string());
+    assert_eq!(
+        found("parse"),
+        [
+            (path_of(&dir, "lib.rs"), 2, kind("function")),
+            (path_of(&dir, "other.rs"), 1, kind("f")),
+        ]
+    );
+    assert_eq!(found("Parser"), [(path_of(&dir, "lib.rs"), 1, kind("s"))]);
+    // Files that aren't indexed are left out.
+    assert!(found("ghost").is_empty());
+
+    let plain = engine(&dir, EngineConfig::default()).await;
+    assert!(plain.find_symbol("parse").is_err());
+}
+
+/// The files with evicted lines, relative to `dir`, and the cached bytes.
+fn cache_of(dir: &TempDir, engine: &CodeSearchEngine) -> (Vec<String>, usize) {
+    let prefix = format!("{}/", root(dir));
+    let mut evicted: Vec<String> = engine
+        .evicted_files()
+        .iter()
+        .map(|path| path.strip_prefix(&prefix).unwrap().to_string())
+        .collect();
+    evicted.sort();
+    let lines_map = engine.lines_map.read().unwrap();
+    let cached = lines_map.values().flatten().map(String::len).sum();
+    (evicted, cached)
+}
+
+#[tokio::test]
+async fn lines_past_max_cached_bytes_are_evicted_least_recently_used_first() {
+    // 50 bytes of lines each.
+    let line = "0123456789012345678";
+    let content = |word: &str| format!("{word} {line}\n{word} {line}");
+    let dir = tree(&[
+        ("a.txt", &content("alpha")),
+        ("b.txt", &content("bravo")),
+        ("c.txt", &content("charl")),
+    ]);
+    let config = EngineConfig {
+        max_cached_bytes: Some(100),
+        ..EngineConfig::default()
+    };
+    let capped = engine(&dir, config).await;
+    // None was used yet, so the first by path goes.
+    assert_eq!(cache_of(&dir, &capped), (vec!["a.txt".to_string()], 100));
+    assert!(capped.lines_map.read().unwrap()[&path_of(&dir, "a.txt")].is_empty());
+
+    search(&capped, "bravo", &SearchOptions::default()).await;
+    // Pushing a file evicts c.txt, used less recently than b.txt.
+    capped.push_document("d.txt", &content("delta")).unwrap();
+    assert_eq!(
+        cache_of(&dir, &capped),
+        (vec!["a.txt".to_string(), "c.txt".to_string()], 100)
+    );
+    let stats = capped.stats(false).unwrap().line_cache.unwrap();
+    assert_eq!(
+        serde_json::to_value(stats).unwrap(),
+        serde_json::json!({"max_bytes": 100, "cached_bytes": 100, "evicted_files": 2})
+    );
+}
+
+#[tokio::test]
+async fn searches_read_evicted_lines_back_from_the_index() {
+    let dir = tree(&[
+        ("a.txt", "one\nalpha needle\nthree"),
+        ("b.txt", "one\nbravo needle\nthree"),
+    ]);
+    let config = EngineConfig {
+        max_cached_bytes: Some(25),
+        ..EngineConfig::default()
+    };
+    let capped = engine(&dir, config).await;
+    assert_eq!(cache_of(&dir, &capped), (vec!["a.txt".to_string()], 20));
+
+    let options = SearchOptions {
+        context_lines: Some(1),
+        ..SearchOptions::default()
+    };
+    let results = search(&capped, "alpha", &options).await;
+    assert_eq!(
+        results.results()[0].snippet,
+        Some(Snippet::Joined {
+            body: "one\nalpha needle\nthree".to_string()
+        })
+    );
+    // Restoring a.txt evicts b.txt in its place.
+    assert_eq!(cache_of(&dir, &capped), (vec!["b.txt".to_string()], 20));
+    assert_eq!(
+        capped.file_lines(&path_of(&dir, "b.txt")).unwrap(),
+        ["one", "bravo needle", "three"]
+    );
+    assert_eq!(cache_of(&dir, &capped), (vec!["a.txt".to_string()], 20));
+}
+
+#[tokio::test]
+async fn content_type_detection_tells_tricky_files_apart() {
+    let dir = tree(&[("plain.txt", "plain")]);
+    write(&dir, "nul.txt", b"text with a stray \0 byte\nsecond line\n");
+    write(&dir, "doc.pdf", b"%PDF-1.7\n%\xe2\xe3\xcf\xd3\n");
+    write(&dir, "utf16.txt", b"\xff\xfeh\0i\0\n\0");
+
+    let config = EngineConfig {
+        skip_non_text: true,
+        ..EngineConfig::default()
+    };
+    let strict = engine(&dir, config).await;
+    assert_eq!(
+        skipped(&dir, &strict),
+        [
+            // A PDF starting without a NUL is only a file of invalid UTF-8.
+            ("doc.pdf".to_string(), SkipReason::InvalidEncoding),
+            ("nul.txt".to_string(), SkipReason::Binary),
+            ("utf16.txt".to_string(), SkipReason::Binary),
+        ]
+    );
+
+    let config = EngineConfig {
+        detect_content_type: true,
+        ..EngineConfig::default()
+    };
+    let detecting = engine(&dir, config).await;
+    assert_eq!(
+        skipped(&dir, &detecting),
+        [("doc.pdf".to_string(), SkipReason::Binary)]
+    );
+    let results = search(&detecting, "stray", &SearchOptions::default()).await;
+    assert_eq!(hits(&dir, &results), [("nul.txt".to_string(), 1)]);
+    // UTF-16 with a byte order mark is decoded.
+    let results = search(&detecting, "hi", &SearchOptions::default()).await;
+    assert_eq!(hits(&dir, &results), [("utf16.txt".to_string(), 1)]);
+    assert_eq!(
+        detecting.file_lines(&path_of(&dir, "utf16.txt")).unwrap(),
+        ["hi"]
+    );
+}
+
+#[tokio::test]
+async fn indexed_paths_and_their_line_counts_are_known_without_searching() {
+    let dir = tree(&[
+        ("three.txt", "one\ntwo\nthree"),
+        ("copy.txt", "one\ntwo\nthree"),
+        ("long.txt", "1\n2\n3\n4\n5"),
+    ]);
+    write(&dir, "data.bin", b"\0\x01");
+    let config = EngineConfig {
+        dedupe_content: true,
+        skip_non_text: true,
+        max_lines_per_file: Some(4),
+        ..EngineConfig::default()
+    };
+    let engine = engine(&dir, config).await;
+    for (path, lines) in [
+        ("three.txt", Some(3)),
+        // Only one copy is indexed, but both are searchable.
+        ("copy.txt", Some(3)),
+        ("long.txt", Some(4)),
+        ("data.bin", None),
+        ("missing.txt", None),
+    ] {
+        let path = path_of(&dir, path);
+        assert_eq!(engine.is_indexed(&path), lines.is_some(), "{path}");
+        assert_eq!(engine.line_count(&path), lines, "{path}");
+    }
+    // Paths are matched exactly, as returned in results.
+    assert!(!engine.is_indexed("three.txt"));
+
+    fs::remove_file(dir.path().join("three.txt")).unwrap();
+    fs::remove_file(dir.path().join("copy.txt")).unwrap();
+    engine.reload(root(&dir)).await.unwrap();
+    assert!(!engine.is_indexed(&path_of(&dir, "three.txt")));
+    assert_eq!(engine.line_count(&path_of(&dir, "copy.txt")), None);
+}
+
+#[tokio::test]
+async fn an_index_without_stored_bodies_finds_the_same_lines() {
+    let dir = tree(&[("a.txt", "one needle\ntwo"), ("b.txt", "needle three")]);
+    let schema = SchemaOptions {
+        store_body: false,
+        ..SchemaOptions::default()
+    };
+    let config = EngineConfig {
+        schema,
+        ..EngineConfig::default()
+    };
+    let unstored = engine(&dir, config).await;
+    let stored = engine(&dir, EngineConfig::default()).await;
+    let bodies = |results: &SearchResults| {
+        let mut bodies: Vec<_> = results
+            .results()
+            .iter()
+            .map(|result| (result.path.clone(), result.line, result.snippet.clone()))
+            .collect();
+        bodies.sort_by(|a, b| (&a.0, a.1).cmp(&(&b.0, b.1)));
+        bodies
+    };
+    assert_eq!(
+        bodies(&search(&unstored, "needle", &SearchOptions::default()).await),
+        bodies(&search(&stored, "needle", &SearchOptions::default()).await)
+    );
+
+    let fixed = SearchOptions {
+        fixed: true,
+        ..SearchOptions::default()
+    };
+    let error = unstored.search("one needle", &fixed).await.unwrap_err();
+    assert!(error.to_string().contains("schema.store_body"));
+
+    let capped = EngineConfig {
+        schema,
+        max_cached_bytes: Some(1000),
+        ..EngineConfig::default()
+    };
+    let error = schema.validate(&capped).unwrap_err();
+    assert!(error.to_string().contains("max_cached_bytes"));
+    assert!(SchemaOptions::default().validate(&capped).is_ok());
+}
+
+#[tokio::test]
+async fn the_code_path_analyzer_splits_file_names_like_identifiers() {
+    let dir = tree(&[("parseConfigFile.rs", "fn main() {}")]);
+    let options = SearchOptions {
+        search_fields: "path".parse().unwrap(),
+        ..SearchOptions::default()
+    };
+    let config = EngineConfig {
+        schema: SchemaOptions {
+            path_analyzer: Analyzer::Code,
+            ..SchemaOptions::default()
+        },
+        ..EngineConfig::default()
+    };
+    let code = engine(&dir, config).await;
+    let results = search(&code, "config", &options).await;
+    assert_eq!(
+        hits(&dir, &results),
+        [("parseConfigFile.rs".to_string(), 1)]
+    );
+
+    let plain = engine(&dir, EngineConfig::default()).await;
+    assert!(search(&plain, "config", &options)
+        .await
+        .results()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn snapshots_open_only_with_the_schema_they_were_exported_with() {
+    let dir = tree(&[("a.txt", "needle")]);
+    let exported = engine(&dir, EngineConfig::default()).await;
+    let out = tempfile::tempdir().unwrap();
+    let archive = out.path().join("index.tar");
+    exported.export(&archive).unwrap();
+    let snapshot = out.path().join("snapshot");
+    CodeSearchEngine::import(&archive, &snapshot).unwrap();
+
+    let config = EngineConfig {
+        schema: SchemaOptions {
+            path_analyzer: Analyzer::Code,
+            ..SchemaOptions::default()
+        },
+        ..EngineConfig::default()
+    };
+    assert!(CodeSearchEngine::open_snapshot(&snapshot, config).is_err());
+    let opened = CodeSearchEngine::open_snapshot(&snapshot, EngineConfig::default()).unwrap();
+    let results = search(&opened, "needle", &SearchOptions::default()).await;
+    assert_eq!(hits(&dir, &results), [("a.txt".to_string(), 1)]);
+}
+
+/// Serve `responses`, raw HTTP responses by request target, on a local
+/// port until the test ends. Unknown targets get a 404. Returns the address
+/// and the targets requested so far.
+fn serve(responses: Arc<Mutex<HashMap<String, String>>>) -> (String, Arc<Mutex<Vec<String>>>) {
+    let requested = Arc::new(Mutex::new(Vec::new()));
+    let targets = requested.clone();
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut request = Vec::new();
+            let mut byte = [0];
+            while !request.ends_with(b"\r\n\r\n") && stream.read(&mut byte).unwrap() == 1 {
+                request.push(byte[0]);
+            }
+            let request = String::from_utf8_lossy(&request);
+            let target = request.split(' ').nth(1).unwrap_or_default();
+            targets.lock().unwrap().push(target.to_string());
+            let response = responses.lock().unwrap().get(target).cloned();
+            let response = response
+                .unwrap_or_else(|| "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".into());
+            // Each connection serves one request, so clients don't reuse it.
+            let response = response.replacen("\r\n", "\r\nConnection: close\r\n", 1);
+            let _ = stream.write_all(response.as_bytes());
+        }
+    });
+    (format!("http://{address}"), requested)
+}
+
+fn ok(body: &str) -> String {
+    format!(
+        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+#[tokio::test]
+async fn an_http_source_indexes_the_files_its_manifest_lists() {
+    let responses = Arc::new(Mutex::new(HashMap::new()));
+    let (host, requested) = serve(responses.clone());
+    let manifest = "# files\nsrc/main.rs\n/shared/util.rs\n\
+                    chunked.rs\nmoved.rs\nmissing.rs\n.git/config\n";
+    responses.lock().unwrap().extend([
+        ("/repo/manifest.txt".to_string(), ok(manifest)),
+        (
+            "/repo/src/main.rs".to_string(),
+            ok("fn main() { needle(); }"),
+        ),
+        ("/shared/util.rs".to_string(), ok("fn needle() {}")),
+        (
+            "/repo/chunked.rs".to_string(),
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+             7;x=y\r\n// need\r\n3\r\nle\n\r\n0\r\n\r\n"
+                .to_string(),
+        ),
+        (
+            "/repo/moved.rs".to_string(),
+            "HTTP/1.1 302 Found\r\nLocation: /repo/src/new.rs\r\nContent-Length: 0\r\n\r\n"
+                .to_string(),
+        ),
+        ("/repo/src/new.rs".to_string(), ok("\nlet needle = 1;")),
+        ("/repo/.git/config".to_string(), ok("needle")),
+    ]);
+    let dir = tree(&[]);
+    let config = EngineConfig {
+        source: Source::Http {
+            manifest_url: format!("{host}/repo/manifest.txt"),
+        },
+        ..EngineConfig::default()
+    };
+    let engine = engine(&dir, config).await;
+    let found = |results: &SearchResults| {
+        let mut found: Vec<(String, usize)> = results
+            .results()
+            .iter()
+            .map(|result| (result.path.replace(&host, ""), result.line))
+            .collect();
+        found.sort();
+        found
+    };
+    // The missing file and the excluded `.git` are left out.
+    assert_eq!(
+        found(&search(&engine, "needle", &SearchOptions::default()).await),
+        [
+            ("/repo/chunked.rs".to_string(), 1),
+            ("/repo/moved.rs".to_string(), 2),
+            ("/repo/src/main.rs".to_string(), 1),
+            ("/shared/util.rs".to_string(), 1),
+        ]
+    );
+    // What was fetched to take the checksums is indexed without a refetch.
+    let fetches = |target: &str| {
+        let requested = requested.lock().unwrap();
+        requested
+            .iter()
+            .filter(|fetched| *fetched == target)
+            .count()
+    };
+    assert_eq!(fetches("/repo/src/main.rs"), 1);
+
+    // Changed files are fetched again on reload, and unlisted ones dropped.
+    responses.lock().unwrap().extend([
+        ("/repo/manifest.txt".to_string(), ok("src/main.rs")),
+        (
+            "/repo/src/main.rs".to_string(),
+            ok("fn main() {}\n// needle"),
+        ),
+    ]);
+    engine.reload(root(&dir)).await.unwrap();
+    assert_eq!(
+        found(&search(&engine, "needle", &SearchOptions::default()).await),
+        [("/repo/src/main.rs".to_string(), 2)]
+    );
+    assert_eq!(fetches("/repo/src/main.rs"), 2);
+}
@@ -0,0 +1,81 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tests of the engine as a whole, indexing small trees written to a
+//! temporary directory.
+
+mod indexing;
+mod reload;
+mod search;
+
+use std::fs;
+
+use tempfile::TempDir;
+
+use crate::snippet::{Snippet, SnippetFormat};
+use crate::{
+    CodeSearchEngine, EngineConfig, Granularity, QueryMode, RootConfig, SearchOptions,
+    SearchResults, SizeBucket, SkipReason, SortOrder,
+};
+
+/// A temporary directory holding `files`, given as `(path, content)` with
+/// `/` separated paths relative to it.
+pub(crate) fn tree(files: &[(&str, &str)]) -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    for (path, content) in files {
+        write(&dir, path, content);
+    }
+    dir
+}
+
+/// Write `content` to `path` below `dir`, creating its parent directories.
+pub(crate) fn write(dir: &TempDir, path: &str, content: impl AsRef<[u8]>) {
+    let path = dir.path().join(path);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, content).unwrap();
+}
+
+/// The path the engine indexes `path` below `dir` under.
+pub(crate) fn path_of(dir: &TempDir, path: &str) -> String {
+    format!("{}/{path}", root(dir))
+}
+
+pub(crate) fn root(dir: &TempDir) -> &str {
+    dir.path().to_str().unwrap()
+}
+
+pub(crate) async fn engine(dir: &TempDir, config: EngineConfig) -> CodeSearchEngine {
+    CodeSearchEngine::new(root(dir), config).await.unwrap()
+}
+
+pub(crate) async fn search(
+    engine: &CodeSearchEngine,
+    query: &str,
+    options: &SearchOptions,
+) -> SearchResults {
+    engine.search(query, options).await.unwrap()
+}
+
+/// `(path relative to dir, line)` of every result, in order.
+pub(crate) fn hits(dir: &TempDir, results: &SearchResults) -> Vec<(String, usize)> {
+    let prefix = format!("{}/", root(dir));
+    results
+        .results()
+        .iter()
+        .map(|result| {
+            let path = result.path.strip_prefix(&prefix).unwrap_or(&result.path);
+            (path.to_string(), result.line)
+        })
+        .collect()
+}
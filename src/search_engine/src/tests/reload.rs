@@ -0,0 +1,244 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Keeping the index up to date as the tree changes: reloads, compaction
+//! and rebuilds.
+
+use super::*;
+
+/// Change `path` below `dir` so a reload picks it up: the size changes
+/// with each `version`, should the modification time not.
+pub(crate) fn change(dir: &TempDir, path: &str, version: usize) {
+    write(dir, path, format!("version{}", "!".repeat(version)));
+}
+
+#[tokio::test]
+async fn compaction_merges_the_segments_left_by_reloads() {
+    let dir = tree(&[("kept.txt", "stable"), ("changed.txt", "version")]);
+    let engine = engine(&dir, EngineConfig::default()).await;
+    for version in 1..=5 {
+        change(&dir, "changed.txt", version);
+        engine.reload(root(&dir)).await.unwrap();
+    }
+    let before = engine.segment_count().unwrap();
+    assert!(before > 1, "reloads left a single segment");
+
+    let compaction = engine.compact().unwrap();
+    assert_eq!(compaction.before.segments, before);
+    assert!(compaction.before.deleted_documents > 0);
+    assert_eq!(compaction.after.segments, 1);
+    assert_eq!(compaction.after.deleted_documents, 0);
+    assert_eq!(compaction.after.documents, 2);
+    assert_eq!(engine.segment_count().unwrap(), 1);
+
+    let options = SearchOptions::default();
+    let stable = search(&engine, "stable", &options).await;
+    assert_eq!(hits(&dir, &stable), [("kept.txt".to_string(), 1)]);
+    let version = search(&engine, "version", &options).await;
+    assert_eq!(hits(&dir, &version), [("changed.txt".to_string(), 1)]);
+}
+
+#[tokio::test]
+async fn a_file_turned_directory_is_replaced_by_its_files() {
+    let dir = tree(&[("config", "old needle")]);
+    let engine = engine(&dir, EngineConfig::default()).await;
+    fs::remove_file(dir.path().join("config")).unwrap();
+    write(&dir, "config/app.txt", "new needle");
+    engine.reload(root(&dir)).await.unwrap();
+    let results = search(&engine, "needle", &SearchOptions::default()).await;
+    assert_eq!(hits(&dir, &results), [("config/app.txt".to_string(), 1)]);
+    assert!(search(&engine, "old", &SearchOptions::default())
+        .await
+        .results()
+        .is_empty());
+
+    // And back again.
+    fs::remove_dir_all(dir.path().join("config")).unwrap();
+    write(&dir, "config", "needle once more");
+    engine.reload(root(&dir)).await.unwrap();
+    let results = search(&engine, "needle", &SearchOptions::default()).await;
+    assert_eq!(hits(&dir, &results), [("config".to_string(), 1)]);
+}
+
+#[tokio::test]
+async fn changed_only_searches_the_files_the_last_reload_changed() {
+    let dir = tree(&[("kept.rs", "// TODO: old"), ("edited.rs", "fn main() {}")]);
+    let engine = engine(&dir, EngineConfig::default()).await;
+    let changed_only = SearchOptions {
+        changed_only: true,
+        ..SearchOptions::default()
+    };
+    // Nothing has been reloaded yet.
+    assert!(search(&engine, "todo", &changed_only)
+        .await
+        .results()
+        .is_empty());
+
+    write(&dir, "edited.rs", "fn main() {}\n// TODO: new");
+    engine.reload(root(&dir)).await.unwrap();
+    let todos = search(&engine, "todo", &changed_only).await;
+    assert_eq!(hits(&dir, &todos), [("edited.rs".to_string(), 2)]);
+    let all = search(&engine, "todo", &SearchOptions::default()).await;
+    assert_eq!(all.results().len(), 2);
+
+    // A reload without changes keeps the last set.
+    engine.reload(root(&dir)).await.unwrap();
+    let todos = search(&engine, "todo", &changed_only).await;
+    assert_eq!(hits(&dir, &todos), [("edited.rs".to_string(), 2)]);
+
+    // A reindex starts over, nothing has changed since.
+    engine.reindex(root(&dir)).await.unwrap();
+    assert!(search(&engine, "todo", &changed_only)
+        .await
+        .results()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn files_with_an_unchanged_stamp_are_not_hashed_again() {
+    let dir = tree(&[("untouched.txt", "same"), ("touched.txt", "before")]);
+    let untouched = path_of(&dir, "untouched.txt");
+    let touched = path_of(&dir, "touched.txt");
+    // Checksums no file has: one coming back means the file wasn't read.
+    let known: crate::KnownFiles = [&untouched, &touched]
+        .into_iter()
+        .map(|path| {
+            let stamp = crate::FileStamp::of(path).unwrap();
+            (path.clone(), (stamp, format!("remembered {path}")))
+        })
+        .collect();
+    change(&dir, "touched.txt", 1);
+
+    let config = EngineConfig::default();
+    let scanned = crate::get_file_hashes(root(&dir), &[], &[], &config, &known)
+        .await
+        .unwrap();
+    assert_eq!(
+        scanned.hashes[&untouched],
+        format!("remembered {untouched}")
+    );
+    assert_eq!(
+        scanned.hashes[&touched],
+        crate::calculate_checksum(&touched).unwrap()
+    );
+}
+
+#[tokio::test]
+async fn followed_files_only_index_their_appended_lines() {
+    let dir = tree(&[("app.log", "started\nserving\n"), ("notes.txt", "one\n")]);
+    let config = EngineConfig {
+        follow_patterns: vec![".log".to_string()],
+        ..EngineConfig::default()
+    };
+    let engine = engine(&dir, config).await;
+    let log = path_of(&dir, "app.log");
+    let mut file = fs::OpenOptions::new().append(true).open(&log).unwrap();
+    std::io::Write::write_all(&mut file, b"stopped\ncrashed\n").unwrap();
+    engine.reload(root(&dir)).await.unwrap();
+
+    // The old lines' documents were kept rather than deleted and added again.
+    let stats = engine.compact().unwrap().before;
+    assert_eq!((stats.documents, stats.deleted_documents), (5, 0));
+    assert_eq!(engine.file_lines(&log).unwrap().len(), 4);
+    let options = SearchOptions::default();
+    let crashed = search(&engine, "crashed", &options).await;
+    assert_eq!(hits(&dir, &crashed), [("app.log".to_string(), 4)]);
+    let started = search(&engine, "started", &options).await;
+    assert_eq!(hits(&dir, &started), [("app.log".to_string(), 1)]);
+
+    // A rewritten line reindexes the whole file.
+    write(
+        &dir,
+        "app.log",
+        "restarted\nserving\nstopped\ncrashed\nagain\n",
+    );
+    engine.reload(root(&dir)).await.unwrap();
+    let stats = engine.compact().unwrap().before;
+    assert_eq!((stats.documents, stats.deleted_documents), (6, 4));
+    let started = search(&engine, "started", &options).await;
+    assert!(started.results().is_empty());
+}
+
+#[tokio::test]
+async fn reindex_drops_stale_and_duplicate_documents() {
+    let dir = tree(&[("a.rs", "fn alpha() {}"), ("b.rs", "fn bravo() {}")]);
+    let engine = engine(&dir, EngineConfig::default()).await;
+    // Drift: a second document for a.rs, a file deleted without a reload
+    // and a pushed document.
+    {
+        let index = engine.index.read().unwrap();
+        let mut writer: tantivy::IndexWriter = index.writer(15_000_000).unwrap();
+        let fields = &engine.fields;
+        let mut document = tantivy::TantivyDocument::default();
+        document.add_text(fields.path, path_of(&dir, "a.rs"));
+        document.add_text(fields.path_key, path_of(&dir, "a.rs"));
+        document.add_i64(fields.line, 1);
+        document.add_text(fields.body, "fn alpha() {}");
+        writer.add_document(document).unwrap();
+        writer.commit().unwrap();
+    }
+    fs::remove_file(dir.path().join("b.rs")).unwrap();
+    engine.push_document("pushed.rs", "fn alpha() {}").unwrap();
+    let options = SearchOptions::default();
+    assert_eq!(search(&engine, "alpha", &options).await.results().len(), 3);
+    assert_eq!(search(&engine, "bravo", &options).await.results().len(), 1);
+
+    let reindex = engine.reindex(root(&dir)).await.unwrap();
+    assert_eq!(reindex.files, 1);
+    let alpha = search(&engine, "alpha", &options).await;
+    assert_eq!(hits(&dir, &alpha), [("a.rs".to_string(), 1)]);
+    assert!(search(&engine, "bravo", &options)
+        .await
+        .results()
+        .is_empty());
+    assert_eq!(engine.readiness(), Ok(1));
+}
+
+#[tokio::test]
+async fn a_file_skipped_on_reload_drops_its_previous_lines() {
+    let dir = tree(&[("a.txt", "needle"), ("b.txt", "needle")]);
+    let config = EngineConfig {
+        dedupe_content: true,
+        skip_non_text: true,
+        ..EngineConfig::default()
+    };
+    let engine = engine(&dir, config).await;
+    let a = path_of(&dir, "a.txt");
+    let b = path_of(&dir, "b.txt");
+
+    write(&dir, "a.txt", b"needle\0");
+    write(&dir, "b.txt", b"needle\0");
+    engine.reload(root(&dir)).await.unwrap();
+    let results = search(&engine, "needle", &SearchOptions::default()).await;
+    assert!(results.results().is_empty());
+    for path in [&a, &b] {
+        assert!(!engine.is_indexed(path), "{path}");
+        assert_eq!(engine.file_lines(path), None, "{path}");
+        assert!(!engine.file_hashes.read().unwrap().contains_key(path));
+    }
+    let skipped: Vec<String> = engine
+        .skipped_files()
+        .into_iter()
+        .map(|file| file.path)
+        .collect();
+    assert_eq!(skipped, [a.clone(), b.clone()]);
+
+    // Untracked, they are read again once fixed, or forgotten once removed.
+    write(&dir, "a.txt", "needle");
+    fs::remove_file(dir.path().join("b.txt")).unwrap();
+    engine.reload(root(&dir)).await.unwrap();
+    let results = search(&engine, "needle", &SearchOptions::default()).await;
+    assert_eq!(hits(&dir, &results), [("a.txt".to_string(), 1)]);
+    assert!(engine.skipped_files().is_empty());
+}
@@ -0,0 +1,1431 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Query options of [`CodeSearchEngine::search`].
+
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime};
+
+use serde_json::{json, Value};
+
+use crate::{Analyzer, FacetField, SchemaOptions};
+
+use super::*;
+
+/// A file with a line matching `alpha` strongly and lines matching it
+/// weakly, among many words.
+fn scored_tree() -> TempDir {
+    tree(&[(
+        "scores.txt",
+        "alpha alpha alpha\n\
+         alpha beta gamma delta epsilon zeta eta theta iota kappa\n\
+         alpha lambda mu nu xi omicron pi rho sigma tau upsilon",
+    )])
+}
+
+fn scores(results: &SearchResults) -> Vec<f32> {
+    results
+        .results()
+        .iter()
+        .map(|result| result.score.unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn min_score_drops_weaker_matches() {
+    let dir = scored_tree();
+    let engine = engine(&dir, EngineConfig::default()).await;
+    let all = scores(&search(&engine, "alpha", &SearchOptions::default()).await);
+    assert_eq!(all.len(), 3);
+    assert!(all[0] > all[2]);
+
+    let options = SearchOptions {
+        min_score: Some(all[0]),
+        ..SearchOptions::default()
+    };
+    let results = search(&engine, "alpha", &options).await;
+    assert_eq!(hits(&dir, &results), [("scores.txt".to_string(), 1)]);
+
+    let options = SearchOptions {
+        min_score: Some(all[0] * 2.0),
+        ..SearchOptions::default()
+    };
+    assert!(search(&engine, "alpha", &options)
+        .await
+        .results()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn top_percent_keeps_results_near_the_best() {
+    let dir = scored_tree();
+    let engine = engine(&dir, EngineConfig::default()).await;
+    let options = |top_percent| SearchOptions {
+        top_percent: Some(top_percent),
+        ..SearchOptions::default()
+    };
+    let best_only = search(&engine, "alpha", &options(0.0)).await;
+    assert_eq!(hits(&dir, &best_only), [("scores.txt".to_string(), 1)]);
+    let everything = search(&engine, "alpha", &options(100.0)).await;
+    assert_eq!(everything.results().len(), 3);
+}
+
+#[test]
+fn score_cutoff_combines_both_filters() {
+    let options = SearchOptions {
+        min_score: Some(2.0),
+        top_percent: Some(20.0),
+        ..SearchOptions::default()
+    };
+    assert_eq!(options.score_cutoff(10.0), Some(8.0));
+    assert_eq!(options.score_cutoff(2.0), Some(2.0));
+    assert_eq!(SearchOptions::default().score_cutoff(10.0), None);
+    // Percentages out of range are clamped.
+    let options = SearchOptions {
+        top_percent: Some(150.0),
+        ..SearchOptions::default()
+    };
+    assert_eq!(options.score_cutoff(10.0), Some(0.0));
+}
+
+#[tokio::test]
+async fn searches_work_after_a_panic_poisoned_the_locks() {
+    let dir = tree(&[("main.rs", "fn main() {}\n")]);
+    let engine = engine(&dir, EngineConfig::default()).await;
+    std::thread::scope(|scope| {
+        let poisoned = scope.spawn(|| {
+            let _index = engine.index.write().unwrap();
+            let _lines = engine.lines_map.write().unwrap();
+            let _hashes = engine.file_hashes.write().unwrap();
+            panic!("poison the locks");
+        });
+        assert!(poisoned.join().is_err());
+    });
+    assert!(engine.index.is_poisoned());
+    assert!(engine.lines_map.is_poisoned());
+
+    let results = search(&engine, "main", &SearchOptions::default()).await;
+    assert_eq!(hits(&dir, &results), [("main.rs".to_string(), 1)]);
+    engine.reload(root(&dir)).await.unwrap();
+    assert!(engine.stats(false).is_ok());
+}
+
+/// A file with `needle` on lines 1, 10 and 60.
+fn needle_tree() -> TempDir {
+    let lines: Vec<String> = (1..=60)
+        .map(|line| match line {
+            1 | 10 | 60 => format!("needle on line {line}"),
+            _ => format!("filler {line}"),
+        })
+        .collect();
+    tree(&[("needles.txt", &lines.join("\n"))])
+}
+
+fn lines(results: &SearchResults) -> Vec<usize> {
+    let mut lines: Vec<usize> = results.results().iter().map(|result| result.line).collect();
+    lines.sort_unstable();
+    lines
+}
+
+#[tokio::test]
+async fn line_range_keeps_matches_within_it() {
+    let dir = needle_tree();
+    let engine = engine(&dir, EngineConfig::default()).await;
+    let within = |first, last| SearchOptions {
+        line_range: Some((first, last)),
+        ..SearchOptions::default()
+    };
+    let all = search(&engine, "needle", &SearchOptions::default()).await;
+    assert_eq!(lines(&all), [1, 10, 60]);
+    let top = search(&engine, "needle", &within(1, 50)).await;
+    assert_eq!(lines(&top), [1, 10]);
+    let middle = search(&engine, "needle", &within(10, 10)).await;
+    assert_eq!(lines(&middle), [10]);
+    let none = search(&engine, "needle", &within(11, 59)).await;
+    assert!(none.results().is_empty());
+}
+
+#[tokio::test]
+async fn matches_in_files_no_longer_cached_are_counted_as_dropped() {
+    let dir = tree(&[("kept.txt", "needle"), ("gone.txt", "needle")]);
+    let engine = engine(&dir, EngineConfig::default()).await;
+    engine
+        .lines_map
+        .write()
+        .unwrap()
+        .remove(&path_of(&dir, "gone.txt"));
+
+    let results = search(&engine, "needle", &SearchOptions::default()).await;
+    assert_eq!(hits(&dir, &results), [("kept.txt".to_string(), 1)]);
+    assert_eq!(results.dropped, 1);
+}
+
+#[tokio::test]
+async fn smart_context_shows_the_enclosing_block() {
+    let dir = tree(&[(
+        "lib.rs",
+        "use std::fs;\n\
+         \n\
+         fn read(path: &str) -> String {\n\
+         \x20   let text = fs::read_to_string(path).unwrap();\n\
+         \x20   text\n\
+         }\n\
+         \n\
+         fn main() {}",
+    )]);
+    let engine = engine(&dir, EngineConfig::default()).await;
+    let range = |results: SearchResults| {
+        let range = results.results()[0].line_range.clone().unwrap();
+        (range.start, range.end)
+    };
+    let fixed = search(&engine, "read_to_string", &SearchOptions::default()).await;
+    assert_eq!(range(fixed), (1, 7));
+    let options = SearchOptions {
+        smart_context: true,
+        ..SearchOptions::default()
+    };
+    assert_eq!(
+        range(search(&engine, "read_to_string", &options).await),
+        (3, 6)
+    );
+}
+
+#[tokio::test]
+async fn wildcards_match_terms_by_pattern() {
+    let dir = tree(&[(
+        "words.txt",
+        "configuration\nreconfigure\nsettings\nconfig\nsetting",
+    )]);
+    let engine = engine(&dir, EngineConfig::default()).await;
+    let wildcard = SearchOptions {
+        wildcard: true,
+        ..SearchOptions::default()
+    };
+    assert_eq!(
+        lines(&search(&engine, "*config*", &wildcard).await),
+        [1, 2, 4]
+    );
+    assert_eq!(lines(&search(&engine, "config*", &wildcard).await), [1, 4]);
+    assert_eq!(lines(&search(&engine, "?etting", &wildcard).await), [5]);
+    assert_eq!(lines(&search(&engine, "Setting?", &wildcard).await), [3]);
+    assert!(engine.search("*", &wildcard).await.is_err());
+}
+
+#[tokio::test]
+async fn dedent_keeps_the_line_numbers() {
+    let dir = tree(&[(
+        "deep.rs",
+        "mod a {\n    mod b {\n        fn c() {\n            needle();\n        }\n    }\n}",
+    )]);
+    let engine = engine(&dir, EngineConfig::default()).await;
+    let options = SearchOptions {
+        context_lines: Some(1),
+        dedent: true,
+        ..SearchOptions::default()
+    };
+    let results = search(&engine, "needle", &options).await;
+    let result = &results.results()[0];
+    assert_eq!(
+        result.snippet,
+        Some(Snippet::Joined {
+            body: "fn c() {\n    needle();\n}".to_string()
+        })
+    );
+    let range = result.line_range.clone().unwrap();
+    assert_eq!((result.line, range.start, range.end), (4, 3, 5));
+}
+
+#[tokio::test]
+async fn field_scopes_only_apply_in_advanced_mode() {
+    let dir = tree(&[
+        ("notes.txt", "path:src is a scope\nuse std:io here"),
+        ("src/main.rs", "fn main() {}"),
+    ]);
+    let engine = engine(&dir, EngineConfig::default()).await;
+    let simple = SearchOptions::default();
+    let advanced = SearchOptions {
+        mode: QueryMode::Advanced,
+        ..SearchOptions::default()
+    };
+
+    let text = search(&engine, "path:src", &simple).await;
+    assert_eq!(hits(&dir, &text), [("notes.txt".to_string(), 1)]);
+    let text = search(&engine, "std:io", &simple).await;
+    assert_eq!(hits(&dir, &text), [("notes.txt".to_string(), 2)]);
+    // An escaped colon stays escaped.
+    let text = search(&engine, r"std\:io", &simple).await;
+    assert_eq!(hits(&dir, &text), [("notes.txt".to_string(), 2)]);
+
+    let scoped = search(&engine, "path:src", &advanced).await;
+    assert_eq!(hits(&dir, &scoped), [("src/main.rs".to_string(), 1)]);
+    // `std` isn't a field.
+    assert!(engine.search("std:io", &advanced).await.is_err());
+}
+
+#[tokio::test]
+async fn split_snippets_stop_at_the_file_boundaries() {
+    let dir = tree(&[("edges.txt", "first needle\ntwo\nthree\nfour\nlast needle")]);
+    let engine = engine(&dir, EngineConfig::default()).await;
+    let options = SearchOptions {
+        context_lines: Some(2),
+        snippet_format: SnippetFormat::Split,
+        ..SearchOptions::default()
+    };
+    let results = search(&engine, "needle", &options).await;
+    let mut snippets: Vec<_> = results
+        .results()
+        .iter()
+        .map(|result| (result.line, result.snippet.clone().unwrap()))
+        .collect();
+    snippets.sort_by_key(|(line, _)| *line);
+    let split = |before: &[&str], matched_line: &str, after: &[&str]| Snippet::Split {
+        before: before.iter().map(|line| line.to_string()).collect(),
+        matched_line: matched_line.to_string(),
+        after: after.iter().map(|line| line.to_string()).collect(),
+    };
+    assert_eq!(
+        snippets,
+        [
+            (1, split(&[], "first needle", &["two", "three"])),
+            (5, split(&["three", "four"], "last needle", &[])),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn serialized_results_read_back_as_they_were() {
+    let dir = tree(&[
+        ("main.rs", "fn main() {\n    needle();\n}"),
+        ("notes", "needle"),
+    ]);
+    let engine = engine(&dir, EngineConfig::default()).await;
+    for options in [
+        SearchOptions::default(),
+        SearchOptions {
+            snippet_format: SnippetFormat::Split,
+            fields: "path,line,body".parse().unwrap(),
+            byte_offset: true,
+            ..SearchOptions::default()
+        },
+    ] {
+        let results = search(&engine, "needle", &options).await;
+        let value = serde_json::to_value(&results).unwrap();
+        let read: SearchResults = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&read).unwrap(), value);
+    }
+}
+
+#[test]
+fn split_snippets_keep_lines_with_escaped_newlines_whole() {
+    let lines = ["a", r"print('\n')", "c"];
+    let snippet = Snippet::new(&lines, 1, SnippetFormat::Split, "\n");
+    assert_eq!(
+        snippet,
+        Snippet::Split {
+            before: vec!["a".to_string()],
+            matched_line: r"print('\n')".to_string(),
+            after: vec!["c".to_string()],
+        }
+    );
+    let joined = Snippet::new(&lines, 1, SnippetFormat::Joined, " | ");
+    assert_eq!(
+        joined,
+        Snippet::Joined {
+            body: r"a | print('\n') | c".to_string()
+        }
+    );
+}
+
+#[tokio::test]
+async fn prefix_last_completes_only_the_last_word() {
+    let dir = tree(&[(
+        "typed.rs",
+        "async fn run() {}\nasync move {}\nfn plain() {}\nformat!(\"x\")",
+    )]);
+    let engine = engine(&dir, EngineConfig::default()).await;
+    let typing = SearchOptions {
+        prefix_last: true,
+        ..SearchOptions::default()
+    };
+    assert_eq!(lines(&search(&engine, "async f", &typing).await), [1]);
+    assert_eq!(lines(&search(&engine, "asy", &typing).await), [1, 2]);
+    assert_eq!(lines(&search(&engine, "fo", &typing).await), [4]);
+    // Earlier words are exact.
+    assert!(search(&engine, "asy fn", &typing)
+        .await
+        .results()
+        .is_empty());
+    // Without it, `fo` is a word of its own.
+    let exact = SearchOptions::default();
+    assert!(search(&engine, "fo", &exact).await.results().is_empty());
+}
+
+#[tokio::test]
+async fn density_puts_the_file_with_most_matches_first() {
+    let dir = tree(&[
+        ("once.txt", "needle needle needle needle"),
+        ("thrice.txt", "needle\nhay\nneedle\nneedle"),
+        ("twice.txt", "needle\nneedle"),
+    ]);
+    let engine = engine(&dir, EngineConfig::default()).await;
+    let by_score = search(&engine, "needle", &SearchOptions::default()).await;
+    assert_eq!(hits(&dir, &by_score)[0].0, "once.txt");
+
+    let options = SearchOptions {
+        sort: SortOrder::Density,
+        ..SearchOptions::default()
+    };
+    let results = search(&engine, "needle", &options).await;
+    let files: Vec<String> = hits(&dir, &results)
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect();
+    assert_eq!(
+        files,
+        [
+            "thrice.txt",
+            "thrice.txt",
+            "thrice.txt",
+            "twice.txt",
+            "twice.txt",
+            "once.txt"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn only_the_requested_fields_are_serialized() {
+    let dir = tree(&[("main.rs", "fn main() {\n    needle();\n}")]);
+    let engine = engine(&dir, EngineConfig::default()).await;
+    let keys = |fields: &str| {
+        let options = SearchOptions {
+            fields: fields.parse().unwrap(),
+            ..SearchOptions::default()
+        };
+        let engine = &engine;
+        async move {
+            let results = search(engine, "needle", &options).await;
+            let result = serde_json::to_value(&results.results()[0]).unwrap();
+            let mut keys: Vec<String> = result.as_object().unwrap().keys().cloned().collect();
+            keys.sort();
+            keys
+        }
+    };
+    assert_eq!(keys("path,line").await, ["line", "path"]);
+    assert_eq!(keys("line_range").await, ["line_range"]);
+    assert_eq!(keys("body,score").await, ["body", "score"]);
+    assert_eq!(
+        keys("body,path,line,line_range,score,language").await,
+        ["body", "language", "line", "line_range", "path", "score"]
+    );
+}
+
+#[tokio::test]
+async fn file_name_boost_ranks_files_named_after_the_query_first() {
+    let dir = tree(&[
+        ("main.rs", "parser parser parser"),
+        ("src/parser.rs", "use parser and more words around it here"),
+    ]);
+    let plain = engine(&dir, EngineConfig::default()).await;
+    let results = search(&plain, "parser", &SearchOptions::default()).await;
+    assert_eq!(hits(&dir, &results)[0].0, "main.rs");
+
+    let config = EngineConfig {
+        file_name_boost: 10.0,
+        ..EngineConfig::default()
+    };
+    let boosted = engine(&dir, config).await;
+    let results = search(&boosted, "parser", &SearchOptions::default()).await;
+    assert_eq!(
+        hits(&dir, &results),
+        [("src/parser.rs".to_string(), 1), ("main.rs".to_string(), 1)]
+    );
+    let [first, second] = scores(&results)[..] else {
+        panic!("expected two results");
+    };
+    assert!(first > second);
+}
+
+/// A file with the terms `open` and `close` on different lines, and one
+/// with only `open`.
+fn split_terms_tree() -> TempDir {
+    tree(&[
+        (
+            "both.rs",
+            "let reader = open(path);\nread(reader);\nreader.close();\n",
+        ),
+        ("open.rs", "let file = open(path);\n"),
+    ])
+}
+
+#[tokio::test]
+async fn line_documents_match_terms_on_one_line() {
+    let dir = split_terms_tree();
+    let engine = engine(&dir, EngineConfig::default()).await;
+    let results = search(&engine, "+open +close", &SearchOptions::default()).await;
+    assert!(results.results().is_empty());
+    let mut open = hits(
+        &dir,
+        &search(&engine, "open", &SearchOptions::default()).await,
+    );
+    open.sort();
+    assert_eq!(
+        open,
+        [("both.rs".to_string(), 1), ("open.rs".to_string(), 1)]
+    );
+}
+
+#[tokio::test]
+async fn file_documents_match_terms_across_lines() {
+    let dir = split_terms_tree();
+    let config = EngineConfig {
+        granularity: Granularity::File,
+        ..EngineConfig::default()
+    };
+    let engine = engine(&dir, config).await;
+    let results = search(&engine, "+open +close", &SearchOptions::default()).await;
+    assert_eq!(hits(&dir, &results), [("both.rs".to_string(), 1)]);
+    // Each file is one result, at the line holding the most query terms.
+    let results = search(&engine, "reader close", &SearchOptions::default()).await;
+    assert_eq!(hits(&dir, &results), [("both.rs".to_string(), 3)]);
+}
+
+#[tokio::test]
+async fn sanitized_snippets_drop_control_characters_but_tabs() {
+    let line = "\x1b[31m needle \x1b[0m\tbell\x07";
+    let dir = tree(&[("control.txt", line)]);
+    let config = EngineConfig {
+        sanitize_output: true,
+        ..EngineConfig::default()
+    };
+    let engine = engine(&dir, config).await;
+    let results = search(&engine, "needle", &SearchOptions::default()).await;
+    assert_eq!(
+        results.results()[0].snippet,
+        Some(Snippet::Joined {
+            body: "[31m needle [0m\tbell".to_string()
+        })
+    );
+    // The cached lines keep them.
+    let path = path_of(&dir, "control.txt");
+    assert_eq!(engine.file_lines(&path), Some(vec![line.to_string()]));
+}
+
+#[tokio::test]
+async fn recently_modified_files_rank_first() {
+    let dir = tree(&[("old.rs", "fn needle() {}"), ("new.rs", "fn needle() {}")]);
+    let month_ago = SystemTime::now() - Duration::from_secs(30 * 24 * 3600);
+    fs::File::options()
+        .write(true)
+        .open(dir.path().join("old.rs"))
+        .unwrap()
+        .set_modified(month_ago)
+        .unwrap();
+    let config = EngineConfig {
+        recency_half_life: Some(Duration::from_secs(24 * 3600)),
+        ..EngineConfig::default()
+    };
+    let engine = engine(&dir, config).await;
+    let results = search(&engine, "needle", &SearchOptions::default()).await;
+    assert_eq!(
+        hits(&dir, &results),
+        [("new.rs".to_string(), 1), ("old.rs".to_string(), 1)]
+    );
+    let scores = scores(&results);
+    // Twice the score when just modified, next to nothing after 30 half-lives.
+    assert!(scores[0] > 1.9 * scores[1], "{scores:?}");
+}
+
+#[tokio::test]
+async fn fixed_strings_match_operators_literally() {
+    let dir = tree(&[(
+        "ops.rs",
+        "if a && b {}\nif a || b {}\nfoo(bar);\nfoo bar;\nlet x: u8 = 1;\nlet x u8 = 1;\n",
+    )]);
+    let engine = engine(&dir, EngineConfig::default()).await;
+    let fixed = SearchOptions {
+        fixed: true,
+        ..SearchOptions::default()
+    };
+    for (text, line) in [("a && b", 1), ("foo(bar)", 3), ("x: u8", 5)] {
+        let results = search(&engine, text, &fixed).await;
+        assert_eq!(
+            hits(&dir, &results),
+            [("ops.rs".to_string(), line)],
+            "{text}"
+        );
+    }
+    // A literal that isn't in the file as is matches nothing, although its
+    // words are.
+    assert!(search(&engine, "b && a", &fixed).await.results().is_empty());
+}
+
+#[tokio::test]
+async fn result_caps_keep_the_best_matches_of_each_file_and_directory() {
+    let noisy = (1..=10).map(|_| "todo").collect::<Vec<_>>().join("\n");
+    let dir = tree(&[
+        ("noisy/many.txt", &noisy),
+        ("noisy/few.txt", "todo todo\ntodo"),
+        ("quiet/one.txt", "todo"),
+    ]);
+    let engine = engine(&dir, EngineConfig::default()).await;
+    let per_file = SearchOptions {
+        max_per_file: Some(2),
+        limit: Some(100),
+        ..SearchOptions::default()
+    };
+    let results = search(&engine, "todo", &per_file).await;
+    let mut counts = std::collections::HashMap::new();
+    for (path, _) in hits(&dir, &results) {
+        *counts.entry(path).or_insert(0) += 1;
+    }
+    assert_eq!(counts["noisy/many.txt"], 2);
+    assert_eq!(counts["noisy/few.txt"], 2);
+    assert_eq!(counts["quiet/one.txt"], 1);
+    // Score order is kept: "todo todo" ranks above every single "todo".
+    assert_eq!(hits(&dir, &results)[0], ("noisy/few.txt".to_string(), 1));
+    let scores = scores(&results);
+    assert!(scores.windows(2).all(|pair| pair[0] >= pair[1]));
+
+    let per_dir = SearchOptions {
+        max_per_dir: Some(3),
+        limit: Some(100),
+        ..SearchOptions::default()
+    };
+    let results = search(&engine, "todo", &per_dir).await;
+    let noisy = hits(&dir, &results)
+        .iter()
+        .filter(|(path, _)| path.starts_with("noisy/"))
+        .count();
+    assert_eq!((noisy, results.results().len()), (3, 4));
+}
+
+#[tokio::test]
+async fn metadata_filters_narrow_by_size_and_directory() {
+    let padding = "filler line\n".repeat(2000);
+    let big = format!("fn needle() {{}}\n{padding}");
+    let dir = tree(&[
+        ("src/small.rs", "fn needle() {}"),
+        ("src/big.rs", &big),
+        ("docs/notes.md", "needle"),
+        ("top.rs", "needle"),
+    ]);
+    let config = EngineConfig {
+        index_metadata: true,
+        ..EngineConfig::default()
+    };
+    let indexed = engine(&dir, config).await;
+    let filtered = |options: SearchOptions| {
+        let indexed = &indexed;
+        let dir = &dir;
+        async move {
+            let mut hits = hits(dir, &search(indexed, "needle", &options).await);
+            hits.sort();
+            hits.into_iter().map(|(path, _)| path).collect::<Vec<_>>()
+        }
+    };
+    let medium = SearchOptions {
+        size: Some(SizeBucket::Medium),
+        ..SearchOptions::default()
+    };
+    assert_eq!(filtered(medium).await, ["src/big.rs"]);
+    let small = SearchOptions {
+        size: Some(SizeBucket::Small),
+        ..SearchOptions::default()
+    };
+    assert_eq!(
+        filtered(small).await,
+        ["docs/notes.md", "src/small.rs", "top.rs"]
+    );
+    let docs = SearchOptions {
+        top_dir: Some("docs".to_string()),
+        ..SearchOptions::default()
+    };
+    assert_eq!(filtered(docs).await, ["docs/notes.md"]);
+    let small_src = SearchOptions {
+        top_dir: Some("src".to_string()),
+        size: Some(SizeBucket::Small),
+        ..SearchOptions::default()
+    };
+    assert_eq!(filtered(small_src).await, ["src/small.rs"]);
+
+    // The filters need the metadata indexed, which takes a reindex.
+    let plain = engine(&dir, EngineConfig::default()).await;
+    let docs = SearchOptions {
+        top_dir: Some("docs".to_string()),
+        ..SearchOptions::default()
+    };
+    let error = plain.search("needle", &docs).await.unwrap_err();
+    assert!(
+        error.to_string().contains("needs index_metadata"),
+        "{error}"
+    );
+}
+
+#[tokio::test]
+async fn mixed_case_regexes_match_with_case_insensitive() {
+    let dir = tree(&[("map.rs", "let map = HashMap::new();\nlet hashmap = 1;")]);
+    let engine = engine(&dir, EngineConfig::default()).await;
+    let regex = SearchOptions {
+        regex: true,
+        ..SearchOptions::default()
+    };
+    let insensitive = SearchOptions {
+        regex: true,
+        case_insensitive: true,
+        ..SearchOptions::default()
+    };
+    // Indexed terms are lowercase, so uppercase letters only match with
+    // case_insensitive, and then match either case.
+    assert!(search(&engine, "Hash[A-Z]ap", &regex)
+        .await
+        .results()
+        .is_empty());
+    let results = search(&engine, "Hash[A-Z]ap", &insensitive).await;
+    assert_eq!(lines(&results), [1, 2]);
+    let results = search(&engine, "HASHMAP", &insensitive).await;
+    assert_eq!(lines(&results), [1, 2]);
+    let error = engine
+        .search("(?-i)HashMap", &insensitive)
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("turns off case-insensitivity"));
+}
+
+#[tokio::test]
+async fn case_sensitive_searches_tell_foo_from_foo() {
+    let dir = tree(&[("case.rs", "struct Foo;\nlet foo = Foo;\nlet FOO = 1;")]);
+    let config = EngineConfig {
+        index_exact_case: true,
+        ..EngineConfig::default()
+    };
+    let exact = engine(&dir, config).await;
+    let sensitive = SearchOptions {
+        case_sensitive: true,
+        ..SearchOptions::default()
+    };
+    assert_eq!(lines(&search(&exact, "Foo", &sensitive).await), [1, 2]);
+    assert_eq!(lines(&search(&exact, "foo", &sensitive).await), [2]);
+    assert_eq!(lines(&search(&exact, "FOO", &sensitive).await), [3]);
+    let options = SearchOptions::default();
+    assert_eq!(lines(&search(&exact, "foo", &options).await), [1, 2, 3]);
+
+    // Without the exact-case field there is nothing to search.
+    let plain = engine(&dir, EngineConfig::default()).await;
+    assert!(plain.search("Foo", &sensitive).await.is_err());
+}
+
+#[tokio::test]
+async fn queries_of_only_short_terms_are_rejected() {
+    let dir = tree(&[("short.rs", "let a = b;\nlet apple = a;")]);
+    let config = EngineConfig {
+        min_term_length: Some(3),
+        ..EngineConfig::default()
+    };
+    let engine = engine(&dir, config).await;
+    let options = SearchOptions::default();
+    for query in ["a", "a b", "a OR b"] {
+        let error = engine.search(query, &options).await.unwrap_err();
+        assert!(
+            error.to_string().contains("shorter than 3 characters"),
+            "{query}: {error}"
+        );
+    }
+    // One long enough term is enough.
+    assert_eq!(lines(&search(&engine, "a apple", &options).await), [1, 2]);
+    let power_user = SearchOptions {
+        allow_short_terms: true,
+        ..SearchOptions::default()
+    };
+    assert_eq!(lines(&search(&engine, "b", &power_user).await), [1]);
+}
+
+#[tokio::test]
+async fn byte_offsets_point_at_the_matched_line_on_disk() {
+    let files = [
+        ("lf.txt", "first\nsecond needle\nthird needle"),
+        ("crlf.txt", "first\r\nsecond needle\r\nthird needle\r\n"),
+        ("mixed.txt", "first\r\nsecond needle\nthird needle\n"),
+        ("ünïcode.txt", "ünïcödé\nsecond needle\nthird needle\n"),
+    ];
+    let dir = tree(&files);
+    let engine = engine(&dir, EngineConfig::default()).await;
+    let options = SearchOptions {
+        byte_offset: true,
+        ..SearchOptions::default()
+    };
+    let results = search(&engine, "needle", &options).await;
+    assert_eq!(results.results().len(), 2 * files.len());
+    for result in results.results() {
+        let bytes = fs::read(&result.path).unwrap();
+        let offset = result.byte_offset.unwrap();
+        let line = if result.line == 2 { "second" } else { "third" };
+        assert!(
+            bytes[offset..].starts_with(line.as_bytes()),
+            "{} line {}",
+            result.path,
+            result.line
+        );
+    }
+    let plain = search(&engine, "needle", &SearchOptions::default()).await;
+    assert!(plain
+        .results()
+        .iter()
+        .all(|result| result.byte_offset.is_none()));
+}
+
+#[tokio::test]
+async fn has_more_tells_whether_a_later_page_has_results() {
+    let content = ["match"; 5].join("\n");
+    let dir = tree(&[("five.txt", &content)]);
+    let engine = engine(&dir, EngineConfig::default()).await;
+    let page = |offset, limit| SearchOptions {
+        offset,
+        limit: Some(limit),
+        ..SearchOptions::default()
+    };
+    for (offset, limit, returned, has_more) in [
+        (0, 2, 2, true),
+        (2, 2, 2, true),
+        (4, 2, 1, false),
+        (0, 5, 5, false),
+        (0, 10, 5, false),
+    ] {
+        let results = search(&engine, "match", &page(offset, limit)).await;
+        assert_eq!(results.results().len(), returned, "{offset}+{limit}");
+        assert_eq!(results.has_more, has_more, "{offset}+{limit}");
+    }
+}
+
+#[tokio::test]
+async fn line_separators_leave_line_ranges_alone() {
+    let dir = tree(&[("five.txt", "one\ntwo\nneedle\nfour\nfive")]);
+    let options = SearchOptions {
+        context_lines: Some(1),
+        ..SearchOptions::default()
+    };
+    for (separator, body) in [
+        ("\n", "two\nneedle\nfour"),
+        ("\r\n", "two\r\nneedle\r\nfour"),
+        (" | ", "two | needle | four"),
+    ] {
+        let config = EngineConfig {
+            snippet_line_separator: separator.to_string(),
+            ..EngineConfig::default()
+        };
+        let engine = engine(&dir, config).await;
+        let results = search(&engine, "needle", &options).await;
+        let result = &results.results()[0];
+        assert_eq!(
+            result.snippet,
+            Some(Snippet::Joined {
+                body: body.to_string()
+            }),
+            "{separator:?}"
+        );
+        let range = result.line_range.as_ref().unwrap();
+        assert_eq!(
+            (result.line, range.start, range.end),
+            (3, 2, 4),
+            "{separator:?}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn comments_only_finds_notes_in_rust_and_python_comments() {
+    let dir = tree(&[
+        (
+            "lib.rs",
+            "// TODO: handle errors\nfn todo() {}\n/* FIXME:\n   todo later */\nlet s = \"TODO\";",
+        ),
+        (
+            "tool.py",
+            "def todo():\n    \"\"\"TODO: document\"\"\"\n    x = 'todo'  # TODO trim\n",
+        ),
+    ]);
+    let config = EngineConfig {
+        index_comments: true,
+        ..EngineConfig::default()
+    };
+    let indexed = engine(&dir, config).await;
+    let comments = SearchOptions {
+        comments_only: true,
+        ..SearchOptions::default()
+    };
+    let mut todos = hits(&dir, &search(&indexed, "todo", &comments).await);
+    todos.sort();
+    assert_eq!(
+        todos,
+        [
+            ("lib.rs".to_string(), 1),
+            ("lib.rs".to_string(), 4),
+            ("tool.py".to_string(), 2),
+            ("tool.py".to_string(), 3),
+        ]
+    );
+    let fixme = search(&indexed, "fixme", &comments).await;
+    assert_eq!(hits(&dir, &fixme), [("lib.rs".to_string(), 3)]);
+    // Code and strings match as usual without it.
+    let all = search(&indexed, "todo", &SearchOptions::default()).await;
+    assert_eq!(all.results().len(), 7);
+
+    let plain = engine(&dir, EngineConfig::default()).await;
+    assert!(plain.search("todo", &comments).await.is_err());
+}
+
+#[tokio::test]
+async fn wide_lines_are_cut_around_the_match_in_snippets_only() {
+    let wide = format!("{} needle {}", "a".repeat(100), "b".repeat(100));
+    let dir = tree(&[("wide.txt", &format!("short\n{wide}\nend"))]);
+    let config = EngineConfig {
+        max_snippet_width: Some(20),
+        ..EngineConfig::default()
+    };
+    let engine = engine(&dir, config).await;
+    let options = SearchOptions {
+        context_lines: Some(1),
+        ..SearchOptions::default()
+    };
+    let results = search(&engine, "needle", &options).await;
+    let Some(Snippet::Joined { body }) = &results.results()[0].snippet else {
+        panic!("expected a joined snippet");
+    };
+    let lines: Vec<&str> = body.lines().collect();
+    assert_eq!(lines[0], "short");
+    assert_eq!(lines[1].chars().count(), 20);
+    assert!(lines[1].starts_with('…') && lines[1].ends_with('…'));
+    assert!(lines[1].contains("needle"), "{}", lines[1]);
+    assert_eq!(lines[2], "end");
+    // The index keeps the whole line.
+    let path = path_of(&dir, "wide.txt");
+    assert_eq!(engine.file_lines(&path).unwrap()[1], wide);
+}
+
+/// Commit everything in `dir` as `author` at `date`, an RFC 3339 time.
+fn commit_as(dir: &TempDir, author: &str, date: &str) {
+    for args in [
+        &["add", "-A"][..],
+        &["commit", "-q", "--no-gpg-sign", "-m", author],
+    ] {
+        let status = std::process::Command::new("git")
+            .args(args)
+            .current_dir(dir.path())
+            .env("GIT_AUTHOR_NAME", author)
+            .env("GIT_AUTHOR_EMAIL", "author@example.com")
+            .env("GIT_AUTHOR_DATE", date)
+            .env("GIT_COMMITTER_NAME", author)
+            .env("GIT_COMMITTER_EMAIL", "author@example.com")
+            .env("GIT_COMMITTER_DATE", date)
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {args:?}");
+    }
+}
+
+fn git_init(dir: &TempDir) {
+    let status = std::process::Command::new("git")
+        .args(["init", "-q"])
+        .current_dir(dir.path())
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[tokio::test]
+async fn blame_names_the_last_commit_of_each_line() {
+    let dir = tree(&[("blamed.txt", "needle one\n")]);
+    git_init(&dir);
+    commit_as(&dir, "Alice", "2024-02-29T12:34:56Z");
+    write(&dir, "blamed.txt", "needle one\nneedle two\n");
+    commit_as(&dir, "Bob", "2024-03-01T00:00:00Z");
+    write(&dir, "blamed.txt", "needle one\nneedle two\nneedle three\n");
+
+    let blamed = engine(&dir, EngineConfig::default()).await;
+    let options = SearchOptions {
+        blame: true,
+        ..SearchOptions::default()
+    };
+    let results = search(&blamed, "needle", &options).await;
+    let mut blames: Vec<_> = results
+        .results()
+        .iter()
+        .map(|result| {
+            let blame = result.blame.as_ref();
+            (
+                result.line,
+                blame.map(|blame| (blame.author.as_str(), blame.date.as_str())),
+            )
+        })
+        .collect();
+    blames.sort();
+    // The third line isn't committed yet.
+    assert_eq!(
+        blames,
+        [
+            (1, Some(("Alice", "2024-02-29T12:34:56Z"))),
+            (2, Some(("Bob", "2024-03-01T00:00:00Z"))),
+            (3, None),
+        ]
+    );
+    let commits: HashSet<&str> = results
+        .results()
+        .iter()
+        .filter_map(|result| Some(result.blame.as_ref()?.commit.as_str()))
+        .collect();
+    assert_eq!(commits.len(), 2);
+    assert!(commits.iter().all(|commit| commit.len() == 40));
+
+    // Without the option no blame is looked up.
+    let results = search(&blamed, "needle", &SearchOptions::default()).await;
+    assert!(results
+        .results()
+        .iter()
+        .all(|result| result.blame.is_none()));
+}
+
+#[tokio::test]
+async fn blame_is_cached_until_the_content_changes() {
+    let dir = tree(&[("blamed.txt", "needle one\n")]);
+    git_init(&dir);
+    commit_as(&dir, "Alice", "2024-02-29T12:34:56Z");
+    write(&dir, "blamed.txt", "needle one\nneedle two\n");
+    let blamed = engine(&dir, EngineConfig::default()).await;
+    let options = SearchOptions {
+        blame: true,
+        ..SearchOptions::default()
+    };
+    let authors = || async {
+        let results = search(&blamed, "needle", &options).await;
+        let mut authors: Vec<(usize, Option<String>)> = results
+            .results()
+            .iter()
+            .map(|result| (result.line, result.blame.clone().map(|blame| blame.author)))
+            .collect();
+        authors.sort();
+        authors
+    };
+    let alice = Some("Alice".to_string());
+    assert_eq!(authors().await, [(1, alice.clone()), (2, None)]);
+
+    // Blame with uncommitted lines isn't kept, committing changes it.
+    commit_as(&dir, "Bob", "2024-03-01T00:00:00Z");
+    let bob = Some("Bob".to_string());
+    assert_eq!(authors().await, [(1, alice.clone()), (2, bob.clone())]);
+
+    // Fully committed, it's kept while the content stays the same, without
+    // running git again.
+    fs::remove_dir_all(dir.path().join(".git")).unwrap();
+    assert_eq!(authors().await, [(1, alice), (2, bob)]);
+
+    write(&dir, "blamed.txt", "needle one\nneedle two\nneedle three\n");
+    blamed.reload(root(&dir)).await.unwrap();
+    assert_eq!(authors().await, [(1, None), (2, None), (3, None)]);
+}
+
+#[tokio::test]
+async fn blame_is_none_outside_a_git_work_tree() {
+    let dir = tree(&[("plain.txt", "needle")]);
+    let plain = engine(&dir, EngineConfig::default()).await;
+    let options = SearchOptions {
+        blame: true,
+        ..SearchOptions::default()
+    };
+    let results = search(&plain, "needle", &options).await;
+    assert_eq!(results.results().len(), 1);
+    assert_eq!(results.results()[0].blame, None);
+}
+
+#[tokio::test]
+async fn hunks_merge_the_context_of_nearby_matches() {
+    let dir = tree(&[
+        (
+            "clustered.txt",
+            "one\nneedle two\nneedle three\nfour\nneedle five\nsix\nseven\neight\nneedle nine\nten",
+        ),
+        ("spread.txt", "needle one\ntwo\nthree\nfour\nneedle five"),
+    ]);
+    let engine = engine(&dir, EngineConfig::default()).await;
+    let options = SearchOptions {
+        context_lines: Some(1),
+        ..SearchOptions::default()
+    };
+    let results = search(&engine, "needle", &options).await;
+    let mut hunks: Vec<_> = engine
+        .hunks(results.results(), &options)
+        .iter()
+        .map(|hunk| {
+            let hunk = serde_json::to_value(hunk).unwrap();
+            let path = hunk["path"].as_str().unwrap();
+            let path = path.rsplit('/').next().unwrap().to_string();
+            let range = &hunk["line_range"];
+            (
+                path,
+                (
+                    range["start"].as_u64().unwrap(),
+                    range["end"].as_u64().unwrap(),
+                ),
+                hunk["body"].as_str().unwrap().to_string(),
+                serde_json::from_value::<Vec<usize>>(hunk["matches"].clone()).unwrap(),
+            )
+        })
+        .collect();
+    hunks.sort();
+    let hunk = |path: &str, range, body: &str, matches: &[usize]| {
+        (path.to_string(), range, body.to_string(), matches.to_vec())
+    };
+    assert_eq!(
+        hunks,
+        [
+            // Lines 2 and 3 overlap, and line 5 touches their context.
+            hunk(
+                "clustered.txt",
+                (1, 6),
+                "one\nneedle two\nneedle three\nfour\nneedle five\nsix",
+                &[2, 3, 5],
+            ),
+            hunk("clustered.txt", (8, 10), "eight\nneedle nine\nten", &[9]),
+            // A line apart from the other's context stays in its own hunk.
+            hunk("spread.txt", (1, 2), "needle one\ntwo", &[1]),
+            hunk("spread.txt", (4, 5), "four\nneedle five", &[5]),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn terminal_lines_carry_ansi_escapes_only_with_color() {
+    let dir = tree(&[("grep.txt", "before\nfind the needle\nafter")]);
+    let engine = engine(&dir, EngineConfig::default()).await;
+    let options = SearchOptions {
+        context_lines: Some(1),
+        ..SearchOptions::default()
+    };
+    let matcher = engine.rg_matcher("needle", &options).unwrap();
+    let results = search(&engine, "needle", &options).await;
+    let result = &results.results()[0];
+    let path = path_of(&dir, "grep.txt");
+
+    assert_eq!(
+        engine.terminal_lines(&matcher, result, false),
+        [
+            format!("{path}-1-before"),
+            format!("{path}:2:find the needle"),
+            format!("{path}-3-after"),
+        ]
+    );
+    let colored = engine.terminal_lines(&matcher, result, true);
+    assert_eq!(
+        colored[1],
+        format!("\x1b[35m{path}\x1b[0m:\x1b[32m2\x1b[0m:find the \x1b[1;31mneedle\x1b[0m")
+    );
+    // Context is dimmed rather than highlighted.
+    assert!(colored[0].ends_with("-\x1b[2mbefore\x1b[0m"));
+    assert!(!colored[2].contains("\x1b[1;31m"));
+}
+
+#[tokio::test]
+async fn excluded_paths_are_dropped_from_the_results() {
+    let dir = tree(&[
+        ("src/lib.rs", "needle"),
+        ("src/lib_test.rs", "needle"),
+        ("tests/integration.rs", "needle"),
+    ]);
+    let engine = engine(&dir, EngineConfig::default()).await;
+    let found = |exclude: &str| {
+        let options = SearchOptions {
+            exclude: exclude.parse().unwrap(),
+            ..SearchOptions::default()
+        };
+        let engine = &engine;
+        let dir = &dir;
+        async move {
+            let mut found: Vec<String> = hits(dir, &search(engine, "needle", &options).await)
+                .into_iter()
+                .map(|(path, _)| path)
+                .collect();
+            found.sort();
+            found
+        }
+    };
+    assert_eq!(
+        found("").await,
+        ["src/lib.rs", "src/lib_test.rs", "tests/integration.rs"]
+    );
+    assert_eq!(found("tests/,*_test.rs").await, ["src/lib.rs"]);
+    assert_eq!(found("lib").await, ["tests/integration.rs"]);
+    // Files stay indexed for the next search.
+    assert_eq!(found("").await.len(), 3);
+}
+
+/// The files of `dir` with a line matching `query`, by path.
+async fn files_matching(dir: &TempDir, engine: &CodeSearchEngine, query: &str) -> Vec<String> {
+    let mut found: Vec<String> = hits(dir, &search(engine, query, &SearchOptions::default()).await)
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect();
+    found.sort();
+    found
+}
+
+#[tokio::test]
+async fn composed_and_decomposed_text_match_with_normalize_unicode() {
+    let dir = tree(&[
+        ("composed.txt", "let caf\u{e9} = 1;"),
+        ("decomposed.txt", "let cafe\u{301} = 2;"),
+    ]);
+    let both = ["composed.txt", "decomposed.txt"];
+    let config = EngineConfig {
+        normalize_unicode: true,
+        ..EngineConfig::default()
+    };
+    let normalizing = engine(&dir, config).await;
+    assert_eq!(files_matching(&dir, &normalizing, "caf\u{e9}").await, both);
+    assert_eq!(
+        files_matching(&dir, &normalizing, "cafe\u{301}").await,
+        both
+    );
+    // Combining marks are put in canonical order before composing.
+    write(&dir, "dotted.txt", "\u{e9}\u{323}t\u{e9}");
+    normalizing.reload(root(&dir)).await.unwrap();
+    assert_eq!(
+        files_matching(&dir, &normalizing, "e\u{323}\u{301}t\u{e9}").await,
+        ["dotted.txt"]
+    );
+
+    // Otherwise each form only matches itself.
+    let plain = engine(&dir, EngineConfig::default()).await;
+    assert_eq!(
+        files_matching(&dir, &plain, "caf\u{e9}").await,
+        ["composed.txt"]
+    );
+    assert_eq!(
+        files_matching(&dir, &plain, "cafe\u{301}").await,
+        ["decomposed.txt"]
+    );
+}
+
+#[tokio::test]
+async fn verify_fresh_flags_lines_changed_on_disk_since_indexing() {
+    let dir = tree(&[
+        ("changed.txt", "needle one\ntwo"),
+        ("deleted.txt", "needle"),
+        ("untouched.txt", "needle"),
+    ]);
+    let engine = engine(&dir, EngineConfig::default()).await;
+    let options = SearchOptions {
+        verify_fresh: true,
+        ..SearchOptions::default()
+    };
+    let freshness = |results: &SearchResults| {
+        let mut freshness: Vec<(String, Value)> = results
+            .results()
+            .iter()
+            .map(|result| {
+                let result = serde_json::to_value(result).unwrap();
+                let path = result["path"].as_str().unwrap();
+                let name = path.rsplit('/').next().unwrap().to_string();
+                let keys = json!({
+                    "stale": result.get("stale"),
+                    "current_line": result.get("current_line"),
+                });
+                (name, keys)
+            })
+            .collect();
+        freshness.sort_by(|a, b| a.0.cmp(&b.0));
+        freshness
+    };
+    let fresh = json!({ "stale": false, "current_line": null });
+    let all_fresh = ["changed.txt", "deleted.txt", "untouched.txt"]
+        .map(|name| (name.to_string(), fresh.clone()));
+    assert_eq!(
+        freshness(&search(&engine, "needle", &options).await),
+        all_fresh
+    );
+
+    // Changed without a reload, so the indexed lines are still matched.
+    write(&dir, "changed.txt", "one changed\ntwo");
+    fs::remove_file(dir.path().join("deleted.txt")).unwrap();
+    let results = search(&engine, "needle", &options).await;
+    assert_eq!(
+        freshness(&results),
+        [
+            (
+                "changed.txt".to_string(),
+                json!({ "stale": true, "current_line": "one changed" })
+            ),
+            (
+                "deleted.txt".to_string(),
+                json!({ "stale": true, "current_line": null })
+            ),
+            ("untouched.txt".to_string(), fresh),
+        ]
+    );
+
+    // Without the option results carry neither key.
+    let results = search(&engine, "needle", &SearchOptions::default()).await;
+    let result = serde_json::to_value(&results.results()[0]).unwrap();
+    assert!(result.get("stale").is_none() && result.get("current_line").is_none());
+}
+
+#[tokio::test]
+async fn facets_count_matches_per_extension_and_top_dir() {
+    let dir = tree(&[
+        ("src/lib.rs", "needle\nneedle\nhay"),
+        ("src/main.rs", "needle"),
+        ("docs/guide.md", "needle"),
+        ("Makefile", "needle"),
+    ]);
+    let config = EngineConfig {
+        index_metadata: true,
+        ..EngineConfig::default()
+    };
+    let engine = engine(&dir, config).await;
+    let options = SearchOptions::default();
+    let facets = |by| serde_json::to_value(engine.facets("needle", &options, by).unwrap()).unwrap();
+    assert_eq!(
+        facets(FacetField::Extension),
+        json!({
+            "total": 5,
+            "counts": [{ "value": "rs", "count": 3 }, { "value": "md", "count": 1 }],
+            // The Makefile has no extension.
+            "other": 1,
+        })
+    );
+    assert_eq!(
+        facets(FacetField::TopDir)["counts"],
+        json!([{ "value": "src", "count": 3 }, { "value": "docs", "count": 1 }])
+    );
+
+    let plain = super::engine(&dir, EngineConfig::default()).await;
+    let error = plain
+        .facets("needle", &options, FacetField::Extension)
+        .unwrap_err();
+    assert!(error.to_string().contains("index_metadata"));
+}
+
+/// The lines matching `query` with `options`, in order.
+async fn matched_lines(
+    engine: &CodeSearchEngine,
+    query: &str,
+    options: &SearchOptions,
+) -> Vec<usize> {
+    let mut lines: Vec<usize> = search(engine, query, options)
+        .await
+        .results()
+        .iter()
+        .map(|result| result.line)
+        .collect();
+    lines.sort_unstable();
+    lines
+}
+
+#[tokio::test]
+async fn match_case_styles_matches_identifiers_across_case_styles() {
+    let dir = tree(&[(
+        "names.rs",
+        "get_foo_bar\ngetFooBar\nGetFooBar\nget_foobar\nget_foo\nHTTPServer\nhttp_server",
+    )]);
+    let options = SearchOptions {
+        analyzer: Analyzer::Code,
+        ..SearchOptions::default()
+    };
+    let code = EngineConfig {
+        extension_analyzers: [("rs".to_string(), Analyzer::Code)].into(),
+        ..EngineConfig::default()
+    };
+    let config = EngineConfig {
+        schema: SchemaOptions {
+            match_case_styles: true,
+            ..SchemaOptions::default()
+        },
+        ..code.clone()
+    };
+    let styled = engine(&dir, config).await;
+    assert_eq!(
+        matched_lines(&styled, "getFooBar", &options).await,
+        [1, 2, 3]
+    );
+    assert_eq!(
+        matched_lines(&styled, "get_foo_bar", &options).await,
+        [1, 2, 3]
+    );
+    assert_eq!(
+        matched_lines(&styled, "http_server", &options).await,
+        [6, 7]
+    );
+    // Other words, or the same words split otherwise, don't match.
+    assert_eq!(matched_lines(&styled, "get_foobar", &options).await, [4]);
+    assert!(matched_lines(&styled, "get_foo_baz", &options)
+        .await
+        .is_empty());
+
+    let plain = engine(&dir, code).await;
+    assert_eq!(matched_lines(&plain, "getFooBar", &options).await, [2, 3]);
+    assert_eq!(matched_lines(&plain, "get_foo_bar", &options).await, [1]);
+}
+
+#[tokio::test]
+async fn previews_cut_long_matched_lines_around_the_match() {
+    let long = format!("{} needle {}", "x".repeat(100), "y".repeat(100));
+    let dir = tree(&[
+        ("short.txt", "a short needle"),
+        ("long.txt", &long),
+        ("start.txt", &format!("needle {}", "z".repeat(50))),
+    ]);
+    let engine = engine(&dir, EngineConfig::default()).await;
+    let options = SearchOptions {
+        preview_chars: Some(30),
+        context_lines: Some(1),
+        ..SearchOptions::default()
+    };
+    let results = search(&engine, "needle", &options).await;
+    let mut previews: Vec<(String, Value)> = results
+        .results()
+        .iter()
+        .map(|result| {
+            let name = result.path.rsplit('/').next().unwrap().to_string();
+            (
+                name,
+                serde_json::to_value(result).unwrap()["preview"].clone(),
+            )
+        })
+        .collect();
+    previews.sort_by(|a, b| a.0.cmp(&b.0));
+    let x = "x".repeat(10);
+    let y = "y".repeat(10);
+    assert_eq!(
+        previews,
+        [
+            // Centered on the match, 30 characters with the markers.
+            ("long.txt".to_string(), json!(format!("…{x} needle {y}…"))),
+            // Shorter lines come whole.
+            ("short.txt".to_string(), json!("a short needle")),
+            // A match at the start is only cut at the end.
+            (
+                "start.txt".to_string(),
+                json!(format!("needle {}…", "z".repeat(22)))
+            ),
+        ]
+    );
+
+    let results = search(&engine, "needle", &SearchOptions::default()).await;
+    let result = serde_json::to_value(&results.results()[0]).unwrap();
+    assert!(result.get("preview").is_none());
+    let too_narrow = SearchOptions {
+        preview_chars: Some(2),
+        ..SearchOptions::default()
+    };
+    let error = engine.search("needle", &too_narrow).await.unwrap_err();
+    assert!(error.to_string().contains("preview_chars"));
+}
@@ -0,0 +1,257 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Text analysis applied to indexed lines and to queries.
+//!
+//! Analyzers are registered on the index, so the query parser tokenizes queries
+//! exactly like the indexed text. Changing any of these settings therefore
+//! requires a reindex.
+//!
+//! Every line is indexed into the `body` field with the default analyzer.
+//! Lines of files whose extension is mapped to [`Analyzer::Code`] are also
+//! indexed into `body_code`, which keeps identifiers whole and additionally
+//! emits their camelCase/snake_case words, so `getFooBar` is found by
+//! `getfoobar` as well as by `foo`. A search picks the field through
+//! `SearchOptions::analyzer`. With `SchemaOptions::match_case_styles` the
+//! whole identifier is indexed as its words joined, so `get_foo_bar`,
+//! `getFooBar` and `GetFooBar` are the same term and find one another.
+//!
+//! With `EngineConfig::index_exact_case` every line is also indexed into
+//! `body_exact`, split like `body` but keeping case, so `HTTP` and `http`
+//! are different terms there. `SearchOptions::case_sensitive` searches it.
+
+use serde::Deserialize;
+use tantivy::schema::{IndexRecordOption, TextFieldIndexing, TextOptions, STORED, TEXT};
+use tantivy::tokenizer::{
+    LowerCaser, RemoveLongFilter, SimpleTokenizer, StopWordFilter, TextAnalyzer, Token,
+    TokenStream, Tokenizer,
+};
+use tantivy::Index;
+
+use crate::EngineConfig;
+
+/// Name of the analyzer used by the `body` field.
+const BODY_TOKENIZER: &str = "body";
+/// Name of the analyzer used by the `body_code` field.
+const CODE_TOKENIZER: &str = "code";
+/// Name of the analyzer used by the `body_code` field with
+/// `SchemaOptions::match_case_styles`.
+const CASE_STYLE_TOKENIZER: &str = "code_case_style";
+/// Name of the analyzer used by the `body_exact` field.
+const EXACT_TOKENIZER: &str = "exact";
+
+/// The analyzers a file can be indexed with and a search can target.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Analyzer {
+    /// Words split on any non alphanumeric character.
+    #[default]
+    Default,
+    /// Whole identifiers plus their camelCase/snake_case words.
+    Code,
+}
+
+fn indexed_options(tokenizer: &str) -> TextOptions {
+    let indexing = TextFieldIndexing::default()
+        .set_tokenizer(tokenizer)
+        .set_index_option(IndexRecordOption::WithFreqsAndPositions);
+    TextOptions::default().set_indexing_options(indexing)
+}
+
+/// Field options for the `body` field, stored unless `stored` is false.
+pub(crate) fn body_options(stored: bool) -> TextOptions {
+    let options = indexed_options(BODY_TOKENIZER);
+    if stored {
+        options | STORED
+    } else {
+        options
+    }
+}
+
+/// Field options for the stored `path` field, split into words by `analyzer`.
+pub(crate) fn path_options(analyzer: Analyzer, match_case_styles: bool) -> TextOptions {
+    match analyzer {
+        Analyzer::Default => TEXT | STORED,
+        Analyzer::Code => code_options(match_case_styles) | STORED,
+    }
+}
+
+/// Field options for the `body_code` field. The text is already stored in `body`.
+pub(crate) fn code_options(match_case_styles: bool) -> TextOptions {
+    if match_case_styles {
+        indexed_options(CASE_STYLE_TOKENIZER)
+    } else {
+        indexed_options(CODE_TOKENIZER)
+    }
+}
+
+/// Field options for the `body_exact` field. The text is already stored in `body`.
+pub(crate) fn exact_options() -> TextOptions {
+    indexed_options(EXACT_TOKENIZER)
+}
+
+/// Register the analyzers referenced by the schema on `index`.
+pub(crate) fn register(index: &Index, config: &EngineConfig) {
+    // Same chain as tantivy's default tokenizer, plus the configured stop
+    // words. Stop words are compared after lowercasing.
+    let stop_words = || config.stop_words.iter().map(|word| word.to_lowercase());
+    let body = TextAnalyzer::builder(SimpleTokenizer::default())
+        .filter(RemoveLongFilter::limit(40))
+        .filter(LowerCaser)
+        .filter(StopWordFilter::remove(stop_words()))
+        .build();
+    index.tokenizers().register(BODY_TOKENIZER, body);
+
+    for (name, join_words) in [(CODE_TOKENIZER, false), (CASE_STYLE_TOKENIZER, true)] {
+        let code = TextAnalyzer::builder(CodeTokenizer { join_words })
+            .filter(RemoveLongFilter::limit(40))
+            .filter(LowerCaser)
+            .filter(StopWordFilter::remove(stop_words()))
+            .build();
+        index.tokenizers().register(name, code);
+    }
+
+    // Without lowercasing, only stop words spelled as configured or in
+    // lowercase are removed.
+    let exact = TextAnalyzer::builder(SimpleTokenizer::default())
+        .filter(RemoveLongFilter::limit(40))
+        .filter(StopWordFilter::remove(
+            stop_words().chain(config.stop_words.iter().cloned()),
+        ))
+        .build();
+    index.tokenizers().register(EXACT_TOKENIZER, exact);
+}
+
+/// Byte ranges of the words in an identifier: `getFooBar`, `get_foo_bar`
+/// and `HTTPServer` split into `get|Foo|Bar`, `get|foo|bar` and `HTTP|Server`.
+pub(crate) fn identifier_words(ident: &str) -> Vec<(usize, usize)> {
+    let chars: Vec<(usize, char)> = ident.char_indices().collect();
+    let mut words = Vec::new();
+    let mut start: Option<usize> = None;
+    for (i, &(offset, c)) in chars.iter().enumerate() {
+        if c == '_' {
+            if let Some(word_start) = start.take() {
+                words.push((word_start, offset));
+            }
+            continue;
+        }
+        let prev = i.checked_sub(1).map(|p| chars[p].1);
+        let next = chars.get(i + 1).map(|&(_, c)| c);
+        let boundary = c.is_uppercase()
+            && prev.is_some_and(|prev| {
+                prev.is_lowercase()
+                    || prev.is_numeric()
+                    || (prev.is_uppercase() && next.is_some_and(char::is_lowercase))
+            });
+        if boundary {
+            if let Some(word_start) = start.take() {
+                words.push((word_start, offset));
+            }
+        }
+        start.get_or_insert(offset);
+    }
+    if let Some(word_start) = start {
+        words.push((word_start, ident.len()));
+    }
+    words
+}
+
+/// Splits text into identifiers (runs of alphanumerics and `_`), emitting each
+/// identifier followed by its words when it has more than one.
+#[derive(Clone)]
+pub(crate) struct CodeTokenizer {
+    /// Emit identifiers as their words joined, dropping `_` and leaving the
+    /// case to the filters, so every case style gives the same token.
+    join_words: bool,
+}
+
+pub(crate) struct CodeTokenStream {
+    tokens: std::vec::IntoIter<Token>,
+    token: Token,
+}
+
+impl Tokenizer for CodeTokenizer {
+    type TokenStream<'a> = CodeTokenStream;
+
+    fn token_stream<'a>(&'a mut self, text: &'a str) -> CodeTokenStream {
+        let mut tokens = Vec::new();
+        let mut push = |offset_from: usize, offset_to: usize, text: String| {
+            tokens.push(Token {
+                offset_from,
+                offset_to,
+                position: tokens.len(),
+                text,
+                position_length: 1,
+            });
+        };
+
+        let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+        let mut rest = text.char_indices().peekable();
+        while let Some((start, c)) = rest.next() {
+            if !is_ident(c) {
+                continue;
+            }
+            let mut end = start + c.len_utf8();
+            while let Some(&(offset, c)) = rest.peek() {
+                if !is_ident(c) {
+                    break;
+                }
+                end = offset + c.len_utf8();
+                rest.next();
+            }
+
+            let ident = &text[start..end];
+            let words = identifier_words(ident);
+            if words.is_empty() {
+                continue;
+            }
+            let whole = if self.join_words {
+                words.iter().map(|&(from, to)| &ident[from..to]).collect()
+            } else {
+                ident.to_string()
+            };
+            push(start, end, whole);
+            if words.len() > 1 {
+                for (from, to) in words {
+                    push(start + from, start + to, ident[from..to].to_string());
+                }
+            }
+        }
+
+        CodeTokenStream {
+            tokens: tokens.into_iter(),
+            token: Token::default(),
+        }
+    }
+}
+
+impl TokenStream for CodeTokenStream {
+    fn advance(&mut self) -> bool {
+        match self.tokens.next() {
+            Some(token) => {
+                self.token = token;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn token(&self) -> &Token {
+        &self.token
+    }
+
+    fn token_mut(&mut self) -> &mut Token {
+        &mut self.token
+    }
+}
@@ -0,0 +1,120 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Translating glob-like `*`/`?` patterns, and checking user regexes, into
+//! regexes matched against index terms.
+
+use tantivy::{Result as TantivyResult, TantivyError};
+
+/// Characters with a meaning in tantivy's regex syntax.
+const REGEX_META: &str = r"\.+*?()|[]{}^$#&-~";
+
+/// Translate a wildcard pattern into a regex matching whole index terms,
+/// `*` matching any run of characters and `?` exactly one. Terms are
+/// lowercased when indexed, so with `lowercase` the pattern is too.
+///
+/// Patterns without a single literal character, such as `*` or `?*`,
+/// match every term in the index and are rejected as too expensive.
+pub(crate) fn to_regex(pattern: &str, lowercase: bool) -> TantivyResult<String> {
+    let pattern = pattern.trim();
+    let pattern = if lowercase {
+        pattern.to_lowercase()
+    } else {
+        pattern.to_string()
+    };
+    if pattern.chars().all(|c| c == '*' || c == '?') {
+        return Err(TantivyError::InvalidArgument(format!(
+            "Wildcard pattern {pattern:?} would match every term"
+        )));
+    }
+
+    let mut regex = String::with_capacity(pattern.len() * 2);
+    for c in pattern.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            c if REGEX_META.contains(c) => {
+                regex.push('\\');
+                regex.push(c);
+            }
+            c => regex.push(c),
+        }
+    }
+    Ok(regex)
+}
+
+/// Prepare a user regex to be matched against whole index terms. Terms are
+/// lowercased when indexed, so without `case_insensitive` uppercase letters
+/// in `pattern` never match. With it the pattern is prefixed with `(?i)`,
+/// which a flag group turning `i` back off, like `(?-i)`, contradicts.
+pub(crate) fn term_regex(pattern: &str, case_insensitive: bool) -> TantivyResult<String> {
+    let pattern = pattern.trim();
+    if !case_insensitive {
+        return Ok(pattern.to_string());
+    }
+    if disables_case_insensitivity(pattern) {
+        return Err(TantivyError::InvalidArgument(format!(
+            "Regex {pattern:?} turns off case-insensitivity, which case_insensitive turns on"
+        )));
+    }
+    Ok(format!("(?i){pattern}"))
+}
+
+/// Whether a flag group of `pattern` clears the `i` flag, e.g. `(?-i)` or
+/// `(?s-i:...)`.
+fn disables_case_insensitivity(pattern: &str) -> bool {
+    pattern.match_indices("(?").any(|(start, _)| {
+        let flags = &pattern[start + 2..];
+        let flags = &flags[..flags.find([':', ')']).unwrap_or(flags.len())];
+        flags
+            .split_once('-')
+            .is_some_and(|(_, cleared)| cleared.contains('i'))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stars_and_question_marks_become_regex_wildcards() {
+        assert_eq!(to_regex("*config*", true).unwrap(), ".*config.*");
+        assert_eq!(to_regex("?etting", true).unwrap(), ".etting");
+        assert_eq!(to_regex("Read*", true).unwrap(), "read.*");
+        assert_eq!(to_regex("Read*", false).unwrap(), "Read.*");
+    }
+
+    #[test]
+    fn regex_metacharacters_match_literally() {
+        assert_eq!(to_regex("a.b", true).unwrap(), r"a\.b");
+        assert_eq!(to_regex("(x|y)+", true).unwrap(), r"\(x\|y\)\+");
+        assert_eq!(to_regex("[a-z]{2}", true).unwrap(), r"\[a\-z\]\{2\}");
+    }
+
+    #[test]
+    fn patterns_matching_every_term_are_rejected() {
+        for pattern in ["*", "?", "?*", "**", " * "] {
+            assert!(to_regex(pattern, true).is_err(), "{pattern:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn case_insensitive_regexes_must_not_turn_it_off() {
+        assert_eq!(term_regex("foo.*", false).unwrap(), "foo.*");
+        assert_eq!(term_regex("foo.*", true).unwrap(), "(?i)foo.*");
+        assert!(term_regex("(?-i)Foo", true).is_err());
+        assert!(term_regex("(?s-i:Foo)", true).is_err());
+        assert!(term_regex("(?i:Foo)", true).is_ok());
+    }
+}
@@ -11,12 +11,17 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
+use crate::response::{Envelope, FieldCase};
+use search_engine::{Analyzer, Granularity, RootConfig, SchemaOptions, Source};
 use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::Path;
 use tantivy::{Result as TantivyResult, TantivyError};
 
-use std::process::Command;
+use std::time::Duration;
+use tokio::process::Command;
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
@@ -26,28 +31,113 @@ pub struct Config {
 #[derive(Debug, Deserialize)]
 pub struct ScanSettings {
     pub rescan_interval: Option<String>,
+    pub initial_delay: Option<String>,
     pub pre_scan_commands: Option<Vec<String>>,
+    pub pre_scan_env: Option<HashMap<String, String>>,
+    pub pre_scan_timeout: Option<String>,
     pub scan_directory: Option<String>,
     pub exclude_patterns: Option<Vec<String>>,
     pub endpoint: Option<String>,
+    pub index_archives: Option<bool>,
+    pub skip_non_text: Option<bool>,
+    pub detect_content_type: Option<bool>,
+    pub normalize_unicode: Option<bool>,
+    pub max_lines_per_file: Option<usize>,
+    pub git_ref: Option<String>,
+    pub source: Option<Source>,
+    pub stop_words: Option<Vec<String>>,
+    pub commit_every_docs: Option<usize>,
+    pub commit_every_bytes: Option<usize>,
+    pub rate_limit_per_second: Option<f64>,
+    pub rate_limit_burst: Option<u32>,
+    pub stream_results_per_second: Option<f64>,
+    pub extension_analyzers: Option<HashMap<String, Analyzer>>,
+    pub default_query: Option<String>,
+    pub file_extensions: Option<Vec<String>>,
+    pub response_envelope: Option<Envelope>,
+    pub field_case: Option<FieldCase>,
+    pub max_concurrent_searches: Option<usize>,
+    pub max_batch_searches: Option<usize>,
+    pub shutdown_timeout: Option<String>,
+    pub bind_retries: Option<u32>,
+    pub bind_retry_delay: Option<String>,
+    pub cors_origins: Option<Vec<String>>,
+    pub cors_max_age_secs: Option<u64>,
+    pub cors_allow_credentials: Option<bool>,
+    pub roots: Option<Vec<RootConfig>>,
+    pub dedupe_content: Option<bool>,
+    pub exclude_extensions: Option<Vec<String>>,
+    pub max_open_files: Option<usize>,
+    pub file_name_boost: Option<f32>,
+    pub trim_trailing_whitespace: Option<bool>,
+    pub granularity: Option<Granularity>,
+    pub use_default_excludes: Option<bool>,
+    pub max_query_length: Option<usize>,
+    pub max_query_terms: Option<usize>,
+    pub min_term_length: Option<usize>,
+    pub sanitize_output: Option<bool>,
+    pub snippet_line_separator: Option<String>,
+    pub max_snippet_width: Option<usize>,
+    pub max_cached_bytes: Option<usize>,
+    pub recency_half_life: Option<String>,
+    pub index_metadata: Option<bool>,
+    pub index_exact_case: Option<bool>,
+    pub index_comments: Option<bool>,
+    pub follow_patterns: Option<Vec<String>>,
+    pub ctags_command: Option<String>,
+    pub auto_compact: Option<bool>,
+    pub auto_compact_segments: Option<usize>,
+    pub schema: Option<SchemaOptions>,
 }
 
-pub fn read_config(path: PathBuf) -> TantivyResult<Config> {
+/// Read a YAML or TOML config, chosen by the file extension, along with its
+/// `scan_settings` as a plain value to tell which settings changed between
+/// two reads.
+pub fn read_config(path: &Path) -> TantivyResult<(Config, Value)> {
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    let extension = extension.map(str::to_ascii_lowercase);
+    let parse: fn(&str) -> Result<(Config, Value), String> = match extension.as_deref() {
+        Some("yaml" | "yml") => |contents| {
+            let config = serde_yaml::from_str(contents).map_err(|e| e.to_string())?;
+            let value: Value = serde_yaml::from_str(contents).map_err(|e| e.to_string())?;
+            Ok((config, value))
+        },
+        Some("toml") => |contents| {
+            let config = toml::from_str(contents).map_err(|e| e.to_string())?;
+            let value: Value = toml::from_str(contents).map_err(|e| e.to_string())?;
+            Ok((config, value))
+        },
+        _ => {
+            return Err(TantivyError::InvalidArgument(format!(
+                "Unsupported config file '{}', expected a .yaml, .yml or .toml extension",
+                path.display()
+            )))
+        }
+    };
     let contents = fs::read_to_string(path)?;
 
-    let config = match serde_yaml::from_str::<Config>(&contents) {
-        Ok(cfg) => cfg,
+    let (config, mut value) = match parse(&contents) {
+        Ok(parsed) => parsed,
         Err(e) => {
             return Err(TantivyError::InvalidArgument(format!(
-                "Failed to parse YAML: {}",
+                "Failed to parse {}: {}",
+                path.display(),
                 e
             )))
         }
     };
-    Ok(config)
+    Ok((config, value["scan_settings"].take()))
 }
 
-pub async fn execute_pre_scan_commands(commands: Vec<String>, cwd: String) -> TantivyResult<()> {
+/// Run each command in `cwd` with `env` added to the environment, stopping
+/// at the first one that fails. A command running longer than `timeout` is
+/// killed and counts as failed.
+pub async fn execute_pre_scan_commands(
+    commands: Vec<String>,
+    cwd: String,
+    env: &HashMap<String, String>,
+    timeout: Option<Duration>,
+) -> TantivyResult<()> {
     for command_str in commands {
         let parts = match shell_words::split(&command_str) {
             Ok(p) => p,
@@ -64,13 +154,34 @@ pub async fn execute_pre_scan_commands(commands: Vec<String>, cwd: String) -> Ta
         }
 
         let (cmd, args) = parts.split_first().unwrap();
-        let status = Command::new(cmd)
+        let run_error = |e: std::io::Error| {
+            TantivyError::InvalidArgument(format!("Failed to run '{}': {}", cmd, e))
+        };
+        let mut command = Command::new(cmd);
+        // Without a scanned directory, e.g. with an http source, commands
+        // run where the server was started.
+        if !cwd.is_empty() {
+            command.current_dir(&cwd);
+        }
+        let mut child = command
             .args(args)
-            .current_dir(&cwd)
-            .status()
-            .map_err(|e| {
-                TantivyError::InvalidArgument(format!("Failed to run '{}': {}", cmd, e))
-            })?;
+            .envs(env)
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(run_error)?;
+        let status = match timeout {
+            Some(limit) => tokio::time::timeout(limit, child.wait())
+                .await
+                .map_err(|_| {
+                    TantivyError::InvalidArgument(format!(
+                        "Command '{}' timed out after {}",
+                        command_str,
+                        humantime::format_duration(limit)
+                    ))
+                })?,
+            None => child.wait().await,
+        }
+        .map_err(run_error)?;
 
         if !status.success() {
             return Err(TantivyError::InvalidArgument(format!(
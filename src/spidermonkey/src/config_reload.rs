@@ -0,0 +1,166 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Applying a changed config file to the running server, for the settings
+//! that take effect without rebuilding the index or restarting.
+
+use clap::ArgMatches;
+use search_engine::CodeSearchEngine;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Mutex, PoisonError, RwLock};
+use tantivy::{Result as TantivyResult, TantivyError};
+use tokio::sync::Notify;
+use tokio::time::Duration;
+
+use crate::{config, AppConfig};
+
+/// Keys of `scan_settings` a config reload applies. Changing any other key
+/// needs a restart.
+const RELOADABLE_SETTINGS: [&str; 5] = [
+    "rescan_interval",
+    "exclude_patterns",
+    "pre_scan_commands",
+    "pre_scan_env",
+    "pre_scan_timeout",
+];
+
+/// Reloadable keys of `scan_settings` by the command line flag that
+/// overrides them.
+const CLI_OVERRIDES: [(&str, &str); 1] = [("rescan_interval", "interval")];
+
+/// What each cycle of the reload loop runs with.
+#[derive(Debug, Clone)]
+pub struct ScanLoop {
+    pub interval: Duration,
+    pub pre_scan_commands: Vec<String>,
+    pub pre_scan_env: HashMap<String, String>,
+    pub pre_scan_timeout: Option<Duration>,
+}
+
+impl ScanLoop {
+    fn of(config: &AppConfig) -> Self {
+        Self {
+            interval: config.interval,
+            pre_scan_commands: config.pre_scan_commands.clone(),
+            pre_scan_env: config.pre_scan_env.clone(),
+            pre_scan_timeout: config.pre_scan_timeout,
+        }
+    }
+}
+
+/// The result of re-reading the config file.
+pub enum Reloaded {
+    /// The reloadable settings that changed, now in effect, and those that
+    /// changed but stay overridden by a command line flag.
+    Applied {
+        applied: Vec<String>,
+        overridden: Vec<String>,
+    },
+    /// Changed settings that need a restart. Nothing was applied.
+    RestartRequired(Vec<String>),
+}
+
+pub struct ConfigReload {
+    /// The config file, if the server was started with one.
+    path: Option<PathBuf>,
+    /// Command line flags, which keep overriding the file.
+    cli: ArgMatches,
+    /// `scan_settings` as last applied.
+    settings: Mutex<Value>,
+    scan_loop: RwLock<ScanLoop>,
+    /// Notified when `scan_loop` changes, to restart the wait for a rescan.
+    pub changed: Notify,
+}
+
+impl ConfigReload {
+    pub fn new(cli: &ArgMatches, settings: Value, config: &AppConfig) -> Self {
+        Self {
+            path: cli.get_one::<PathBuf>("config").cloned(),
+            cli: cli.clone(),
+            settings: Mutex::new(settings),
+            scan_loop: RwLock::new(ScanLoop::of(config)),
+            changed: Notify::new(),
+        }
+    }
+
+    pub fn scan_loop(&self) -> ScanLoop {
+        self.scan_loop
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Re-read the config file and apply it if only reloadable settings
+    /// changed. Exclude patterns take effect with the next rescan.
+    pub fn reload(&self, engine: &CodeSearchEngine) -> TantivyResult<Reloaded> {
+        let Some(path) = &self.path else {
+            return Err(TantivyError::InvalidArgument(
+                "Started without a config file, there is nothing to reload".to_string(),
+            ));
+        };
+        let (conf, read) = config::read_config(path)?;
+        let mut config = AppConfig::new();
+        config.with_config(conf.scan_settings).with_cli(&self.cli);
+        config.validate()?;
+
+        let mut settings = self.settings.lock().unwrap_or_else(PoisonError::into_inner);
+        let (reloadable, fixed): (Vec<_>, Vec<_>) = changed_keys(&settings, &read)
+            .into_iter()
+            .partition(|key| RELOADABLE_SETTINGS.contains(&key.as_str()));
+        if !fixed.is_empty() {
+            return Ok(Reloaded::RestartRequired(fixed));
+        }
+        engine.set_exclude_patterns(config.engine.exclude_patterns.clone());
+        *self
+            .scan_loop
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = ScanLoop::of(&config);
+        *settings = read;
+        self.changed.notify_one();
+        let (overridden, applied) = reloadable
+            .into_iter()
+            .partition(|key| self.overridden_by_cli(key));
+        Ok(Reloaded::Applied {
+            applied,
+            overridden,
+        })
+    }
+
+    /// Whether the setting `key` of the file is overridden by a flag given
+    /// on the command line.
+    fn overridden_by_cli(&self, key: &str) -> bool {
+        CLI_OVERRIDES
+            .iter()
+            .any(|(setting, flag)| *setting == key && self.cli.contains_id(flag))
+    }
+}
+
+/// Keys of `scan_settings` added, removed or given another value between
+/// `old` and `new`, sorted.
+fn changed_keys(old: &Value, new: &Value) -> Vec<String> {
+    let empty = serde_json::Map::new();
+    let old = old.as_object().unwrap_or(&empty);
+    let new = new.as_object().unwrap_or(&empty);
+    let mut keys: Vec<String> = old
+        .keys()
+        .chain(new.keys())
+        .filter(|key| old.get(*key) != new.get(*key))
+        .cloned()
+        .collect();
+    keys.sort();
+    keys.dedup();
+    keys
+}
@@ -13,46 +13,900 @@
 // limitations under the License.
 
 mod config;
+mod config_reload;
+mod msgpack;
+mod params;
+mod rate_limit;
+mod response;
+mod search_limit;
 
+use futures_util::{stream, StreamExt};
 use humantime::parse_duration;
 
 use axum::{
-    extract::{Query, State},
-    http::Method,
-    response::Json,
-    routing::get,
+    body::Body,
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Json, Response,
+    },
+    routing::{get, post},
     Router,
 };
-use search_engine;
-use search_engine::CodeSearchEngine;
+use search_engine::{
+    Analyzer, CodeSearchEngine, EngineConfig, ExcludePaths, FacetField, QueryFields, QueryMode,
+    ResultFields, SearchOptions, SearchResults, SizeBucket, SnippetFormat, SortOrder, Source,
+};
 use serde::Deserialize;
 use serde_json::{json, Value};
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::fs;
+use std::future::IntoFuture;
+use std::io::{self, IsTerminal};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{RecvTimeoutError, Sender};
 use std::sync::Arc;
 use tantivy::{Result as TantivyResult, TantivyError};
-use tokio::time::{sleep, Duration};
-use tower_http::cors::{Any, CorsLayer};
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, mpsc, Notify};
+use tokio::task;
+use tokio::time::{self, sleep, Duration, Instant, MissedTickBehavior};
+use tower_http::cors::{AllowHeaders, AllowOrigin, Any, CorsLayer};
 
 use clap::{Arg, ArgGroup, Command};
+use config_reload::{ConfigReload, Reloaded};
+use params::{invalid_param, Params};
+use rate_limit::RateLimitConfig;
+use response::{OutputFormat, ResponseFormat};
+use search_limit::SearchLimit;
+
+/// Shared state handed to every route.
+#[derive(Clone)]
+struct AppState {
+    engine: Arc<CodeSearchEngine>,
+    response: ResponseFormat,
+    /// Query run when a request has no query text.
+    default_query: Option<String>,
+    searches: SearchLimit,
+    /// Most searches a `/search/batch` request may hold.
+    max_batch_searches: usize,
+    /// Set while the reload loop should skip its cycles.
+    reload_paused: Arc<AtomicBool>,
+    /// The scanned directory, rebuilt from by `/reindex`.
+    directory: String,
+    /// Extensions `/file` serves, lowercase without the dot. Any when `None`.
+    file_extensions: Option<Arc<HashSet<String>>>,
+    /// Least time between two lines of `/search/stream`, unpaced when `None`.
+    stream_interval: Option<Duration>,
+    config_reload: Arc<ConfigReload>,
+}
+
+impl AppState {
+    /// Whether `/file` may serve `path`. Files without an extension are
+    /// served when the list holds `""`.
+    fn viewable(&self, path: &str) -> bool {
+        let Some(allowed) = &self.file_extensions else {
+            return true;
+        };
+        let extension = Path::new(path)
+            .extension()
+            .map_or(String::new(), |extension| {
+                extension.to_string_lossy().to_lowercase()
+            });
+        allowed.contains(&extension)
+    }
+}
+
+/// Returned alongside empty results when there is nothing to search for.
+const EMPTY_QUERY_MESSAGE: &str = "No query given, pass one with ?text=...";
+
+/// Below the 30s orchestrators such as Kubernetes wait before killing.
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Wait before retrying to listen on an endpoint in use, e.g. by the
+/// previous process of a rolling restart.
+const DEFAULT_BIND_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Searches slow down noticeably past a few dozen segments.
+const DEFAULT_AUTO_COMPACT_SEGMENTS: usize = 20;
+
+/// Far more than a search page asks for at once.
+const DEFAULT_MAX_BATCH_SEARCHES: usize = 100;
+
+/// Returned with 503 when `max_concurrent_searches` are already running.
+fn too_many_searches() -> (StatusCode, Json<Value>) {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(json!({ "error": "Too many concurrent searches, try again later" })),
+    )
+}
+
+/// The analyzed field a search is run against.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Tokenizer {
+    /// Lowercased words, every file.
+    #[default]
+    Default,
+    /// Identifiers and their words, files mapped in `extension_analyzers`.
+    Code,
+    /// Words keeping their case, like `case_sensitive`.
+    Exact,
+}
 
 #[derive(Debug, Deserialize)]
 struct SearchParams {
-    text: String,
+    text: Option<String>,
+    min_score: Option<f32>,
+    top_percent: Option<f32>,
+    line_start: Option<usize>,
+    line_end: Option<usize>,
+    #[serde(default)]
+    smart_context: bool,
+    #[serde(default)]
+    dedent: bool,
+    /// `advanced` allows field scopes such as `path:src` in the query.
+    #[serde(default)]
+    mode: QueryMode,
+    /// `split` returns `before`, `matched_line` and `after` instead of `body`.
+    #[serde(default)]
+    snippet_format: SnippetFormat,
+    /// Joins the lines of `body`, such as `%0D%0A` for `\r\n`.
+    line_separator: Option<String>,
+    #[serde(default)]
+    prefix_last: bool,
+    /// `density` puts the files with the most matches first.
+    #[serde(default)]
+    sort: SortOrder,
+    /// A `*`/`?` pattern matched against each term, searched instead of `text`.
+    wildcard: Option<String>,
+    /// A regex matched against each term, searched instead of `text`.
+    regex: Option<String>,
+    /// Match `regex` regardless of case.
+    #[serde(default)]
+    case_insensitive: bool,
+    /// Comma-separated fields of each result to return, e.g. `path,line`.
+    #[serde(default)]
+    fields: ResultFields,
+    /// Comma-separated fields to match the query in, any one sufficing:
+    /// `body`, `path` or `comments`. Only `body` by default.
+    #[serde(default)]
+    search_fields: QueryFields,
+    /// Only search the files changed by the last reload.
+    #[serde(default)]
+    changed_only: bool,
+    /// `rg` answers with ripgrep's JSON Lines instead of a JSON document,
+    /// `hunks` merges the results of each file into hunks of context.
+    #[serde(default)]
+    format: OutputFormat,
+    /// Match the text literally, like `grep -F`.
+    #[serde(default)]
+    fixed: bool,
+    /// Keep at most this many results per file.
+    max_per_file: Option<usize>,
+    /// Keep at most this many results per directory.
+    max_per_dir: Option<usize>,
+    /// Only match files with this extension, e.g. `rs`. Needs `index_metadata`.
+    extension: Option<String>,
+    /// Only match files of this size: `small`, `medium`, `large` or `huge`.
+    size: Option<SizeBucket>,
+    /// Only match files below this top-level directory.
+    top_dir: Option<String>,
+    /// Comma-separated substrings or globs of paths to leave out, e.g.
+    /// `tests,*_test.rs`.
+    #[serde(default)]
+    exclude: ExcludePaths,
+    /// What `/search/facets` counts matches by: `extension`, `size` or
+    /// `top_dir`.
+    #[serde(default)]
+    by: FacetField,
+    /// Add the name of the enclosing function or class to each result.
+    #[serde(default)]
+    scope: bool,
+    /// Tell `HTTP` from `http`. Needs `index_exact_case`.
+    #[serde(default)]
+    case_sensitive: bool,
+    /// Search even if every term is shorter than `min_term_length`.
+    #[serde(default)]
+    allow_short_terms: bool,
+    /// Match only comments and docstrings. Needs `index_comments`.
+    #[serde(default)]
+    comments_only: bool,
+    /// Add where each matched line starts in its file, in bytes.
+    #[serde(default)]
+    byte_offset: bool,
+    /// Add the author, commit and date that last changed each matched line.
+    #[serde(default)]
+    blame: bool,
+    /// Flag results whose line on disk changed since it was indexed.
+    #[serde(default)]
+    verify_fresh: bool,
+    /// Add `preview`, the matched line cut to this many characters around
+    /// the match, e.g. for list views.
+    preview_chars: Option<usize>,
+    /// Match at most this many documents, see `has_more` in the response.
+    limit: Option<usize>,
+    /// Skip this many of the best matching documents.
+    #[serde(default)]
+    offset: usize,
+    /// `code` or `exact` search the terms of that analyzer instead.
+    #[serde(default)]
+    tokenizer: Tokenizer,
+}
+
+impl SearchParams {
+    /// The trimmed query text, or the wildcard or regex pattern when one is given.
+    /// A missing or whitespace-only query falls back to `default_query`, or
+    /// is `None` when there is no default either.
+    fn query_text<'a>(&'a self, default_query: Option<&'a str>) -> Option<&'a str> {
+        if let Some(pattern) = self.wildcard.as_ref().or(self.regex.as_ref()) {
+            return Some(pattern.as_str());
+        }
+        self.text
+            .as_deref()
+            .map(str::trim)
+            .filter(|text| !text.is_empty())
+            .or(default_query)
+    }
+
+    fn options(&self) -> SearchOptions {
+        let line_range = match (self.line_start, self.line_end) {
+            (None, None) => None,
+            (start, end) => Some((start.unwrap_or(1), end.unwrap_or(usize::MAX))),
+        };
+        SearchOptions {
+            min_score: self.min_score,
+            top_percent: self.top_percent,
+            line_range,
+            smart_context: self.smart_context,
+            wildcard: self.wildcard.is_some(),
+            regex: self.regex.is_some(),
+            case_insensitive: self.case_insensitive,
+            dedent: self.dedent,
+            mode: self.mode,
+            snippet_format: self.snippet_format,
+            line_separator: self.line_separator.clone(),
+            prefix_last: self.prefix_last,
+            sort: self.sort,
+            fields: self.fields,
+            search_fields: self.search_fields,
+            changed_only: self.changed_only,
+            fixed: self.fixed,
+            max_per_file: self.max_per_file,
+            max_per_dir: self.max_per_dir,
+            extension: self.extension.clone(),
+            size: self.size,
+            top_dir: self.top_dir.clone(),
+            exclude: self.exclude.clone(),
+            scope: self.scope,
+            analyzer: match self.tokenizer {
+                Tokenizer::Code => Analyzer::Code,
+                Tokenizer::Default | Tokenizer::Exact => Analyzer::Default,
+            },
+            case_sensitive: self.case_sensitive || self.tokenizer == Tokenizer::Exact,
+            allow_short_terms: self.allow_short_terms,
+            comments_only: self.comments_only,
+            byte_offset: self.byte_offset,
+            blame: self.blame,
+            verify_fresh: self.verify_fresh,
+            preview_chars: self.preview_chars,
+            limit: self.limit,
+            offset: self.offset,
+            ..SearchOptions::default()
+        }
+    }
+}
+
+/// Run the query described by `params`, or explain that there is none.
+/// Rejected queries, such as a wildcard matching every term, are a 400.
+/// What a search answers with: its results, or a body without any when
+/// there is no query or the search failed.
+enum SearchAnswer {
+    Results(SearchResults),
+    NoResults(Value),
+}
+
+impl SearchAnswer {
+    fn into_value(self) -> Value {
+        match self {
+            SearchAnswer::Results(results) => {
+                serde_json::to_value(results).unwrap_or_else(|_| json!({ "results": [] }))
+            }
+            SearchAnswer::NoResults(value) => value,
+        }
+    }
+
+    /// The answer as JSON, shaped by `format`.
+    fn render(self, format: &ResponseFormat) -> Value {
+        format.render(self.into_value())
+    }
+}
+
+async fn run_search(
+    state: &AppState,
+    params: &SearchParams,
+) -> Result<SearchAnswer, (StatusCode, Json<Value>)> {
+    let Some(text) = params.query_text(state.default_query.as_deref()) else {
+        let empty = json!({ "results": [], "message": EMPTY_QUERY_MESSAGE });
+        return Ok(SearchAnswer::NoResults(empty));
+    };
+    match blocking_search(state, text, &params.options()).await {
+        Ok(results) => Ok(SearchAnswer::Results(results)),
+        Err(TantivyError::InvalidArgument(e)) => Err(bad_request(e)),
+        Err(_) => Ok(SearchAnswer::NoResults(json!({ "results": [] }))),
+    }
+}
+
+/// Search on a blocking thread, as a search reads files and may run git
+/// for blame.
+async fn blocking_search(
+    state: &AppState,
+    text: &str,
+    options: &SearchOptions,
+) -> TantivyResult<SearchResults> {
+    let engine = state.engine.clone();
+    let (text, options) = (text.to_string(), options.clone());
+    task::spawn_blocking(move || engine.search_blocking(&text, &options))
+        .await
+        .map_err(|e| TantivyError::InternalError(e.to_string()))?
 }
 
+/// Answer with `answer` as JSON, or as MessagePack when `Accept` prefers
+/// it. Results are serialized as they are unless the response format
+/// reshapes them.
+fn respond(state: &AppState, headers: &HeaderMap, answer: SearchAnswer) -> Response {
+    match answer {
+        SearchAnswer::Results(results) if !state.response.reshapes() => {
+            msgpack::negotiate(headers, &results)
+        }
+        answer => msgpack::negotiate(headers, &answer.render(&state.response)),
+    }
+}
+
+fn bad_request(error: String) -> (StatusCode, Json<Value>) {
+    (StatusCode::BAD_REQUEST, Json(json!({ "error": error })))
+}
+
+/// Answers with MessagePack instead of JSON for `Accept: application/msgpack`.
 async fn search_handler(
-    State(search_engine): State<Arc<CodeSearchEngine>>,
-    Query(params): Query<SearchParams>,
-) -> Json<Value> {
-    match search_engine.search(&params.text).await {
-        Ok(value) => match serde_json::to_value(value) {
-            Ok(json_val) => Json(json_val),
-            Err(_) => Json(json!({ "results": [] })),
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Params(params): Params<SearchParams>,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    let _permit = state.searches.try_acquire().ok_or_else(too_many_searches)?;
+    match params.format {
+        OutputFormat::Json => {}
+        OutputFormat::Rg => return rg_search(&state, &params).await,
+        OutputFormat::Hunks => return hunk_search(&state, &headers, &params).await,
+    }
+    let answer = run_search(&state, &params).await?;
+    Ok(respond(&state, &headers, answer))
+}
+
+/// Run several searches at once, each given as an object of `/search`
+/// parameters. The answer holds each search's results in the order given,
+/// or `{"error": ...}` for a search that was invalid. JSON unless
+/// MessagePack is asked for, whatever each search's `format`. Each search
+/// takes a slot of `max_concurrent_searches`, waiting for one to free up,
+/// but the batch is refused when none is free to begin with.
+async fn search_batch_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(specs): Json<Vec<Value>>,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    if specs.len() > state.max_batch_searches {
+        let error = format!(
+            "A batch holds at most {} searches, got {}",
+            state.max_batch_searches,
+            specs.len()
+        );
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(json!({ "error": error })),
+        ));
+    }
+    let mut first = Some(state.searches.try_acquire().ok_or_else(too_many_searches)?);
+    let searches = specs.into_iter().map(|spec| {
+        let permit = first.take();
+        let state = state.clone();
+        async move {
+            let _permit = match permit {
+                Some(permit) => permit,
+                None => state.searches.acquire().await,
+            };
+            let params: SearchParams = match serde_path_to_error::deserialize(spec) {
+                Ok(params) => params,
+                Err(e) => {
+                    let (error, param) = invalid_param(&e);
+                    return json!({ "error": error, "param": param });
+                }
+            };
+            match run_search(&state, &params).await {
+                Ok(answer) => answer.render(&state.response),
+                Err((_, Json(error))) => error,
+            }
+        }
+    });
+    // Slots bound the searches running, this only the ones waiting for one.
+    let waiting = state.searches.max().unwrap_or(state.max_batch_searches);
+    let results = stream::iter(searches)
+        .buffered(waiting.max(1))
+        .collect()
+        .await;
+    Ok(msgpack::negotiate(&headers, &Value::Array(results)))
+}
+
+/// Answer a search with a ripgrep `match` record per result. Like
+/// `run_search`, a missing query or a failed search gives no results.
+async fn rg_search(
+    state: &AppState,
+    params: &SearchParams,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    let mut body = String::new();
+    if let Some(text) = params.query_text(state.default_query.as_deref()) {
+        let options = params.options();
+        let results = match blocking_search(state, text, &options).await {
+            Ok(results) => Some(results),
+            Err(TantivyError::InvalidArgument(e)) => return Err(bad_request(e)),
+            Err(_) => None,
+        };
+        let matcher = state.engine.rg_matcher(text, &options).ok();
+        if let (Some(results), Some(matcher)) = (results, matcher) {
+            for result in results.results() {
+                if let Some(record) = state.engine.rg_match(&matcher, result) {
+                    body.push_str(&format!("{record}\n"));
+                }
+            }
+        }
+    }
+    Ok(([(header::CONTENT_TYPE, "application/x-ndjson")], body).into_response())
+}
+
+/// Answer a search with hunks in place of results, each covering the
+/// neighbouring matches of a file.
+async fn hunk_search(
+    state: &AppState,
+    headers: &HeaderMap,
+    params: &SearchParams,
+) -> Result<Response, (StatusCode, Json<Value>)> {
+    let Some(text) = params.query_text(state.default_query.as_deref()) else {
+        let empty = json!({ "results": [], "message": EMPTY_QUERY_MESSAGE });
+        return Ok(respond(state, headers, SearchAnswer::NoResults(empty)));
+    };
+    let options = params.options();
+    let results = match blocking_search(state, text, &options).await {
+        Ok(results) => results,
+        Err(TantivyError::InvalidArgument(e)) => return Err(bad_request(e)),
+        Err(_) => {
+            let empty = SearchAnswer::NoResults(json!({ "results": [] }));
+            return Ok(respond(state, headers, empty));
+        }
+    };
+    let hunks = state.engine.hunks(results.results(), &options);
+    let mut value = serde_json::to_value(results).unwrap_or_else(|_| json!({}));
+    value["results"] = json!(hunks);
+    Ok(msgpack::negotiate(headers, &state.response.render(value)))
+}
+
+/// How many matches of a search fall into each file extension, size bucket
+/// or top-level directory. Needs `index_metadata`.
+async fn search_facets_handler(
+    State(state): State<AppState>,
+    Params(params): Params<SearchParams>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let _permit = state.searches.try_acquire().ok_or_else(too_many_searches)?;
+    let Some(text) = params.query_text(state.default_query.as_deref()) else {
+        return Ok(Json(
+            json!({ "counts": [], "message": EMPTY_QUERY_MESSAGE }),
+        ));
+    };
+    match state.engine.facets(text, &params.options(), params.by) {
+        Ok(facets) => Ok(Json(json!(facets))),
+        Err(TantivyError::InvalidArgument(e)) => Err(bad_request(e)),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": e.to_string() })),
+        )),
+    }
+}
+
+/// Number of serialized results buffered ahead of a slow streaming client.
+const STREAM_BUFFER: usize = 256;
+
+/// Stream results as newline-delimited JSON while they are produced, ending
+/// with a metadata line holding the timing and total count.
+async fn search_stream_handler(
+    State(state): State<AppState>,
+    Params(params): Params<SearchParams>,
+) -> Response {
+    // Hunks need every result of a file before the first can be sent.
+    if params.format == OutputFormat::Hunks {
+        return bad_request("format=hunks can't be streamed, use /search".to_string())
+            .into_response();
+    }
+    let Some(permit) = state.searches.try_acquire() else {
+        return too_many_searches().into_response();
+    };
+    let (tx, rx) = mpsc::channel::<String>(STREAM_BUFFER);
+    let engine = state.engine.clone();
+    let text = params
+        .query_text(state.default_query.as_deref())
+        .map(str::to_string);
+
+    task::spawn_blocking(move || {
+        let _permit = permit;
+        let Some(text) = text else {
+            let meta = json!({ "total": 0, "message": EMPTY_QUERY_MESSAGE });
+            let _ = tx.blocking_send(format!("{meta}\n"));
+            return;
+        };
+        let start = Instant::now();
+        let mut total = 0;
+        let options = params.options();
+        let matcher = match params.format {
+            OutputFormat::Json | OutputFormat::Hunks => None,
+            OutputFormat::Rg => engine.rg_matcher(&text, &options).ok(),
+        };
+        let outcome = engine.search_with(&text, &options, |mut result| {
+            total += 1;
+            engine.annotate(std::slice::from_mut(&mut result), &options);
+            let line = match &matcher {
+                Some(matcher) => engine
+                    .rg_match(matcher, &result)
+                    .map(|record| record.to_string()),
+                None => serde_json::to_string(&result).ok(),
+            };
+            match line {
+                // Stop searching once the client has gone away.
+                Some(line) => tx.blocking_send(line + "\n").is_ok(),
+                None => true,
+            }
+        });
+        let meta = match outcome {
+            // Ripgrep records have no metadata line to put it in.
+            Ok(_) if matcher.is_some() => return,
+            Ok(summary) => json!({
+                "time": start.elapsed().as_secs_f64(),
+                "total": total,
+                "dropped": summary.dropped,
+                "has_more": summary.has_more,
+                "timings": summary.timings,
+            }),
+            Err(e) => json!({ "error": e.to_string() }),
+        };
+        let _ = tx.blocking_send(format!("{meta}\n"));
+    });
+
+    // A late line doesn't make up for lost time with a burst.
+    let pacing = state.stream_interval.map(|period| {
+        let mut pacing = time::interval(period);
+        pacing.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        pacing
+    });
+    // Once the buffer is full, the search waits for the paced client.
+    let stream = futures_util::stream::unfold((rx, pacing), |(mut rx, mut pacing)| async move {
+        let line = rx.recv().await?;
+        if let Some(pacing) = &mut pacing {
+            pacing.tick().await;
+        }
+        Some((Ok::<_, Infallible>(line), (rx, pacing)))
+    });
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(stream),
+    )
+        .into_response()
+}
+
+/// Push fresh results over server-sent events whenever a reload changes the
+/// results of the query. The current results are sent right away.
+async fn search_live_handler(
+    State(state): State<AppState>,
+    Params(params): Params<SearchParams>,
+) -> Sse<impl futures_util::Stream<Item = Result<Event, Infallible>>> {
+    let changes = state.engine.subscribe();
+    let stream = futures_util::stream::unfold(
+        (state, params, changes, None::<Value>),
+        |(state, params, mut changes, mut last)| async move {
+            loop {
+                if last.is_some() {
+                    if let Err(broadcast::error::RecvError::Closed) = changes.recv().await {
+                        return None;
+                    }
+                }
+                let results = match run_search(&state, &params).await {
+                    Ok(answer) => answer.into_value(),
+                    Err((_, Json(error))) => error,
+                };
+
+                let unchanged = last
+                    .as_ref()
+                    .is_some_and(|last| last["results"] == results["results"]);
+                last = Some(results.clone());
+                if unchanged {
+                    continue;
+                }
+                let event = Event::default()
+                    .json_data(state.response.render(results))
+                    .unwrap_or_default();
+                return Some((Ok(event), (state, params, changes, last)));
+            }
         },
-        Err(_) => Json(json!({ "results": [] })),
+    );
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+#[derive(Debug, Deserialize)]
+struct PushedDocument {
+    path: String,
+    content: String,
+}
+
+/// Index content supplied in the request body rather than read from disk.
+async fn push_document_handler(
+    State(state): State<AppState>,
+    Json(document): Json<PushedDocument>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let engine = state.engine.clone();
+    let path = document.path.clone();
+    let indexed =
+        task::spawn_blocking(move || engine.push_document(&document.path, &document.content)).await;
+    match indexed {
+        Ok(Ok(lines)) => Ok(Json(json!({ "path": path, "lines": lines }))),
+        Ok(Err(e)) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": e.to_string() })),
+        )),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": e.to_string() })),
+        )),
+    }
+}
+
+/// Merge the index segments, dropping documents deleted by reloads.
+async fn compact_handler(
+    State(state): State<AppState>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let engine = state.engine.clone();
+    let compacted = task::spawn_blocking(move || engine.compact()).await;
+    let error = |e: String| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": e })),
+        )
+    };
+    match compacted {
+        Ok(Ok(compaction)) => Ok(Json(json!(compaction))),
+        Ok(Err(e)) => Err(error(e.to_string())),
+        Err(e) => Err(error(e.to_string())),
+    }
+}
+
+/// Throw the index away and build it again from the scanned directory.
+async fn reindex_handler(
+    State(state): State<AppState>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    match state.engine.reindex(&state.directory).await {
+        Ok(reindex) => Ok(Json(json!(reindex))),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": e.to_string() })),
+        )),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct FileParams {
+    path: String,
+}
+
+async fn file_handler(
+    State(state): State<AppState>,
+    Params(params): Params<FileParams>,
+) -> Result<Json<Value>, StatusCode> {
+    // Checked first, so disallowed paths don't reveal whether they're indexed.
+    if !state.viewable(&params.path) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    match state.engine.file_lines(&params.path) {
+        Some(lines) => Ok(Json(json!({ "path": params.path, "lines": lines }))),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+/// Whether `path` is indexed, without running a search.
+async fn file_exists_handler(
+    State(state): State<AppState>,
+    Params(params): Params<FileParams>,
+) -> Json<Value> {
+    Json(json!({
+        "path": params.path,
+        "indexed": state.engine.is_indexed(&params.path),
+        "lines": state.engine.line_count(&params.path),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct SnippetParams {
+    path: String,
+    line: usize,
+    /// Lines on each side of `line`, 3 by default.
+    context: Option<usize>,
+    /// Add the name of the enclosing function or class.
+    #[serde(default)]
+    scope: bool,
+    /// Add where `line` starts in the file, in bytes.
+    #[serde(default)]
+    byte_offset: bool,
+    /// Add the author, commit and date that last changed `line`.
+    #[serde(default)]
+    blame: bool,
+    /// Joins the lines of `body`.
+    line_separator: Option<String>,
+}
+
+/// Render the snippet search would return for a given location.
+async fn snippet_handler(
+    State(state): State<AppState>,
+    Params(params): Params<SnippetParams>,
+) -> Result<Json<Value>, StatusCode> {
+    let options = SearchOptions {
+        context_lines: params.context,
+        scope: params.scope,
+        byte_offset: params.byte_offset,
+        blame: params.blame,
+        line_separator: params.line_separator,
+        ..SearchOptions::default()
+    };
+    // Blame runs git.
+    let engine = state.engine.clone();
+    let snippet =
+        task::spawn_blocking(move || engine.snippet_at(&params.path, params.line, &options))
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+            .ok_or(StatusCode::NOT_FOUND)?;
+    serde_json::to_value(snippet)
+        .map(Json)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Default number of paths returned by `/files/name`.
+const DEFAULT_NAME_LIMIT: usize = 100;
+
+#[derive(Debug, Deserialize)]
+struct NameParams {
+    q: String,
+    limit: Option<usize>,
+}
+
+async fn file_name_handler(
+    State(state): State<AppState>,
+    Params(params): Params<NameParams>,
+) -> Json<Value> {
+    let limit = params.limit.unwrap_or(DEFAULT_NAME_LIMIT);
+    Json(json!({ "paths": state.engine.find_by_name(&params.q, limit) }))
+}
+
+#[derive(Debug, Deserialize)]
+struct SymbolParams {
+    name: String,
+}
+
+/// Where a symbol is defined according to ctags.
+async fn symbol_handler(
+    State(state): State<AppState>,
+    Params(params): Params<SymbolParams>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    match state.engine.find_symbol(&params.name) {
+        Ok(definitions) => Ok(Json(
+            json!({ "name": params.name, "definitions": definitions }),
+        )),
+        Err(TantivyError::InvalidArgument(e)) => Err(bad_request(e)),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": e.to_string() })),
+        )),
     }
 }
 
+/// Files found while scanning that aren't searchable, with the reason.
+async fn skipped_files_handler(State(state): State<AppState>) -> Json<Value> {
+    Json(json!(state.engine.skipped_files()))
+}
+
+#[derive(Debug, Deserialize)]
+struct StatsParams {
+    #[serde(default)]
+    memory: bool,
+}
+
+async fn stats_handler(
+    State(state): State<AppState>,
+    Params(params): Params<StatsParams>,
+) -> Result<Json<Value>, StatusCode> {
+    let stats = state
+        .engine
+        .stats(params.memory)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let mut stats = serde_json::to_value(stats).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    stats["searches"] = json!(state.searches.stats());
+    stats["reload"] = json!({ "paused": state.reload_paused.load(Ordering::Relaxed) });
+    Ok(Json(stats))
+}
+
+/// Stop the reload loop from rescanning until `/reload/resume`, e.g. during
+/// a large git operation. A reload already running is finished.
+async fn reload_pause_handler(State(state): State<AppState>) -> Json<Value> {
+    state.reload_paused.store(true, Ordering::Relaxed);
+    Json(json!({ "paused": true }))
+}
+
+/// Let the reload loop rescan again from its next cycle.
+async fn reload_resume_handler(State(state): State<AppState>) -> Json<Value> {
+    state.reload_paused.store(false, Ordering::Relaxed);
+    Json(json!({ "paused": false }))
+}
+
+/// Re-read the config file, applying the rescan interval, exclude patterns
+/// and pre-scan commands. Changes to other settings are refused with 409
+/// and nothing is applied.
+async fn config_reload_handler(
+    State(state): State<AppState>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    match state.config_reload.reload(&state.engine) {
+        Ok(Reloaded::Applied {
+            applied,
+            overridden,
+        }) => {
+            let mut body = json!({ "applied": applied });
+            if !overridden.is_empty() {
+                body["overridden_by_cli"] = json!(overridden);
+            }
+            Ok(Json(body))
+        }
+        Ok(Reloaded::RestartRequired(changed)) => Err((
+            StatusCode::CONFLICT,
+            Json(json!({
+                "error": format!("Changing {} requires a restart", changed.join(", ")),
+                "restart_required": changed,
+            })),
+        )),
+        Err(TantivyError::InvalidArgument(e)) => Err(bad_request(e)),
+        Err(e) => Err(bad_request(e.to_string())),
+    }
+}
+
+/// Readiness probe: 200 once a match-all search runs against the index,
+/// 503 with the reason otherwise. The server only listens once the initial
+/// index is built, so a 503 points at a broken index rather than a slow start.
+async fn ready_handler(State(state): State<AppState>) -> (StatusCode, Json<Value>) {
+    match state.engine.readiness() {
+        Ok(documents) => (
+            StatusCode::OK,
+            Json(json!({ "status": "ready", "documents": documents })),
+        ),
+        Err(reason) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "status": "not_ready", "reason": reason })),
+        ),
+    }
+}
+
+/// Identify the running build. Values are fixed at compile time.
+async fn version_handler() -> Json<Value> {
+    Json(json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_hash": env!("GIT_HASH"),
+        "build_timestamp": env!("BUILD_TIMESTAMP"),
+        "tantivy": tantivy::version(),
+    }))
+}
+
 fn build_cli() -> Command {
     Command::new("spidermonkey")
         .about("A rest api to index and search through the files.")
@@ -82,62 +936,402 @@ fn build_cli() -> Command {
                 .help("File path to YAML config to load.")
                 .value_parser(clap::value_parser!(PathBuf)),
         )
+        .arg(
+            Arg::new("snapshot")
+                .long("snapshot")
+                .value_name("DIR")
+                .help("Serve an index restored with `import` instead of indexing from scratch.")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
         .group(
             ArgGroup::new("input")
-                .args(&["directory", "config"])
+                .args(["directory", "config"])
                 .required(true), // Require one of the group
         )
+        .subcommand_negates_reqs(true)
+        .subcommand(
+            Command::new("export")
+                .about("Index the directory and write the index to a tarball.")
+                .arg(
+                    Arg::new("out")
+                        .long("out")
+                        .value_name("FILE")
+                        .required(true)
+                        .value_parser(clap::value_parser!(PathBuf)),
+                ),
+        )
+        .subcommand(
+            Command::new("search")
+                .about("Index the directory and print the lines matching TEXT, like grep.")
+                .arg(Arg::new("text").value_name("TEXT").required(true))
+                .arg(
+                    Arg::new("color")
+                        .long("color")
+                        .value_name("WHEN")
+                        .value_parser(["auto", "always", "never"])
+                        .default_value("auto")
+                        .help("Highlight matches, by default only when printing to a terminal."),
+                )
+                .arg(
+                    Arg::new("limit")
+                        .long("limit")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(usize)),
+                ),
+        )
+        .subcommand(
+            Command::new("import")
+                .about("Restore a tarball written by `export` into a directory.")
+                .arg(
+                    Arg::new("in")
+                        .long("in")
+                        .value_name("FILE")
+                        .required(true)
+                        .value_parser(clap::value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("dir")
+                        .long("dir")
+                        .value_name("DIR")
+                        .required(true)
+                        .value_parser(clap::value_parser!(PathBuf)),
+                ),
+        )
+}
+
+/// Merge the segments on a blocking thread when there are more than
+/// `max_segments`, unless a merge is already running.
+fn auto_compact(engine: &Arc<CodeSearchEngine>, max_segments: usize, compacting: &Arc<AtomicBool>) {
+    match engine.segment_count() {
+        Ok(segments) if segments > max_segments => {}
+        Ok(_) => return,
+        Err(e) => {
+            eprintln!("{e:#}");
+            return;
+        }
+    }
+    if compacting.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    let engine = engine.clone();
+    let compacting = compacting.clone();
+    task::spawn_blocking(move || {
+        match engine.compact() {
+            Ok(compaction) => println!("Auto-compacted segments: {}", json!(compaction)),
+            Err(e) => eprintln!("Auto-compaction failed: {e:#}"),
+        }
+        compacting.store(false, Ordering::Relaxed);
+    });
+}
+
+/// Rescan `directory` every scan loop interval, the first time after
+/// `first_delay`. Cycles are skipped while `paused` is set, and with
+/// `auto_compact` the segments are merged once there are more than that.
+async fn reload_loop(
+    engine: Arc<CodeSearchEngine>,
+    directory: String,
+    config_reload: Arc<ConfigReload>,
+    first_delay: Duration,
+    paused: Arc<AtomicBool>,
+    auto_compact: Option<usize>,
+) {
+    let compacting = Arc::new(AtomicBool::new(false));
+    let mut delay = first_delay;
+    loop {
+        // Wait for n seconds, counted again from a config reload.
+        tokio::select! {
+            _ = sleep(delay) => {}
+            _ = config_reload.changed.notified() => {
+                delay = config_reload.scan_loop().interval;
+                continue;
+            }
+        }
+        let scan_loop = config_reload.scan_loop();
+        delay = scan_loop.interval;
+        // Reloads wait for a running merge, skip the cycle rather than
+        // hold up a runtime thread on the writer.
+        if paused.load(Ordering::Relaxed) || compacting.load(Ordering::Relaxed) {
+            continue;
+        }
+        // A failed command is reported, the rescan runs regardless.
+        if let Err(e) = config::execute_pre_scan_commands(
+            scan_loop.pre_scan_commands,
+            directory.clone(),
+            &scan_loop.pre_scan_env,
+            scan_loop.pre_scan_timeout,
+        )
+        .await
+        {
+            eprintln!("{e:#}");
+        }
+        // Execute command.
+        if let Err(e) = engine.reload(directory.as_str()).await {
+            eprintln!("{e:#}");
+        }
+        if let Some(max_segments) = auto_compact {
+            self::auto_compact(&engine, max_segments, &compacting);
+        }
+    }
+}
+
+/// Print the matches of the `search` subcommand with their context, groups
+/// separated by `--` like `grep -C`.
+async fn cli_search(engine: &CodeSearchEngine, matches: &clap::ArgMatches) -> TantivyResult<()> {
+    let text = matches.get_one::<String>("text").unwrap();
+    let color = match matches.get_one::<String>("color").map(String::as_str) {
+        Some("always") => true,
+        Some("never") => false,
+        _ => std::io::stdout().is_terminal(),
+    };
+    let options = SearchOptions {
+        limit: matches.get_one::<usize>("limit").copied(),
+        ..SearchOptions::default()
+    };
+    let matcher = engine.rg_matcher(text, &options)?;
+    let results = engine.search(text, &options).await?;
+    for (i, result) in results.results().iter().enumerate() {
+        if i > 0 {
+            println!("--");
+        }
+        for line in engine.terminal_lines(&matcher, result, color) {
+            println!("{line}");
+        }
+    }
+    Ok(())
+}
+
+/// Say what failed during startup, keeping the underlying message. `main`
+/// returns it, so the process exits with a non-zero status.
+fn startup_error(context: String, e: TantivyError) -> TantivyError {
+    let reason = match e {
+        TantivyError::InvalidArgument(message) => message,
+        e => e.to_string(),
+    };
+    TantivyError::InvalidArgument(format!("{context}: {reason}"))
 }
 
 #[tokio::main]
 async fn main() -> TantivyResult<()> {
-    let app_conf = exec_cli()?;
+    let matches = build_cli().get_matches();
+    if let Some(("import", import)) = matches.subcommand() {
+        let archive = import.get_one::<PathBuf>("in").unwrap();
+        let target = import.get_one::<PathBuf>("dir").unwrap();
+        CodeSearchEngine::import(archive, target)?;
+        println!("Imported index into {}", target.display());
+        return Ok(());
+    }
+    let (app_conf, settings) = exec_cli(&matches)?;
+    // Build CORS middleware, failing on origins browsers would refuse.
+    let cors = app_conf.cors_layer()?;
+    let rate_limit = app_conf
+        .rate_limit
+        .map(|rate_limit| rate_limit.layer())
+        .transpose()?;
+    let config_reload = Arc::new(ConfigReload::new(&matches, settings, &app_conf));
 
-    println!("Spidermonkey startup");
+    let searching = matches!(matches.subcommand(), Some(("search", _)));
+    if !searching {
+        println!("Spidermonkey startup");
+    }
 
-    let search_app = Arc::new(
-        CodeSearchEngine::new(app_conf.directory.as_str(), app_conf.exclude_patterns)
+    let search_app = Arc::new(match &app_conf.snapshot {
+        Some(snapshot) => {
+            CodeSearchEngine::open_snapshot(snapshot, app_conf.engine).map_err(|e| {
+                startup_error(
+                    format!("Failed to open snapshot '{}'", snapshot.display()),
+                    e,
+                )
+            })?
+        }
+        None => CodeSearchEngine::new(app_conf.directory.as_str(), app_conf.engine)
             .await
-            .unwrap(),
-    );
-    let search_engine = Arc::new(search_app.clone());
+            .map_err(|e| startup_error(format!("Failed to index '{}'", app_conf.directory), e))?,
+    });
+
+    if let Some(("export", export)) = matches.subcommand() {
+        let out = export.get_one::<PathBuf>("out").unwrap();
+        search_app.export(out)?;
+        println!("Exported index to {}", out.display());
+        return Ok(());
+    }
+    if let Some(("search", search)) = matches.subcommand() {
+        return cli_search(&search_app, search).await;
+    }
+    let reload_paused = Arc::new(AtomicBool::new(false));
+    let directory = app_conf.directory.clone();
 
     // Spawn a task to scan disk for changes every n seconds.
-    tokio::spawn(async move {
-        loop {
-            sleep(app_conf.interval).await; // Wait for n seconds.
-            let _ = config::execute_pre_scan_commands(
-                app_conf.pre_scan_commands.clone(),
-                app_conf.directory.clone(),
-            )
-            .await;
-            // Execute command.
-            match search_engine.reload(app_conf.directory.as_str()).await {
-                Err(e) => {
-                    eprintln!("{e:#}");
-                }
-                _ => {}
-            }
-        }
+    // The first reload can come sooner, e.g. to catch up on changes made
+    // while a large tree was being indexed.
+    tokio::spawn(reload_loop(
+        search_app.clone(),
+        app_conf.directory.clone(),
+        config_reload.clone(),
+        app_conf.initial_delay.unwrap_or(app_conf.interval),
+        reload_paused.clone(),
+        app_conf
+            .auto_compact
+            .then_some(app_conf.auto_compact_segments),
+    ));
+
+    // Pass state into the router
+    let mut app = router(AppState {
+        engine: search_app.clone(),
+        response: app_conf.response,
+        default_query: app_conf.default_query,
+        searches: SearchLimit::new(app_conf.max_concurrent_searches),
+        max_batch_searches: app_conf.max_batch_searches,
+        reload_paused: reload_paused.clone(),
+        directory,
+        file_extensions: app_conf.file_extensions.map(Arc::new),
+        stream_interval: app_conf
+            .stream_results_per_second
+            .map(|per_second| Duration::from_secs_f64(1.0 / per_second)),
+        config_reload,
     });
+    if let Some(rate_limit) = rate_limit {
+        app = app.layer(rate_limit);
+    }
+    let app = app.layer(cors);
 
-    // Build CORS middleware
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods([Method::GET, Method::OPTIONS])
-        .allow_headers(Any);
+    let listener = bind(
+        &app_conf.endpoint,
+        app_conf.bind_retries,
+        app_conf.bind_retry_delay,
+    )
+    .await
+    .map_err(|e| {
+        // Only an endpoint in use is retried.
+        let context = match app_conf.bind_retries {
+            retries if retries > 0 && e.kind() == io::ErrorKind::AddrInUse => format!(
+                "Failed to listen on {} after {} attempts",
+                app_conf.endpoint,
+                retries + 1
+            ),
+            _ => format!("Failed to listen on {}", app_conf.endpoint),
+        };
+        startup_error(context, e.into())
+    })?;
+    println!("listening on {}", listener.local_addr()?);
+    // Client addresses are needed to rate limit per IP.
+    let stop_serving = Arc::new(Notify::new());
+    let server = axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown({
+        let stop_serving = stop_serving.clone();
+        async move { stop_serving.notified().await }
+    })
+    .into_future();
+    tokio::pin!(server);
+    tokio::select! {
+        served = &mut server => served?,
+        () = shutdown_signal() => {
+            let timeout = app_conf.shutdown_timeout;
+            println!(
+                "Shutting down, waiting up to {} for commits and requests",
+                humantime::format_duration(timeout)
+            );
+            // No reload starts anymore, one already running gets to commit.
+            reload_paused.store(true, Ordering::Relaxed);
+            stop_serving.notify_one();
+            let finished = shutdown_deadline(timeout, move || {
+                eprintln!(
+                    "Warning: shutdown didn't finish within {}, exiting anyway",
+                    humantime::format_duration(timeout)
+                );
+                std::process::exit(1);
+            });
+            let _ = task::spawn_blocking(move || search_app.wait_for_writes()).await;
+            server.await?;
+            let _ = finished.send(());
+        }
+    }
+    Ok(())
+}
 
-    // Pass state into the router
-    let app = Router::new()
+/// The routes of the server, without the CORS and rate limiting layers.
+fn router(state: AppState) -> Router {
+    Router::new()
         .route("/search", get(search_handler))
-        .with_state(search_app)
-        .layer(cors);
-    let listener = tokio::net::TcpListener::bind(app_conf.endpoint)
-        .await
-        .unwrap();
-    println!("listening on {}", listener.local_addr().unwrap());
-    axum::serve(listener, app).await?;
-    Ok(())
+        .route("/search/batch", post(search_batch_handler))
+        .route("/search/stream", get(search_stream_handler))
+        .route("/search/live", get(search_live_handler))
+        .route("/search/facets", get(search_facets_handler))
+        .route("/documents", post(push_document_handler))
+        .route("/compact", post(compact_handler))
+        .route("/reindex", post(reindex_handler))
+        .route("/reload/pause", post(reload_pause_handler))
+        .route("/reload/resume", post(reload_resume_handler))
+        .route("/config/reload", post(config_reload_handler))
+        .route("/file", get(file_handler))
+        .route("/snippet", get(snippet_handler))
+        .route("/files/name", get(file_name_handler))
+        .route("/files/skipped", get(skipped_files_handler))
+        .route("/files/exists", get(file_exists_handler))
+        .route("/symbol", get(symbol_handler))
+        .route("/stats", get(stats_handler))
+        .route("/health/ready", get(ready_handler))
+        .route("/version", get(version_handler))
+        .with_state(state)
+}
+
+/// Listen on `endpoint`, trying up to `retries` more times while the
+/// address is in use, waiting `delay` before the first retry and twice as
+/// long before each next one.
+async fn bind(endpoint: &str, retries: u32, delay: Duration) -> io::Result<TcpListener> {
+    let mut delay = delay;
+    let mut attempt = 0;
+    loop {
+        match TcpListener::bind(endpoint).await {
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse && attempt < retries => {
+                attempt += 1;
+                eprintln!(
+                    "Failed to listen on {endpoint}: {e}, retrying in {} ({attempt}/{retries})",
+                    humantime::format_duration(delay)
+                );
+                sleep(delay).await;
+                delay = delay.saturating_mul(2);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Resolves on Ctrl+C, or on SIGTERM as sent by orchestrators.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to listen for Ctrl+C");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        () = ctrl_c => {}
+        () = terminate => {}
+    }
+}
+
+/// Run `on_timeout` unless the returned sender is used within `timeout`.
+/// A thread of its own keeps time even while a reload's indexing keeps
+/// every runtime worker busy.
+fn shutdown_deadline(timeout: Duration, on_timeout: impl FnOnce() + Send + 'static) -> Sender<()> {
+    let (finished, deadline) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        if let Err(RecvTimeoutError::Timeout) = deadline.recv_timeout(timeout) {
+            on_timeout();
+        }
+    });
+    finished
 }
 
 #[derive(Debug)]
@@ -145,8 +1339,43 @@ struct AppConfig {
     directory: String,
     endpoint: String,
     pre_scan_commands: Vec<String>,
+    /// Variables added to the environment of the pre-scan commands.
+    pre_scan_env: HashMap<String, String>,
+    /// Longest a single pre-scan command may run before it is killed.
+    pre_scan_timeout: Option<Duration>,
     interval: Duration,
-    exclude_patterns: Vec<String>,
+    /// Wait before the first reload, `interval` when unset.
+    initial_delay: Option<Duration>,
+    engine: EngineConfig,
+    response: ResponseFormat,
+    rate_limit: Option<RateLimitConfig>,
+    default_query: Option<String>,
+    snapshot: Option<PathBuf>,
+    max_concurrent_searches: Option<usize>,
+    max_batch_searches: usize,
+    /// Longest a shutdown waits for a running commit and open requests.
+    shutdown_timeout: Duration,
+    /// Merge the segments in the background once a reload leaves more than
+    /// `auto_compact_segments`.
+    auto_compact: bool,
+    auto_compact_segments: usize,
+    /// Extensions `/file` serves, lowercase without the dot. Any when `None`.
+    file_extensions: Option<HashSet<String>>,
+    /// Pace `/search/stream` to at most this many lines per second.
+    stream_results_per_second: Option<f64>,
+    /// Further attempts to listen on `endpoint` while it is in use.
+    bind_retries: u32,
+    /// Wait before the first retry, doubled before each next one.
+    bind_retry_delay: Duration,
+    /// Origins browsers may call from. Any when `None`.
+    cors_origins: Option<Vec<String>>,
+    /// How long browsers may cache a preflight response.
+    cors_max_age: Option<Duration>,
+    /// Let browsers send cookies and authorization headers.
+    cors_allow_credentials: bool,
+    /// Why settings given an unusable value were left out, for
+    /// `validate()` to report rather than ignore.
+    invalid_settings: Vec<String>,
 }
 
 impl AppConfig {
@@ -155,8 +1384,28 @@ impl AppConfig {
             directory: String::new(),
             endpoint: "127.0.0.1:3000".to_string(),
             pre_scan_commands: Vec::new(),
+            pre_scan_env: HashMap::new(),
+            pre_scan_timeout: None,
             interval: Duration::from_secs(30),
-            exclude_patterns: vec![".git".to_string()],
+            initial_delay: None,
+            engine: EngineConfig::default(),
+            response: ResponseFormat::default(),
+            rate_limit: None,
+            default_query: None,
+            snapshot: None,
+            max_concurrent_searches: None,
+            max_batch_searches: DEFAULT_MAX_BATCH_SEARCHES,
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            auto_compact: false,
+            auto_compact_segments: DEFAULT_AUTO_COMPACT_SEGMENTS,
+            file_extensions: None,
+            stream_results_per_second: None,
+            bind_retries: 0,
+            bind_retry_delay: DEFAULT_BIND_RETRY_DELAY,
+            cors_origins: None,
+            cors_max_age: None,
+            cors_allow_credentials: false,
+            invalid_settings: Vec::new(),
         }
     }
 
@@ -170,13 +1419,191 @@ impl AppConfig {
         if let Some(cmds) = settings.pre_scan_commands {
             self.pre_scan_commands = cmds;
         }
+        if let Some(env) = settings.pre_scan_env {
+            self.pre_scan_env = env;
+        }
+        if let Some(timeout) = settings.pre_scan_timeout {
+            if let Some(dur) = self.duration("pre_scan_timeout", &timeout) {
+                self.pre_scan_timeout = Some(dur);
+            }
+        }
         if let Some(intv) = settings.rescan_interval {
-            if let Ok(dur) = parse_duration(&intv) {
+            if let Some(dur) = self.duration("rescan_interval", &intv) {
                 self.interval = dur;
             }
         }
+        if let Some(delay) = settings.initial_delay {
+            if let Some(dur) = self.duration("initial_delay", &delay) {
+                self.initial_delay = Some(dur);
+            }
+        }
         if let Some(excludes) = settings.exclude_patterns {
-            self.exclude_patterns = excludes;
+            self.engine.exclude_patterns = excludes;
+        }
+        if let Some(index_archives) = settings.index_archives {
+            self.engine.index_archives = index_archives;
+        }
+        if let Some(skip_non_text) = settings.skip_non_text {
+            self.engine.skip_non_text = skip_non_text;
+        }
+        if let Some(detect_content_type) = settings.detect_content_type {
+            self.engine.detect_content_type = detect_content_type;
+        }
+        if let Some(normalize_unicode) = settings.normalize_unicode {
+            self.engine.normalize_unicode = normalize_unicode;
+        }
+        if let Some(max_lines) = settings.max_lines_per_file {
+            self.engine.max_lines_per_file = Some(max_lines);
+        }
+        if let Some(git_ref) = settings.git_ref {
+            self.engine.git_ref = Some(git_ref);
+        }
+        if let Some(source) = settings.source {
+            self.engine.source = source;
+        }
+        if let Some(stop_words) = settings.stop_words {
+            self.engine.stop_words = stop_words;
+        }
+        if let Some(docs) = settings.commit_every_docs {
+            self.engine.commit_every_docs = Some(docs);
+        }
+        if let Some(bytes) = settings.commit_every_bytes {
+            self.engine.commit_every_bytes = Some(bytes);
+        }
+        if let Some(per_second) = settings.rate_limit_per_second {
+            self.rate_limit = Some(RateLimitConfig {
+                per_second,
+                // Default to a one second burst.
+                burst: settings
+                    .rate_limit_burst
+                    .unwrap_or(per_second.ceil() as u32),
+            });
+        }
+        if let Some(analyzers) = settings.extension_analyzers {
+            self.engine.extension_analyzers = analyzers;
+        }
+        if let Some(default_query) = settings.default_query {
+            self.default_query = Some(default_query);
+        }
+        if let Some(extensions) = settings.file_extensions {
+            let normalize = |extension: String| extension.trim_start_matches('.').to_lowercase();
+            self.file_extensions = Some(extensions.into_iter().map(normalize).collect());
+        }
+        if let Some(per_second) = settings.stream_results_per_second {
+            self.stream_results_per_second = Some(per_second);
+        }
+        if let Some(retries) = settings.bind_retries {
+            self.bind_retries = retries;
+        }
+        if let Some(delay) = settings.bind_retry_delay {
+            if let Some(dur) = self.duration("bind_retry_delay", &delay) {
+                self.bind_retry_delay = dur;
+            }
+        }
+        if let Some(origins) = settings.cors_origins {
+            self.cors_origins = Some(origins);
+        }
+        if let Some(secs) = settings.cors_max_age_secs {
+            self.cors_max_age = Some(Duration::from_secs(secs));
+        }
+        if let Some(allow) = settings.cors_allow_credentials {
+            self.cors_allow_credentials = allow;
+        }
+        if let Some(max) = settings.max_concurrent_searches {
+            self.max_concurrent_searches = Some(max);
+        }
+        if let Some(max) = settings.max_batch_searches {
+            self.max_batch_searches = max;
+        }
+        if let Some(timeout) = settings.shutdown_timeout {
+            if let Some(dur) = self.duration("shutdown_timeout", &timeout) {
+                self.shutdown_timeout = dur;
+            }
+        }
+        if let Some(schema) = settings.schema {
+            self.engine.schema = schema;
+        }
+        if let Some(roots) = settings.roots {
+            self.engine.roots = roots;
+        }
+        if let Some(dedupe) = settings.dedupe_content {
+            self.engine.dedupe_content = dedupe;
+        }
+        if let Some(extensions) = settings.exclude_extensions {
+            self.engine.exclude_extensions = extensions;
+        }
+        if let Some(max_open_files) = settings.max_open_files {
+            self.engine.max_open_files = max_open_files;
+        }
+        if let Some(boost) = settings.file_name_boost {
+            self.engine.file_name_boost = boost;
+        }
+        if let Some(trim) = settings.trim_trailing_whitespace {
+            self.engine.trim_trailing_whitespace = trim;
+        }
+        if let Some(granularity) = settings.granularity {
+            self.engine.granularity = granularity;
+        }
+        if let Some(use_defaults) = settings.use_default_excludes {
+            self.engine.use_default_excludes = use_defaults;
+        }
+        if let Some(max) = settings.max_query_length {
+            self.engine.max_query_length = Some(max);
+        }
+        if let Some(max) = settings.max_query_terms {
+            self.engine.max_query_terms = Some(max);
+        }
+        if let Some(min) = settings.min_term_length {
+            self.engine.min_term_length = Some(min);
+        }
+        if let Some(sanitize) = settings.sanitize_output {
+            self.engine.sanitize_output = sanitize;
+        }
+        if let Some(separator) = settings.snippet_line_separator {
+            self.engine.snippet_line_separator = separator;
+        }
+        if let Some(width) = settings.max_snippet_width {
+            self.engine.max_snippet_width = Some(width);
+        }
+        if let Some(max) = settings.max_cached_bytes {
+            self.engine.max_cached_bytes = Some(max);
+        }
+        if let Some(index_metadata) = settings.index_metadata {
+            self.engine.index_metadata = index_metadata;
+        }
+        if let Some(command) = settings.ctags_command {
+            match shell_words::split(&command) {
+                Ok(command) => self.engine.ctags_command = command,
+                Err(e) => self
+                    .invalid_settings
+                    .push(format!("Invalid ctags_command '{command}': {e}")),
+            }
+        }
+        if let Some(patterns) = settings.follow_patterns {
+            self.engine.follow_patterns = patterns;
+        }
+        if let Some(exact_case) = settings.index_exact_case {
+            self.engine.index_exact_case = exact_case;
+        }
+        if let Some(comments) = settings.index_comments {
+            self.engine.index_comments = comments;
+        }
+        if let Some(half_life) = settings.recency_half_life {
+            if let Some(dur) = self.duration("recency_half_life", &half_life) {
+                self.engine.recency_half_life = Some(dur);
+            }
+        }
+        if let Some(auto_compact) = settings.auto_compact {
+            self.auto_compact = auto_compact;
+        }
+        if let Some(segments) = settings.auto_compact_segments {
+            self.auto_compact_segments = segments;
+        }
+        if let Some(envelope) = settings.response_envelope {
+            self.response.envelope = envelope;
+        }
+        if let Some(field_case) = settings.field_case {
+            self.response.field_case = field_case;
         }
         self
     }
@@ -189,34 +1616,135 @@ impl AppConfig {
             self.endpoint = endpoint.clone();
         }
         if let Some(interval) = matches.get_one::<String>("interval") {
-            if let Ok(dur) = parse_duration(interval) {
+            if let Some(dur) = self.duration("--interval", interval) {
                 self.interval = dur;
             }
         }
+        if let Some(snapshot) = matches.get_one::<PathBuf>("snapshot") {
+            self.snapshot = Some(snapshot.clone());
+        }
         self
     }
 
+    /// Parse `value`, the duration given for `setting`. A value that isn't
+    /// a duration is kept for `validate()` to reject, rather than ignored.
+    fn duration(&mut self, setting: &str, value: &str) -> Option<Duration> {
+        match parse_duration(value) {
+            Ok(duration) => Some(duration),
+            Err(e) => {
+                self.invalid_settings.push(format!(
+                    "Invalid {setting} '{value}': {e}, expected e.g. 30s, 5m or 1h 30m"
+                ));
+                None
+            }
+        }
+    }
+
     fn validate(&self) -> TantivyResult<()> {
-        if self.directory.trim().is_empty() {
+        if let Some(invalid) = self.invalid_settings.first() {
+            return Err(TantivyError::InvalidArgument(invalid.clone()));
+        }
+        self.engine.source.validate(&self.engine)?;
+        // The files of a remote source are listed by it, a directory is
+        // only needed to run the pre-scan commands in.
+        let remote = self.engine.source != Source::Filesystem;
+        if !(remote && self.directory.is_empty()) {
+            if self.directory.trim().is_empty() {
+                return Err(TantivyError::InvalidArgument(
+                    "Directory path cannot be empty.".to_string(),
+                ));
+            }
+            // Otherwise a mistyped path serves an empty index without complaint.
+            if let Err(e) = fs::read_dir(&self.directory) {
+                return Err(TantivyError::InvalidArgument(format!(
+                    "Cannot read directory '{}': {}",
+                    self.directory, e
+                )));
+            }
+        }
+        if self.engine.max_snippet_width.is_some_and(|width| width < 3) {
             return Err(TantivyError::InvalidArgument(
-                "Directory path cannot be empty.".to_string(),
+                "max_snippet_width must be at least 3, to fit a character between two `…`"
+                    .to_string(),
             ));
         }
+        self.engine.schema.validate(&self.engine)?;
+        if let Some(per_second) = self.stream_results_per_second {
+            // The time per line has to fit a `Duration` without rounding to zero.
+            let period = Duration::try_from_secs_f64(1.0 / per_second);
+            if !(per_second > 0.0 && period.is_ok_and(|period| !period.is_zero())) {
+                return Err(TantivyError::InvalidArgument(format!(
+                    "stream_results_per_second must be a positive number, got {per_second}"
+                )));
+            }
+        }
 
         Ok(())
     }
 }
 
-fn exec_cli() -> TantivyResult<AppConfig> {
-    let matches = build_cli().get_matches();
+impl AppConfig {
+    /// The CORS middleware for the configured origins, or why they are
+    /// invalid. Browsers refuse credentials with a wildcard origin, so
+    /// `cors_allow_credentials` needs `cors_origins` to list each one.
+    fn cors_layer(&self) -> TantivyResult<CorsLayer> {
+        let methods = [Method::GET, Method::POST, Method::OPTIONS];
+        let mut cors = CorsLayer::new().allow_methods(methods);
+        let origins = match &self.cors_origins {
+            Some(origins) if !origins.iter().any(|origin| origin == "*") => origins,
+            _ if self.cors_allow_credentials => {
+                return Err(TantivyError::InvalidArgument(
+                    "cors_allow_credentials needs cors_origins to list each origin, \
+                     browsers refuse credentials with a wildcard origin"
+                        .to_string(),
+                ));
+            }
+            _ => return Ok(self.with_max_age(cors.allow_origin(Any).allow_headers(Any))),
+        };
+        let origins = origins
+            .iter()
+            .map(|origin| {
+                HeaderValue::from_str(origin).map_err(|_| {
+                    TantivyError::InvalidArgument(format!("Invalid CORS origin '{origin}'"))
+                })
+            })
+            .collect::<TantivyResult<Vec<_>>>()?;
+        cors = cors.allow_origin(AllowOrigin::list(origins));
+        // A wildcard header list is refused with credentials as well.
+        if self.cors_allow_credentials {
+            cors = cors
+                .allow_credentials(true)
+                .allow_headers(AllowHeaders::mirror_request());
+        } else {
+            cors = cors.allow_headers(Any);
+        }
+        Ok(self.with_max_age(cors))
+    }
+
+    fn with_max_age(&self, cors: CorsLayer) -> CorsLayer {
+        match self.cors_max_age {
+            Some(max_age) => cors.max_age(max_age),
+            None => cors,
+        }
+    }
+}
+
+/// The config given by the file and flags in `matches`, and the file's
+/// `scan_settings` as read (`null` without a file).
+fn exec_cli(matches: &clap::ArgMatches) -> TantivyResult<(AppConfig, Value)> {
     let mut config = AppConfig::new();
+    let mut settings = Value::Null;
 
     if let Some(config_path) = matches.get_one::<PathBuf>("config") {
-        let conf = config::read_config(config_path.to_path_buf())?;
+        let (conf, read) = config::read_config(config_path)?;
         config.with_config(conf.scan_settings);
+        settings = read;
     }
 
-    config.with_cli(&matches);
+    config.with_cli(matches);
     config.validate()?;
-    Ok(config)
+    Ok((config, settings))
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,99 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Answering with MessagePack instead of JSON when the client asks for it.
+
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Json, Response};
+use serde::Serialize;
+use serde_json::json;
+
+/// Media types accepted for MessagePack. `application/x-msgpack` predates
+/// the registered one but is still common.
+const MSGPACK_TYPES: [&str; 2] = ["application/msgpack", "application/x-msgpack"];
+const JSON_TYPES: [&str; 3] = ["application/json", "application/*", "*/*"];
+
+/// `body` as MessagePack when `Accept` prefers it over JSON, as JSON
+/// otherwise. A tie goes to MessagePack, which has to be asked for by name.
+/// Maps are written with their keys, as in JSON.
+pub fn negotiate<T: Serialize>(headers: &HeaderMap, body: &T) -> Response {
+    let accept = headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(",");
+    let msgpack = quality(&accept, &MSGPACK_TYPES);
+    if msgpack == 0.0 || msgpack < quality(&accept, &JSON_TYPES) {
+        return Json(body).into_response();
+    }
+    match rmp_serde::to_vec_named(body) {
+        Ok(bytes) => ([(header::CONTENT_TYPE, MSGPACK_TYPES[0])], bytes).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+/// The highest `q` of the media ranges in `accept` that are one of `types`,
+/// 0 when none is.
+fn quality(accept: &str, types: &[&str]) -> f32 {
+    accept
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';').map(str::trim);
+            let media_type = parts.next()?;
+            if !types.iter().any(|t| t.eq_ignore_ascii_case(media_type)) {
+                return None;
+            }
+            let q = parts
+                .filter_map(|param| param.strip_prefix("q="))
+                .find_map(|q| q.parse().ok())
+                .unwrap_or(1.0);
+            Some(q)
+        })
+        .fold(0.0, f32::max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn msgpack_is_sent_only_when_preferred() {
+        let content_type = |accept: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::ACCEPT, accept.parse().unwrap());
+            let response = negotiate(&headers, &json!({}));
+            response.headers()[header::CONTENT_TYPE].clone()
+        };
+        assert_eq!(content_type("application/msgpack"), MSGPACK_TYPES[0]);
+        assert_eq!(content_type("application/x-msgpack"), MSGPACK_TYPES[0]);
+        assert_eq!(
+            content_type("application/json, application/msgpack"),
+            MSGPACK_TYPES[0]
+        );
+        assert_eq!(
+            content_type("application/msgpack;q=0.5, application/json"),
+            "application/json"
+        );
+        assert_eq!(content_type("*/*"), "application/json");
+        assert_eq!(
+            negotiate(&HeaderMap::new(), &json!({})).headers()[header::CONTENT_TYPE],
+            "application/json"
+        );
+    }
+}
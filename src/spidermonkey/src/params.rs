@@ -0,0 +1,63 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Parsing query string parameters into a 400 naming the invalid one.
+
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::Json;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::fmt::Display;
+
+/// Like [`axum::extract::Query`], but rejects invalid parameters with 400
+/// and `{"error": ..., "param": ...}` instead of a plain text 422. `param`
+/// is `null` when the error isn't about a single parameter.
+pub struct Params<T>(pub T);
+
+impl<T, S> FromRequestParts<S> for Params<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, Json<Value>);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let query = parts.uri.query().unwrap_or_default();
+        let deserializer =
+            serde_urlencoded::Deserializer::new(form_urlencoded::parse(query.as_bytes()));
+        serde_path_to_error::deserialize(deserializer)
+            .map(Params)
+            .map_err(|e| {
+                let (error, param) = invalid_param(&e);
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({ "error": error, "param": param })),
+                )
+            })
+    }
+}
+
+/// The error message for a failed deserialization and the parameter it
+/// is about, if any.
+pub fn invalid_param<E: Display>(e: &serde_path_to_error::Error<E>) -> (String, Option<String>) {
+    let param = e.path().to_string();
+    if param == "." {
+        (format!("Invalid query string: {}", e.inner()), None)
+    } else {
+        let error = format!("Invalid value for `{}`: {}", param, e.inner());
+        (error, Some(param))
+    }
+}
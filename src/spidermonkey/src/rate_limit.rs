@@ -0,0 +1,72 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Per client IP rate limiting.
+
+use axum::body::Body;
+use governor::middleware::NoOpMiddleware;
+use std::time::Duration;
+use tantivy::{Result as TantivyResult, TantivyError};
+use tokio::time;
+use tower_governor::governor::GovernorConfigBuilder;
+use tower_governor::key_extractor::PeerIpKeyExtractor;
+use tower_governor::GovernorLayer;
+
+/// How often clients that got their whole burst back are forgotten.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Middleware answering 429 once a client exceeds its rate.
+pub type RateLimitLayer = GovernorLayer<PeerIpKeyExtractor, NoOpMiddleware, Body>;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitConfig {
+    /// Sustained requests per second allowed for each client.
+    pub per_second: f64,
+    /// Requests a client may make in a burst after being idle.
+    pub burst: u32,
+}
+
+impl RateLimitConfig {
+    /// The middleware limiting each client IP to this rate, or why the rate
+    /// is invalid. Spawns a task forgetting idle clients, so it has to be
+    /// called within the runtime.
+    pub fn layer(&self) -> TantivyResult<RateLimitLayer> {
+        let period = Duration::try_from_secs_f64(1.0 / self.per_second)
+            .ok()
+            .filter(|period| self.per_second > 0.0 && !period.is_zero());
+        let config = period
+            .and_then(|period| {
+                GovernorConfigBuilder::default()
+                    .period(period)
+                    .burst_size(self.burst.max(1))
+                    .finish()
+            })
+            .ok_or_else(|| {
+                TantivyError::InvalidArgument(format!(
+                    "rate_limit_per_second must be a positive number, got {}",
+                    self.per_second
+                ))
+            })?;
+
+        let limiter = config.limiter().clone();
+        tokio::spawn(async move {
+            let mut prune = time::interval(PRUNE_INTERVAL);
+            loop {
+                prune.tick().await;
+                limiter.retain_recent();
+            }
+        });
+        Ok(GovernorLayer::new(config))
+    }
+}
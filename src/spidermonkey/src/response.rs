@@ -0,0 +1,176 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Shaping of the JSON returned to clients.
+
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+/// How search results are wrapped in the response body.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Envelope {
+    /// `{"results": [...], "time": ...}`
+    #[default]
+    Plain,
+    /// `{"data": [...], "meta": {"time": ..., "total": ...}}`
+    Data,
+}
+
+/// Naming convention of the keys in the response body.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldCase {
+    /// `line_range`
+    #[default]
+    Snake,
+    /// `lineRange`
+    Camel,
+}
+
+/// Serialization of the search results themselves.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Spidermonkey's own JSON, shaped by [`ResponseFormat`].
+    #[default]
+    Json,
+    /// One ripgrep `--json` `match` record per line, without the `begin`,
+    /// `end` and `summary` records.
+    Rg,
+    /// Spidermonkey's own JSON, with the results of each file merged into
+    /// hunks of context that don't overlap, like `grep -C`.
+    Hunks,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ResponseFormat {
+    pub envelope: Envelope,
+    pub field_case: FieldCase,
+}
+
+impl ResponseFormat {
+    /// Whether [`Self::render`] changes anything.
+    pub fn reshapes(&self) -> bool {
+        self.envelope != Envelope::Plain || self.field_case != FieldCase::Snake
+    }
+
+    /// Reshape serialized `SearchResults` according to the configured format.
+    pub fn render(&self, mut value: Value) -> Value {
+        if self.envelope == Envelope::Data {
+            let results = value["results"].take();
+            let total = results.as_array().map_or(0, |r| r.len());
+            let mut meta = value;
+            if let Some(meta) = meta.as_object_mut() {
+                meta.remove("results");
+                meta.insert("total".to_string(), json!(total));
+            }
+            value = json!({ "data": results, "meta": meta });
+        }
+
+        match self.field_case {
+            FieldCase::Snake => value,
+            FieldCase::Camel => rename_keys(value, &snake_to_camel),
+        }
+    }
+}
+
+fn rename_keys(value: Value, rename: &dyn Fn(&str) -> String) -> Value {
+    match value {
+        Value::Object(object) => Value::Object(
+            object
+                .into_iter()
+                .map(|(key, value)| (rename(&key), rename_keys(value, rename)))
+                .collect::<Map<String, Value>>(),
+        ),
+        Value::Array(values) => Value::Array(
+            values
+                .into_iter()
+                .map(|value| rename_keys(value, rename))
+                .collect(),
+        ),
+        other => other,
+    }
+}
+
+fn snake_to_camel(key: &str) -> String {
+    let mut camel = String::with_capacity(key.len());
+    let mut upper = false;
+    for c in key.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            camel.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            camel.push(c);
+        }
+    }
+    camel
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn results() -> Value {
+        json!({
+            "results": [{ "path": "src/main.rs", "line": 3, "line_range": { "start": 1, "end": 6 } }],
+            "time": 0.5,
+            "has_more": false,
+        })
+    }
+
+    #[test]
+    fn plain_envelope_keeps_the_results_as_they_are() {
+        assert_eq!(ResponseFormat::default().render(results()), results());
+    }
+
+    #[test]
+    fn data_envelope_moves_the_rest_into_meta() {
+        let format = ResponseFormat {
+            envelope: Envelope::Data,
+            field_case: FieldCase::Snake,
+        };
+        assert_eq!(
+            format.render(results()),
+            json!({
+                "data": [{ "path": "src/main.rs", "line": 3, "line_range": { "start": 1, "end": 6 } }],
+                "meta": { "time": 0.5, "has_more": false, "total": 1 },
+            })
+        );
+    }
+
+    #[test]
+    fn camel_case_renames_nested_keys() {
+        let format = ResponseFormat {
+            envelope: Envelope::Data,
+            field_case: FieldCase::Camel,
+        };
+        assert_eq!(
+            format.render(results()),
+            json!({
+                "data": [{ "path": "src/main.rs", "line": 3, "lineRange": { "start": 1, "end": 6 } }],
+                "meta": { "time": 0.5, "hasMore": false, "total": 1 },
+            })
+        );
+    }
+
+    #[test]
+    fn envelope_and_case_are_read_from_config_names() {
+        let envelope: Envelope = serde_json::from_value(json!("data")).unwrap();
+        let field_case: FieldCase = serde_json::from_value(json!("camel")).unwrap();
+        assert_eq!((envelope, field_case), (Envelope::Data, FieldCase::Camel));
+    }
+}
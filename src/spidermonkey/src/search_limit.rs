@@ -0,0 +1,174 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Bounding the number of searches running at once.
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Counts in-flight searches and, when a maximum is set, refuses new ones
+/// beyond it.
+#[derive(Clone, Default)]
+pub struct SearchLimit {
+    permits: Option<Arc<Semaphore>>,
+    max: Option<usize>,
+    in_flight: Arc<AtomicUsize>,
+}
+
+/// Held for the duration of a search.
+pub struct SearchPermit {
+    _permit: Option<OwnedSemaphorePermit>,
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl Drop for SearchPermit {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchLimitStats {
+    in_flight: usize,
+    max: Option<usize>,
+}
+
+impl SearchLimit {
+    pub fn new(max: Option<usize>) -> Self {
+        Self {
+            permits: max.map(|max| Arc::new(Semaphore::new(max))),
+            max,
+            in_flight: Arc::default(),
+        }
+    }
+
+    /// Start a search, or return `None` when the maximum is already running.
+    pub fn try_acquire(&self) -> Option<SearchPermit> {
+        let permit = match &self.permits {
+            Some(permits) => Some(permits.clone().try_acquire_owned().ok()?),
+            None => None,
+        };
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        Some(SearchPermit {
+            _permit: permit,
+            in_flight: self.in_flight.clone(),
+        })
+    }
+
+    /// Start a search once fewer than the maximum are running.
+    pub async fn acquire(&self) -> SearchPermit {
+        let permit = match &self.permits {
+            Some(permits) => Some(
+                permits
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("the semaphore is never closed"),
+            ),
+            None => None,
+        };
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        SearchPermit {
+            _permit: permit,
+            in_flight: self.in_flight.clone(),
+        }
+    }
+
+    pub fn max(&self) -> Option<usize> {
+        self.max
+    }
+
+    pub fn stats(&self) -> SearchLimitStats {
+        SearchLimitStats {
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+            max: self.max,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn searches_past_the_maximum_are_refused_until_one_ends() {
+        let limit = SearchLimit::new(Some(2));
+        let first = limit.try_acquire().unwrap();
+        let _second = limit.try_acquire().unwrap();
+        assert!(limit.try_acquire().is_none());
+        assert_eq!(limit.stats().in_flight, 2);
+        drop(first);
+        assert_eq!(limit.stats().in_flight, 1);
+        assert!(limit.try_acquire().is_some());
+    }
+
+    #[tokio::test]
+    async fn acquire_waits_for_a_running_search_to_end() {
+        let limit = SearchLimit::new(Some(1));
+        let running = limit.try_acquire().unwrap();
+        let waiting = tokio::spawn({
+            let limit = limit.clone();
+            async move { limit.acquire().await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+        assert_eq!(limit.stats().in_flight, 1);
+        drop(running);
+        let _next = waiting.await.unwrap();
+        assert_eq!(limit.stats().in_flight, 1);
+        assert!(limit.try_acquire().is_none());
+    }
+
+    #[test]
+    fn without_a_maximum_searches_are_only_counted() {
+        let limit = SearchLimit::new(None);
+        let permits: Vec<_> = (0..100).map(|_| limit.try_acquire().unwrap()).collect();
+        assert_eq!(limit.stats().in_flight, 100);
+        drop(permits);
+        assert_eq!(limit.stats().in_flight, 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn the_maximum_holds_under_load() {
+        let limit = SearchLimit::new(Some(4));
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let tasks: Vec<_> = (0..200)
+            .map(|_| {
+                let (limit, running, peak) = (limit.clone(), running.clone(), peak.clone());
+                tokio::spawn(async move {
+                    let Some(_permit) = limit.try_acquire() else {
+                        return false;
+                    };
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    true
+                })
+            })
+            .collect();
+        let mut served = 0;
+        for task in tasks {
+            served += usize::from(task.await.unwrap());
+        }
+        assert!(peak.load(Ordering::SeqCst) <= 4);
+        assert!(served >= 4);
+        assert!(served < 200, "no search was refused");
+        assert_eq!(limit.stats().in_flight, 0);
+    }
+}
@@ -0,0 +1,388 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reading and validating the config file at startup.
+
+use super::*;
+
+/// Start from the config file `name` holding `contents`, written to `dir`.
+fn load(dir: &TempDir, name: &str, contents: &str) -> TantivyResult<AppConfig> {
+    let path = dir.path().join(name);
+    fs::write(&path, contents).unwrap();
+    let cli = build_cli().get_matches_from(["spidermonkey", "--config", path.to_str().unwrap()]);
+    exec_cli(&cli).map(|(config, _)| config)
+}
+
+#[test]
+fn toml_and_yaml_configs_read_the_same() {
+    let dir = tree(&[]);
+    let directory = dir.path().to_str().unwrap();
+    let yaml = load(
+        &dir,
+        "config.yaml",
+        &format!(
+            "scan_settings:\n  \
+               scan_directory: \"{directory}\"\n  \
+               rescan_interval: \"5m\"\n  \
+               exclude_patterns: [\".git\", \"build\"]\n  \
+               pre_scan_env:\n    TOKEN: \"secret\"\n  \
+               max_lines_per_file: 500\n  \
+               rate_limit_per_second: 2.5\n"
+        ),
+    )
+    .unwrap();
+    let toml = load(
+        &dir,
+        "config.TOML",
+        &format!(
+            "[scan_settings]\n\
+             scan_directory = \"{directory}\"\n\
+             rescan_interval = \"5m\"\n\
+             exclude_patterns = [\".git\", \"build\"]\n\
+             pre_scan_env = {{ TOKEN = \"secret\" }}\n\
+             max_lines_per_file = 500\n\
+             rate_limit_per_second = 2.5\n"
+        ),
+    )
+    .unwrap();
+    assert_eq!(format!("{yaml:?}"), format!("{toml:?}"));
+    assert_eq!(toml.interval, Duration::from_secs(300));
+    assert_eq!(toml.engine.max_lines_per_file, Some(500));
+}
+
+#[test]
+fn unknown_config_extensions_are_rejected() {
+    let dir = tree(&[]);
+    let error = load(&dir, "config.json", "{}").unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("expected a .yaml, .yml or .toml extension"));
+    let error = load(&dir, "config.toml", "scan_settings = [").unwrap_err();
+    assert!(error.to_string().contains("Failed to parse"));
+}
+
+#[test]
+fn unparsable_durations_are_rejected() {
+    let dir = tree(&[]);
+    let error = load(
+        &dir,
+        "config.toml",
+        "[scan_settings]\nrescan_interval = \"5m\"\ninitial_delay = \"soon\"\n",
+    )
+    .unwrap_err();
+    assert!(error.to_string().contains("Invalid initial_delay 'soon'"));
+
+    let cli = build_cli().get_matches_from([
+        "spidermonkey",
+        "--directory",
+        dir.path().to_str().unwrap(),
+        "--interval",
+        "often",
+    ]);
+    let error = exec_cli(&cli).unwrap_err();
+    assert!(error.to_string().contains("Invalid --interval 'often'"));
+}
+
+#[tokio::test]
+async fn pre_scan_commands_get_the_configured_environment() {
+    let dir = tree(&[]);
+    let cwd = dir.path().to_str().unwrap().to_string();
+    let env = HashMap::from([("TOKEN".to_string(), "secret".to_string())]);
+    let check = |value: &str| vec![format!("sh -c 'test \"$TOKEN\" = {value}'")];
+    crate::config::execute_pre_scan_commands(check("secret"), cwd.clone(), &env, None)
+        .await
+        .unwrap();
+    let error = crate::config::execute_pre_scan_commands(check("other"), cwd, &env, None)
+        .await
+        .unwrap_err();
+    assert!(
+        error.to_string().contains("exited with status 1"),
+        "{error}"
+    );
+}
+
+#[tokio::test]
+async fn hung_pre_scan_commands_time_out() {
+    let dir = tree(&[]);
+    let cwd = dir.path().to_str().unwrap().to_string();
+    let commands = vec!["sleep 30".to_string(), "touch ran".to_string()];
+    let started = std::time::Instant::now();
+    let error = crate::config::execute_pre_scan_commands(
+        commands,
+        cwd,
+        &HashMap::new(),
+        Some(Duration::from_millis(100)),
+    )
+    .await
+    .unwrap_err();
+    assert!(started.elapsed() < Duration::from_secs(10));
+    assert!(
+        error
+            .to_string()
+            .contains("Command 'sleep 30' timed out after 100ms"),
+        "{error}"
+    );
+    // Commands after the one that timed out don't run.
+    assert!(!dir.path().join("ran").exists());
+}
+
+#[test]
+fn unparsable_pre_scan_timeouts_are_rejected() {
+    let dir = tree(&[]);
+    let error = load(
+        &dir,
+        "config.yaml",
+        "scan_settings:\n  pre_scan_timeout: \"a while\"\n",
+    )
+    .unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("Invalid pre_scan_timeout 'a while'"));
+}
+
+#[test]
+fn unparsable_recency_half_lives_are_rejected() {
+    let dir = tree(&[]);
+    let error = load(
+        &dir,
+        "config.toml",
+        "[scan_settings]\nrecency_half_life = \"-1d\"\n",
+    )
+    .unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("Invalid recency_half_life '-1d'"));
+}
+
+#[test]
+fn unparsable_shutdown_timeouts_are_rejected() {
+    let dir = tree(&[]);
+    let error = load(
+        &dir,
+        "config.yaml",
+        "scan_settings:\n  shutdown_timeout: \"until done\"\n",
+    )
+    .unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("Invalid shutdown_timeout 'until done'"));
+}
+
+#[test]
+fn unparsable_bind_retry_delays_are_rejected() {
+    let dir = tree(&[]);
+    let error = load(
+        &dir,
+        "config.yaml",
+        "scan_settings:\n  bind_retry_delay: \"soon\"\n",
+    )
+    .unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("Invalid bind_retry_delay 'soon'"));
+}
+
+#[test]
+fn unsplittable_ctags_commands_are_rejected() {
+    let dir = tree(&[]);
+    let error = load(
+        &dir,
+        "config.toml",
+        "[scan_settings]\nctags_command = \"ctags --exclude='node_modules\"\n",
+    )
+    .unwrap_err();
+    assert!(
+        error
+            .to_string()
+            .contains("Invalid ctags_command 'ctags --exclude='node_modules'"),
+        "{error}"
+    );
+}
+
+#[test]
+fn unreadable_directories_fail_startup_with_the_path() {
+    let dir = tree(&[]);
+    let missing = dir.path().join("missing");
+    let missing = missing.to_str().unwrap();
+    let cli = build_cli().get_matches_from(["spidermonkey", "--directory", missing]);
+    let error = exec_cli(&cli).unwrap_err().to_string();
+    assert!(
+        error.contains(&format!("Cannot read directory '{missing}'")),
+        "{error}"
+    );
+
+    let cli = build_cli().get_matches_from(["spidermonkey", "--directory", " "]);
+    let error = exec_cli(&cli).unwrap_err().to_string();
+    assert!(error.contains("Directory path cannot be empty"), "{error}");
+}
+
+#[test]
+fn startup_errors_keep_the_underlying_message() {
+    let invalid = TantivyError::InvalidArgument("bad snapshot".to_string());
+    let error = startup_error("Failed to open snapshot 'x.tar'".to_string(), invalid);
+    assert_eq!(
+        error.to_string(),
+        "An invalid argument was passed: 'Failed to open snapshot 'x.tar': bad snapshot'"
+    );
+}
+
+#[test]
+fn file_extensions_are_lowercased_without_the_dot() {
+    let dir = tree(&[]);
+    let directory = dir.path().to_str().unwrap();
+    let config = load(
+        &dir,
+        "config.toml",
+        &format!(
+            "[scan_settings]\n\
+             scan_directory = \"{directory}\"\n\
+             file_extensions = [\".RS\", \"md\", \"\"]\n"
+        ),
+    )
+    .unwrap();
+    let expected: HashSet<String> = ["rs", "md", ""].map(String::from).into();
+    assert_eq!(config.file_extensions, Some(expected));
+}
+
+#[test]
+fn schema_options_are_read_and_checked_against_the_rest() {
+    let dir = tree(&[]);
+    let directory = dir.path().to_str().unwrap();
+    let config = load(
+        &dir,
+        "config.yaml",
+        &format!(
+            "scan_settings:\n  \
+               scan_directory: \"{directory}\"\n  \
+               schema:\n    \
+                 store_body: false\n    \
+                 path_analyzer: \"code\"\n"
+        ),
+    )
+    .unwrap();
+    assert!(!config.engine.schema.store_body);
+    assert_eq!(config.engine.schema.path_analyzer, Analyzer::Code);
+
+    for (schema, reason) in [
+        (
+            "schema:\n    store_body: false\n  max_cached_bytes: 1000\n",
+            "max_cached_bytes",
+        ),
+        ("schema:\n    store_lines: false\n", "store_lines"),
+    ] {
+        let contents = format!("scan_settings:\n  scan_directory: \"{directory}\"\n  {schema}");
+        let error = load(&dir, "config.yaml", &contents).unwrap_err();
+        assert!(error.to_string().contains(reason), "{error}");
+    }
+}
+
+/// The headers `app` answers the preflight of a GET to `/search` from a
+/// page at `origin` with.
+async fn preflight(app: Router, origin: &str) -> axum::http::HeaderMap {
+    let request = Request::options("/search")
+        .header("origin", origin)
+        .header("access-control-request-method", "GET")
+        .header("access-control-request-headers", "x-token")
+        .body(Body::empty())
+        .unwrap();
+    app.oneshot(request).await.unwrap().headers().clone()
+}
+
+#[tokio::test]
+async fn cors_preflights_carry_the_configured_max_age_and_credentials() {
+    let dir = tree(&[("main.rs", "fn main() {}")]);
+    let app = router(state(&dir, AppConfig::new(), &[]).await);
+
+    let headers = preflight(
+        app.clone().layer(AppConfig::new().cors_layer().unwrap()),
+        "https://a.example",
+    )
+    .await;
+    assert_eq!(headers["access-control-allow-origin"], "*");
+    assert!(headers.get("access-control-max-age").is_none());
+    assert!(headers.get("access-control-allow-credentials").is_none());
+
+    let mut config = AppConfig::new();
+    config.cors_origins = Some(vec!["https://a.example".to_string()]);
+    config.cors_max_age = Some(Duration::from_secs(600));
+    config.cors_allow_credentials = true;
+    let cors = config.cors_layer().unwrap();
+    let headers = preflight(app.clone().layer(cors.clone()), "https://a.example").await;
+    assert_eq!(headers["access-control-allow-origin"], "https://a.example");
+    assert_eq!(headers["access-control-max-age"], "600");
+    assert_eq!(headers["access-control-allow-credentials"], "true");
+    // Requested headers are echoed, as a wildcard doesn't hold with credentials.
+    assert_eq!(headers["access-control-allow-headers"], "x-token");
+    let headers = preflight(app.layer(cors), "https://b.example").await;
+    assert!(headers.get("access-control-allow-origin").is_none());
+}
+
+#[test]
+fn cors_credentials_with_a_wildcard_origin_are_rejected() {
+    for origins in [None, Some(vec!["*".to_string()])] {
+        let mut config = AppConfig::new();
+        config.cors_origins = origins;
+        config.cors_allow_credentials = true;
+        let error = config.cors_layer().unwrap_err();
+        assert!(error.to_string().contains("cors_allow_credentials"));
+    }
+    let mut config = AppConfig::new();
+    config.cors_origins = Some(vec!["bad\norigin".to_string()]);
+    assert!(config.cors_layer().is_err());
+}
+
+#[tokio::test]
+async fn binding_retries_until_the_endpoint_is_free() {
+    let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint = taken.local_addr().unwrap().to_string();
+    let error = bind(&endpoint, 0, Duration::from_millis(10))
+        .await
+        .unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::AddrInUse);
+
+    // Freed between the first attempt and the retry 200ms later.
+    let release = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(100));
+        drop(taken);
+    });
+    let listener = bind(&endpoint, 3, Duration::from_millis(200))
+        .await
+        .unwrap();
+    assert_eq!(listener.local_addr().unwrap().to_string(), endpoint);
+    release.join().unwrap();
+}
+
+#[test]
+fn http_sources_are_checked_when_loaded() {
+    let dir = tree(&[]);
+    let source =
+        |url: &str| format!("[scan_settings.source]\ntype = \"http\"\nmanifest_url = \"{url}\"\n");
+    // No scan_directory is needed to list the files.
+    let config = load(&dir, "config.toml", &source("http://host/manifest.txt")).unwrap();
+    assert_eq!(
+        config.engine.source,
+        Source::Http {
+            manifest_url: "http://host/manifest.txt".to_string()
+        }
+    );
+    assert!(load(&dir, "config.toml", &source("https://host/manifest.txt")).is_ok());
+    let error = load(&dir, "config.toml", &source("ftp://host/manifest.txt")).unwrap_err();
+    assert!(error.to_string().contains("manifest_url"), "{error}");
+    let with_ref = format!(
+        "[scan_settings]\ngit_ref = \"main\"\n{}",
+        source("http://host/m.txt")
+    );
+    let error = load(&dir, "config.toml", &with_ref).unwrap_err();
+    assert!(error.to_string().contains("git_ref"), "{error}");
+}
@@ -0,0 +1,147 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Routes answering about indexed files rather than searching them.
+
+use super::*;
+
+#[tokio::test]
+async fn snippet_renders_the_lines_around_a_location() {
+    let dir = tree(&[("five.txt", "one\ntwo\nthree\nfour\nfive")]);
+    let app = router(state(&dir, AppConfig::new(), &[]).await);
+    let path = path_of(&dir, "five.txt");
+    let snippet = get_json(
+        &app,
+        &uri(
+            "/snippet",
+            &[("path", &path), ("line", "3"), ("context", "1")],
+        ),
+    )
+    .await;
+    assert_eq!(snippet["body"], "two\nthree\nfour");
+    assert_eq!(snippet["line_range"], json!({ "start": 2, "end": 4 }));
+
+    let last = get_json(
+        &app,
+        &uri(
+            "/snippet",
+            &[("path", &path), ("line", "5"), ("context", "2")],
+        ),
+    )
+    .await;
+    assert_eq!(last["line_range"], json!({ "start": 3, "end": 5 }));
+}
+
+#[tokio::test]
+async fn snippet_of_a_line_out_of_range_or_unknown_path_is_404() {
+    let dir = tree(&[("five.txt", "one\ntwo\nthree\nfour\nfive")]);
+    let app = router(state(&dir, AppConfig::new(), &[]).await);
+    let path = path_of(&dir, "five.txt");
+    for line in ["0", "6", "1000"] {
+        let (status, _) = get(&app, &uri("/snippet", &[("path", &path), ("line", line)])).await;
+        assert_eq!(status, StatusCode::NOT_FOUND, "line {line}");
+    }
+    let missing = path_of(&dir, "missing.txt");
+    let (status, _) = get(&app, &uri("/snippet", &[("path", &missing), ("line", "1")])).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn skipped_files_are_listed_with_their_reason() {
+    let dir = tree(&[("text.txt", "plain")]);
+    fs::write(dir.path().join("data.bin"), b"\0\x01").unwrap();
+    fs::write(dir.path().join("latin1.txt"), b"caf\xe9").unwrap();
+    let mut config = AppConfig::new();
+    config.engine.skip_non_text = true;
+    let app = router(state(&dir, config, &[]).await);
+    assert_eq!(
+        get_json(&app, "/files/skipped").await,
+        json!([
+            { "path": path_of(&dir, "data.bin"), "reason": "binary" },
+            { "path": path_of(&dir, "latin1.txt"), "reason": "invalid_encoding" },
+        ])
+    );
+}
+
+#[tokio::test]
+async fn file_serves_only_the_allowed_extensions() {
+    let dir = tree(&[
+        ("main.rs", "fn main() {}"),
+        ("data.csv", "a,b"),
+        ("Makefile", "all:"),
+    ]);
+    let mut config = AppConfig::new();
+    config.file_extensions = Some(["rs".to_string(), String::new()].into());
+    let app = router(state(&dir, config, &[]).await);
+    let file = |path: &str| uri("/file", &[("path", &path_of(&dir, path))]);
+
+    let body = get_json(&app, &file("main.rs")).await;
+    assert_eq!(body["lines"], json!(["fn main() {}"]));
+    // `""` allows files without an extension.
+    let body = get_json(&app, &file("Makefile")).await;
+    assert_eq!(body["lines"], json!(["all:"]));
+    // Disallowed files are forbidden though indexed, and searchable.
+    let (status, _) = get(&app, &file("data.csv")).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let body = get_json(&app, &uri("/search", &[("text", "a")])).await;
+    assert_eq!(body["results"][0]["path"], path_of(&dir, "data.csv"));
+    // Even when not indexed at all, so the list hides which files exist.
+    let (status, _) = get(&app, &file("missing.csv")).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = get(&app, &file("missing.rs")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // Without the list every indexed file is served.
+    let app = router(state(&dir, AppConfig::new(), &[]).await);
+    let body = get_json(&app, &file("data.csv")).await;
+    assert_eq!(body["lines"], json!(["a,b"]));
+}
+
+#[tokio::test]
+async fn files_exists_answers_without_searching() {
+    let dir = tree(&[("two.txt", "one\ntwo")]);
+    let app = router(state(&dir, AppConfig::new(), &[]).await);
+    let path = path_of(&dir, "two.txt");
+    assert_eq!(
+        get_json(&app, &uri("/files/exists", &[("path", &path)])).await,
+        json!({ "path": path, "indexed": true, "lines": 2 })
+    );
+    let missing = path_of(&dir, "missing.txt");
+    assert_eq!(
+        get_json(&app, &uri("/files/exists", &[("path", &missing)])).await,
+        json!({ "path": missing, "indexed": false, "lines": null })
+    );
+    let (status, _) = get(&app, "/files/exists").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn files_exists_is_false_once_a_reload_skips_the_file() {
+    let dir = tree(&[("a.txt", "one"), ("copy.txt", "one")]);
+    let mut config = AppConfig::new();
+    config.engine.skip_non_text = true;
+    config.engine.dedupe_content = true;
+    let state = state(&dir, config, &[]).await;
+    let app = router(state.clone());
+    fs::write(dir.path().join("a.txt"), b"one\0").unwrap();
+    fs::write(dir.path().join("copy.txt"), b"one\0").unwrap();
+    state.engine.reload(&state.directory).await.unwrap();
+    for name in ["a.txt", "copy.txt"] {
+        let path = path_of(&dir, name);
+        assert_eq!(
+            get_json(&app, &uri("/files/exists", &[("path", &path)])).await,
+            json!({ "path": path, "indexed": false, "lines": null })
+        );
+    }
+}
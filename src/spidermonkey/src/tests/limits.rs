@@ -0,0 +1,168 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Limits on how much clients may ask of the server.
+
+use std::net::SocketAddr;
+
+use axum::extract::ConnectInfo;
+use axum::Extension;
+
+use super::*;
+
+/// `app` answering as if called from `client`, as the server passes the
+/// address of each connection.
+fn from_client(app: Router, client: [u8; 4]) -> Router {
+    app.layer(Extension(ConnectInfo(SocketAddr::from((client, 4000)))))
+}
+
+#[tokio::test]
+async fn a_burst_past_the_rate_limit_gets_429() {
+    let dir = tree(&[("main.rs", "fn main() {}")]);
+    let state = state(&dir, AppConfig::new(), &[]).await;
+    let limit = RateLimitConfig {
+        per_second: 0.1,
+        burst: 3,
+    }
+    .layer()
+    .unwrap();
+    let client = from_client(router(state.clone()).layer(limit.clone()), [10, 0, 0, 1]);
+    for _ in 0..3 {
+        assert_eq!(get(&client, "/search?text=main").await.0, StatusCode::OK);
+    }
+    let (status, _) = get(&client, "/search?text=main").await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+
+    // Each client IP has a rate of its own.
+    let other = from_client(router(state).layer(limit), [10, 0, 0, 2]);
+    assert_eq!(get(&other, "/search?text=main").await.0, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn a_rate_limit_must_be_positive() {
+    for per_second in [0.0, -1.0, f64::NAN] {
+        let config = RateLimitConfig {
+            per_second,
+            burst: 1,
+        };
+        assert!(config.layer().is_err(), "{per_second} was accepted");
+    }
+}
+
+#[tokio::test]
+async fn searches_past_max_concurrent_searches_get_503() {
+    let dir = tree(&[("main.rs", "fn main() {}")]);
+    let mut config = AppConfig::new();
+    config.max_concurrent_searches = Some(2);
+    let state = state(&dir, config, &[]).await;
+    let app = router(state.clone());
+    let running = [
+        state.searches.try_acquire().unwrap(),
+        state.searches.try_acquire().unwrap(),
+    ];
+    let (status, _) = get(&app, "/search?text=main").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    let stats = get_json(&app, "/stats").await;
+    assert_eq!(stats["searches"], json!({ "in_flight": 2, "max": 2 }));
+
+    drop(running);
+    assert_eq!(get(&app, "/search?text=main").await.0, StatusCode::OK);
+}
+
+/// POST `specs` to `/search/batch`.
+async fn batch(app: &Router, specs: &Value) -> (StatusCode, String) {
+    let request = Request::post("/search/batch")
+        .header("content-type", "application/json")
+        .body(Body::from(specs.to_string()))
+        .unwrap();
+    send(app, request).await
+}
+
+#[tokio::test]
+async fn batches_past_max_batch_searches_get_413() {
+    let dir = tree(&[("main.rs", "fn main() {}")]);
+    let mut config = AppConfig::new();
+    config.max_batch_searches = 2;
+    let app = router(state(&dir, config, &[]).await);
+    let specs = |count: usize| Value::Array(vec![json!({ "text": "main" }); count]);
+    assert_eq!(batch(&app, &specs(2)).await.0, StatusCode::OK);
+    let (status, body) = batch(&app, &specs(3)).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert!(body.contains("at most 2"), "{body}");
+}
+
+#[tokio::test]
+async fn batch_searches_share_max_concurrent_searches() {
+    let dir = tree(&[("main.rs", "fn main() {}")]);
+    let mut config = AppConfig::new();
+    config.max_concurrent_searches = Some(2);
+    let state = state(&dir, config, &[]).await;
+    let app = router(state.clone());
+    let specs = Value::Array(vec![json!({ "text": "main" }); 5]);
+
+    // With one slot left the searches run one after another.
+    let running = state.searches.try_acquire().unwrap();
+    let (status, body) = batch(&app, &specs).await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    let answers: Vec<Value> = serde_json::from_str(&body).unwrap();
+    assert!(answers
+        .iter()
+        .all(|answer| answer["results"][0]["line"] == 1));
+    let stats = get_json(&app, "/stats").await;
+    assert_eq!(stats["searches"]["in_flight"], 1);
+
+    let _also_running = state.searches.try_acquire().unwrap();
+    assert_eq!(batch(&app, &specs).await.0, StatusCode::SERVICE_UNAVAILABLE);
+    drop(running);
+}
+
+#[tokio::test]
+async fn streamed_results_are_paced_at_stream_results_per_second() {
+    let content = (1..=5).map(|i| format!("needle {i}\n")).collect::<String>();
+    let dir = tree(&[("needles.txt", &content)]);
+    let mut state = state(&dir, AppConfig::new(), &[]).await;
+    let unpaced = router(state.clone());
+    state.stream_interval = Some(Duration::from_millis(50));
+    let paced = router(state);
+
+    let start = Instant::now();
+    let (status, body) = get(&paced, "/search/stream?text=needle").await;
+    let elapsed = start.elapsed();
+    assert_eq!(status, StatusCode::OK);
+    // Five results and the metadata line, the first sent right away.
+    assert_eq!(body.lines().count(), 6);
+    assert!(elapsed >= Duration::from_millis(250), "took {elapsed:?}");
+
+    let (_, unpaced_body) = get(&unpaced, "/search/stream?text=needle").await;
+    assert_eq!(unpaced_body.lines().count(), 6);
+}
+
+#[test]
+fn stream_results_per_second_must_fit_a_period() {
+    let dir = tree(&[]);
+    for per_second in [0.0, -2.0, f64::NAN, 1e-300, 1e300] {
+        let mut config = AppConfig::new();
+        config.directory = dir.path().to_str().unwrap().to_string();
+        config.stream_results_per_second = Some(per_second);
+        let error = config.validate().unwrap_err();
+        assert!(
+            error.to_string().contains("stream_results_per_second"),
+            "{per_second}"
+        );
+    }
+    let mut config = AppConfig::new();
+    config.directory = dir.path().to_str().unwrap().to_string();
+    config.stream_results_per_second = Some(20.0);
+    assert!(config.validate().is_ok());
+}
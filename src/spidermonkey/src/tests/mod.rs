@@ -0,0 +1,102 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tests of the server, sending requests to its router over a small tree
+//! written to a temporary directory.
+
+mod config;
+mod files;
+mod limits;
+mod reload;
+mod search;
+
+use std::fs;
+
+use axum::body::{to_bytes, Body};
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use tempfile::TempDir;
+use tower::ServiceExt;
+
+use super::*;
+
+/// A temporary directory holding `files`, given as `(path, content)`.
+pub(crate) fn tree(files: &[(&str, &str)]) -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    for (path, content) in files {
+        let path = dir.path().join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+    dir
+}
+
+/// The state of a server indexing `dir` with `config` and the command
+/// line `args`, besides `--directory`.
+pub(crate) async fn state(dir: &TempDir, config: AppConfig, args: &[&str]) -> AppState {
+    let directory = dir.path().to_str().unwrap().to_string();
+    let cli = build_cli().get_matches_from(
+        ["spidermonkey", "--directory", &directory]
+            .into_iter()
+            .chain(args.iter().copied()),
+    );
+    let config_reload = Arc::new(ConfigReload::new(&cli, Value::Null, &config));
+    let engine = CodeSearchEngine::new(&directory, config.engine)
+        .await
+        .unwrap();
+    AppState {
+        engine: Arc::new(engine),
+        response: config.response,
+        default_query: config.default_query,
+        searches: SearchLimit::new(config.max_concurrent_searches),
+        max_batch_searches: config.max_batch_searches,
+        reload_paused: Arc::new(AtomicBool::new(false)),
+        directory,
+        file_extensions: config.file_extensions.map(Arc::new),
+        stream_interval: None,
+        config_reload,
+    }
+}
+
+/// Send `request` and return the status and body of the response.
+pub(crate) async fn send(app: &Router, request: Request<Body>) -> (StatusCode, String) {
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+pub(crate) async fn get(app: &Router, uri: &str) -> (StatusCode, String) {
+    send(app, Request::get(uri).body(Body::empty()).unwrap()).await
+}
+
+/// `path` with the query string of `params`, encoded.
+pub(crate) fn uri(path: &str, params: &[(&str, &str)]) -> String {
+    let query = form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(params)
+        .finish();
+    format!("{path}?{query}")
+}
+
+/// The path `path` below `dir` is indexed under.
+pub(crate) fn path_of(dir: &TempDir, path: &str) -> String {
+    format!("{}/{path}", dir.path().to_str().unwrap())
+}
+
+/// Send a GET request expecting 200 and return the JSON body.
+pub(crate) async fn get_json(app: &Router, uri: &str) -> Value {
+    let (status, body) = get(app, uri).await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    serde_json::from_str(&body).unwrap()
+}
@@ -0,0 +1,283 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The reload loop rescanning the directory in the background.
+
+use super::*;
+
+/// Start the reload loop of `state`, the first rescan after `first_delay`.
+fn start(state: &AppState, first_delay: Duration) -> task::JoinHandle<()> {
+    tokio::spawn(reload_loop(
+        state.engine.clone(),
+        state.directory.clone(),
+        state.config_reload.clone(),
+        first_delay,
+        state.reload_paused.clone(),
+        None,
+    ))
+}
+
+/// Whether `text` becomes searchable within `wait`.
+async fn found_within(state: &AppState, text: &str, wait: Duration) -> bool {
+    let deadline = tokio::time::Instant::now() + wait;
+    while tokio::time::Instant::now() < deadline {
+        let results = state
+            .engine
+            .search(text, &SearchOptions::default())
+            .await
+            .unwrap();
+        if !results.results().is_empty() {
+            return true;
+        }
+        sleep(Duration::from_millis(20)).await;
+    }
+    false
+}
+
+/// An hour between rescans, so only the first one can run in a test.
+fn hourly() -> AppConfig {
+    let mut config = AppConfig::new();
+    config.interval = Duration::from_secs(3600);
+    config
+}
+
+#[tokio::test]
+async fn initial_delay_brings_the_first_rescan_forward() {
+    let dir = tree(&[("a.rs", "fn alpha() {}\n")]);
+    let state = state(&dir, hourly(), &[]).await;
+    let reloads = start(&state, Duration::from_millis(50));
+    fs::write(dir.path().join("b.rs"), "fn bravo() {}\n").unwrap();
+    assert!(found_within(&state, "bravo", Duration::from_secs(10)).await);
+    reloads.abort();
+}
+
+#[tokio::test]
+async fn without_initial_delay_the_first_rescan_waits_an_interval() {
+    let dir = tree(&[("a.rs", "fn alpha() {}\n")]);
+    let state = state(&dir, hourly(), &[]).await;
+    let reloads = start(&state, Duration::from_secs(3600));
+    fs::write(dir.path().join("b.rs"), "fn bravo() {}\n").unwrap();
+    assert!(!found_within(&state, "bravo", Duration::from_millis(500)).await);
+    reloads.abort();
+}
+
+async fn post(app: &Router, uri: &str) -> Value {
+    let (status, body) = send(app, Request::post(uri).body(Body::empty()).unwrap()).await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    serde_json::from_str(&body).unwrap()
+}
+
+#[tokio::test]
+async fn paused_reloads_skip_their_cycles() {
+    let dir = tree(&[("a.rs", "fn alpha() {}\n")]);
+    let mut config = AppConfig::new();
+    config.interval = Duration::from_millis(20);
+    let state = state(&dir, config, &[]).await;
+    let app = router(state.clone());
+    let reloads = start(&state, Duration::from_millis(20));
+
+    assert_eq!(post(&app, "/reload/pause").await, json!({ "paused": true }));
+    assert_eq!(
+        get_json(&app, "/stats").await["reload"],
+        json!({ "paused": true })
+    );
+    // Let a cycle that began before the pause finish.
+    sleep(Duration::from_millis(200)).await;
+    fs::write(dir.path().join("b.rs"), "fn bravo() {}\n").unwrap();
+    assert!(!found_within(&state, "bravo", Duration::from_millis(300)).await);
+
+    assert_eq!(
+        post(&app, "/reload/resume").await,
+        json!({ "paused": false })
+    );
+    assert_eq!(
+        get_json(&app, "/stats").await["reload"],
+        json!({ "paused": false })
+    );
+    assert!(found_within(&state, "bravo", Duration::from_secs(10)).await);
+    reloads.abort();
+}
+
+/// Run `commit` within a shutdown deadline of 100ms, returning whether the
+/// deadline passed.
+fn timed_out(commit: impl FnOnce()) -> bool {
+    let passed = Arc::new(AtomicBool::new(false));
+    let flag = passed.clone();
+    let finished = shutdown_deadline(Duration::from_millis(100), move || {
+        flag.store(true, Ordering::Relaxed);
+    });
+    commit();
+    let _ = finished.send(());
+    std::thread::sleep(Duration::from_millis(300));
+    passed.load(Ordering::Relaxed)
+}
+
+#[test]
+fn shutdown_gives_up_on_a_slow_commit() {
+    assert!(timed_out(|| std::thread::sleep(Duration::from_millis(500))));
+    assert!(!timed_out(|| {}));
+}
+
+#[tokio::test]
+async fn reloads_past_the_segment_threshold_trigger_a_merge() {
+    let dir = tree(&[("kept.rs", "fn kept() {}\n")]);
+    let state = state(&dir, AppConfig::new(), &[]).await;
+    let compacting = Arc::new(AtomicBool::new(false));
+    let mut merges = 0;
+    for file in 1..=10 {
+        // Each reload adds a segment holding the new file.
+        let path = dir.path().join(format!("added_{file}.rs"));
+        fs::write(path, "fn added() {}\n").unwrap();
+        state.engine.reload(&state.directory).await.unwrap();
+        let segments = state.engine.segment_count().unwrap();
+        assert!(segments <= 4, "{segments} segments");
+        auto_compact(&state.engine, 3, &compacting);
+        if segments > 3 {
+            merges += 1;
+            // The merge runs in the background, wait for it to finish.
+            while compacting.load(Ordering::Relaxed) {
+                sleep(Duration::from_millis(10)).await;
+            }
+            assert_eq!(state.engine.segment_count().unwrap(), 1);
+        }
+    }
+    assert_eq!(merges, 3);
+    let results = state
+        .engine
+        .search("added", &SearchOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(results.results().len(), 10);
+}
+
+/// Write a TOML config scanning `dir` with `settings` after the directory.
+fn write_config(dir: &TempDir, path: &Path, settings: &str) {
+    let directory = dir.path().to_str().unwrap();
+    let contents = format!("[scan_settings]\nscan_directory = \"{directory}\"\n{settings}");
+    fs::write(path, contents).unwrap();
+}
+
+#[tokio::test]
+async fn a_config_reload_applies_a_shorter_interval() {
+    let dir = tree(&[("a.rs", "fn alpha() {}\n")]);
+    let config_dir = tree(&[]);
+    let path = config_dir.path().join("config.toml");
+    write_config(&dir, &path, "rescan_interval = \"1h\"\n");
+    let cli = build_cli().get_matches_from(["spidermonkey", "--config", path.to_str().unwrap()]);
+    let (config, settings) = exec_cli(&cli).unwrap();
+    let config_reload = Arc::new(ConfigReload::new(&cli, settings, &config));
+    let mut state = state(&dir, config, &[]).await;
+    state.config_reload = config_reload;
+    let app = router(state.clone());
+    let reloads = start(&state, Duration::from_secs(3600));
+
+    fs::write(dir.path().join("b.rs"), "fn bravo() {}\n").unwrap();
+    assert!(!found_within(&state, "bravo", Duration::from_millis(300)).await);
+    write_config(&dir, &path, "rescan_interval = \"50ms\"\n");
+    assert_eq!(
+        post(&app, "/config/reload").await,
+        json!({ "applied": ["rescan_interval"] })
+    );
+    assert_eq!(
+        state.config_reload.scan_loop().interval,
+        Duration::from_millis(50)
+    );
+    // The hour long wait is cut short by the reload.
+    assert!(found_within(&state, "bravo", Duration::from_secs(10)).await);
+
+    // Settings needing a restart are refused, leaving the rest unapplied.
+    write_config(
+        &dir,
+        &path,
+        "rescan_interval = \"1h\"\nmax_lines_per_file = 10\n",
+    );
+    let request = Request::post("/config/reload").body(Body::empty()).unwrap();
+    let (status, body) = send(&app, request).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let body: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(body["restart_required"], json!(["max_lines_per_file"]));
+    assert_eq!(
+        state.config_reload.scan_loop().interval,
+        Duration::from_millis(50)
+    );
+    reloads.abort();
+}
+
+#[tokio::test]
+async fn a_config_reload_reports_an_interval_overridden_by_the_cli() {
+    let dir = tree(&[("a.rs", "fn alpha() {}\n")]);
+    let config_dir = tree(&[]);
+    let path = config_dir.path().join("config.toml");
+    write_config(&dir, &path, "rescan_interval = \"1h\"\n");
+    let cli = build_cli().get_matches_from([
+        "spidermonkey",
+        "--config",
+        path.to_str().unwrap(),
+        "--interval",
+        "2h",
+    ]);
+    let (config, settings) = exec_cli(&cli).unwrap();
+    let config_reload = Arc::new(ConfigReload::new(&cli, settings, &config));
+    let mut state = state(&dir, config, &[]).await;
+    state.config_reload = config_reload;
+    let app = router(state.clone());
+
+    write_config(&dir, &path, "rescan_interval = \"50ms\"\n");
+    assert_eq!(
+        post(&app, "/config/reload").await,
+        json!({ "applied": [], "overridden_by_cli": ["rescan_interval"] })
+    );
+    assert_eq!(
+        state.config_reload.scan_loop().interval,
+        Duration::from_secs(7200)
+    );
+}
+
+#[tokio::test]
+async fn a_config_reload_needs_a_config_file() {
+    let dir = tree(&[("a.rs", "fn alpha() {}\n")]);
+    let app = router(state(&dir, AppConfig::new(), &[]).await);
+    let request = Request::post("/config/reload").body(Body::empty()).unwrap();
+    let (status, body) = send(&app, request).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body.contains("without a config file"), "{body}");
+}
+
+#[tokio::test]
+async fn a_reindex_keeps_exclude_patterns_applied_by_a_config_reload() {
+    let dir = tree(&[
+        ("a.rs", "fn alpha() {}\n"),
+        ("vendor/b.rs", "fn alpha() {}\n"),
+    ]);
+    let config_dir = tree(&[]);
+    let path = config_dir.path().join("config.toml");
+    write_config(&dir, &path, "");
+    let cli = build_cli().get_matches_from(["spidermonkey", "--config", path.to_str().unwrap()]);
+    let (config, settings) = exec_cli(&cli).unwrap();
+    let config_reload = Arc::new(ConfigReload::new(&cli, settings, &config));
+    let mut state = state(&dir, config, &[]).await;
+    state.config_reload = config_reload;
+    let app = router(state.clone());
+
+    write_config(&dir, &path, "exclude_patterns = [\".git\", \"vendor\"]\n");
+    assert_eq!(
+        post(&app, "/config/reload").await,
+        json!({ "applied": ["exclude_patterns"] })
+    );
+    post(&app, "/reindex").await;
+    let body = get_json(&app, "/search?text=alpha").await;
+    let results = body["results"].as_array().unwrap();
+    assert_eq!(results.len(), 1, "{body}");
+    assert_eq!(results[0]["path"], path_of(&dir, "a.rs"));
+}
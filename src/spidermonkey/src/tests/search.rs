@@ -0,0 +1,375 @@
+// Copyright (C) 2025  Max Wiklund
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The parameters of `/search`.
+
+use super::*;
+
+fn todo_tree() -> TempDir {
+    tree(&[("notes.txt", "TODO write tests\ndone")])
+}
+
+#[tokio::test]
+async fn missing_empty_and_blank_queries_explain_there_is_nothing_to_search() {
+    let dir = todo_tree();
+    let app = router(state(&dir, AppConfig::new(), &[]).await);
+    for uri in ["/search", "/search?text=", "/search?text=%20%09%20"] {
+        let body = get_json(&app, uri).await;
+        assert_eq!(body["results"], json!([]), "{uri}");
+        assert_eq!(body["message"], EMPTY_QUERY_MESSAGE, "{uri}");
+    }
+}
+
+#[tokio::test]
+async fn missing_empty_and_blank_queries_run_the_default_query() {
+    let dir = todo_tree();
+    let mut config = AppConfig::new();
+    config.default_query = Some("TODO".to_string());
+    let app = router(state(&dir, config, &[]).await);
+    for uri in ["/search", "/search?text=", "/search?text=%20%20"] {
+        let body = get_json(&app, uri).await;
+        let results = body["results"].as_array().unwrap();
+        assert_eq!(results.len(), 1, "{uri}");
+        assert_eq!(results[0]["line"], 1, "{uri}");
+    }
+}
+
+#[tokio::test]
+async fn queries_are_trimmed() {
+    let dir = todo_tree();
+    let app = router(state(&dir, AppConfig::new(), &[]).await);
+    let body = get_json(&app, "/search?text=%20%20done%20").await;
+    let results = body["results"].as_array().unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0]["line"], 2);
+}
+
+#[tokio::test]
+async fn oversized_queries_are_rejected() {
+    let dir = todo_tree();
+    let mut config = AppConfig::new();
+    config.engine.max_query_length = Some(20);
+    config.engine.max_query_terms = Some(3);
+    let app = router(state(&dir, config, &[]).await);
+
+    let (status, body) = get(&app, &uri("/search", &[("text", &"todo ".repeat(5))])).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(
+        body.contains("Query is 24 characters long, the limit is 20"),
+        "{body}"
+    );
+
+    let (status, body) = get(&app, &uri("/search", &[("text", "a b c d")])).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body.contains("Query has 4 terms, the limit is 3"), "{body}");
+
+    let body = get_json(&app, &uri("/search", &[("text", "todo write tests")])).await;
+    assert_eq!(body["results"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn rg_format_matches_ripgrep_json_lines() {
+    let dir = tree(&[("src/lib.rs", "use std::fs;\nfn needle() { needle() }\n")]);
+    let app = router(state(&dir, AppConfig::new(), &[]).await);
+    let (status, body) = get(
+        &app,
+        &uri("/search", &[("text", "needle"), ("format", "rg")]),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let records: Vec<Value> = body
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    // As printed by `rg --json needle` for the same file.
+    let path = path_of(&dir, "src/lib.rs");
+    assert_eq!(
+        records,
+        [json!({
+            "type": "match",
+            "data": {
+                "path": { "text": path },
+                "lines": { "text": "fn needle() { needle() }\n" },
+                "line_number": 2,
+                "absolute_offset": 13,
+                "submatches": [
+                    { "match": { "text": "needle" }, "start": 3, "end": 9 },
+                    { "match": { "text": "needle" }, "start": 14, "end": 20 },
+                ],
+            },
+        })]
+    );
+}
+
+#[tokio::test]
+async fn invalid_parameters_are_named_in_a_400() {
+    let dir = todo_tree();
+    let app = router(state(&dir, AppConfig::new(), &[]).await);
+    for (query, param) in [
+        ("text=todo&limit=abc", "limit"),
+        ("text=todo&limit=-1", "limit"),
+        ("text=todo&offset=1.5", "offset"),
+        ("text=todo&fixed=maybe", "fixed"),
+        ("text=todo&limit=5&format=xml", "format"),
+    ] {
+        let (status, body) = get(&app, &format!("/search?{query}")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{query}");
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["param"], param, "{query}");
+        let error = body["error"].as_str().unwrap();
+        assert!(
+            error.starts_with(&format!("Invalid value for `{param}`: ")),
+            "{error}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn batches_answer_each_query_in_order_with_its_own_errors() {
+    let dir = todo_tree();
+    let app = router(state(&dir, AppConfig::new(), &[]).await);
+    let specs = json!([
+        { "text": "done" },
+        { "text": "todo", "limit": "many" },
+        { "text": "todo" },
+        { "text": "(unclosed", "mode": "advanced" },
+    ]);
+    let request = Request::post("/search/batch")
+        .header("content-type", "application/json")
+        .body(Body::from(specs.to_string()))
+        .unwrap();
+    let (status, body) = send(&app, request).await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    let answers: Vec<Value> = serde_json::from_str(&body).unwrap();
+    assert_eq!(answers.len(), 4);
+    assert_eq!(answers[0]["results"][0]["line"], 2);
+    assert_eq!(answers[1]["param"], "limit");
+    assert_eq!(answers[2]["results"][0]["line"], 1);
+    assert!(answers[3]["error"].is_string(), "{}", answers[3]);
+}
+
+#[tokio::test]
+async fn tokenizers_match_the_same_content_differently() {
+    let dir = tree(&[("client.rs", "let parseConfig = HttpClient::new();")]);
+    let mut config = AppConfig::new();
+    config.engine.extension_analyzers = HashMap::from([("rs".to_string(), Analyzer::Code)]);
+    config.engine.index_exact_case = true;
+    let app = router(state(&dir, config, &[]).await);
+    let count = |text: &'static str, tokenizer: &'static str| {
+        let app = app.clone();
+        async move {
+            let uri = uri("/search", &[("text", text), ("tokenizer", tokenizer)]);
+            get_json(&app, &uri).await["results"]
+                .as_array()
+                .unwrap()
+                .len()
+        }
+    };
+    // Only the code analyzer splits camelCase identifiers into words.
+    assert_eq!(count("config", "default").await, 0);
+    assert_eq!(count("config", "code").await, 1);
+    assert_eq!(count("parseconfig", "default").await, 1);
+    // Only the exact one keeps the case.
+    assert_eq!(count("httpclient", "default").await, 1);
+    assert_eq!(count("httpclient", "exact").await, 0);
+    assert_eq!(count("HttpClient", "exact").await, 1);
+
+    let (status, body) = get(
+        &app,
+        &uri("/search", &[("text", "x"), ("tokenizer", "ngram")]),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let body: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(body["param"], "tokenizer");
+}
+
+#[tokio::test]
+async fn search_fields_match_in_any_of_the_named_fields() {
+    let dir = tree(&[
+        ("main.rs", "fn main() {\n    // router setup\n}"),
+        ("router/mod.rs", "pub fn new() {}\npub fn get() {}"),
+    ]);
+    let mut config = AppConfig::new();
+    config.engine.index_comments = true;
+    let app = router(state(&dir, config, &[]).await);
+    let main = path_of(&dir, "main.rs");
+    let module = path_of(&dir, "router/mod.rs");
+    let found = |params: Vec<(&'static str, &'static str)>| {
+        let app = app.clone();
+        async move {
+            let mut found: Vec<(String, u64)> = get_json(&app, &uri("/search", &params)).await
+                ["results"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|result| {
+                    let path = result["path"].as_str().unwrap().to_string();
+                    (path, result["line"].as_u64().unwrap())
+                })
+                .collect();
+            found.sort();
+            found
+        }
+    };
+    let in_body = vec![(main.clone(), 2)];
+    let in_path = vec![(module.clone(), 1), (module.clone(), 2)];
+    let in_both = vec![(main.clone(), 2), (module.clone(), 1), (module.clone(), 2)];
+    for (fields, expected) in [
+        ("body", &in_body),
+        ("path", &in_path),
+        ("comments", &in_body),
+        ("path,body", &in_both),
+        ("path,comments", &in_both),
+        ("body,comments", &in_body),
+        ("body,path,comments", &in_both),
+    ] {
+        let params = vec![("text", "router"), ("search_fields", fields)];
+        assert_eq!(&found(params).await, expected, "{fields}");
+    }
+    // Without search_fields only the body is searched.
+    assert_eq!(found(vec![("text", "router")]).await, in_body);
+    let params = vec![("wildcard", "rout*"), ("search_fields", "path,body")];
+    assert_eq!(found(params).await, in_both);
+
+    for params in [
+        [("text", "router"), ("search_fields", "symbol")],
+        [("text", "router"), ("search_fields", "")],
+    ] {
+        let (status, body) = get(&app, &uri("/search", &params)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{params:?}");
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["param"], "search_fields");
+    }
+    let params = [
+        ("text", "router"),
+        ("fixed", "true"),
+        ("search_fields", "path"),
+    ];
+    let (status, _) = get(&app, &uri("/search", &params)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn format_hunks_answers_with_hunks_and_cant_be_streamed() {
+    let content = "TODO 1\nTODO 2\n3\n4\n5\n6\n7\n8\n9\nTODO 10";
+    let dir = tree(&[("notes.txt", content)]);
+    let app = router(state(&dir, AppConfig::new(), &[]).await);
+    let params = [("text", "TODO"), ("format", "hunks")];
+    let body = get_json(&app, &uri("/search", &params)).await;
+    let mut hunks: Vec<Value> = body["results"].as_array().unwrap().clone();
+    hunks.sort_by_key(|hunk| hunk["line_range"]["start"].as_u64());
+    // Three lines of context around each match.
+    assert_eq!(
+        hunks
+            .iter()
+            .map(|hunk| (&hunk["line_range"], &hunk["body"], &hunk["matches"]))
+            .collect::<Vec<_>>(),
+        [
+            (
+                &json!({"start": 1, "end": 5}),
+                &json!("TODO 1\nTODO 2\n3\n4\n5"),
+                &json!([1, 2])
+            ),
+            (
+                &json!({"start": 7, "end": 10}),
+                &json!("7\n8\n9\nTODO 10"),
+                &json!([10])
+            ),
+        ]
+    );
+
+    let (status, body) = get(&app, &uri("/search/stream", &params)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+}
+
+#[tokio::test]
+async fn facets_count_matches_per_extension_over_http() {
+    let dir = tree(&[
+        ("a.rs", "TODO one\nTODO two"),
+        ("b.py", "# TODO three"),
+        ("c.md", "nothing"),
+    ]);
+    let mut config = AppConfig::new();
+    config.engine.index_metadata = true;
+    let app = router(state(&dir, config, &[]).await);
+    let params = [("text", "TODO"), ("by", "extension")];
+    assert_eq!(
+        get_json(&app, &uri("/search/facets", &params)).await,
+        json!({
+            "total": 3,
+            "counts": [{ "value": "rs", "count": 2 }, { "value": "py", "count": 1 }],
+            "other": 0,
+        })
+    );
+    let (status, _) = get(
+        &app,
+        &uri("/search/facets", &[("text", "TODO"), ("by", "owner")]),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let app = router(state(&dir, AppConfig::new(), &[]).await);
+    let (status, body) = get(&app, &uri("/search/facets", &params)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body.contains("index_metadata"), "{body}");
+}
+
+#[tokio::test]
+async fn msgpack_responses_decode_to_the_same_results_as_json() {
+    let dir = tree(&[
+        ("notes.txt", "TODO write tests\nTODO ship"),
+        ("b.rs", "// TODO"),
+    ]);
+    let app = router(state(&dir, AppConfig::new(), &[]).await);
+    let uri = uri("/search", &[("text", "TODO")]);
+    let request = Request::get(&uri)
+        .header("accept", "application/msgpack")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "application/msgpack");
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let packed: SearchResults = rmp_serde::from_slice(&body).unwrap();
+    assert_eq!(packed.results().len(), 3);
+
+    let (_, body) = get(&app, &uri).await;
+    let json: SearchResults = serde_json::from_str(&body).unwrap();
+    let [mut packed, mut json] =
+        [packed, json].map(|results| serde_json::to_value(results).unwrap());
+    for body in [&mut packed, &mut json] {
+        // Timings differ between the two searches.
+        let body = body.as_object_mut().unwrap();
+        body.remove("time");
+        body.remove("timings");
+    }
+    assert_eq!(packed, json);
+}
+
+#[tokio::test]
+async fn blame_outside_a_git_work_tree_leaves_results_unblamed() {
+    let dir = tree(&[("main.rs", "fn main() {}")]);
+    let app = router(state(&dir, AppConfig::new(), &[]).await);
+    let params = [("text", "main"), ("blame", "true")];
+    let body = get_json(&app, &uri("/search", &params)).await;
+    assert_eq!(body["results"][0]["line"], 1);
+    assert!(body["results"][0]["blame"].is_null(), "{body}");
+
+    let (status, body) = get(&app, &uri("/search/stream", &params)).await;
+    assert_eq!(status, StatusCode::OK);
+    let first: Value = serde_json::from_str(body.lines().next().unwrap()).unwrap();
+    assert_eq!(first["line"], 1);
+    assert!(first["blame"].is_null(), "{first}");
+}